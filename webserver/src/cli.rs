@@ -1,6 +1,9 @@
 use clap::Parser;
 use clap::Subcommand;
 
+use crate::http::handler_frontend::users::schema::UserLanguage;
+use crate::models::UserRole;
+
 /// The cli
 #[derive(Parser)]
 pub struct Cli {
@@ -17,18 +20,181 @@ pub struct Cli {
 #[derive(Subcommand)]
 pub enum Command {
     /// Start the server
-    Start,
+    Start {
+        /// The directory where the migration files are located in
+        ///
+        /// The server refuses to start if the database is missing any of them.
+        #[clap(long, default_value_t = String::from("migrations"))]
+        migrations_dir: String,
+        /// Start without comparing the database with the migrations directory
+        #[clap(long)]
+        skip_migration_check: bool,
+    },
     /// Run the migrations on the database
     Migrate {
         /// The directory where the migration files are located in
         migrations_dir: String,
     },
+    /// List the migrations and whether they have been applied
+    ///
+    /// Exits with an error if any migration is pending.
+    MigrationStatus {
+        /// The directory where the migration files are located in
+        migrations_dir: String,
+    },
+    /// Print a config file containing every option with its default or a placeholder
+    PrintConfigTemplate,
     /// Create new migrations
     #[cfg(debug_assertions)]
     MakeMigrations {
         /// The directory where the migration files are located in
         migrations_dir: String,
     },
+    /// Write the OpenAPI document to a file
+    ///
+    /// This neither requires a config file nor a database.
+    ExportOpenApi {
+        /// The path to write the json document to
+        output: String,
+    },
     /// Create a local admin user
-    CreateAdminUser,
+    CreateAdminUser {
+        /// Don't send the invite link via mail, even if SMTP has been configured
+        #[clap(long)]
+        no_email: bool,
+    },
+    /// Create an invite without prompting for its details
+    CreateInvite {
+        /// The mail of the invited user
+        #[clap(long)]
+        mail: String,
+        /// The display name of the invited user
+        #[clap(long)]
+        display_name: String,
+        /// The language of the invited user
+        #[clap(long, default_value_t = UserLanguage::EN)]
+        lang: UserLanguage,
+        /// The role of the invited user
+        #[clap(long, default_value_t = UserRole::Internal)]
+        role: UserRole,
+        /// Don't send the invite link via mail, even if SMTP has been configured
+        #[clap(long)]
+        no_email: bool,
+    },
+    /// List all users
+    ListUsers,
+    /// Delete a user
+    DeleteUser {
+        /// The mail of the user to delete
+        #[clap(long)]
+        mail: String,
+    },
+    /// Change a user's role
+    ///
+    /// Internal users keep their groups.
+    SetRole {
+        /// The mail of the user to modify
+        #[clap(long)]
+        mail: String,
+        /// The new role
+        #[clap(long)]
+        role: UserRole,
+    },
+    /// Overwrite a local user's password and log them out
+    ///
+    /// The new password is read from stdin.
+    ResetPassword {
+        /// The mail of the user to modify
+        #[clap(long)]
+        mail: String,
+        /// Also remove all TOTP and WebAuthn keys
+        #[clap(long)]
+        remove_mfa: bool,
+    },
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::Cli;
+    use super::Command;
+    use crate::http::handler_frontend::users::schema::UserLanguage;
+    use crate::models::UserRole;
+
+    #[test]
+    fn set_role_parses_the_role() -> Result<(), clap::Error> {
+        let cli = Cli::try_parse_from([
+            "webserver",
+            "set-role",
+            "--mail",
+            "jane@example.com",
+            "--role",
+            "Internal",
+        ])?;
+        assert!(matches!(
+            cli.command,
+            Command::SetRole { mail, role: UserRole::Internal } if mail == "jane@example.com"
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn set_role_rejects_unknown_roles() {
+        assert!(Cli::try_parse_from([
+            "webserver",
+            "set-role",
+            "--mail",
+            "jane@example.com",
+            "--role",
+            "Owner",
+        ])
+        .is_err());
+    }
+
+    #[test]
+    fn reset_password_keeps_the_second_factors_by_default() -> Result<(), clap::Error> {
+        let cli =
+            Cli::try_parse_from(["webserver", "reset-password", "--mail", "jane@example.com"])?;
+        assert!(matches!(
+            cli.command,
+            Command::ResetPassword {
+                remove_mfa: false,
+                ..
+            }
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn create_invite_defaults_to_an_internal_user() -> Result<(), clap::Error> {
+        let cli = Cli::try_parse_from([
+            "webserver",
+            "create-invite",
+            "--mail",
+            "jane@example.com",
+            "--display-name",
+            "Jane Doe",
+        ])?;
+        assert!(matches!(
+            cli.command,
+            Command::CreateInvite {
+                lang: UserLanguage::EN,
+                role: UserRole::Internal,
+                no_email: false,
+                ..
+            }
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn migration_status_takes_the_migrations_dir() -> Result<(), clap::Error> {
+        let cli = Cli::try_parse_from(["webserver", "migration-status", "/migrations"])?;
+        assert!(matches!(
+            cli.command,
+            Command::MigrationStatus { migrations_dir } if migrations_dir == "/migrations"
+        ));
+        Ok(())
+    }
 }
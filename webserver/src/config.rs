@@ -1,5 +1,6 @@
 //! Definitions of the configuration file
 
+use std::env;
 use std::fs;
 use std::io;
 use std::path::Path;
@@ -13,8 +14,15 @@ use rorm::DatabaseDriver;
 use serde::Deserialize;
 use serde::Serialize;
 use thiserror::Error;
+use tracing::debug;
 use webauthn_rs::prelude::Url;
 
+use crate::http::handler_frontend::auth::schema::LoginFlowPreference;
+use crate::models::UserRole;
+use crate::utils::display_name::DisplayNamePolicy;
+use crate::utils::ip_network::IpNetwork;
+use crate::utils::secure_string::SecureString;
+
 /// Server related configuration.
 #[derive(Deserialize, Serialize, Debug, Clone)]
 #[serde(rename_all = "PascalCase")]
@@ -27,6 +35,48 @@ pub struct ServerConfig {
     ///
     /// Used for generating links which should point back to {{project-name}}
     pub origin: String,
+    /// Networks whose clients aren't rate limited, like `10.0.0.0/8` for internal monitoring
+    ///
+    /// Clients from these networks may forward requests naming the original client in `X-Forwarded-For`.
+    /// So, a reverse proxy's address has to be listed for the rate limits to apply to its clients.
+    /// The header is ignored for any other client.
+    #[serde(default)]
+    pub trusted_networks: Vec<IpNetwork>,
+    /// The number of seconds to wait for in-flight requests to finish when shutting down
+    ///
+    /// Remaining connections are dropped afterward.
+    #[serde(default = "ServerConfig::default_shutdown_timeout_secs")]
+    pub shutdown_timeout_secs: u64,
+    /// The maximum number of open connections
+    ///
+    /// Further connections are closed right after being accepted.
+    /// Websockets and idle keep-alive connections count towards the limit.
+    /// Unlimited if not set.
+    #[serde(default)]
+    pub max_connections: Option<usize>,
+    /// The maximum size of a request's body in bytes
+    ///
+    /// Larger requests are rejected with `413 Payload Too Large`.
+    #[serde(default = "ServerConfig::default_body_limit_bytes")]
+    pub body_limit_bytes: usize,
+    /// Serve the Swagger UI and the OpenAPI document under `/docs`
+    ///
+    /// Defaults to `true` in debug builds and `false` in release builds.
+    #[serde(default = "ServerConfig::default_serve_docs")]
+    pub serve_docs: bool,
+}
+impl ServerConfig {
+    fn default_shutdown_timeout_secs() -> u64 {
+        30
+    }
+
+    fn default_body_limit_bytes() -> usize {
+        1024 * 1024
+    }
+
+    fn default_serve_docs() -> bool {
+        cfg!(debug_assertions)
+    }
 }
 
 /// WebAuthn related configuration.
@@ -50,7 +100,693 @@ pub struct WebAuthnConfig {
     /// List of attestation CAs accepted when registering new webauthn keys with login privileges.
     ///
     /// This option should be a path to a json file generated by `fido-mds-tool query`.
+    /// The server refuses to start if the list is empty or malformed.
+    /// When reloading, such a list is ignored and the current one kept.
     pub attestation_ca_list: PathBuf,
+
+    /// The interval in minutes to reload `AttestationCaList` from disk in
+    ///
+    /// If omitted, the list is only read on startup.
+    #[serde(default)]
+    pub attestation_ca_list_reload_minutes: Option<u32>,
+}
+
+/// Authentication related configuration.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(rename_all = "PascalCase")]
+pub struct AuthConfig {
+    /// The login flow the frontend should offer by default
+    /// to users who have both a password and a security key able to log in.
+    #[serde(default)]
+    pub login_flow_preference: LoginFlowPreference,
+
+    /// An application wide secret mixed into every password hash
+    ///
+    /// It has to be at least 16 bytes long and should be kept outside the database.
+    ///
+    /// **Warning:** Changing or removing the pepper invalidates all existing password hashes,
+    /// i.e. every local user will have to reset their password.
+    #[serde(default)]
+    pub password_pepper: Option<SecureString>,
+
+    /// The minimum number of characters a password has to consist of
+    ///
+    /// It applies to every password set through the api, including those set by admins.
+    /// Existing passwords are not affected.
+    #[serde(default = "AuthConfig::default_min_password_length")]
+    pub min_password_length: usize,
+
+    /// The number of minutes a started WebAuthn key registration can be completed in
+    #[serde(default = "AuthConfig::default_enrollment_timeout_minutes")]
+    pub enrollment_timeout_minutes: u32,
+
+    /// Notify users by mail and websocket when they log in from a device they haven't used before
+    ///
+    /// Devices are told apart by their user agent and ip address.
+    #[serde(default)]
+    pub notify_new_devices: bool,
+
+    /// The maximum number of sessions a user may be logged in with at the same time
+    ///
+    /// Logging in beyond this number ends the user's least recently used sessions.
+    /// `0` means unlimited.
+    #[serde(default)]
+    pub max_sessions_per_user: u32,
+
+    /// Users with a second factor have to provide it to change their password,
+    /// unless they did so within this number of minutes (e.g. while logging in)
+    ///
+    /// If omitted, the current password suffices.
+    #[serde(default)]
+    pub password_change_step_up_minutes: Option<u32>,
+
+    /// The number of 30 second time steps a TOTP token may lie in the past or future
+    ///
+    /// This tolerates clocks of hardware tokens which drifted apart from the server's.
+    /// Every step accepts two more tokens per attempt and keeps an observed token valid
+    /// for 30 seconds longer, so the window should be kept as small as possible.
+    #[serde(default = "AuthConfig::default_totp_skew")]
+    pub totp_skew: u8,
+
+    /// Tell users during the login whether an account exists for the mail they entered
+    ///
+    /// Revealing it improves the login's usability:
+    /// users are told about a mistyped mail instead of a wrong password
+    /// and are only offered the login flows their account supports.
+    /// On the other hand, anyone may probe which mails are registered.
+    ///
+    /// If disabled, unknown mails are answered like accounts which only have a password.
+    /// Accounts using other flows (e.g. oidc or security keys) remain distinguishable.
+    #[serde(default)]
+    pub reveal_account_existence: bool,
+}
+
+impl AuthConfig {
+    fn default_min_password_length() -> usize {
+        8
+    }
+
+    fn default_enrollment_timeout_minutes() -> u32 {
+        5
+    }
+
+    fn default_totp_skew() -> u8 {
+        1
+    }
+}
+impl Default for AuthConfig {
+    fn default() -> Self {
+        Self {
+            login_flow_preference: LoginFlowPreference::default(),
+            password_pepper: None,
+            min_password_length: Self::default_min_password_length(),
+            enrollment_timeout_minutes: Self::default_enrollment_timeout_minutes(),
+            notify_new_devices: false,
+            max_sessions_per_user: 0,
+            password_change_step_up_minutes: None,
+            totp_skew: Self::default_totp_skew(),
+            reveal_account_existence: false,
+        }
+    }
+}
+
+/// Session related configuration.
+///
+/// A session ends when it hasn't been used for `IdleTimeoutMinutes`
+/// or when `MaxLifetimeHours` have passed since the login, whichever happens first.
+/// Using a session extends its idle timeout but never its maximum lifetime.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(rename_all = "PascalCase")]
+pub struct SessionsConfig {
+    /// The number of minutes a session may be unused before it expires
+    #[serde(default = "SessionsConfig::default_idle_timeout_minutes")]
+    pub idle_timeout_minutes: u32,
+
+    /// The number of hours after the login a session expires regardless of its use
+    ///
+    /// If omitted, sessions can be kept alive indefinitely.
+    #[serde(default)]
+    pub max_lifetime_hours: Option<u32>,
+
+    /// The number of minutes a user may take to provide their second factor after their password
+    #[serde(default = "SessionsConfig::default_mfa_timeout_minutes")]
+    pub mfa_timeout_minutes: u32,
+
+    /// The name of the session cookie
+    #[serde(default = "SessionsConfig::default_cookie_name")]
+    pub cookie_name: String,
+
+    /// Whether the session cookie is only sent over https
+    ///
+    /// Defaults to `true` if `Tls` is configured or `Server.Origin` uses https.
+    /// Set it explicitly when a reverse proxy terminates tls.
+    #[serde(default)]
+    pub cookie_secure: Option<bool>,
+
+    /// The `Domain` attribute of the session cookie
+    ///
+    /// Set it to share the session among subdomains.
+    /// If omitted, the cookie is only sent to the host which set it.
+    #[serde(default)]
+    pub cookie_domain: Option<String>,
+
+    /// The `Path` attribute of the session cookie
+    #[serde(default = "SessionsConfig::default_cookie_path")]
+    pub cookie_path: String,
+
+    /// The `SameSite` attribute of the session cookie
+    ///
+    /// Frontends hosted on another site require `None` which in turn requires `CookieSecure`.
+    #[serde(default)]
+    pub cookie_same_site: CookieSameSite,
+
+    /// Where the sessions' data is stored
+    #[serde(default)]
+    pub store: SessionStoreKind,
+
+    /// The url of the redis server
+    ///
+    /// Only used if `Store` is `Redis`.
+    #[serde(default = "SessionsConfig::default_redis_url")]
+    pub redis_url: String,
+}
+impl SessionsConfig {
+    fn default_idle_timeout_minutes() -> u32 {
+        24 * 60
+    }
+
+    fn default_mfa_timeout_minutes() -> u32 {
+        10
+    }
+
+    fn default_redis_url() -> String {
+        "redis://127.0.0.1:6379".to_string()
+    }
+
+    fn default_cookie_name() -> String {
+        "id".to_string()
+    }
+
+    fn default_cookie_path() -> String {
+        "/".to_string()
+    }
+}
+impl Default for SessionsConfig {
+    fn default() -> Self {
+        Self {
+            idle_timeout_minutes: Self::default_idle_timeout_minutes(),
+            max_lifetime_hours: None,
+            mfa_timeout_minutes: Self::default_mfa_timeout_minutes(),
+            cookie_name: Self::default_cookie_name(),
+            cookie_secure: None,
+            cookie_domain: None,
+            cookie_path: Self::default_cookie_path(),
+            cookie_same_site: Default::default(),
+            store: Default::default(),
+            redis_url: Self::default_redis_url(),
+        }
+    }
+}
+
+/// Where the sessions' data is stored
+///
+/// Every session has a row in the database in any case,
+/// as it maps the session to its user.
+#[derive(Deserialize, Serialize, Debug, Copy, Clone, Default)]
+pub enum SessionStoreKind {
+    /// Store the data in the database
+    ///
+    /// Requires no additional infrastructure and is shared among all instances.
+    #[default]
+    Database,
+    /// Store the data in the server's memory
+    ///
+    /// Sessions are lost on restart and not shared among instances.
+    /// Only intended for development.
+    Memory,
+    /// Store the data in redis
+    ///
+    /// Takes load off the database, but requires it to be consulted on every request anyway.
+    Redis,
+}
+
+/// The `SameSite` attribute of a cookie
+#[derive(Deserialize, Serialize, Debug, Copy, Clone, Default)]
+pub enum CookieSameSite {
+    /// Only send the cookie in requests originating from the same site
+    Strict,
+    /// Like `Strict` but also send the cookie when navigating to the site
+    #[default]
+    Lax,
+    /// Send the cookie in cross-site requests as well
+    None,
+}
+
+/// Cross-site request forgery protection.
+///
+/// Applies to all state-changing requests.
+/// This includes logging in and accepting invites,
+/// otherwise another site could log a user into an account controlled by the attacker.
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+#[serde(rename_all = "PascalCase")]
+pub struct CsrfConfig {
+    /// What is required of state-changing requests
+    #[serde(default)]
+    pub mode: CsrfMode,
+}
+
+/// What is required of state-changing requests to be considered same-site
+#[derive(Deserialize, Serialize, Debug, Copy, Clone, Default)]
+pub enum CsrfMode {
+    /// Require the `X-CSRF-Token` header to contain the token issued by `/auth/csrf-token`
+    #[default]
+    Token,
+    /// Require the `X-CSRF-Token` header to be present with any value
+    ///
+    /// Browsers only send custom headers cross-origin if the CORS config allows them.
+    Header,
+    /// Don't check anything and rely on the session cookie's `SameSite` attribute
+    Disabled,
+}
+
+/// User related configuration.
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+#[serde(rename_all = "PascalCase")]
+pub struct UsersConfig {
+    /// How strictly display names should be normalized before being stored
+    ///
+    /// One of `Off`, `Normalize` (default) or `Strict`.
+    #[serde(default)]
+    pub display_name_policy: DisplayNamePolicy,
+
+    /// Reject display names which are already used by another user or open invite
+    ///
+    /// The comparison is case-insensitive.
+    #[serde(default)]
+    pub unique_display_names: bool,
+}
+
+/// Configuration of one-time login links issued by admins.
+///
+/// Anyone in possession of such a link can log in as its user without any further factor.
+/// Therefore, this feature is disabled by default.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(rename_all = "PascalCase")]
+pub struct MagicLinksConfig {
+    /// Whether admins may issue login links
+    #[serde(default)]
+    pub enabled: bool,
+
+    /// The number of minutes a login link is valid for
+    #[serde(default = "MagicLinksConfig::default_expiry_minutes")]
+    pub expiry_minutes: u32,
+}
+impl MagicLinksConfig {
+    fn default_expiry_minutes() -> u32 {
+        15
+    }
+}
+impl Default for MagicLinksConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            expiry_minutes: Self::default_expiry_minutes(),
+        }
+    }
+}
+
+/// Email verification related configuration.
+///
+/// Users created through an invite or by an admin start with an unverified mail,
+/// if verification is required, and are sent a link to verify it.
+/// Users from oidc are verified if the identity provider claims so.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(rename_all = "PascalCase")]
+pub struct EmailVerificationConfig {
+    /// Require users to verify their mail before they may use admin functionality
+    #[serde(default)]
+    pub required: bool,
+
+    /// The number of hours a verification link is valid for
+    #[serde(default = "EmailVerificationConfig::default_expiry_hours")]
+    pub expiry_hours: u32,
+}
+impl EmailVerificationConfig {
+    fn default_expiry_hours() -> u32 {
+        24
+    }
+}
+impl Default for EmailVerificationConfig {
+    fn default() -> Self {
+        Self {
+            required: false,
+            expiry_hours: Self::default_expiry_hours(),
+        }
+    }
+}
+
+/// User invite related configuration.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(rename_all = "PascalCase")]
+pub struct InvitesConfig {
+    /// The number of hours an invite is valid for, if not specified otherwise upon creation
+    #[serde(default = "InvitesConfig::default_expiry_hours")]
+    pub default_expiry_hours: u32,
+
+    /// The maximum number of hours an invite may be valid for
+    #[serde(default = "InvitesConfig::default_max_expiry_hours")]
+    pub max_expiry_hours: u32,
+}
+impl InvitesConfig {
+    fn default_expiry_hours() -> u32 {
+        24
+    }
+    fn default_max_expiry_hours() -> u32 {
+        24 * 30
+    }
+}
+impl Default for InvitesConfig {
+    fn default() -> Self {
+        Self {
+            default_expiry_hours: Self::default_expiry_hours(),
+            max_expiry_hours: Self::default_max_expiry_hours(),
+        }
+    }
+}
+
+/// Configuration of paginated endpoints.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy)]
+#[serde(rename_all = "PascalCase")]
+pub struct PaginationConfig {
+    /// The number of items returned if a request doesn't specify a limit (default: 100)
+    #[serde(default = "PaginationConfig::default_limit")]
+    pub default_limit: u64,
+
+    /// The upper bound for a request's limit, larger limits will be clamped (default: 1000)
+    #[serde(default = "PaginationConfig::default_max_limit")]
+    pub max_limit: u64,
+}
+impl PaginationConfig {
+    fn default_limit() -> u64 {
+        100
+    }
+    fn default_max_limit() -> u64 {
+        1000
+    }
+}
+impl Default for PaginationConfig {
+    fn default() -> Self {
+        Self {
+            default_limit: Self::default_limit(),
+            max_limit: Self::default_max_limit(),
+        }
+    }
+}
+
+/// Configuration of the background task purging expired invites and sessions.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(rename_all = "PascalCase")]
+pub struct CleanupConfig {
+    /// Whether the task should run at all
+    #[serde(default = "CleanupConfig::default_enabled")]
+    pub enabled: bool,
+
+    /// The number of minutes between two runs
+    #[serde(default = "CleanupConfig::default_interval_minutes")]
+    pub interval_minutes: u32,
+
+    /// The number of hours expired invites are kept for before they are purged
+    ///
+    /// Admins can still renew an expired invite during this time.
+    #[serde(default)]
+    pub expired_invite_retention_hours: u32,
+}
+impl CleanupConfig {
+    fn default_enabled() -> bool {
+        true
+    }
+    fn default_interval_minutes() -> u32 {
+        60
+    }
+}
+impl Default for CleanupConfig {
+    fn default() -> Self {
+        Self {
+            enabled: Self::default_enabled(),
+            interval_minutes: Self::default_interval_minutes(),
+            expired_invite_retention_hours: 0,
+        }
+    }
+}
+
+/// CORS related configuration.
+///
+/// Required if the frontend is hosted on a different origin than the api.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(rename_all = "PascalCase")]
+pub struct CorsConfig {
+    /// The origins allowed to make cross-origin requests
+    ///
+    /// `*` allows any origin, but can't be combined with `AllowCredentials`.
+    /// Defaults to the server's origin if empty.
+    #[serde(default)]
+    pub allowed_origins: Vec<String>,
+
+    /// The methods allowed in cross-origin requests
+    #[serde(default = "CorsConfig::default_allowed_methods")]
+    pub allowed_methods: Vec<String>,
+
+    /// The headers allowed in cross-origin requests
+    #[serde(default = "CorsConfig::default_allowed_headers")]
+    pub allowed_headers: Vec<String>,
+
+    /// Whether cross-origin requests may include credentials i.e. the session cookie
+    #[serde(default = "CorsConfig::default_allow_credentials")]
+    pub allow_credentials: bool,
+}
+impl CorsConfig {
+    fn default_allowed_methods() -> Vec<String> {
+        ["GET", "POST", "PUT", "PATCH", "DELETE"]
+            .map(String::from)
+            .to_vec()
+    }
+    fn default_allowed_headers() -> Vec<String> {
+        ["content-type", "x-csrf-token"].map(String::from).to_vec()
+    }
+    fn default_allow_credentials() -> bool {
+        true
+    }
+
+    /// Checks whether any origin is allowed
+    pub fn allows_any_origin(&self) -> bool {
+        self.allowed_origins.iter().any(|origin| origin == "*")
+    }
+}
+impl Default for CorsConfig {
+    fn default() -> Self {
+        Self {
+            allowed_origins: Vec::new(),
+            allowed_methods: Self::default_allowed_methods(),
+            allowed_headers: Self::default_allowed_headers(),
+            allow_credentials: Self::default_allow_credentials(),
+        }
+    }
+}
+
+/// Security headers added to every response.
+///
+/// Each header can be overridden or disabled by setting it to an empty string.
+/// Headers set by a handler itself are left untouched.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(rename_all = "PascalCase")]
+pub struct SecurityHeadersConfig {
+    /// The `X-Content-Type-Options` header
+    #[serde(default = "SecurityHeadersConfig::default_content_type_options")]
+    pub content_type_options: String,
+
+    /// The `X-Frame-Options` header
+    #[serde(default = "SecurityHeadersConfig::default_frame_options")]
+    pub frame_options: String,
+
+    /// The `Referrer-Policy` header
+    #[serde(default = "SecurityHeadersConfig::default_referrer_policy")]
+    pub referrer_policy: String,
+
+    /// The `Content-Security-Policy` header
+    ///
+    /// The default only forbids framing,
+    /// deployments serving their frontend through this server should tighten it.
+    #[serde(default = "SecurityHeadersConfig::default_content_security_policy")]
+    pub content_security_policy: String,
+
+    /// The `Strict-Transport-Security` header
+    ///
+    /// This is only sent if `Tls` is configured.
+    #[serde(default = "SecurityHeadersConfig::default_strict_transport_security")]
+    pub strict_transport_security: String,
+}
+impl SecurityHeadersConfig {
+    fn default_content_type_options() -> String {
+        "nosniff".to_string()
+    }
+    fn default_frame_options() -> String {
+        "DENY".to_string()
+    }
+    fn default_referrer_policy() -> String {
+        "no-referrer".to_string()
+    }
+    fn default_content_security_policy() -> String {
+        "frame-ancestors 'none'".to_string()
+    }
+    fn default_strict_transport_security() -> String {
+        "max-age=31536000; includeSubDomains".to_string()
+    }
+}
+impl Default for SecurityHeadersConfig {
+    fn default() -> Self {
+        Self {
+            content_type_options: Self::default_content_type_options(),
+            frame_options: Self::default_frame_options(),
+            referrer_policy: Self::default_referrer_policy(),
+            content_security_policy: Self::default_content_security_policy(),
+            strict_transport_security: Self::default_strict_transport_security(),
+        }
+    }
+}
+
+/// Avatar related configuration.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(rename_all = "PascalCase")]
+pub struct AvatarsConfig {
+    /// Where the avatars are stored
+    #[serde(flatten)]
+    pub storage: AvatarStorageConfig,
+
+    /// The maximum size of uploaded images in bytes
+    ///
+    /// Must not exceed `Server.BodyLimitBytes`.
+    #[serde(default = "AvatarsConfig::default_max_upload_bytes")]
+    pub max_upload_bytes: usize,
+
+    /// The width and height in pixels avatars are resized to
+    #[serde(default = "AvatarsConfig::default_size")]
+    pub size: u32,
+}
+impl AvatarsConfig {
+    fn default_max_upload_bytes() -> usize {
+        1024 * 1024
+    }
+
+    fn default_size() -> u32 {
+        256
+    }
+}
+
+/// Where avatars are stored
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(tag = "Storage")]
+pub enum AvatarStorageConfig {
+    /// Store avatars as files in a local directory
+    #[serde(rename_all = "PascalCase")]
+    Filesystem {
+        /// The directory to store the avatars in
+        directory: PathBuf,
+    },
+    /// Store avatars in an S3 compatible object store
+    #[serde(rename_all = "PascalCase")]
+    S3 {
+        /// The bucket to store the avatars in
+        bucket: String,
+        /// The bucket's region
+        region: String,
+        /// The url of the object store
+        ///
+        /// Only required for object stores other than AWS.
+        endpoint: Option<Url>,
+        /// The access key's id
+        access_key_id: String,
+        /// The access key's secret
+        secret_access_key: SecureString,
+    },
+}
+
+/// SMTP related configuration.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(rename_all = "PascalCase")]
+pub struct SmtpConfig {
+    /// The host of the SMTP server
+    pub host: String,
+
+    /// The port of the SMTP server
+    ///
+    /// Defaults to the standard port of the chosen `Encryption`.
+    #[serde(default)]
+    pub port: Option<u16>,
+
+    /// How the connection to the SMTP server should be secured
+    #[serde(default)]
+    pub encryption: SmtpEncryption,
+
+    /// The user to authenticate as
+    pub user: String,
+
+    /// The password to authenticate with
+    pub password: SecureString,
+
+    /// The sender of all mails, e.g. `{{project-name}} <noreply@example.com>`
+    pub from: String,
+}
+
+/// Webhook related configuration.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(rename_all = "PascalCase")]
+pub struct WebhooksConfig {
+    /// The urls every event is posted to
+    pub endpoints: Vec<Url>,
+
+    /// The secret to sign the payloads with
+    ///
+    /// The hex encoded HMAC-SHA256 of the body is sent in the `X-Webhook-Signature` header.
+    pub secret: SecureString,
+
+    /// The number of events which may wait to be delivered
+    ///
+    /// Further events are dropped until the queue has room again.
+    #[serde(default = "WebhooksConfig::default_queue_size")]
+    pub queue_size: usize,
+
+    /// How often a failed delivery is retried
+    #[serde(default = "WebhooksConfig::default_max_retries")]
+    pub max_retries: u32,
+
+    /// The time in seconds a single delivery attempt may take
+    #[serde(default = "WebhooksConfig::default_timeout_secs")]
+    pub timeout_secs: u64,
+}
+impl WebhooksConfig {
+    fn default_queue_size() -> usize {
+        1024
+    }
+
+    fn default_max_retries() -> u32 {
+        5
+    }
+
+    fn default_timeout_secs() -> u64 {
+        10
+    }
+}
+
+/// How the connection to the SMTP server should be secured
+#[derive(Deserialize, Serialize, Debug, Copy, Clone, Default)]
+pub enum SmtpEncryption {
+    /// Use implicit TLS
+    #[default]
+    Tls,
+    /// Upgrade the connection using `STARTTLS`
+    StartTls,
+    /// Don't encrypt the connection at all
+    None,
 }
 
 /// Database related configuration.
@@ -68,7 +804,7 @@ pub struct DBConfig {
     /// The user to use for the database connection
     pub user: String,
     /// Password for the user
-    pub password: String,
+    pub password: SecureString,
 }
 
 impl From<DBConfig> for DatabaseDriver {
@@ -78,7 +814,7 @@ impl From<DBConfig> for DatabaseDriver {
             host: value.host,
             port: value.port,
             user: value.user,
-            password: value.password,
+            password: value.password.into_inner(),
         }
     }
 }
@@ -95,11 +831,68 @@ pub struct OpenIdConnect {
     pub redirect_url: RedirectUrl,
     /// The discover url
     pub discover_url: IssuerUrl,
+    /// The secret to encrypt the tokens issued by the provider with
+    ///
+    /// If set, the access and refresh tokens are stored to call downstream APIs on the user's behalf.
+    /// It should be a long random string.
+    #[serde(default)]
+    pub token_encryption_key: Option<SecureString>,
+    /// The role assigned to users created upon their first login
+    #[serde(default = "OpenIdConnect::default_default_role")]
+    pub default_role: UserRole,
+}
+impl OpenIdConnect {
+    fn default_default_role() -> UserRole {
+        UserRole::Internal
+    }
+}
+
+/// TLS related configuration.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(rename_all = "PascalCase")]
+pub struct TlsConfig {
+    /// Path to the PEM encoded certificate chain
+    pub cert_path: PathBuf,
+    /// Path to the PEM encoded private key
+    pub key_path: PathBuf,
 }
 
 /// Definition of the main configuration.
 ///
 /// This model can be parsed from the config.toml
+///
+/// ## Live reload
+///
+/// Upon `SIGHUP` the file is read again and changes to the following values are applied
+/// without a restart:
+///
+/// - `Auth.LoginFlowPreference`, `Auth.EnrollmentTimeoutMinutes`, `Auth.NotifyNewDevices`,
+///   `Auth.MaxSessionsPerUser`, `Auth.PasswordChangeStepUpMinutes`, `Auth.MinPasswordLength`,
+///   `Auth.TotpSkew` and `Auth.RevealAccountExistence`
+/// - `Sessions.MaxLifetimeHours` and `Sessions.MfaTimeoutMinutes`
+/// - `Csrf`
+/// - `Invites`
+/// - `MagicLinks`
+/// - `EmailVerification`
+/// - `Pagination`
+/// - The contents of the file at `Webauthn.AttestationCaList`
+///
+/// Changes to every other value (e.g. `Server`, `Database` or `Sessions.IdleTimeoutMinutes`)
+/// require a restart.
+///
+/// ## Environment variables
+///
+/// Every value can be overridden by an environment variable which is named after its path
+/// in upper snake case, prefixed by [`ENV_PREFIX`] and separated by [`ENV_SEPARATOR`].
+/// For example, `WS_DATABASE__PASSWORD` overrides `Database.Password`
+/// and `WS_SESSIONS__IDLE_TIMEOUT_MINUTES` overrides `Sessions.IdleTimeoutMinutes`.
+///
+/// Values for string options are used as is.
+/// Values for other options are parsed as toml (e.g. `8080`, `true` or `["a", "b"]`)
+/// and fall back to a string if that fails.
+/// The option's type is taken from the file or, if it isn't set there, from [`Config::template`].
+/// Unknown options are treated as strings.
+/// Overrides are applied on reload as well.
 #[derive(Deserialize, Serialize, Debug, Clone)]
 #[serde(rename_all = "PascalCase")]
 pub struct Config {
@@ -107,35 +900,1222 @@ pub struct Config {
     pub server: ServerConfig,
     /// Webauthn configuration
     pub webauthn: WebAuthnConfig,
+    /// Authentication configuration
+    #[serde(default)]
+    pub auth: AuthConfig,
+    /// Session configuration
+    #[serde(default)]
+    pub sessions: SessionsConfig,
+    /// CSRF protection configuration
+    #[serde(default)]
+    pub csrf: CsrfConfig,
+    /// User configuration
+    #[serde(default)]
+    pub users: UsersConfig,
+    /// User invite configuration
+    #[serde(default)]
+    pub invites: InvitesConfig,
+    /// Login link configuration
+    #[serde(default)]
+    pub magic_links: MagicLinksConfig,
+    /// Email verification configuration
+    #[serde(default)]
+    pub email_verification: EmailVerificationConfig,
+    /// Pagination configuration
+    #[serde(default)]
+    pub pagination: PaginationConfig,
     /// Database configuration
     pub database: DBConfig,
+    /// Cleanup task configuration
+    #[serde(default)]
+    pub cleanup: CleanupConfig,
+    /// CORS configuration
+    #[serde(default)]
+    pub cors: CorsConfig,
+    /// Security headers configuration
+    #[serde(default)]
+    pub security_headers: SecurityHeadersConfig,
+    /// Avatar configuration
+    ///
+    /// If omitted, users can't upload avatars.
+    pub avatars: Option<AvatarsConfig>,
+    /// The SMTP server to send mails with
+    ///
+    /// If omitted, no mails will be sent.
+    pub smtp: Option<SmtpConfig>,
+    /// The endpoints to notify about events like the creation of users
+    ///
+    /// If omitted, no webhooks will be sent.
+    pub webhooks: Option<WebhooksConfig>,
     /// The config for oidc
     pub openid_connect: Option<OpenIdConnect>,
+    /// The config for serving https directly
+    ///
+    /// If omitted, plain http is served which should be put behind a reverse proxy terminating tls.
+    pub tls: Option<TlsConfig>,
 }
 
 /// All errors that can occur when parsing a configuration file
 #[derive(Debug, Error)]
 #[allow(missing_docs)]
 pub enum ConfigError {
-    #[error("{0}")]
-    Io(#[from] io::Error),
-    #[error("The config file is missing")]
-    MissingFile,
-    #[error("Parsing of config file failed: {0}")]
-    ParsingFailed(#[from] toml::de::Error),
+    #[error("Could not read the config file {}: {source}", path.display())]
+    Io { path: PathBuf, source: io::Error },
+    #[error("The config file {} is missing", path.display())]
+    MissingFile { path: PathBuf },
+    /// The toml error's message already contains the line, column and offending key
+    #[error("Parsing of config file {} failed:\n{source}", path.display())]
+    ParsingFailed {
+        path: PathBuf,
+        source: toml::de::Error,
+    },
+    /// The variable's name doesn't point to a single option
+    #[error("The environment variable {variable} doesn't point to a config option")]
+    InvalidEnvOverride { variable: String },
+    /// Every problem found by [`Config::validate`]
+    #[error("Invalid config:{}", .0.iter().map(|problem| format!("\n - {problem}")).collect::<String>())]
+    Invalid(Vec<String>),
 }
 
 impl Config {
-    /// Read and parse a [Config] from a path
+    /// Read, parse and validate a [Config] from a path
     pub fn try_from_path(path: &str) -> Result<Self, ConfigError> {
         let p = Path::new(path);
         if !p.exists() {
-            return Err(ConfigError::MissingFile);
+            return Err(ConfigError::MissingFile {
+                path: p.to_path_buf(),
+            });
         }
 
-        let c_str = fs::read_to_string(p)?;
-        let config = toml::from_str(&c_str)?;
+        let c_str = fs::read_to_string(p).map_err(|source| ConfigError::Io {
+            path: p.to_path_buf(),
+            source,
+        })?;
+        let parsing_failed = |source| ConfigError::ParsingFailed {
+            path: p.to_path_buf(),
+            source,
+        };
+
+        // Variables which aren't valid unicode are ignored
+        let overrides: Vec<(String, String)> = env::vars_os()
+            .filter_map(|(name, value)| Some((name.into_string().ok()?, value.into_string().ok()?)))
+            .filter(|(name, _)| name.starts_with(ENV_PREFIX))
+            .collect();
+        // Parsing the string directly keeps the line and column in the error message
+        let config: Self = if overrides.is_empty() {
+            toml::from_str(&c_str).map_err(parsing_failed)?
+        } else {
+            let mut table: toml::Table = toml::from_str(&c_str).map_err(parsing_failed)?;
+            // The full example contains every option and therefore knows their types
+            let example = toml::Table::try_from(Self::example(true)).unwrap_or_default();
+            for (name, value) in overrides {
+                apply_env_override(&mut table, &example, &name, value)?;
+            }
+            toml::Value::Table(table)
+                .try_into()
+                .map_err(parsing_failed)?
+        };
+        config.validate()?;
 
         Ok(config)
     }
+
+    /// Check the constraints between values which can't be expressed by their types
+    ///
+    /// All problems are collected instead of stopping at the first one.
+    /// Referenced files are only checked for being readable,
+    /// their contents are reported when they are loaded.
+    fn validate(&self) -> Result<(), ConfigError> {
+        let mut problems = Vec::new();
+
+        if Url::parse(&self.server.origin).map_or(true, |origin| {
+            !matches!(origin.scheme(), "http" | "https") || !origin.has_host()
+        }) {
+            problems.push("Server.Origin must be an absolute http(s) url".to_string());
+        }
+        if !matches!(self.webauthn.origin.scheme(), "http" | "https") {
+            problems.push("Webauthn.Origin must be an http(s) url".to_string());
+        }
+        match self.webauthn.origin.host_str() {
+            Some(host)
+                if host == self.webauthn.id
+                    || host.ends_with(&format!(".{}", self.webauthn.id)) => {}
+            _ => problems.push(format!(
+                "Webauthn.Id \"{}\" must be the domain of Webauthn.Origin or one of its parents",
+                self.webauthn.id
+            )),
+        }
+        let ca_list = &self.webauthn.attestation_ca_list;
+        match fs::File::open(ca_list).and_then(|file| file.metadata()) {
+            Ok(metadata) if metadata.is_file() => {}
+            Ok(_) => problems.push(format!(
+                "Webauthn.AttestationCaList {} is not a file",
+                ca_list.display()
+            )),
+            Err(error) => problems.push(format!(
+                "Webauthn.AttestationCaList {} can't be read: {error}",
+                ca_list.display()
+            )),
+        }
+        if self.database.host.is_empty() {
+            problems.push("Database.Host must not be empty".to_string());
+        }
+        if self.database.port == 0 {
+            problems.push("Database.Port must be greater than 0".to_string());
+        }
+        if self.database.name.is_empty() {
+            problems.push("Database.Name must not be empty".to_string());
+        }
+        if self.database.user.is_empty() {
+            problems.push("Database.User must not be empty".to_string());
+        }
+        if self.invites.default_expiry_hours > self.invites.max_expiry_hours {
+            problems.push(
+                "Invites.DefaultExpiryHours must not exceed Invites.MaxExpiryHours".to_string(),
+            );
+        }
+        if self.pagination.default_limit > self.pagination.max_limit {
+            problems
+                .push("Pagination.DefaultLimit must not exceed Pagination.MaxLimit".to_string());
+        }
+        if self.auth.totp_skew > 10 {
+            problems.push("Auth.TotpSkew must not exceed 10".to_string());
+        }
+        if self.auth.min_password_length == 0 {
+            problems.push("Auth.MinPasswordLength must be greater than 0".to_string());
+        }
+        if self.email_verification.expiry_hours == 0 {
+            problems.push("EmailVerification.ExpiryHours must be greater than 0".to_string());
+        }
+        if self.sessions.idle_timeout_minutes == 0 {
+            problems.push("Sessions.IdleTimeoutMinutes must be greater than 0".to_string());
+        }
+        if self.sessions.max_lifetime_hours == Some(0) {
+            problems.push("Sessions.MaxLifetimeHours must be greater than 0".to_string());
+        }
+        if self.sessions.mfa_timeout_minutes == 0 {
+            problems.push("Sessions.MfaTimeoutMinutes must be greater than 0".to_string());
+        }
+        if self.webauthn.attestation_ca_list_reload_minutes == Some(0) {
+            problems
+                .push("Webauthn.AttestationCaListReloadMinutes must be greater than 0".to_string());
+        }
+        if self.server.max_connections == Some(0) {
+            problems.push("Server.MaxConnections must be greater than 0".to_string());
+        }
+        if self.server.body_limit_bytes == 0 {
+            problems.push("Server.BodyLimitBytes must be greater than 0".to_string());
+        }
+        if let Some(avatars) = &self.avatars {
+            if avatars.max_upload_bytes > self.server.body_limit_bytes {
+                problems.push(
+                    "Avatars.MaxUploadBytes must not exceed Server.BodyLimitBytes".to_string(),
+                );
+            }
+            if avatars.size == 0 {
+                problems.push("Avatars.Size must be greater than 0".to_string());
+            }
+        }
+        if let Some(webhooks) = &self.webhooks {
+            if webhooks.queue_size == 0 {
+                problems.push("Webhooks.QueueSize must be greater than 0".to_string());
+            }
+            if webhooks.timeout_secs == 0 {
+                problems.push("Webhooks.TimeoutSecs must be greater than 0".to_string());
+            }
+        }
+        if self.cors.allow_credentials && self.cors.allows_any_origin() {
+            problems.push(
+                "Cors.AllowedOrigins must not contain \"*\" when Cors.AllowCredentials is set"
+                    .to_string(),
+            );
+        }
+        // Any site could send the header
+        if matches!(self.csrf.mode, CsrfMode::Header) && self.cors.allows_any_origin() {
+            problems.push(
+                "Csrf.Mode = Header requires Cors.AllowedOrigins to not contain \"*\"".to_string(),
+            );
+        }
+        if matches!(self.sessions.cookie_same_site, CookieSameSite::None)
+            && !self.session_cookie_secure()
+        {
+            problems
+                .push("Sessions.CookieSameSite = None requires Sessions.CookieSecure".to_string());
+        }
+        if self.sessions.cookie_name.is_empty() {
+            problems.push("Sessions.CookieName must not be empty".to_string());
+        }
+        if !self.sessions.cookie_path.starts_with('/') {
+            problems.push("Sessions.CookiePath must start with \"/\"".to_string());
+        }
+        if self.tls.is_some() && self.webauthn.origin.scheme() != "https" {
+            problems.push("Webauthn.Origin must use https when Tls is configured".to_string());
+        }
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(ConfigError::Invalid(problems))
+        }
+    }
+}
+
+impl Config {
+    /// Whether the session cookie is only sent over https
+    ///
+    /// Unless set explicitly, this is detected from the tls config and the server's origin.
+    pub fn session_cookie_secure(&self) -> bool {
+        self.sessions
+            .cookie_secure
+            .unwrap_or_else(|| self.tls.is_some() || self.server.origin.starts_with("https://"))
+    }
+}
+
+/// The prefix of environment variables overriding config values
+pub const ENV_PREFIX: &str = "WS_";
+
+/// The separator between the sections of an environment variable overriding a config value
+pub const ENV_SEPARATOR: &str = "__";
+
+/// Sets the value at the path encoded in an environment variable's name
+///
+/// Only single options can be overridden, not whole sections.
+/// Missing sections are created.
+/// The value is parsed according to the type of the value it replaces
+/// or the one at the same path in `example` if there is none.
+/// The value is never logged as it will likely contain a secret.
+fn apply_env_override(
+    table: &mut toml::Table,
+    example: &toml::Table,
+    name: &str,
+    value: String,
+) -> Result<(), ConfigError> {
+    let invalid = || ConfigError::InvalidEnvOverride {
+        variable: name.to_string(),
+    };
+
+    let mut keys = name
+        .trim_start_matches(ENV_PREFIX)
+        .split(ENV_SEPARATOR)
+        .map(|segment| {
+            segment
+                .split('_')
+                .map(|word| {
+                    let word = word.to_lowercase();
+                    let mut chars = word.chars();
+                    chars
+                        .next()
+                        .map(|first| first.to_uppercase().chain(chars).collect::<String>())
+                        .unwrap_or_default()
+                })
+                .collect::<String>()
+        })
+        .collect::<Vec<_>>();
+    let key = keys
+        .pop()
+        .filter(|key| !key.is_empty())
+        .ok_or_else(invalid)?;
+    // Every option is part of a section
+    if keys.is_empty() {
+        return Err(invalid());
+    }
+
+    let mut table = table;
+    let mut example = Some(example);
+    for section in keys {
+        example = example
+            .and_then(|example| example.get(&section))
+            .and_then(toml::Value::as_table);
+        table = table
+            .entry(section)
+            .or_insert_with(|| toml::Value::Table(toml::Table::new()))
+            .as_table_mut()
+            .ok_or_else(invalid)?;
+    }
+
+    let replaced = table
+        .get(&key)
+        .or_else(|| example.and_then(|example| example.get(&key)));
+    if replaced.is_some_and(toml::Value::is_table) {
+        return Err(invalid());
+    }
+    let is_string = replaced.map_or(true, toml::Value::is_str);
+    let value = if is_string {
+        toml::Value::String(value)
+    } else {
+        toml::from_str::<toml::Table>(&format!("value = {value}"))
+            .ok()
+            .and_then(|mut parsed| parsed.remove("value"))
+            .unwrap_or(toml::Value::String(value))
+    };
+    debug!(
+        variable = name,
+        "Overriding config value from the environment"
+    );
+    table.insert(key, value);
+    Ok(())
+}
+
+/// Placeholder for values which have to be chosen by the operator
+const PLACEHOLDER: &str = "<CHANGE ME>";
+
+impl Config {
+    /// Render a config file containing every option
+    ///
+    /// Required options are set to their defaults or placeholders,
+    /// optional ones are commented out.
+    pub fn template() -> Result<String, toml::ser::Error> {
+        let required = toml::Table::try_from(Self::example(false))?;
+        let full = toml::Table::try_from(Self::example(true))?;
+
+        let mut template = String::new();
+        for (section, values) in full {
+            let toml::Value::Table(values) = values else {
+                continue;
+            };
+            let required_values = required.get(&section).and_then(toml::Value::as_table);
+
+            let (enabled, disabled): (toml::Table, toml::Table) = values
+                .into_iter()
+                .partition(|(key, _)| required_values.is_some_and(|v| v.contains_key(key)));
+
+            let prefix = if required_values.is_some() { "" } else { "# " };
+            template.push_str(&format!("{prefix}[{section}]\n"));
+            for line in toml::to_string(&enabled)?.lines() {
+                template.push_str(&format!("{prefix}{line}\n"));
+            }
+            for line in toml::to_string(&disabled)?.lines() {
+                template.push_str(&format!("# {line}\n"));
+            }
+            template.push('\n');
+        }
+        Ok(template)
+    }
+
+    /// Construct a config with placeholders for all values without defaults
+    ///
+    /// If `full` is set, optional values are filled in as well.
+    #[allow(clippy::expect_used)]
+    fn example(full: bool) -> Self {
+        let origin = Url::parse("https://example.com").expect("The example url should be valid");
+        Self {
+            server: ServerConfig {
+                listen_address: "127.0.0.1".to_string(),
+                listen_port: 8080,
+                origin: origin.to_string(),
+                trusted_networks: Vec::new(),
+                shutdown_timeout_secs: ServerConfig::default_shutdown_timeout_secs(),
+                max_connections: full.then_some(1024),
+                body_limit_bytes: ServerConfig::default_body_limit_bytes(),
+                serve_docs: ServerConfig::default_serve_docs(),
+            },
+            webauthn: WebAuthnConfig {
+                id: "example.com".to_string(),
+                origin: origin.clone(),
+                name: "{{project-name}}".to_string(),
+                attestation_ca_list: PathBuf::from(
+                    "/etc/{{project-name}}/attestation_ca_list.json",
+                ),
+                attestation_ca_list_reload_minutes: full.then_some(60),
+            },
+            auth: AuthConfig {
+                password_pepper: full.then(|| SecureString::new(PLACEHOLDER.to_string())),
+                password_change_step_up_minutes: full.then_some(10),
+                ..Default::default()
+            },
+            sessions: SessionsConfig {
+                max_lifetime_hours: full.then_some(7 * 24),
+                cookie_secure: full.then_some(true),
+                cookie_domain: full.then(|| "example.com".to_string()),
+                ..Default::default()
+            },
+            csrf: Default::default(),
+            users: Default::default(),
+            invites: Default::default(),
+            magic_links: Default::default(),
+            email_verification: Default::default(),
+            pagination: Default::default(),
+            database: DBConfig {
+                host: "127.0.0.1".to_string(),
+                port: 5432,
+                name: "{{project-name}}".to_string(),
+                user: "{{project-name}}".to_string(),
+                password: SecureString::new(PLACEHOLDER.to_string()),
+            },
+            cleanup: Default::default(),
+            cors: Default::default(),
+            security_headers: Default::default(),
+            avatars: full.then(|| AvatarsConfig {
+                storage: AvatarStorageConfig::Filesystem {
+                    directory: PathBuf::from("/var/lib/{{project-name}}/avatars"),
+                },
+                max_upload_bytes: AvatarsConfig::default_max_upload_bytes(),
+                size: AvatarsConfig::default_size(),
+            }),
+            smtp: full.then(|| SmtpConfig {
+                host: "smtp.example.com".to_string(),
+                port: Some(587),
+                encryption: Default::default(),
+                user: PLACEHOLDER.to_string(),
+                password: SecureString::new(PLACEHOLDER.to_string()),
+                from: "{{project-name}} <noreply@example.com>".to_string(),
+            }),
+            webhooks: full.then(|| WebhooksConfig {
+                endpoints: vec![Url::parse("https://hooks.example.com/{{project-name}}")
+                    .expect("The example url should be valid")],
+                secret: SecureString::new(PLACEHOLDER.to_string()),
+                queue_size: WebhooksConfig::default_queue_size(),
+                max_retries: WebhooksConfig::default_max_retries(),
+                timeout_secs: WebhooksConfig::default_timeout_secs(),
+            }),
+            openid_connect: full.then(|| OpenIdConnect {
+                client_id: ClientId::new(PLACEHOLDER.to_string()),
+                client_secret: ClientSecret::new(PLACEHOLDER.to_string()),
+                redirect_url: RedirectUrl::from_url(
+                    origin
+                        .join("/api/frontend/v1/common/oidc/finish-login")
+                        .expect("The example url should be valid"),
+                ),
+                discover_url: IssuerUrl::from_url(
+                    Url::parse("https://idm.example.com/oauth2/openid/{{project-name}}")
+                        .expect("The example url should be valid"),
+                ),
+                token_encryption_key: Some(SecureString::new(PLACEHOLDER.to_string())),
+                default_role: OpenIdConnect::default_default_role(),
+            }),
+            tls: full.then(|| TlsConfig {
+                cert_path: PathBuf::from("/etc/{{project-name}}/tls/cert.pem"),
+                key_path: PathBuf::from("/etc/{{project-name}}/tls/key.pem"),
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::path::Path;
+    use std::path::PathBuf;
+
+    use uuid::Uuid;
+    use webauthn_rs::prelude::Url;
+
+    use super::apply_env_override;
+    use super::Config;
+    use super::ConfigError;
+    use super::CsrfMode;
+    use super::IpNetwork;
+    use super::LoginFlowPreference;
+    use super::SessionStoreKind;
+    use crate::models::UserRole;
+
+    /// A file which is readable in every test environment
+    ///
+    /// Its contents don't matter to [`Config::validate`].
+    const READABLE_FILE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/Cargo.toml");
+
+    /// A config containing only the required options
+    fn minimal() -> toml::Table {
+        toml::toml! {
+            [Server]
+            ListenAddress = "127.0.0.1"
+            ListenPort = 8080
+            Origin = "http://localhost:8080"
+
+            [Webauthn]
+            Id = "localhost"
+            Origin = "http://localhost:8080"
+            Name = "Webserver"
+            AttestationCaList = "/etc/webserver/attestation_ca_list.json"
+
+            [Database]
+            Host = "127.0.0.1"
+            Port = 5432
+            Name = "webserver"
+            User = "webserver"
+            Password = "<CHANGE ME>"
+        }
+    }
+
+    /// Parses the [`minimal`] config after modifying its toml
+    ///
+    /// The attestation CA list points to [`READABLE_FILE`] unless `modify` changes it.
+    fn config_with(
+        modify: impl FnOnce(&mut toml::Table),
+    ) -> Result<Config, Box<dyn std::error::Error>> {
+        let mut table = minimal();
+        set(
+            &mut table,
+            "Webauthn",
+            "AttestationCaList",
+            READABLE_FILE.into(),
+        );
+        modify(&mut table);
+        Ok(toml::Value::Table(table).try_into()?)
+    }
+
+    /// Sets a value in a config's toml
+    fn set(table: &mut toml::Table, section: &str, key: &str, value: toml::Value) {
+        let section = table
+            .entry(section)
+            .or_insert_with(|| toml::Table::new().into());
+        if let toml::Value::Table(section) = section {
+            section.insert(key.to_string(), value);
+        }
+    }
+
+    #[test]
+    fn login_flow_preference_defaults_to_passkey() -> Result<(), Box<dyn std::error::Error>> {
+        let config = config_with(|_| {})?;
+        assert_eq!(
+            config.auth.login_flow_preference,
+            LoginFlowPreference::Passkey
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn login_flow_preference_is_configurable() -> Result<(), Box<dyn std::error::Error>> {
+        let config = config_with(|table| {
+            set(table, "Auth", "LoginFlowPreference", "Password".into());
+        })?;
+        assert_eq!(
+            config.auth.login_flow_preference,
+            LoginFlowPreference::Password
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn missing_file_reports_its_path() {
+        assert!(matches!(
+            Config::try_from_path("/nonexistent/config.toml"),
+            Err(ConfigError::MissingFile { path }) if path == Path::new("/nonexistent/config.toml")
+        ));
+    }
+
+    #[test]
+    fn parse_error_reports_path_and_location() -> Result<(), Box<dyn std::error::Error>> {
+        let path = env::temp_dir().join(format!("config-{}.toml", Uuid::new_v4()));
+        fs::write(&path, "[Server]\nListenPort = \"\n")?;
+        let result = Config::try_from_path(path.to_str().ok_or("temp dir is not unicode")?);
+        fs::remove_file(&path)?;
+
+        let Err(error @ ConfigError::ParsingFailed { .. }) = result else {
+            return Err("expected a parse error".into());
+        };
+        let message = error.to_string();
+        assert!(message.contains(&path.display().to_string()));
+        assert!(message.contains("line 2, column 15"));
+        Ok(())
+    }
+
+    #[test]
+    fn trusted_networks_are_parsed() -> Result<(), Box<dyn std::error::Error>> {
+        let networks = toml::Value::Array(vec!["10.0.0.0/8".into(), "fd00::/8".into()]);
+        let config = config_with(|table| set(table, "Server", "TrustedNetworks", networks))?;
+        assert_eq!(
+            config.server.trusted_networks,
+            ["10.0.0.0/8".parse::<IpNetwork>()?, "fd00::/8".parse()?]
+        );
+
+        let invalid = toml::Value::Array(vec!["10.0.0.0/33".into()]);
+        assert!(config_with(|table| set(table, "Server", "TrustedNetworks", invalid)).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn magic_links_are_disabled_by_default() -> Result<(), Box<dyn std::error::Error>> {
+        assert!(!config_with(|_| {})?.magic_links.enabled);
+        Ok(())
+    }
+
+    #[test]
+    fn magic_link_expiry_is_configurable() -> Result<(), Box<dyn std::error::Error>> {
+        let config = config_with(|table| {
+            set(table, "MagicLinks", "Enabled", true.into());
+            set(
+                table,
+                "MagicLinks",
+                "ExpiryMinutes",
+                toml::Value::Integer(30),
+            );
+        })?;
+        assert!(config.magic_links.enabled);
+        assert_eq!(config.magic_links.expiry_minutes, 30);
+        Ok(())
+    }
+
+    #[test]
+    fn shutdown_timeout_is_configurable() -> Result<(), Box<dyn std::error::Error>> {
+        assert_eq!(config_with(|_| {})?.server.shutdown_timeout_secs, 30);
+
+        let config = config_with(|table| {
+            set(
+                table,
+                "Server",
+                "ShutdownTimeoutSecs",
+                toml::Value::Integer(5),
+            );
+        })?;
+        assert_eq!(config.server.shutdown_timeout_secs, 5);
+        Ok(())
+    }
+
+    #[test]
+    fn tls_is_optional() -> Result<(), Box<dyn std::error::Error>> {
+        assert!(config_with(|_| {})?.tls.is_none());
+
+        let config = config_with(|table| {
+            set(table, "Tls", "CertPath", "/etc/webserver/cert.pem".into());
+            set(table, "Tls", "KeyPath", "/etc/webserver/key.pem".into());
+        })?;
+        let tls = config.tls.ok_or("expected a tls config")?;
+        assert_eq!(tls.cert_path, Path::new("/etc/webserver/cert.pem"));
+        assert_eq!(tls.key_path, Path::new("/etc/webserver/key.pem"));
+        Ok(())
+    }
+
+    #[test]
+    fn cors_defaults_to_the_own_origin_with_credentials() -> Result<(), Box<dyn std::error::Error>>
+    {
+        let config = config_with(|_| {})?;
+        assert!(config.cors.allowed_origins.is_empty());
+        assert!(!config.cors.allows_any_origin());
+        assert!(config.cors.allow_credentials);
+
+        let config = config_with(|table| {
+            set(
+                table,
+                "Cors",
+                "AllowedOrigins",
+                toml::Value::Array(vec!["*".into()]),
+            );
+            set(table, "Cors", "AllowCredentials", false.into());
+        })?;
+        assert!(config.cors.allows_any_origin());
+        assert!(!config.cors.allow_credentials);
+        Ok(())
+    }
+
+    #[test]
+    fn serving_docs_defaults_to_debug_builds() -> Result<(), Box<dyn std::error::Error>> {
+        assert_eq!(
+            config_with(|_| {})?.server.serve_docs,
+            cfg!(debug_assertions)
+        );
+
+        let config = config_with(|table| set(table, "Server", "ServeDocs", true.into()))?;
+        assert!(config.server.serve_docs);
+        Ok(())
+    }
+
+    #[test]
+    fn template_parses_and_validates() -> Result<(), Box<dyn std::error::Error>> {
+        let mut config: Config = toml::from_str(&Config::template()?)?;
+        assert_eq!(config.webauthn.id, "example.com");
+        config.webauthn.attestation_ca_list = PathBuf::from(READABLE_FILE);
+        assert!(config.smtp.is_none());
+        config.validate()?;
+        Ok(())
+    }
+
+    /// The problems reported by [`Config::validate`]
+    fn problems(config: &Config) -> Vec<String> {
+        match config.validate() {
+            Err(ConfigError::Invalid(problems)) => problems,
+            _ => Vec::new(),
+        }
+    }
+
+    #[test]
+    fn invite_expiry_must_not_exceed_maximum() -> Result<(), Box<dyn std::error::Error>> {
+        let mut config = config_with(|_| {})?;
+        config.invites.default_expiry_hours = 48;
+        config.invites.max_expiry_hours = 24;
+        assert_eq!(
+            problems(&config),
+            ["Invites.DefaultExpiryHours must not exceed Invites.MaxExpiryHours"]
+        );
+
+        config.invites.max_expiry_hours = 48;
+        assert!(problems(&config).is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn header_csrf_requires_restricted_origins() -> Result<(), Box<dyn std::error::Error>> {
+        let mut config = config_with(|table| set(table, "Csrf", "Mode", "Header".into()))?;
+        config.cors.allow_credentials = false;
+        config.cors.allowed_origins = vec!["*".to_string()];
+        assert_eq!(
+            problems(&config),
+            ["Csrf.Mode = Header requires Cors.AllowedOrigins to not contain \"*\""]
+        );
+
+        config.cors.allowed_origins = vec!["https://example.com".to_string()];
+        assert!(problems(&config).is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn password_change_step_up_is_disabled_by_default() -> Result<(), Box<dyn std::error::Error>> {
+        assert_eq!(
+            config_with(|_| {})?.auth.password_change_step_up_minutes,
+            None
+        );
+
+        let config = config_with(|table| {
+            set(
+                table,
+                "Auth",
+                "PasswordChangeStepUpMinutes",
+                toml::Value::Integer(10),
+            );
+        })?;
+        assert_eq!(config.auth.password_change_step_up_minutes, Some(10));
+        Ok(())
+    }
+
+    #[test]
+    fn sessions_are_kept_alive_without_maximum_lifetime() -> Result<(), Box<dyn std::error::Error>>
+    {
+        let config = config_with(|_| {})?;
+        assert_eq!(config.sessions.idle_timeout_minutes, 24 * 60);
+        assert_eq!(config.sessions.max_lifetime_hours, None);
+        assert_eq!(config.sessions.mfa_timeout_minutes, 10);
+
+        let config = config_with(|table| {
+            set(
+                table,
+                "Sessions",
+                "MaxLifetimeHours",
+                toml::Value::Integer(12),
+            );
+        })?;
+        assert_eq!(config.sessions.max_lifetime_hours, Some(12));
+        Ok(())
+    }
+
+    #[test]
+    fn csrf_protection_defaults_to_tokens() -> Result<(), Box<dyn std::error::Error>> {
+        assert!(matches!(config_with(|_| {})?.csrf.mode, CsrfMode::Token));
+
+        let config = config_with(|table| set(table, "Csrf", "Mode", "Header".into()))?;
+        assert!(matches!(config.csrf.mode, CsrfMode::Header));
+        Ok(())
+    }
+
+    #[test]
+    fn sessions_are_stored_in_the_database_by_default() -> Result<(), Box<dyn std::error::Error>> {
+        let config = config_with(|_| {})?;
+        assert!(matches!(config.sessions.store, SessionStoreKind::Database));
+
+        let config = config_with(|table| set(table, "Sessions", "Store", "Redis".into()))?;
+        assert!(matches!(config.sessions.store, SessionStoreKind::Redis));
+        assert_eq!(config.sessions.redis_url, "redis://127.0.0.1:6379");
+        Ok(())
+    }
+
+    #[test]
+    fn session_cookie_is_secure_for_https_origins() -> Result<(), Box<dyn std::error::Error>> {
+        assert!(!config_with(|_| {})?.session_cookie_secure());
+
+        let config =
+            config_with(|table| set(table, "Server", "Origin", "https://example.com".into()))?;
+        assert!(config.session_cookie_secure());
+
+        // Behind a reverse proxy terminating tls
+        let config = config_with(|table| set(table, "Sessions", "CookieSecure", true.into()))?;
+        assert!(config.session_cookie_secure());
+        assert_eq!(config.sessions.cookie_name, "id");
+        assert_eq!(config.sessions.cookie_path, "/");
+        Ok(())
+    }
+
+    #[test]
+    fn ca_list_is_not_reloaded_by_default() -> Result<(), Box<dyn std::error::Error>> {
+        let config = config_with(|_| {})?;
+        assert_eq!(config.webauthn.attestation_ca_list_reload_minutes, None);
+
+        let config = config_with(|table| {
+            set(
+                table,
+                "Webauthn",
+                "AttestationCaListReloadMinutes",
+                60.into(),
+            )
+        })?;
+        assert_eq!(config.webauthn.attestation_ca_list_reload_minutes, Some(60));
+        Ok(())
+    }
+
+    #[test]
+    fn email_verification_is_optional_by_default() -> Result<(), Box<dyn std::error::Error>> {
+        let config = config_with(|_| {})?;
+        assert!(!config.email_verification.required);
+        assert_eq!(config.email_verification.expiry_hours, 24);
+
+        let mut config = config_with(|table| {
+            set(table, "EmailVerification", "Required", true.into());
+        })?;
+        assert!(config.email_verification.required);
+
+        config.email_verification.expiry_hours = 0;
+        assert_eq!(
+            problems(&config),
+            ["EmailVerification.ExpiryHours must be greater than 0"]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn totp_skew_is_limited() -> Result<(), Box<dyn std::error::Error>> {
+        let mut config = config_with(|_| {})?;
+        assert_eq!(config.auth.totp_skew, 1);
+
+        config.auth.totp_skew = 11;
+        assert_eq!(problems(&config), ["Auth.TotpSkew must not exceed 10"]);
+        config.auth.totp_skew = 10;
+        assert!(problems(&config).is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn webhooks_are_optional() -> Result<(), Box<dyn std::error::Error>> {
+        assert!(config_with(|_| {})?.webhooks.is_none());
+
+        let endpoints = toml::Value::Array(vec!["https://hooks.example.com/".into()]);
+        let config = config_with(|table| {
+            set(table, "Webhooks", "Endpoints", endpoints);
+            set(table, "Webhooks", "Secret", "secret".into());
+        })?;
+        let webhooks = config
+            .webhooks
+            .as_ref()
+            .ok_or("expected a webhooks config")?;
+        assert_eq!(webhooks.endpoints.len(), 1);
+        assert_eq!(webhooks.queue_size, 1024);
+        assert_eq!(webhooks.max_retries, 5);
+        assert_eq!(webhooks.timeout_secs, 10);
+        assert!(problems(&config).is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn account_existence_is_hidden_by_default() -> Result<(), Box<dyn std::error::Error>> {
+        assert!(!config_with(|_| {})?.auth.reveal_account_existence);
+
+        let config = config_with(|table| {
+            set(table, "Auth", "RevealAccountExistence", true.into());
+        })?;
+        assert!(config.auth.reveal_account_existence);
+        Ok(())
+    }
+
+    #[test]
+    fn new_oidc_users_are_internal_by_default() -> Result<(), Box<dyn std::error::Error>> {
+        let oidc = |table: &mut toml::Table| {
+            set(table, "OpenidConnect", "ClientId", "webserver".into());
+            set(table, "OpenidConnect", "ClientSecret", "secret".into());
+            set(
+                table,
+                "OpenidConnect",
+                "RedirectUrl",
+                "http://localhost:8080/api/frontend/v1/common/oidc/finish-login".into(),
+            );
+            set(
+                table,
+                "OpenidConnect",
+                "DiscoverUrl",
+                "https://idm.example.com".into(),
+            );
+        };
+
+        let config = config_with(oidc)?;
+        let openid_connect = config.openid_connect.ok_or("expected an oidc config")?;
+        assert_eq!(openid_connect.default_role, UserRole::Internal);
+
+        let config = config_with(|table| {
+            oidc(table);
+            set(
+                table,
+                "OpenidConnect",
+                "DefaultRole",
+                "Administrator".into(),
+            );
+        })?;
+        let openid_connect = config.openid_connect.ok_or("expected an oidc config")?;
+        assert_eq!(openid_connect.default_role, UserRole::Administrator);
+        Ok(())
+    }
+
+    #[test]
+    fn server_origin_must_be_an_absolute_http_url() -> Result<(), Box<dyn std::error::Error>> {
+        let message = "Server.Origin must be an absolute http(s) url";
+        let mut config = config_with(|_| {})?;
+        for origin in ["example.com", "ftp://example.com", "http:/"] {
+            config.server.origin = origin.to_string();
+            assert_eq!(problems(&config), [message], "{origin}");
+        }
+
+        config.server.origin = "http://localhost:8080".to_string();
+        assert!(problems(&config).is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn webauthn_origin_must_be_an_http_url() -> Result<(), Box<dyn std::error::Error>> {
+        let mut config = config_with(|_| {})?;
+        config.webauthn.origin = Url::parse("ftp://example.com")?;
+        assert_eq!(
+            problems(&config),
+            ["Webauthn.Origin must be an http(s) url"]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn webauthn_id_must_be_the_origins_domain() -> Result<(), Box<dyn std::error::Error>> {
+        let mut config = config_with(|_| {})?;
+        config.webauthn.origin = Url::parse("https://login.example.com")?;
+        for id in ["example.com", "login.example.com"] {
+            config.webauthn.id = id.to_string();
+            assert!(problems(&config).is_empty(), "{id}");
+        }
+
+        for id in ["ample.com", "other.com", "auth.login.example.com"] {
+            config.webauthn.id = id.to_string();
+            assert_eq!(
+                problems(&config),
+                [format!(
+                    "Webauthn.Id \"{id}\" must be the domain of Webauthn.Origin or one of its parents"
+                )]
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn attestation_ca_list_must_be_readable() -> Result<(), Box<dyn std::error::Error>> {
+        let mut config = config_with(|_| {})?;
+        config.webauthn.attestation_ca_list = PathBuf::from("/nonexistent/ca-list.json");
+        let problems_found = problems(&config);
+        assert_eq!(problems_found.len(), 1);
+        assert!(problems_found[0]
+            .starts_with("Webauthn.AttestationCaList /nonexistent/ca-list.json can't be read"));
+
+        config.webauthn.attestation_ca_list = env::temp_dir();
+        assert_eq!(
+            problems(&config),
+            [format!(
+                "Webauthn.AttestationCaList {} is not a file",
+                env::temp_dir().display()
+            )]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn database_fields_must_be_set() -> Result<(), Box<dyn std::error::Error>> {
+        let mut config = config_with(|_| {})?;
+        config.database.host = String::new();
+        config.database.port = 0;
+        config.database.name = String::new();
+        config.database.user = String::new();
+        assert_eq!(
+            problems(&config),
+            [
+                "Database.Host must not be empty",
+                "Database.Port must be greater than 0",
+                "Database.Name must not be empty",
+                "Database.User must not be empty",
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn all_problems_are_reported_together() -> Result<(), Box<dyn std::error::Error>> {
+        let mut config = config_with(|_| {})?;
+        config.server.origin = "example.com".to_string();
+        config.database.port = 0;
+        config.auth.totp_skew = 11;
+        config.sessions.cookie_path = "api".to_string();
+
+        let error = config
+            .validate()
+            .err()
+            .ok_or("expected the config to be invalid")?;
+        let ConfigError::Invalid(problems) = &error else {
+            return Err(format!("unexpected error: {error}").into());
+        };
+        assert_eq!(
+            problems,
+            &[
+                "Server.Origin must be an absolute http(s) url",
+                "Database.Port must be greater than 0",
+                "Auth.TotpSkew must not exceed 10",
+                "Sessions.CookiePath must start with \"/\"",
+            ]
+        );
+        assert_eq!(
+            error.to_string(),
+            "Invalid config:\n - Server.Origin must be an absolute http(s) url\n - Database.Port must be greater than 0\n - Auth.TotpSkew must not exceed 10\n - Sessions.CookiePath must start with \"/\""
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn min_password_length_must_be_positive() -> Result<(), Box<dyn std::error::Error>> {
+        let mut config = config_with(|_| {})?;
+        config.auth.min_password_length = 0;
+        assert_eq!(
+            problems(&config),
+            ["Auth.MinPasswordLength must be greater than 0"]
+        );
+        Ok(())
+    }
+
+    /// Options of the example config as far as the overrides below need them
+    fn example() -> toml::Table {
+        toml::toml! {
+            [Server]
+            ListenPort = 8080
+            Origin = "https://example.com"
+
+            [Webhooks]
+            Secret = "<CHANGE ME>"
+        }
+    }
+
+    #[test]
+    fn env_override_keeps_numeric_secret_a_string() -> Result<(), ConfigError> {
+        let mut table = toml::Table::new();
+        apply_env_override(
+            &mut table,
+            &example(),
+            "WS_WEBHOOKS__SECRET",
+            "123456".into(),
+        )?;
+        assert_eq!(table["Webhooks"]["Secret"].as_str(), Some("123456"));
+        Ok(())
+    }
+
+    #[test]
+    fn env_override_parses_non_string_options() -> Result<(), ConfigError> {
+        let mut table = toml::Table::new();
+        apply_env_override(
+            &mut table,
+            &example(),
+            "WS_SERVER__LISTEN_PORT",
+            "9000".into(),
+        )?;
+        assert_eq!(table["Server"]["ListenPort"].as_integer(), Some(9000));
+        Ok(())
+    }
+
+    #[test]
+    fn env_override_uses_the_type_from_the_file() -> Result<(), ConfigError> {
+        let mut table = toml::toml! {
+            [Server]
+            ListenPort = 8080
+        };
+        apply_env_override(
+            &mut table,
+            &toml::Table::new(),
+            "WS_SERVER__LISTEN_PORT",
+            "9000".into(),
+        )?;
+        assert_eq!(table["Server"]["ListenPort"].as_integer(), Some(9000));
+        Ok(())
+    }
+
+    #[test]
+    fn env_override_treats_unknown_options_as_strings() -> Result<(), ConfigError> {
+        let mut table = toml::Table::new();
+        apply_env_override(&mut table, &example(), "WS_UNKNOWN__VALUE", "true".into())?;
+        assert_eq!(table["Unknown"]["Value"].as_str(), Some("true"));
+        Ok(())
+    }
+
+    #[test]
+    fn env_override_rejects_sections() {
+        let mut table = toml::toml! {
+            [Avatars.Storage]
+            Directory = "/var/lib/avatars"
+        };
+        for name in ["WS_SERVER", "WS_WEBHOOKS", "WS_AVATARS__STORAGE"] {
+            assert!(
+                matches!(
+                    apply_env_override(&mut table, &example(), name, "x".into()),
+                    Err(ConfigError::InvalidEnvOverride { .. })
+                ),
+                "{name}"
+            );
+        }
+        assert_eq!(
+            table["Avatars"]["Storage"]["Directory"].as_str(),
+            Some("/var/lib/avatars")
+        );
+        assert!(!table.contains_key("Server"));
+    }
+
+    #[test]
+    fn env_override_rejects_an_empty_key() {
+        let mut table = toml::Table::new();
+        assert!(matches!(
+            apply_env_override(&mut table, &example(), "WS_SERVER__", "1".into()),
+            Err(ConfigError::InvalidEnvOverride { .. })
+        ));
+    }
+
+    #[test]
+    fn env_overrides_the_file_until_removed() -> Result<(), Box<dyn std::error::Error>> {
+        let mut table: toml::Table = toml::from_str(&Config::template()?)?;
+        set(
+            &mut table,
+            "Webauthn",
+            "AttestationCaList",
+            READABLE_FILE.into(),
+        );
+        set(
+            &mut table,
+            "Server",
+            "ListenPort",
+            toml::Value::Integer(8081),
+        );
+        if let Some(toml::Value::Table(database)) = table.get_mut("Database") {
+            database.remove("Password");
+        }
+        let path = env::temp_dir().join(format!("config-{}.toml", Uuid::new_v4()));
+        fs::write(&path, toml::to_string(&table)?)?;
+        let path_str = path.to_str().ok_or("temp dir is not unicode")?;
+
+        // No other test sets these variables
+        env::set_var("WS_DATABASE__PASSWORD", "from-env");
+        let from_file = Config::try_from_path(path_str);
+        env::set_var("WS_SERVER__LISTEN_PORT", "9000");
+        let overridden = Config::try_from_path(path_str);
+        env::remove_var("WS_SERVER__LISTEN_PORT");
+        let fallback = Config::try_from_path(path_str);
+        env::remove_var("WS_DATABASE__PASSWORD");
+        let without_password = Config::try_from_path(path_str);
+        fs::remove_file(&path)?;
+
+        let from_file = from_file?;
+        assert_eq!(from_file.server.listen_port, 8081);
+        assert_eq!(from_file.database.password.into_inner(), "from-env");
+
+        let overridden = overridden?;
+        assert_eq!(overridden.server.listen_port, 9000);
+        assert_eq!(overridden.server.origin, from_file.server.origin);
+
+        assert_eq!(fallback?.server.listen_port, 8081);
+        assert!(matches!(
+            without_password,
+            Err(ConfigError::ParsingFailed { .. })
+        ));
+        Ok(())
+    }
 }
@@ -4,10 +4,22 @@ use std::ops::Deref;
 use std::sync::OnceLock;
 
 use rorm::Database;
+use time::Duration;
 use webauthn_rs::prelude::AttestationCaList;
 use webauthn_rs::Webauthn;
 
+use crate::config::Config;
+use crate::config::CsrfMode;
+use crate::config::PaginationConfig;
 use crate::global::ws::GlobalWs;
+use crate::http::handler_frontend::auth::schema::LoginFlowPreference;
+use crate::models::UserRole;
+use crate::utils::avatars::Avatars;
+use crate::utils::ip_network::IpNetwork;
+use crate::utils::mailer::Mailer;
+use crate::utils::oidc_tokens::TokenCipher;
+use crate::utils::swap_lock::SwapLock;
+use crate::utils::webhooks::Webhooks;
 
 pub mod ws;
 
@@ -22,11 +34,37 @@ pub struct GlobalEntities {
     /// The global websocket instance
     pub ws: GlobalWs,
 
+    /// The mailer, if SMTP has been configured
+    pub mailer: Option<Mailer>,
+
+    /// The avatar store, if avatars have been configured
+    pub avatars: Option<Avatars>,
+
+    /// The webhook dispatcher, if webhooks have been configured
+    pub webhooks: Option<Webhooks>,
+
+    /// The cipher for the stored oidc tokens, if storing them has been configured
+    pub oidc_token_cipher: Option<TokenCipher>,
+
+    /// The role assigned to users created upon their first oidc login
+    pub oidc_default_role: UserRole,
+
     /// Global WebAuthn state
     pub webauthn: Webauthn,
 
     /// List of attestation cas accepted when registering new webauthn keys with login privileges.
-    pub webauthn_attestation_ca_list: AttestationCaList,
+    ///
+    /// It might be reloaded from disk while running.
+    pub webauthn_attestation_ca_list: SwapLock<AttestationCaList>,
+
+    /// The duration a session may be unused before it expires
+    pub session_idle_timeout: Duration,
+
+    /// The networks whose clients aren't rate limited
+    pub trusted_networks: Vec<IpNetwork>,
+
+    /// The settings which are reloaded from the config file on `SIGHUP`
+    pub settings: SwapLock<Settings>,
 
     /// The url this server is reachable under
     ///
@@ -34,6 +72,102 @@ pub struct GlobalEntities {
     pub origin: String,
 }
 
+/// The part of the configuration which can be changed without restarting
+///
+/// It is re-read from the config file upon `SIGHUP`
+/// and retrieved by calling [`SwapLock::get`] on [`GlobalEntities::settings`].
+#[derive(Debug, Copy, Clone)]
+pub struct Settings {
+    /// The login flow to offer by default to users supporting multiple ones
+    pub login_flow_preference: LoginFlowPreference,
+
+    /// The duration after the login a session expires regardless of its use
+    pub session_max_lifetime: Option<Duration>,
+
+    /// The duration a user may take to provide their second factor after their password
+    pub mfa_timeout: Duration,
+
+    /// What is required of state-changing requests to pass the csrf protection
+    pub csrf_mode: CsrfMode,
+
+    /// The duration a started WebAuthn key registration can be completed in
+    pub enrollment_timeout: Duration,
+
+    /// Whether users are notified about logins from new devices
+    pub notify_new_devices: bool,
+
+    /// The maximum number of sessions per user, `0` means unlimited
+    pub max_sessions_per_user: u32,
+
+    /// How recently users with a second factor have to have provided it to change their password
+    ///
+    /// `None` if the current password suffices.
+    pub password_change_step_up: Option<Duration>,
+
+    /// The minimum number of characters a password has to consist of
+    pub min_password_length: usize,
+    /// The number of time steps a TOTP token may lie in the past or future
+    pub totp_skew: u8,
+
+    /// Whether the login tells users if an account exists for a mail
+    pub reveal_account_existence: bool,
+
+    /// The duration an invite is valid for, if not specified otherwise upon creation
+    pub invite_expiry: Duration,
+
+    /// The maximum duration an invite may be valid for
+    pub max_invite_expiry: Duration,
+
+    /// The duration a login link is valid for
+    ///
+    /// `None` if issuing login links is disabled.
+    pub magic_link_expiry: Option<Duration>,
+
+    /// Whether users have to verify their mail to use admin functionality
+    pub email_verification_required: bool,
+
+    /// The duration an email verification link is valid for
+    pub email_verification_expiry: Duration,
+
+    /// The default and maximum page size of paginated endpoints
+    pub pagination: PaginationConfig,
+}
+impl Settings {
+    /// Extract the live-reloadable settings from the config
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            login_flow_preference: config.auth.login_flow_preference,
+            session_max_lifetime: config
+                .sessions
+                .max_lifetime_hours
+                .map(|hours| Duration::hours(hours.into())),
+            mfa_timeout: Duration::minutes(config.sessions.mfa_timeout_minutes.into()),
+            csrf_mode: config.csrf.mode,
+            enrollment_timeout: Duration::minutes(config.auth.enrollment_timeout_minutes.into()),
+            notify_new_devices: config.auth.notify_new_devices,
+            max_sessions_per_user: config.auth.max_sessions_per_user,
+            password_change_step_up: config
+                .auth
+                .password_change_step_up_minutes
+                .map(|minutes| Duration::minutes(minutes.into())),
+            min_password_length: config.auth.min_password_length,
+            totp_skew: config.auth.totp_skew,
+            reveal_account_existence: config.auth.reveal_account_existence,
+            invite_expiry: Duration::hours(config.invites.default_expiry_hours.into()),
+            max_invite_expiry: Duration::hours(config.invites.max_expiry_hours.into()),
+            magic_link_expiry: config
+                .magic_links
+                .enabled
+                .then(|| Duration::minutes(config.magic_links.expiry_minutes.into())),
+            email_verification_required: config.email_verification.required,
+            email_verification_expiry: Duration::hours(
+                config.email_verification.expiry_hours.into(),
+            ),
+            pagination: config.pagination,
+        }
+    }
+}
+
 /// Simple [`OnceLock`] which panics in case of error.
 pub struct GlobalOnceCell<T>(OnceLock<T>);
 impl<T> GlobalOnceCell<T> {
@@ -73,3 +207,51 @@ impl<T> Deref for GlobalOnceCell<T> {
             .expect("`GlobalLock.init` has not been called yet. Please open an issues.")
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use time::Duration;
+
+    use super::Settings;
+    use crate::config::Config;
+
+    /// Parses the generated config template
+    fn template() -> Result<Config, Box<dyn std::error::Error>> {
+        Ok(toml::from_str(&Config::template()?)?)
+    }
+
+    #[test]
+    fn magic_link_expiry_is_configured_in_minutes() -> Result<(), Box<dyn std::error::Error>> {
+        let mut config = template()?;
+        config.magic_links.enabled = true;
+        config.magic_links.expiry_minutes = 30;
+        assert_eq!(
+            Settings::from_config(&config).magic_link_expiry,
+            Some(Duration::minutes(30))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn password_change_step_up_in_minutes() -> Result<(), Box<dyn std::error::Error>> {
+        let mut config = template()?;
+        config.auth.password_change_step_up_minutes = Some(10);
+        assert_eq!(
+            Settings::from_config(&config).password_change_step_up,
+            Some(Duration::minutes(10))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn email_verification_expiry_is_configured_in_hours() -> Result<(), Box<dyn std::error::Error>>
+    {
+        let mut config = template()?;
+        config.email_verification.required = true;
+        config.email_verification.expiry_hours = 48;
+        let settings = Settings::from_config(&config);
+        assert!(settings.email_verification_required);
+        assert_eq!(settings.email_verification_expiry, Duration::hours(48));
+        Ok(())
+    }
+}
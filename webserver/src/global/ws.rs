@@ -1,8 +1,10 @@
 //! The global websocket manager
 
 use std::collections::HashMap;
+use std::collections::HashSet;
 
 use tokio::sync::mpsc;
+use tokio::sync::oneshot;
 use tower_sessions::session::Id;
 use tracing::debug;
 use tracing::error;
@@ -41,6 +43,15 @@ impl GlobalWs {
         }
     }
 
+    /// Close all websocket connections
+    ///
+    /// Every connection receives a [`WsServerMsg::Disconnect`] with the `reason` before being closed.
+    pub async fn close_all(&self, reason: Option<String>) {
+        if let Err(err) = self.tx.send(WsMessage::CloseAll(reason)).await {
+            error!("Could not send to GlobalWs: {err}");
+        }
+    }
+
     /// Send a message to the user.
     ///
     /// Note that the message will be sent to every session of the user
@@ -66,16 +77,53 @@ impl GlobalWs {
         }
     }
 
+    /// Remove a websocket connection which has been closed
+    ///
+    /// This should be called once the connection's tasks have ended
+    /// to keep the manager from holding on to stale senders.
+    ///
+    /// `conn_id` has to be the same id the connection was registered with.
+    /// If the session has registered a newer connection in the meantime, it won't be removed.
+    pub async fn deregister(&self, user: Uuid, session: Id, conn_id: Uuid) {
+        if let Err(err) = self
+            .tx
+            .send(WsMessage::Deregister((user, session, conn_id)))
+            .await
+        {
+            error!("Could not send to GlobalWs: {err}");
+        }
+    }
+
+    /// Retrieve which of the given users have at least one open websocket connection
+    pub async fn online_users(&self, users: Vec<Uuid>) -> HashSet<Uuid> {
+        let (tx, rx) = oneshot::channel();
+        if let Err(err) = self.tx.send(WsMessage::OnlineUsers((users, tx))).await {
+            error!("Could not send to GlobalWs: {err}");
+            return HashSet::new();
+        }
+        rx.await.unwrap_or_default()
+    }
+
     /// Register a new websocket connection
+    ///
+    /// The `conn_id` identifies this connection and has to be passed to [`GlobalWs::deregister`].
     pub async fn register_ws(
         &self,
         sender: mpsc::Sender<WsServerMsg>,
         user: Uuid,
         session: Id,
+        conn_id: Uuid,
     ) -> bool {
         if self
             .tx
-            .send(WsMessage::NewClient((sender, user, session)))
+            .send(WsMessage::NewClient((
+                WsConnection {
+                    id: conn_id,
+                    sender,
+                },
+                user,
+                session,
+            )))
             .await
             .is_err()
         {
@@ -87,17 +135,12 @@ impl GlobalWs {
 }
 
 async fn run_ws_manager(mut rx: mpsc::Receiver<WsMessage>) {
-    let mut clients: HashMap<Uuid, HashMap<Id, mpsc::Sender<WsServerMsg>>> = HashMap::new();
+    let mut clients: HashMap<Uuid, HashMap<Id, WsConnection>> = HashMap::new();
 
     while let Some(ws_msg) = rx.recv().await {
         match ws_msg {
-            WsMessage::NewClient((sender, user, session)) => {
-                clients
-                    .entry(user)
-                    .and_modify(|sessions| {
-                        sessions.insert(session, sender.clone());
-                    })
-                    .or_insert(HashMap::from([(session, sender)]));
+            WsMessage::NewClient((connection, user, session)) => {
+                clients.entry(user).or_default().insert(session, connection);
             }
 
             WsMessage::SessionMessage((user, session, msg)) => {
@@ -106,11 +149,11 @@ async fn run_ws_manager(mut rx: mpsc::Receiver<WsMessage>) {
                     continue;
                 };
 
-                let Some(sender) = sessions.get(&session) else {
+                let Some(connection) = sessions.get(&session) else {
                     continue;
                 };
 
-                if sender.send(msg).await.is_err() {
+                if connection.sender.send(msg).await.is_err() {
                     sessions.remove(&session);
                 }
             }
@@ -122,8 +165,8 @@ async fn run_ws_manager(mut rx: mpsc::Receiver<WsMessage>) {
                 };
 
                 let mut failed = vec![];
-                for (id, sender) in sessions.iter() {
-                    if sender.send(msg.clone()).await.is_err() {
+                for (id, connection) in sessions.iter() {
+                    if connection.sender.send(msg.clone()).await.is_err() {
                         debug!("Sending to websocket failed");
                         failed.push(*id);
                     }
@@ -138,15 +181,49 @@ async fn run_ws_manager(mut rx: mpsc::Receiver<WsMessage>) {
             }
             WsMessage::SessionClose((user, session)) => {
                 if let Some(sessions) = clients.get_mut(&user) {
-                    if let Some(sender) = sessions.remove(&session) {
-                        let _ = sender.send(WsServerMsg::Close).await;
+                    if let Some(connection) = sessions.remove(&session) {
+                        let _ = connection.sender.send(WsServerMsg::Close).await;
+                    }
+                }
+            }
+            WsMessage::Deregister((user, session, conn_id)) => {
+                if let Some(sessions) = clients.get_mut(&user) {
+                    if sessions
+                        .get(&session)
+                        .is_some_and(|connection| connection.id == conn_id)
+                    {
+                        sessions.remove(&session);
+                    }
+                    if sessions.is_empty() {
+                        clients.remove(&user);
                     }
                 }
             }
+            WsMessage::OnlineUsers((users, tx)) => {
+                let _ = tx.send(
+                    users
+                        .into_iter()
+                        .filter(|user| clients.get(user).is_some_and(|s| !s.is_empty()))
+                        .collect(),
+                );
+            }
             WsMessage::UserClose(user) => {
                 if let Some(sessions) = clients.remove(&user) {
-                    for (_, sender) in sessions {
-                        let _ = sender.send(WsServerMsg::Close).await;
+                    for (_, connection) in sessions {
+                        let _ = connection.sender.send(WsServerMsg::Close).await;
+                    }
+                }
+            }
+            WsMessage::CloseAll(reason) => {
+                for (_, sessions) in clients.drain() {
+                    for (_, connection) in sessions {
+                        let _ = connection
+                            .sender
+                            .send(WsServerMsg::Disconnect {
+                                reason: reason.clone(),
+                            })
+                            .await;
+                        let _ = connection.sender.send(WsServerMsg::Close).await;
                     }
                 }
             }
@@ -154,12 +231,22 @@ async fn run_ws_manager(mut rx: mpsc::Receiver<WsMessage>) {
     }
 }
 
+/// A single websocket connection registered in the manager
+struct WsConnection {
+    /// Identifies the connection across its session's reconnects
+    id: Uuid,
+    sender: mpsc::Sender<WsServerMsg>,
+}
+
 enum WsMessage {
-    NewClient((mpsc::Sender<WsServerMsg>, Uuid, Id)),
+    NewClient((WsConnection, Uuid, Id)),
     SessionMessage((Uuid, Id, WsServerMsg)),
     UserMessage((Uuid, WsServerMsg)),
     SessionClose((Uuid, Id)),
     UserClose(Uuid),
+    CloseAll(Option<String>),
+    Deregister((Uuid, Id, Uuid)),
+    OnlineUsers((Vec<Uuid>, oneshot::Sender<HashSet<Uuid>>)),
 }
 
 impl Default for GlobalWs {
@@ -167,3 +254,110 @@ impl Default for GlobalWs {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use tokio::sync::mpsc;
+    use tower_sessions::session::Id;
+    use uuid::Uuid;
+
+    use super::GlobalWs;
+    use crate::http::handler_frontend::ws::schema::WsServerMsg;
+
+    #[tokio::test]
+    async fn deregistered_connection_is_removed() {
+        let ws = GlobalWs::new();
+        let (user, session, conn_id) = (Uuid::new_v4(), Id::default(), Uuid::new_v4());
+        let (tx, mut rx) = mpsc::channel(1);
+
+        assert!(ws.register_ws(tx, user, session, conn_id).await);
+        ws.deregister(user, session, conn_id).await;
+
+        // A connection which is still registered would receive the close message
+        ws.close_user(user).await;
+        assert!(rx.recv().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn stale_deregister_keeps_newer_connection() {
+        let ws = GlobalWs::new();
+        let (user, session) = (Uuid::new_v4(), Id::default());
+        let (old_tx, _old_rx) = mpsc::channel(1);
+        let (new_tx, mut new_rx) = mpsc::channel(1);
+        let (old_conn, new_conn) = (Uuid::new_v4(), Uuid::new_v4());
+
+        assert!(ws.register_ws(old_tx, user, session, old_conn).await);
+        assert!(ws.register_ws(new_tx, user, session, new_conn).await);
+        ws.deregister(user, session, old_conn).await;
+
+        ws.close_user(user).await;
+        assert!(matches!(new_rx.recv().await, Some(WsServerMsg::Close)));
+    }
+
+    #[tokio::test]
+    async fn online_users_only_contains_connected_users() {
+        let ws = GlobalWs::new();
+        let (online, offline) = (Uuid::new_v4(), Uuid::new_v4());
+        let (tx, _rx) = mpsc::channel(1);
+
+        assert!(
+            ws.register_ws(tx, online, Id::default(), Uuid::new_v4())
+                .await
+        );
+        assert_eq!(
+            ws.online_users(vec![online, offline]).await,
+            HashSet::from([online])
+        );
+        assert!(ws.online_users(Vec::new()).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn close_all_disconnects_every_connection() {
+        let ws = GlobalWs::new();
+        let users = [Uuid::new_v4(), Uuid::new_v4()];
+        let mut receivers = Vec::new();
+        for user in [users[0], users[0], users[1]] {
+            // Room for the disconnect and the close message
+            let (tx, rx) = mpsc::channel(2);
+            assert!(
+                ws.register_ws(tx, user, Id::default(), Uuid::new_v4())
+                    .await
+            );
+            receivers.push(rx);
+        }
+
+        ws.close_all(Some("Deploying".to_string())).await;
+        assert!(ws.online_users(users.to_vec()).await.is_empty());
+        for mut rx in receivers {
+            assert!(matches!(
+                rx.recv().await,
+                Some(WsServerMsg::Disconnect { reason: Some(reason) }) if reason == "Deploying"
+            ));
+            assert!(matches!(rx.recv().await, Some(WsServerMsg::Close)));
+        }
+    }
+
+    #[tokio::test]
+    async fn close_user_closes_all_connections() {
+        let ws = GlobalWs::new();
+        let user = Uuid::new_v4();
+        let (first_tx, mut first_rx) = mpsc::channel(1);
+        let (second_tx, mut second_rx) = mpsc::channel(1);
+
+        assert!(
+            ws.register_ws(first_tx, user, Id::default(), Uuid::new_v4())
+                .await
+        );
+        assert!(
+            ws.register_ws(second_tx, user, Id::default(), Uuid::new_v4())
+                .await
+        );
+
+        ws.close_user(user).await;
+        assert!(ws.online_users(vec![user]).await.is_empty());
+        assert!(matches!(first_rx.recv().await, Some(WsServerMsg::Close)));
+        assert!(matches!(second_rx.recv().await, Some(WsServerMsg::Close)));
+    }
+}
@@ -6,6 +6,8 @@ use std::panic::Location;
 use std::time::SystemTimeError;
 
 use axum::extract::rejection::JsonRejection;
+use axum::http::header::RETRY_AFTER;
+use axum::http::HeaderValue;
 use axum::http::StatusCode;
 use axum::response::IntoResponse;
 use axum::response::Response;
@@ -19,12 +21,21 @@ use swaggapi::re_exports::openapiv3;
 use swaggapi::re_exports::openapiv3::MediaType;
 use swaggapi::re_exports::openapiv3::Responses;
 use thiserror::Error;
+use tower::load_shed::error::Overloaded;
+use tower::BoxError;
 use tracing::error;
+use tracing::warn;
 use webauthn_rs::prelude::WebauthnError;
 
 use crate::http::common::schemas::ApiErrorResponse;
 use crate::http::common::schemas::ApiStatusCode;
+use crate::http::middlewares::language::current_language;
+use crate::http::middlewares::request_id::current_request_id;
+use crate::models::CreateUserError;
+use crate::utils::avatars::AvatarStoreError;
 use crate::utils::checked_string;
+use crate::utils::i18n::Message;
+use crate::utils::oidc_tokens::OidcTokenError;
 use crate::utils::totp::TotpFromError;
 
 /// A type alias that includes the ApiError
@@ -43,8 +54,30 @@ pub enum ApiError {
     #[error("Bad request")]
     BadRequest,
 
+    #[error("Not found")]
+    NotFound,
+
+    #[error("Conflict")]
+    Conflict,
+
+    #[error("The session is corrupt")]
+    SessionCorrupt,
+
     #[error("Invalid json received: {0}")]
-    InvalidJson(#[from] JsonRejection),
+    InvalidJson(JsonRejection),
+
+    #[error("The request's body exceeds the configured limit")]
+    PayloadTooLarge,
+
+    /// The client should wait for `retry_after_secs` before sending the request again
+    #[error("Too many requests")]
+    TooManyRequests { retry_after_secs: u64 },
+
+    #[error("The request's csrf token is missing or invalid")]
+    CsrfFailed,
+
+    #[error("The user's mail has to be verified first")]
+    EmailUnverified,
 
     #[error("An internal server error occurred")]
     InternalServerError {
@@ -70,16 +103,34 @@ impl ApiError {
 impl IntoResponse for ApiError {
     fn into_response(self) -> Response {
         let (status_code, message) = match self {
-            ApiError::Unauthenticated => (
-                ApiStatusCode::Unauthenticated,
-                "Unauthenticated".to_string(),
-            ),
-            ApiError::BadRequest => (ApiStatusCode::BadRequest, "Bad Request".to_string()),
-            ApiError::MissingPrivileges => (
-                ApiStatusCode::MissingPrivileges,
-                "Missing Privileges".to_string(),
-            ),
-            ApiError::InvalidJson(msg) => (ApiStatusCode::InvalidJson, msg.to_string()),
+            ApiError::Unauthenticated => (ApiStatusCode::Unauthenticated, Message::Unauthenticated),
+            ApiError::BadRequest => (ApiStatusCode::BadRequest, Message::BadRequest),
+            ApiError::NotFound => (ApiStatusCode::NotFound, Message::NotFound),
+            ApiError::Conflict => (ApiStatusCode::Conflict, Message::Conflict),
+            ApiError::SessionCorrupt => {
+                warn!("Encountered a session without id");
+                (ApiStatusCode::SessionCorrupt, Message::SessionCorrupt)
+            }
+            ApiError::MissingPrivileges => {
+                (ApiStatusCode::MissingPrivileges, Message::MissingPrivileges)
+            }
+            ApiError::InvalidJson(msg) => {
+                // The rejection's message describes the offending json and is not translated
+                return Self::response(ApiStatusCode::InvalidJson, msg.to_string());
+            }
+            ApiError::TooManyRequests { retry_after_secs } => {
+                let mut response = Self::response(
+                    ApiStatusCode::TooManyRequests,
+                    Message::TooManyRequests.translate(current_language()),
+                );
+                response
+                    .headers_mut()
+                    .insert(RETRY_AFTER, HeaderValue::from(retry_after_secs));
+                return response;
+            }
+            ApiError::PayloadTooLarge => (ApiStatusCode::PayloadTooLarge, Message::PayloadTooLarge),
+            ApiError::CsrfFailed => (ApiStatusCode::CsrfFailed, Message::CsrfFailed),
+            ApiError::EmailUnverified => (ApiStatusCode::EmailUnverified, Message::EmailUnverified),
             ApiError::InternalServerError { location, source } => {
                 error!(
                     error.display = %source,
@@ -91,20 +142,37 @@ impl IntoResponse for ApiError {
                 );
                 (
                     ApiStatusCode::InternalServerError,
-                    "Internal server error occurred".to_string(),
+                    Message::InternalServerError,
                 )
             }
         };
 
+        Self::response(status_code, message.translate(current_language()))
+    }
+}
+
+impl ApiError {
+    /// Builds the response for an error
+    fn response(status_code: ApiStatusCode, message: String) -> Response {
+        let request_id = if (status_code as u16) < 2000 {
+            None
+        } else {
+            current_request_id()
+        };
+
         let res = (
-            if (status_code as u16) < 2000 {
-                StatusCode::BAD_REQUEST
-            } else {
-                StatusCode::INTERNAL_SERVER_ERROR
+            match status_code {
+                ApiStatusCode::NotFound => StatusCode::NOT_FOUND,
+                ApiStatusCode::Conflict => StatusCode::CONFLICT,
+                ApiStatusCode::PayloadTooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+                ApiStatusCode::TooManyRequests => StatusCode::TOO_MANY_REQUESTS,
+                _ if (status_code as u16) < 2000 => StatusCode::BAD_REQUEST,
+                _ => StatusCode::INTERNAL_SERVER_ERROR,
             },
             Json(ApiErrorResponse {
                 status_code,
                 message,
+                request_id,
             }),
         );
 
@@ -126,6 +194,32 @@ impl AsResponses for ApiError {
                 description: "Client side error".to_string(),
                 media_type: media_type.clone(),
             },
+            SimpleResponse {
+                status_code: openapiv3::StatusCode::Code(404),
+                mime_type: mime::APPLICATION_JSON,
+                description: "The requested resource doesn't exist".to_string(),
+                media_type: media_type.clone(),
+            },
+            SimpleResponse {
+                status_code: openapiv3::StatusCode::Code(409),
+                mime_type: mime::APPLICATION_JSON,
+                description: "The request conflicts with existing data".to_string(),
+                media_type: media_type.clone(),
+            },
+            SimpleResponse {
+                status_code: openapiv3::StatusCode::Code(413),
+                mime_type: mime::APPLICATION_JSON,
+                description: "The request's body exceeds the server's limit".to_string(),
+                media_type: media_type.clone(),
+            },
+            SimpleResponse {
+                status_code: openapiv3::StatusCode::Code(429),
+                mime_type: mime::APPLICATION_JSON,
+                description:
+                    "Too many requests, retry after the seconds in the `Retry-After` header"
+                        .to_string(),
+                media_type: media_type.clone(),
+            },
             SimpleResponse {
                 status_code: openapiv3::StatusCode::Code(500),
                 mime_type: mime::APPLICATION_JSON,
@@ -136,6 +230,25 @@ impl AsResponses for ApiError {
     }
 }
 
+/// The seconds a client should wait after being rejected by a concurrency limit
+///
+/// Requests finish quickly, so a slot is likely free again by then.
+const OVERLOADED_RETRY_AFTER_SECS: u64 = 1;
+
+/// Converts the error of a [`LoadShed`](tower::load_shed::LoadShed)ed service into an [`ApiError`]
+///
+/// Use it with [`HandleErrorLayer`](axum::error_handling::HandleErrorLayer)
+/// to reject requests exceeding a concurrency limit instead of queueing them.
+pub async fn handle_overloaded(error: BoxError) -> ApiError {
+    if error.is::<Overloaded>() {
+        ApiError::TooManyRequests {
+            retry_after_secs: OVERLOADED_RETRY_AFTER_SECS,
+        }
+    } else {
+        ApiError::new_internal_server_error(error)
+    }
+}
+
 /// Simple macro to reduce the noise of several identical `From` implementations
 ///
 /// It takes a list of error types
@@ -150,13 +263,226 @@ macro_rules! impl_into_internal_server_error {
         }
     )+};
 }
+impl From<JsonRejection> for ApiError {
+    fn from(value: JsonRejection) -> Self {
+        // Exceeding the `DefaultBodyLimit` surfaces as rejection while buffering the body
+        if value.status() == StatusCode::PAYLOAD_TOO_LARGE {
+            Self::PayloadTooLarge
+        } else {
+            Self::InvalidJson(value)
+        }
+    }
+}
+impl From<CreateUserError> for ApiError {
+    #[track_caller]
+    fn from(value: CreateUserError) -> Self {
+        match value {
+            CreateUserError::MailOccupied
+            | CreateUserError::DisplayNameOccupied
+            | CreateUserError::Conflict => Self::Conflict,
+            _ => Self::new_internal_server_error(value),
+        }
+    }
+}
+
 impl_into_internal_server_error!(
     rorm::Error,
     argon2::password_hash::Error,
     tower_sessions::session::Error,
     strum::ParseError,
+    serde_json::Error,
     checked_string::ConstraintsViolated,
     SystemTimeError,
     TotpFromError,
     WebauthnError,
+    AvatarStoreError,
+    OidcTokenError,
 );
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+    use std::sync::Arc;
+
+    use axum::body::to_bytes;
+    use axum::body::Body;
+    use axum::error_handling::HandleErrorLayer;
+    use axum::extract::DefaultBodyLimit;
+    use axum::extract::Request;
+    use axum::http::header::CONTENT_TYPE;
+    use axum::http::header::RETRY_AFTER;
+    use axum::http::StatusCode;
+    use axum::response::IntoResponse;
+    use axum::routing::get;
+    use axum::routing::post;
+    use axum::Router;
+    use serde_json::Value;
+    use tokio::sync::Notify;
+    use tower::ServiceBuilder;
+    use tower::ServiceExt;
+
+    use super::handle_overloaded;
+    use super::ApiError;
+    use crate::http::extractors::api_json::ApiJson;
+    use crate::http::handler_frontend::users::schema::UserLanguage;
+    use crate::http::middlewares::language::request_language;
+    use crate::http::middlewares::language::set_current_language;
+
+    #[tokio::test]
+    async fn internal_server_errors_hide_their_cause() -> Result<(), Box<dyn Error>> {
+        let response = ApiError::new_internal_server_error("secret details").into_response();
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+
+        let body: Value =
+            serde_json::from_slice(&to_bytes(response.into_body(), usize::MAX).await?)?;
+        assert_eq!(body["status_code"], 2000);
+        assert_eq!(body["message"], "Internal server error occurred");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn too_many_requests_sets_retry_after() -> Result<(), Box<dyn Error>> {
+        let response = ApiError::TooManyRequests {
+            retry_after_secs: 42,
+        }
+        .into_response();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()[RETRY_AFTER], "42");
+
+        let body: Value =
+            serde_json::from_slice(&to_bytes(response.into_body(), usize::MAX).await?)?;
+        assert_eq!(body["status_code"], 1004);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn requests_beyond_the_concurrency_limit_are_shed() -> Result<(), Box<dyn Error>> {
+        let release = Arc::new(Notify::new());
+        let router = Router::new()
+            .route(
+                "/",
+                get({
+                    let release = release.clone();
+                    || async move { release.notified().await }
+                }),
+            )
+            .route_layer(
+                ServiceBuilder::new()
+                    .layer(HandleErrorLayer::new(handle_overloaded))
+                    .load_shed()
+                    .concurrency_limit(1),
+            );
+
+        // Occupies the only slot until released
+        let mut first = Box::pin(
+            router
+                .clone()
+                .oneshot(Request::get("/").body(Body::empty())?),
+        );
+        assert!(futures::poll!(&mut first).is_pending());
+
+        let response = router
+            .clone()
+            .oneshot(Request::get("/").body(Body::empty())?)
+            .await?;
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()[RETRY_AFTER], "1");
+
+        release.notify_one();
+        assert_eq!(first.await?.status(), StatusCode::OK);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn not_found_and_conflict_are_client_errors() -> Result<(), Box<dyn Error>> {
+        for (error, status, status_code) in [
+            (ApiError::NotFound, StatusCode::NOT_FOUND, 1005),
+            (ApiError::Conflict, StatusCode::CONFLICT, 1006),
+        ] {
+            let response = error.into_response();
+            assert_eq!(response.status(), status);
+
+            let body: Value =
+                serde_json::from_slice(&to_bytes(response.into_body(), usize::MAX).await?)?;
+            assert_eq!(body["status_code"], status_code);
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn session_corrupt_is_a_client_error() -> Result<(), Box<dyn Error>> {
+        let response = ApiError::SessionCorrupt.into_response();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let body: Value =
+            serde_json::from_slice(&to_bytes(response.into_body(), usize::MAX).await?)?;
+        assert_eq!(body["status_code"], 1007);
+        assert_eq!(
+            body["message"],
+            "The session is corrupt, please log in again"
+        );
+        assert_eq!(body["request_id"], Value::Null);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn exceeding_the_body_limit_is_payload_too_large() -> Result<(), Box<dyn Error>> {
+        let router = Router::new()
+            .route("/", post(|ApiJson(_): ApiJson<Value>| async {}))
+            .layer(DefaultBodyLimit::max(16));
+
+        let request = Request::post("/")
+            .header(CONTENT_TYPE, "application/json")
+            .body(Body::from(r#"{"padding": "more than sixteen bytes"}"#))?;
+        let response = router.oneshot(request).await?;
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+        let body: Value =
+            serde_json::from_slice(&to_bytes(response.into_body(), usize::MAX).await?)?;
+        assert_eq!(body["status_code"], 1008);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn csrf_failed_is_a_client_error() -> Result<(), Box<dyn Error>> {
+        let response = ApiError::CsrfFailed.into_response();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let body: Value =
+            serde_json::from_slice(&to_bytes(response.into_body(), usize::MAX).await?)?;
+        assert_eq!(body["status_code"], 1009);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn email_unverified_is_a_client_error() -> Result<(), Box<dyn Error>> {
+        let response = ApiError::EmailUnverified.into_response();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let body: Value =
+            serde_json::from_slice(&to_bytes(response.into_body(), usize::MAX).await?)?;
+        assert_eq!(body["status_code"], 1010);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn messages_are_translated_into_the_request_language() -> Result<(), Box<dyn Error>> {
+        let router = Router::new()
+            .route(
+                "/",
+                get(|| async {
+                    set_current_language(UserLanguage::DE);
+                    ApiError::NotFound
+                }),
+            )
+            .layer(axum::middleware::from_fn(request_language));
+
+        let response = router
+            .oneshot(Request::get("/").body(Body::empty())?)
+            .await?;
+        let body: Value =
+            serde_json::from_slice(&to_bytes(response.into_body(), usize::MAX).await?)?;
+        assert_eq!(body["message"], "Nicht gefunden");
+        Ok(())
+    }
+}
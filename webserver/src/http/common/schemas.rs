@@ -8,6 +8,9 @@ use serde_repr::Deserialize_repr;
 use serde_repr::Serialize_repr;
 use uuid::Uuid;
 
+use crate::config::PaginationConfig;
+use crate::global::GLOBAL;
+
 /// A single uuid wrapped in a struct
 #[derive(Debug, Clone, Copy, Deserialize, Serialize, JsonSchema)]
 pub struct SingleUuid {
@@ -53,12 +56,95 @@ impl<T> Optional<T> {
 ///
 /// It simply wraps the vector into a struct with a single field
 /// to ensure the json returned from a handler is always an object.
+///
+/// Use it for small lists which are always returned as a whole and can't be filtered.
+/// Listings which are paginated or filtered should return a [`Collection`] instead.
 #[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
 pub struct List<T> {
     #[allow(missing_docs)]
     pub list: Vec<T>,
 }
 
+/// Query parameters selecting a single page of a list
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, JsonSchema)]
+pub struct PageParams {
+    /// The maximum number of items to return
+    ///
+    /// Defaults to and is clamped by the server's configuration.
+    pub limit: Option<u64>,
+
+    /// The number of items to skip
+    #[serde(default)]
+    pub offset: u64,
+}
+impl PageParams {
+    /// The `limit` clamped to the configured maximum
+    ///
+    /// If no `limit` was requested, the configured default is used.
+    pub fn limit(&self) -> u64 {
+        self.limit_with(&GLOBAL.settings.get().pagination)
+    }
+
+    /// Implementation of [`PageParams::limit`] with a given configuration
+    fn limit_with(&self, config: &PaginationConfig) -> u64 {
+        self.limit
+            .unwrap_or(config.default_limit)
+            .min(config.max_limit)
+    }
+}
+
+/// # Collection
+/// A list of items with some metadata about the listing
+///
+/// ## Rust Usage
+///
+/// Return this from handlers which accept [`PageParams`] or some filters.
+/// The filters are echoed back as `F`, use [`NoFilters`] if the listing can't be filtered.
+///
+/// Use [`Collection::page`] to construct a single page
+/// and [`Collection::all`] for listings which are returned as a whole.
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+pub struct Collection<T, F> {
+    /// The items in this collection
+    pub items: Vec<T>,
+    /// The total number of items matching the filters
+    pub total: u64,
+    /// The limit used to query the items
+    pub limit: u64,
+    /// The offset used to query the items
+    pub offset: u64,
+    /// The filters applied to the items
+    pub filters: F,
+}
+impl<T, F> Collection<T, F> {
+    /// Constructs a single page of a paginated listing
+    pub fn page(items: Vec<T>, total: u64, page: &PageParams, filters: F) -> Self {
+        Self {
+            items,
+            total,
+            limit: page.limit(),
+            offset: page.offset,
+            filters,
+        }
+    }
+
+    /// Constructs a collection containing all items matching the filters
+    pub fn all(items: Vec<T>, filters: F) -> Self {
+        let total = items.len() as u64;
+        Self {
+            items,
+            total,
+            limit: total,
+            offset: 0,
+            filters,
+        }
+    }
+}
+
+/// The filters of a [`Collection`] which can't be filtered
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize, JsonSchema)]
+pub struct NoFilters {}
+
 /// The Status code that are returned throughout the API
 #[derive(Debug, Clone, Copy, Deserialize_repr, Serialize_repr, JsonSchema_repr)]
 #[repr(u16)]
@@ -68,6 +154,13 @@ pub enum ApiStatusCode {
     BadRequest = 1001,
     InvalidJson = 1002,
     MissingPrivileges = 1003,
+    TooManyRequests = 1004,
+    NotFound = 1005,
+    Conflict = 1006,
+    SessionCorrupt = 1007,
+    PayloadTooLarge = 1008,
+    CsrfFailed = 1009,
+    EmailUnverified = 1010,
 
     InternalServerError = 2000,
 }
@@ -82,8 +175,13 @@ pub struct ApiErrorResponse {
     pub status_code: ApiStatusCode,
     /// A human-readable error message.
     ///
-    /// May be used for displaying purposes
+    /// May be used for displaying purposes.
+    /// It is translated into the session user's preferred language, english for anonymous requests.
     pub message: String,
+    /// The id of the failed request
+    ///
+    /// Only set for server side errors to correlate them with the server's logs
+    pub request_id: Option<String>,
 }
 
 /// A `Result` with a custom serialization
@@ -105,3 +203,61 @@ impl<T, E> FormResult<T, E> {
         Self::Err { error }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::Collection;
+    use super::NoFilters;
+    use super::PageParams;
+    use crate::config::PaginationConfig;
+
+    const CONFIG: PaginationConfig = PaginationConfig {
+        default_limit: 100,
+        max_limit: 1000,
+    };
+
+    #[test]
+    fn limit_defaults_to_configured_default() {
+        let params = PageParams {
+            limit: None,
+            offset: 0,
+        };
+        assert_eq!(params.limit_with(&CONFIG), 100);
+    }
+
+    #[test]
+    fn limit_keeps_requested_limit() {
+        let params = PageParams {
+            limit: Some(250),
+            offset: 0,
+        };
+        assert_eq!(params.limit_with(&CONFIG), 250);
+    }
+
+    #[test]
+    fn limit_is_clamped_to_configured_maximum() {
+        let params = PageParams {
+            limit: Some(5000),
+            offset: 0,
+        };
+        assert_eq!(params.limit_with(&CONFIG), 1000);
+    }
+
+    #[test]
+    fn unpaginated_collection_contains_all_items() -> Result<(), serde_json::Error> {
+        let collection = Collection::all(vec!["a", "b", "c"], NoFilters {});
+        assert_eq!(
+            serde_json::to_value(&collection)?,
+            json!({
+                "items": ["a", "b", "c"],
+                "total": 3,
+                "limit": 3,
+                "offset": 0,
+                "filters": {},
+            })
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,164 @@
+//! Limit on the number of open connections
+//!
+//! The limit is enforced when a connection is accepted,
+//! so connections exceeding it never reach the router.
+
+use std::io;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::Context;
+use std::task::Poll;
+
+use axum_server::accept::Accept;
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use tokio::io::AsyncRead;
+use tokio::io::AsyncWrite;
+use tokio::io::ReadBuf;
+use tokio::sync::OwnedSemaphorePermit;
+use tokio::sync::Semaphore;
+use tracing::debug;
+
+/// An [`Accept`]or closing connections exceeding a limit before passing them to `A`
+#[derive(Debug, Clone)]
+pub struct ConnectionLimitAcceptor<A> {
+    inner: A,
+    slots: Option<Arc<Semaphore>>,
+}
+
+impl<A> ConnectionLimitAcceptor<A> {
+    /// Limit the connections accepted by `inner` to `max_connections`
+    ///
+    /// `None` doesn't limit the connections.
+    pub fn new(inner: A, max_connections: Option<usize>) -> Self {
+        Self {
+            inner,
+            slots: max_connections.map(|max| Arc::new(Semaphore::new(max))),
+        }
+    }
+}
+
+impl<A, I, S> Accept<I, S> for ConnectionLimitAcceptor<A>
+where
+    A: Accept<I, S>,
+    A::Future: Send + 'static,
+    A::Stream: 'static,
+    A::Service: 'static,
+{
+    type Stream = LimitedStream<A::Stream>;
+    type Service = A::Service;
+    type Future = BoxFuture<'static, io::Result<(Self::Stream, Self::Service)>>;
+
+    fn accept(&self, stream: I, service: S) -> Self::Future {
+        let slot = match &self.slots {
+            None => None,
+            Some(slots) => match slots.clone().try_acquire_owned() {
+                Ok(slot) => Some(slot),
+                Err(_) => {
+                    debug!("Closing a connection exceeding the connection limit");
+                    // Dropping the stream closes the connection
+                    return futures::future::ready(Err(io::Error::other(
+                        "Too many open connections",
+                    )))
+                    .boxed();
+                }
+            },
+        };
+
+        let accept = self.inner.accept(stream, service);
+        async move {
+            let (stream, service) = accept.await?;
+            Ok((
+                LimitedStream {
+                    inner: stream,
+                    _slot: slot,
+                },
+                service,
+            ))
+        }
+        .boxed()
+    }
+}
+
+/// A stream which frees its slot of the connection limit when it's dropped
+#[derive(Debug)]
+pub struct LimitedStream<S> {
+    inner: S,
+    _slot: Option<OwnedSemaphorePermit>,
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for LimitedStream<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_read(cx, buf)
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for LimitedStream<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().inner).poll_write_vectored(cx, bufs)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.inner.is_write_vectored()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum_server::accept::Accept;
+    use axum_server::accept::DefaultAcceptor;
+
+    use super::ConnectionLimitAcceptor;
+
+    #[tokio::test]
+    async fn connections_exceeding_the_limit_are_closed() -> Result<(), Box<dyn std::error::Error>>
+    {
+        let acceptor = ConnectionLimitAcceptor::new(DefaultAcceptor::new(), Some(2));
+        let first = acceptor.accept((), ()).await?;
+        let second = acceptor.accept((), ()).await?;
+        assert!(acceptor.accept((), ()).await.is_err());
+
+        drop(first);
+        let third = acceptor.accept((), ()).await?;
+        assert!(acceptor.accept((), ()).await.is_err());
+
+        drop((second, third));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn connections_are_unlimited_without_a_limit() -> Result<(), Box<dyn std::error::Error>> {
+        let acceptor = ConnectionLimitAcceptor::new(DefaultAcceptor::new(), None);
+        let connections = [
+            acceptor.accept((), ()).await?,
+            acceptor.accept((), ()).await?,
+            acceptor.accept((), ()).await?,
+        ];
+        assert_eq!(connections.len(), 3);
+        Ok(())
+    }
+}
@@ -0,0 +1,48 @@
+//! An extractor module for extracting information about the client's device
+
+use std::convert::Infallible;
+use std::net::IpAddr;
+use std::net::SocketAddr;
+
+use axum::async_trait;
+use axum::extract::ConnectInfo;
+use axum::extract::FromRequestParts;
+use axum::http::header::USER_AGENT;
+use axum::http::request::Parts;
+
+/// Upper bound for the length of stored user agents
+const MAX_USER_AGENT_LEN: usize = 255;
+
+/// The extractor for the client's user agent and ip address
+///
+/// Both are informational only as they are controlled by the client.
+/// Behind a reverse proxy, the ip address is the proxy's.
+#[derive(Debug, Clone, Default)]
+pub struct DeviceInfo {
+    /// The `User-Agent` header, truncated to 255 characters
+    pub user_agent: Option<String>,
+    /// The address of the connection's peer
+    pub ip: Option<IpAddr>,
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for DeviceInfo
+where
+    S: Send + Sync,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(req: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let user_agent = req
+            .headers
+            .get(USER_AGENT)
+            .and_then(|value| value.to_str().ok())
+            .map(|value| value.chars().take(MAX_USER_AGENT_LEN).collect());
+        let ip = req
+            .extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| addr.ip());
+
+        Ok(DeviceInfo { user_agent, ip })
+    }
+}
@@ -0,0 +1,87 @@
+//! An extractor module for receiving images as raw request body
+
+use axum::async_trait;
+use axum::body::Bytes;
+use axum::extract::FromRequest;
+use axum::extract::Request;
+use axum::http::header::CONTENT_TYPE;
+use axum::http::StatusCode;
+use image::ImageFormat;
+use swaggapi::handler_argument::HandlerArgument;
+use swaggapi::handler_argument::ShouldBeHandlerArgument;
+use swaggapi::internals::SchemaGenerator;
+use swaggapi::re_exports::openapiv3::MediaType;
+use swaggapi::re_exports::openapiv3::Parameter;
+use swaggapi::re_exports::openapiv3::RequestBody;
+
+use crate::http::common::errors::ApiError;
+
+/// The content types accepted by [`ImageUpload`] and their formats
+const ACCEPTED_TYPES: [(&str, ImageFormat); 3] = [
+    ("image/png", ImageFormat::Png),
+    ("image/jpeg", ImageFormat::Jpeg),
+    ("image/webp", ImageFormat::WebP),
+];
+
+/// The extractor for an image sent as raw request body
+///
+/// The image's format is taken from the `Content-Type` header.
+/// The data itself is not validated.
+#[derive(Debug, Clone)]
+pub struct ImageUpload {
+    /// The format declared by the `Content-Type` header
+    pub format: ImageFormat,
+    /// The raw request body
+    pub data: Bytes,
+}
+
+#[async_trait]
+impl<S> FromRequest<S> for ImageUpload
+where
+    S: Send + Sync,
+{
+    type Rejection = ApiError;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let content_type = req
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default();
+        let Some((_, format)) = ACCEPTED_TYPES
+            .into_iter()
+            .find(|(mime, _)| content_type.eq_ignore_ascii_case(mime))
+        else {
+            return Err(ApiError::BadRequest);
+        };
+
+        let data = Bytes::from_request(req, state).await.map_err(|rejection| {
+            // Exceeding the `DefaultBodyLimit` surfaces as rejection while buffering the body
+            if rejection.status() == StatusCode::PAYLOAD_TOO_LARGE {
+                ApiError::PayloadTooLarge
+            } else {
+                ApiError::BadRequest
+            }
+        })?;
+
+        Ok(Self { format, data })
+    }
+}
+
+impl ShouldBeHandlerArgument for ImageUpload {}
+impl HandlerArgument for ImageUpload {
+    fn request_body(_gen: &mut SchemaGenerator) -> Option<RequestBody> {
+        Some(RequestBody {
+            description: Some("A png, jpeg or webp image".to_string()),
+            content: ACCEPTED_TYPES
+                .into_iter()
+                .map(|(mime, _)| (mime.to_string(), MediaType::default()))
+                .collect(),
+            required: true,
+            ..Default::default()
+        })
+    }
+    fn parameters(_gen: &mut SchemaGenerator, _path: &[&str]) -> Vec<Parameter> {
+        Vec::new()
+    }
+}
@@ -1,3 +1,5 @@
 //! Custom extractors are defined in this module
 pub mod api_json;
+pub mod device_info;
+pub mod image_upload;
 pub mod session_user;
@@ -6,6 +6,7 @@ use axum::http::request::Parts;
 use rorm::query;
 use rorm::FieldAccess;
 use rorm::Model;
+use time::OffsetDateTime;
 use tower_sessions::Session;
 use tracing::instrument;
 use tracing::trace;
@@ -13,8 +14,11 @@ use uuid::Uuid;
 
 use crate::global::GLOBAL;
 use crate::http::common::errors::ApiError;
+use crate::http::handler_frontend::users::schema::UserLanguage;
 use crate::http::handler_frontend::users::schema::UserPermissions;
 use crate::http::handler_frontend::users::utils::get_user_permissions;
+use crate::http::middlewares::language::set_current_language;
+use crate::http::session_keys::SESSION_LOGGED_IN_AT;
 use crate::http::session_keys::SESSION_USER;
 use crate::models::User;
 
@@ -39,22 +43,57 @@ where
             Ok(session) => session,
             Err((_, error_msg)) => return Err(ApiError::new_internal_server_error(error_msg)),
         };
+        Self::from_session(&session).await
+    }
+}
+
+impl SessionUser {
+    /// Retrieves the logged-in user of a session
+    ///
+    /// This is what the extractor does.
+    /// Use it directly in handlers which support other ways of authentication as well.
+    pub async fn from_session(session: &Session) -> Result<Self, ApiError> {
         let Some(user) = session.get::<Uuid>(SESSION_USER).await? else {
             trace!("{SESSION_USER} is missing in session");
             return Err(ApiError::Unauthenticated);
         };
 
+        if let Some(max_lifetime) = GLOBAL.settings.get().session_max_lifetime {
+            // Sessions from before the lifetime was introduced count as expired
+            let logged_in_at = session.get::<OffsetDateTime>(SESSION_LOGGED_IN_AT).await?;
+            if logged_in_at.map_or(true, |logged_in_at| {
+                OffsetDateTime::now_utc() - logged_in_at > max_lifetime
+            }) {
+                trace!("Session exceeded its maximum lifetime");
+                session.flush().await?;
+                return Err(ApiError::Unauthenticated);
+            }
+        }
+
+        Self::load(user).await
+    }
+
+    /// Loads a user who has been authenticated by other means
+    ///
+    /// Disabled users are rejected.
+    pub async fn load(user: Uuid) -> Result<Self, ApiError> {
         let mut tx = GLOBAL.db.start_transaction().await?;
         let user = query!(&mut tx, User)
             .condition(User::F.uuid.equals(user))
             .optional()
             .await?
             .ok_or(ApiError::Unauthenticated)?;
+
+        if !user.enabled {
+            trace!("User {} is disabled", user.uuid);
+            return Err(ApiError::Unauthenticated);
+        }
+
+        let permissions = get_user_permissions(&mut tx, &user).await?;
         tx.commit().await?;
 
-        Ok(SessionUser {
-            permissions: get_user_permissions(&user)?,
-            user,
-        })
+        set_current_language(UserLanguage::from_stored(&user.preferred_lang));
+
+        Ok(SessionUser { permissions, user })
     }
 }
@@ -0,0 +1,84 @@
+//! Admin handlers for the audit log
+
+use axum::extract::Query;
+use rorm::conditions::BoxedCondition;
+use rorm::conditions::Condition;
+use rorm::conditions::DynamicCollection;
+use rorm::query;
+use rorm::FieldAccess;
+use rorm::Model;
+use swaggapi::get;
+
+use crate::global::GLOBAL;
+use crate::http::common::errors::ApiResult;
+use crate::http::common::schemas::Collection;
+use crate::http::common::schemas::PageParams;
+use crate::http::extractors::api_json::ApiJson;
+use crate::http::handler_frontend::audit_log::schema::AuditLogEntry;
+use crate::http::handler_frontend::audit_log::schema::GetAuditLogRequest;
+use crate::models::AuditLog;
+use crate::utils::schemars::SchemaDateTime;
+
+/// Retrieves a page of the audit log ordered from newest to oldest
+///
+/// The entries can be filtered by their actor, action and a time range.
+#[get("/")]
+pub async fn get_audit_log(
+    Query(page): Query<PageParams>,
+    Query(filter): Query<GetAuditLogRequest>,
+) -> ApiResult<ApiJson<Collection<AuditLogEntry, GetAuditLogRequest>>> {
+    let mut tx = GLOBAL.db.start_transaction().await?;
+
+    let (total,) = query!(&mut tx, (AuditLog::F.uuid.count(),))
+        .condition(audit_log_filter(&filter))
+        .one()
+        .await?;
+
+    let entries = query!(&mut tx, AuditLog)
+        .condition(audit_log_filter(&filter))
+        .order_desc(AuditLog::F.created_at)
+        .limit(page.limit())
+        .offset(page.offset)
+        .all()
+        .await?;
+
+    tx.commit().await?;
+
+    let items = entries
+        .into_iter()
+        .map(|entry| {
+            Ok(AuditLogEntry {
+                uuid: entry.uuid,
+                actor: entry.actor,
+                action: entry.action.parse()?,
+                target: entry.target,
+                detail: entry.detail.0,
+                created_at: SchemaDateTime(entry.created_at),
+            })
+        })
+        .collect::<ApiResult<_>>()?;
+    Ok(ApiJson(Collection::page(
+        items,
+        total as u64,
+        &page,
+        filter,
+    )))
+}
+
+/// Builds the condition selecting the entries matching a [`GetAuditLogRequest`]
+fn audit_log_filter(filter: &GetAuditLogRequest) -> DynamicCollection<BoxedCondition<'static>> {
+    let mut conditions = Vec::new();
+    if let Some(actor) = filter.actor {
+        conditions.push(AuditLog::F.actor.equals(actor).boxed());
+    }
+    if let Some(action) = filter.action {
+        conditions.push(AuditLog::F.action.equals(action.to_string()).boxed());
+    }
+    if let Some(SchemaDateTime(since)) = filter.since {
+        conditions.push(AuditLog::F.created_at.greater_or_equals(since).boxed());
+    }
+    if let Some(SchemaDateTime(until)) = filter.until {
+        conditions.push(AuditLog::F.created_at.less_than(until).boxed());
+    }
+    DynamicCollection::and(conditions)
+}
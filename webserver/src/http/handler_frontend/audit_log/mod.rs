@@ -0,0 +1,4 @@
+//! Everything regarding the audit log is defined in this module
+
+pub mod handler_admin;
+pub mod schema;
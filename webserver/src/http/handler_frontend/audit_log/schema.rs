@@ -0,0 +1,50 @@
+//! The schema for the audit log
+
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde::Serialize;
+use serde_json::Value;
+use uuid::Uuid;
+
+use crate::models::AuditAction;
+use crate::utils::schemars::SchemaDateTime;
+
+/// The filters for retrieving the audit log
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct GetAuditLogRequest {
+    /// Only return entries of actions performed by this user
+    pub actor: Option<Uuid>,
+
+    /// Only return entries of this action
+    pub action: Option<AuditAction>,
+
+    /// Only return entries created at or after this point in time
+    pub since: Option<SchemaDateTime>,
+
+    /// Only return entries created before this point in time
+    pub until: Option<SchemaDateTime>,
+}
+
+/// An entry in the audit log
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct AuditLogEntry {
+    /// Primary key of the entry
+    pub uuid: Uuid,
+
+    /// The user who performed the action
+    ///
+    /// `None` if it was performed by the system.
+    pub actor: Option<Uuid>,
+
+    /// The action which was performed
+    pub action: AuditAction,
+
+    /// The entity the action was performed on
+    pub target: Option<Uuid>,
+
+    /// Additional details depending on the `action`
+    pub detail: Value,
+
+    /// The point in time the action was performed
+    pub created_at: SchemaDateTime,
+}
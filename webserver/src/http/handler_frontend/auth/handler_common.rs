@@ -1,16 +1,20 @@
 //! The handler for local authentication
 
-use std::time::SystemTime;
-use std::time::UNIX_EPOCH;
-
+use axum::extract::Path;
+use axum::response::Redirect;
 use futures::TryStreamExt;
+use rand::distributions::Alphanumeric;
+use rand::distributions::DistString;
 use rorm::query;
 use rorm::FieldAccess;
 use rorm::Model;
+use swaggapi::get;
 use swaggapi::post;
 use swaggapi::utils::SchemalessJson;
+use time::OffsetDateTime;
 use tower_sessions::Session;
 use tracing::debug;
+use tracing::info;
 use tracing::instrument;
 use webauthn_rs::prelude::PublicKeyCredential;
 use webauthn_rs::prelude::RequestChallengeResponse;
@@ -20,7 +24,10 @@ use crate::http::common::errors::ApiError;
 use crate::http::common::errors::ApiResult;
 use crate::http::common::schemas::FormResult;
 use crate::http::common::schemas::Optional;
+use crate::http::common::schemas::SingleUuid;
 use crate::http::extractors::api_json::ApiJson;
+use crate::http::extractors::device_info::DeviceInfo;
+use crate::http::handler_frontend::auth::schema::CsrfToken;
 use crate::http::handler_frontend::auth::schema::LoginFlowsRequest;
 use crate::http::handler_frontend::auth::schema::LoginPasswordErrors;
 use crate::http::handler_frontend::auth::schema::LoginPasswordRequest;
@@ -31,23 +38,31 @@ use crate::http::handler_frontend::auth::schema::SupportedLoginFlows;
 use crate::http::handler_frontend::auth::schema::VerifyTotpRequest;
 use crate::http::handler_frontend::auth::schema::WebAuthnAuthenticateResult;
 use crate::http::handler_frontend::auth::schema::MFA;
+use crate::http::handler_frontend::auth::utils::consume_totp_token;
+use crate::http::handler_frontend::auth::utils::get_mfa;
 use crate::http::handler_frontend::auth::utils::get_partial_session_user;
+use crate::http::handler_frontend::auth::utils::is_local_user_enabled;
+use crate::http::handler_frontend::auth::utils::is_user_verification_sufficient;
+use crate::http::handler_frontend::auth::utils::set_mfa_verified;
 use crate::http::handler_frontend::auth::utils::set_partial_session_user;
 use crate::http::handler_frontend::auth::utils::set_session_user;
 use crate::http::session_keys::WebAuthnAuthentication;
 use crate::http::session_keys::WebAuthnAuthenticationState;
+use crate::http::session_keys::SESSION_CSRF_TOKEN;
 use crate::http::session_keys::SESSION_WEBAUTHN_AUTHENTICATION;
 use crate::models::LocalUser;
+use crate::models::MagicLoginLink;
 use crate::models::OidcUser;
-use crate::models::TotpKey;
 use crate::models::User;
 use crate::models::WebAuthnKey;
 use crate::utils::hashing;
 use crate::utils::hashing::VerifyPwError;
 use crate::utils::schemars::WebAuthnSchema;
-use crate::utils::totp;
 
 /// Get the login flows available to a user
+///
+/// Unless `Auth.RevealAccountExistence` is enabled,
+/// unknown mails are answered like accounts which only have a password.
 #[post("/flows")]
 pub async fn get_login_flows(
     ApiJson(LoginFlowsRequest { mail }): ApiJson<LoginFlowsRequest>,
@@ -59,7 +74,18 @@ pub async fn get_login_flows(
         .optional()
         .await?
     else {
-        return Ok(ApiJson(Optional::none()));
+        let settings = GLOBAL.settings.get();
+        return Ok(ApiJson(if settings.reveal_account_existence {
+            Optional::none()
+        } else {
+            Optional::some(SupportedLoginFlows {
+                mail,
+                oidc: false,
+                password: true,
+                key: false,
+                preference: settings.login_flow_preference,
+            })
+        }));
     };
 
     let is_oidc = query!(&mut tx, (OidcUser::F.uuid))
@@ -74,6 +100,7 @@ pub async fn get_login_flows(
             oidc: true,
             password: false,
             key: false,
+            preference: GLOBAL.settings.get().login_flow_preference,
         })));
     }
 
@@ -90,7 +117,7 @@ pub async fn get_login_flows(
         .condition(WebAuthnKey::F.local_user.equals(local_user_uuid))
         .stream();
     while let Some((passkey,)) = stream.try_next().await? {
-        if passkey.0.attested().is_some() {
+        if passkey.0.can_login() {
             key = true;
             break;
         }
@@ -103,17 +130,22 @@ pub async fn get_login_flows(
         oidc: false,
         password: password.is_some(),
         key,
+        preference: GLOBAL.settings.get().login_flow_preference,
     })))
 }
 
 /// Local login using webauthn
 ///
 /// Doesn't require another factor
+///
+/// Unless `Auth.RevealAccountExistence` is enabled,
+/// disabled users and users without a login key are answered like unknown mails.
 #[post("/login-webauthn")]
 pub async fn login_webauthn(
     session: Session,
     ApiJson(request): ApiJson<LoginWebauthnRequest>,
 ) -> ApiResult<ApiJson<FormResult<WebAuthnSchema<RequestChallengeResponse>, LoginWebauthnErrors>>> {
+    let reveal_account_existence = GLOBAL.settings.get().reveal_account_existence;
     let mut tx = GLOBAL.db.start_transaction().await?;
 
     let Some(local_user) = query!(&mut tx, LocalUser)
@@ -123,13 +155,30 @@ pub async fn login_webauthn(
     else {
         return Ok(ApiJson(FormResult::err(LoginWebauthnErrors { mail: true })));
     };
+    if !is_local_user_enabled(&mut tx, local_user.uuid).await? {
+        return if reveal_account_existence {
+            Err(ApiError::Unauthenticated)
+        } else {
+            Ok(ApiJson(FormResult::err(LoginWebauthnErrors { mail: true })))
+        };
+    }
 
     let keys = query!(&mut tx, (WebAuthnKey::F.key,))
         .condition(WebAuthnKey::F.local_user.equals(local_user.uuid))
         .stream()
-        .try_filter_map(|(json,)| async move { Ok(json.0.attested()) })
+        .try_filter_map(|(json,)| async move {
+            let key = json.0;
+            Ok(if key.can_login() {
+                key.attested()
+            } else {
+                None
+            })
+        })
         .try_collect::<Vec<_>>()
         .await?;
+    if keys.is_empty() && !reveal_account_existence {
+        return Ok(ApiJson(FormResult::err(LoginWebauthnErrors { mail: true })));
+    }
 
     let (challenge, state) = GLOBAL
         .webauthn
@@ -151,11 +200,16 @@ pub async fn login_webauthn(
 /// Local login using a password
 ///
 /// Might require another factor
+///
+/// Unless `Auth.RevealAccountExistence` is enabled,
+/// unknown mails and users without a password are answered like a wrong password.
 #[post("/login-password")]
 pub async fn login_password(
     session: Session,
+    device: DeviceInfo,
     ApiJson(request): ApiJson<LoginPasswordRequest>,
 ) -> ApiResult<ApiJson<FormResult<LoginPasswordResponse, LoginPasswordErrors>>> {
+    let reveal_account_existence = GLOBAL.settings.get().reveal_account_existence;
     let mut tx = GLOBAL.db.start_transaction().await?;
 
     let Some(local_user) = query!(&mut tx, LocalUser)
@@ -163,16 +217,26 @@ pub async fn login_password(
         .optional()
         .await?
     else {
+        hashing::verify_dummy(&request.password);
         return Ok(ApiJson(FormResult::err(LoginPasswordErrors {
-            mail: true,
-            ..Default::default()
+            mail: reveal_account_existence,
+            password: !reveal_account_existence,
         })));
     };
 
     let Some(hashed_password) = local_user.password.as_deref() else {
-        return Err(ApiError::BadRequest);
+        hashing::verify_dummy(&request.password);
+        return if reveal_account_existence {
+            Err(ApiError::BadRequest)
+        } else {
+            Ok(ApiJson(FormResult::err(LoginPasswordErrors {
+                password: true,
+                ..Default::default()
+            })))
+        };
     };
 
+    // Only reveal a disabled account to someone knowing its password
     match hashing::verify_pw(&request.password, hashed_password) {
         Ok(()) => {}
         Err(VerifyPwError::Hash(error)) => return Err(error.into()),
@@ -184,35 +248,38 @@ pub async fn login_password(
         }
     }
 
-    let has_totp = query!(&mut tx, (TotpKey::F.uuid,))
-        .condition(TotpKey::F.local_user.equals(local_user.uuid))
-        .optional()
-        .await?
-        .is_some();
-    let has_webauthn = query!(&mut tx, (WebAuthnKey::F.uuid,))
-        .condition(WebAuthnKey::F.local_user.equals(local_user.uuid))
-        .optional()
-        .await?
-        .is_some();
+    if !is_local_user_enabled(&mut tx, local_user.uuid).await? {
+        return Err(ApiError::Unauthenticated);
+    }
+
+    let mfa = get_mfa(&mut tx, local_user.uuid).await?;
 
-    if has_totp || has_webauthn {
+    if mfa.is_required() {
         set_partial_session_user(&session, local_user.uuid).await?;
 
         tx.commit().await?;
         Ok(ApiJson(FormResult::ok(LoginPasswordResponse::NeedMFA {
-            mfa: MFA {
-                has_totp,
-                has_webauthn,
-            },
+            mfa,
         })))
     } else {
-        set_session_user(&mut tx, &session, local_user.uuid).await?;
+        set_session_user(&mut tx, &session, local_user.uuid, &device).await?;
 
         tx.commit().await?;
         Ok(ApiJson(FormResult::ok(LoginPasswordResponse::Finished)))
     }
 }
 
+/// Get the second factors available to complete a password login
+///
+/// This allows a client to resume the login after it lost the response of `/login-password`.
+#[get("/mfa-status")]
+pub async fn get_mfa_status(session: Session) -> ApiResult<ApiJson<MFA>> {
+    let local_user_uuid = get_partial_session_user(&GLOBAL.db, &session).await?;
+
+    let mfa = get_mfa(&GLOBAL.db, local_user_uuid).await?;
+    Ok(ApiJson(mfa))
+}
+
 /// Verify a password login using an WebAuthn key
 #[post("/verify-webauthn")]
 pub async fn verify_webauthn(
@@ -220,7 +287,7 @@ pub async fn verify_webauthn(
 ) -> ApiResult<ApiJson<WebAuthnSchema<RequestChallengeResponse>>> {
     let mut tx = GLOBAL.db.start_transaction().await?;
 
-    let local_user_uuid = get_partial_session_user(&session).await?;
+    let local_user_uuid = get_partial_session_user(&mut tx, &session).await?;
 
     let keys = query!(&mut tx, (WebAuthnKey::F.key,))
         .condition(WebAuthnKey::F.local_user.equals(local_user_uuid))
@@ -249,32 +316,19 @@ pub async fn verify_webauthn(
 #[instrument(skip(session))]
 pub async fn verify_totp(
     session: Session,
+    device: DeviceInfo,
     ApiJson(request): ApiJson<VerifyTotpRequest>,
 ) -> ApiResult<()> {
     let mut tx = GLOBAL.db.start_transaction().await?;
 
-    let local_user_uuid = get_partial_session_user(&session).await?;
-
-    let keys = query!(&mut tx, TotpKey)
-        .condition(TotpKey::F.local_user.equals(local_user_uuid))
-        .all()
-        .await?;
-
-    let mut is_valid = false;
-    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
-    for key in keys {
-        let totp = totp::totp_from_binary(key.secret)?;
-        if totp.check(&request.token, now) {
-            is_valid = true;
-            break;
-        }
-    }
+    let local_user_uuid = get_partial_session_user(&mut tx, &session).await?;
 
-    if !is_valid {
+    if !consume_totp_token(&mut tx, local_user_uuid, &request.token).await? {
         return Err(ApiError::Unauthenticated); // TODO form error
     }
 
-    set_session_user(&mut tx, &session, local_user_uuid).await?;
+    set_session_user(&mut tx, &session, local_user_uuid, &device).await?;
+    set_mfa_verified(&session).await?;
 
     tx.commit().await?;
 
@@ -285,6 +339,7 @@ pub async fn verify_totp(
 #[post("/complete-webauthn")]
 pub async fn complete_auth_webauthn(
     session: Session,
+    device: DeviceInfo,
     SchemalessJson(request): SchemalessJson<PublicKeyCredential>,
 ) -> ApiResult<ApiJson<WebAuthnAuthenticateResult>> {
     let WebAuthnAuthentication { local_user, state } = session
@@ -292,24 +347,109 @@ pub async fn complete_auth_webauthn(
         .await?
         .ok_or(ApiError::BadRequest)?;
 
-    let webauthn_result = match state {
-        WebAuthnAuthenticationState::NotAttested(state) => GLOBAL
-            .webauthn
-            .finish_passkey_authentication(&request, &state),
-        WebAuthnAuthenticationState::Attested(state) => GLOBAL
-            .webauthn
-            .finish_attested_passkey_authentication(&request, &state),
+    let (webauthn_result, passwordless) = match state {
+        WebAuthnAuthenticationState::NotAttested(state) => (
+            GLOBAL
+                .webauthn
+                .finish_passkey_authentication(&request, &state),
+            false,
+        ),
+        WebAuthnAuthenticationState::Attested(state) => (
+            GLOBAL
+                .webauthn
+                .finish_attested_passkey_authentication(&request, &state),
+            true,
+        ),
     };
-    if let Err(error) = webauthn_result {
-        debug!(error.display = %error, error.debug = ?error, "WebAuthn Challenge failed");
-        return Ok(ApiJson(WebAuthnAuthenticateResult::Err));
+    let result = match webauthn_result {
+        Ok(result) => result,
+        Err(error) => {
+            debug!(error.display = %error, error.debug = ?error, "WebAuthn Challenge failed");
+            return Ok(ApiJson(WebAuthnAuthenticateResult::Err));
+        }
+    };
+    if !is_user_verification_sufficient(passwordless, result.user_verified()) {
+        debug!("Passwordless WebAuthn login without user verification");
+        return Ok(ApiJson(WebAuthnAuthenticateResult::UserNotVerified));
+    }
+    if !passwordless {
+        // The partial login might have expired since `verify_webauthn`
+        get_partial_session_user(&GLOBAL.db, &session).await?;
     }
 
-    set_session_user(&GLOBAL.db, &session, local_user).await?;
+    set_session_user(&GLOBAL.db, &session, local_user, &device).await?;
+    // A passwordless login's user verification counts as second factor as well
+    set_mfa_verified(&session).await?;
 
     Ok(ApiJson(WebAuthnAuthenticateResult::Ok))
 }
 
+/// Log in using a magic login link issued by an admin
+///
+/// The link can only be used once and redirects to the frontend afterward.
+#[get("/magic/:uuid")]
+#[instrument(skip_all)]
+pub async fn login_magic_link(
+    session: Session,
+    device: DeviceInfo,
+    Path(SingleUuid { uuid }): Path<SingleUuid>,
+) -> ApiResult<Redirect> {
+    if GLOBAL.settings.get().magic_link_expiry.is_none() {
+        debug!("Magic login links are disabled");
+        return Err(ApiError::Unauthenticated);
+    }
+
+    let mut tx = GLOBAL.db.start_transaction().await?;
+
+    let Some(link) = query!(&mut tx, MagicLoginLink)
+        .condition(MagicLoginLink::F.uuid.equals(uuid))
+        .optional()
+        .await?
+    else {
+        debug!("Unknown magic login link");
+        return Err(ApiError::Unauthenticated);
+    };
+
+    // Consume the link before checking its expiry to get rid of expired ones as well
+    rorm::delete!(&mut tx, MagicLoginLink)
+        .condition(MagicLoginLink::F.uuid.equals(uuid))
+        .await?;
+    if link.expires_at < OffsetDateTime::now_utc() {
+        tx.commit().await?;
+        debug!("Magic login link expired");
+        return Err(ApiError::Unauthenticated);
+    }
+
+    let local_user_uuid = *link.local_user.key();
+    set_session_user(&mut tx, &session, local_user_uuid, &device).await?;
+
+    tx.commit().await?;
+
+    info!(
+        local_user.uuid = %local_user_uuid,
+        created_by = ?link.created_by.as_ref().map(|admin| *admin.key()),
+        "Logged in using a magic login link"
+    );
+
+    Ok(Redirect::temporary("/"))
+}
+
+/// Get the token state-changing requests have to send in the `X-CSRF-Token` header
+///
+/// The token is bound to the session and stays valid until it is dropped.
+#[get("/csrf-token")]
+pub async fn get_csrf_token(session: Session) -> ApiResult<ApiJson<CsrfToken>> {
+    let token = match session.get::<String>(SESSION_CSRF_TOKEN).await? {
+        Some(token) => token,
+        None => {
+            let token = Alphanumeric.sample_string(&mut rand::thread_rng(), 32);
+            session.insert(SESSION_CSRF_TOKEN, &token).await?;
+            token
+        }
+    };
+    Ok(ApiJson(CsrfToken { token }))
+}
+
 /// Drop the current session and logg-out
 #[post("/logout")]
 #[instrument(skip_all)]
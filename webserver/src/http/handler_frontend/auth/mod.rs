@@ -2,4 +2,4 @@
 
 pub mod handler_common;
 pub mod schema;
-mod utils;
+pub mod utils;
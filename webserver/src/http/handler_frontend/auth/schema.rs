@@ -31,6 +31,21 @@ pub struct SupportedLoginFlows {
 
     /// Does this email support password-less login through a security key?
     pub key: bool,
+
+    /// Which flow should be offered by default if `password` and `key` are both `true`
+    pub preference: LoginFlowPreference,
+}
+
+/// The login flow to offer by default to users who support both password and password-less login
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub enum LoginFlowPreference {
+    /// Default to the password-less login using a security key
+    #[default]
+    Passkey,
+    /// Default to the password login
+    Password,
+    /// Don't default to either and let the user choose
+    UserChoice,
 }
 
 /// The request for local login using webauthn
@@ -44,6 +59,9 @@ pub struct LoginWebauthnRequest {
 #[derive(Debug, Copy, Clone, Default, Serialize, Deserialize, JsonSchema)]
 pub struct LoginWebauthnErrors {
     /// This mail doesn't correspond to a local user
+    ///
+    /// Unless the server reveals the existence of accounts,
+    /// this is also set for users who can't log in with a key.
     pub mail: bool,
 }
 
@@ -60,6 +78,9 @@ pub struct LoginPasswordRequest {
 #[derive(Debug, Copy, Clone, Default, Serialize, Deserialize, JsonSchema)]
 pub struct LoginPasswordErrors {
     /// This mail doesn't correspond to a local user
+    ///
+    /// Only set if the server reveals the existence of accounts,
+    /// otherwise unknown mails are reported as invalid password.
     pub mail: bool,
     /// The password was invalid
     pub password: bool,
@@ -73,6 +94,12 @@ pub struct MFA {
     /// The user has the option of using a webauthn key
     pub has_webauthn: bool,
 }
+impl MFA {
+    /// Checks whether the user has any second factor and therefore has to provide one
+    pub fn is_required(&self) -> bool {
+        self.has_totp || self.has_webauthn
+    }
+}
 
 /// The response for local login using a password
 #[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
@@ -104,4 +131,44 @@ pub struct VerifyTotpRequest {
 pub enum WebAuthnAuthenticateResult {
     Ok,
     Err,
+    UserNotVerified,
+}
+
+/// The token state-changing requests have to send in the `X-CSRF-Token` header
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct CsrfToken {
+    /// The token bound to the current session
+    pub token: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::MFA;
+
+    #[test]
+    fn mfa_is_required_with_any_second_factor() {
+        let mfa = |has_totp, has_webauthn| MFA {
+            has_totp,
+            has_webauthn,
+        };
+        assert!(mfa(true, false).is_required());
+        assert!(mfa(false, true).is_required());
+        assert!(mfa(true, true).is_required());
+        assert!(!mfa(false, false).is_required());
+    }
+
+    #[test]
+    fn mfa_lists_the_available_factors() -> Result<(), serde_json::Error> {
+        let mfa = MFA {
+            has_totp: true,
+            has_webauthn: false,
+        };
+        assert_eq!(
+            serde_json::to_value(mfa)?,
+            json!({ "has_totp": true, "has_webauthn": false })
+        );
+        Ok(())
+    }
 }
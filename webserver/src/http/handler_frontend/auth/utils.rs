@@ -1,3 +1,7 @@
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+use rorm::and;
 use rorm::db::Executor;
 use rorm::prelude::ForeignModelByField;
 use rorm::query;
@@ -6,21 +10,125 @@ use rorm::FieldAccess;
 use rorm::Model;
 use time::Duration;
 use time::OffsetDateTime;
+use tower_sessions::Expiry;
 use tower_sessions::Session;
+use tracing::debug;
 use tracing::trace;
 use uuid::Uuid;
 
+use crate::global::GLOBAL;
 use crate::http::common::errors::ApiError;
 use crate::http::common::errors::ApiResult;
+use crate::http::extractors::device_info::DeviceInfo;
+use crate::http::handler_frontend::auth::schema::MFA;
+use crate::http::handler_frontend::users::utils::set_logged_in;
 use crate::http::session_keys::PartiallyAuthedSessionUser;
 use crate::http::session_keys::PARTIALLY_AUTHED_SESSION_USER;
-use crate::http::session_keys::SESSION_USER;
-use crate::models;
+use crate::http::session_keys::SESSION_MFA_VERIFIED;
 use crate::models::LocalUser;
+use crate::models::TotpKey;
+use crate::models::WebAuthnKey;
+use crate::utils::totp;
+
+/// Checks whether the `User` associated with a `LocalUser` is enabled
+pub async fn is_local_user_enabled(
+    executor: impl Executor<'_>,
+    local_user_uuid: Uuid,
+) -> ApiResult<bool> {
+    Ok(query!(executor, (LocalUser::F.user.enabled,))
+        .condition(LocalUser::F.uuid.equals(local_user_uuid))
+        .optional()
+        .await?
+        .is_some_and(|(enabled,)| enabled))
+}
+
+/// Checks which second factors a local user has registered
+pub async fn get_mfa(executor: impl Executor<'_>, local_user_uuid: Uuid) -> ApiResult<MFA> {
+    let mut guard = executor.ensure_transaction().await?;
+
+    let has_totp = query!(guard.get_transaction(), (TotpKey::F.uuid,))
+        .condition(TotpKey::F.local_user.equals(local_user_uuid))
+        .optional()
+        .await?
+        .is_some();
+    let has_webauthn = query!(guard.get_transaction(), (WebAuthnKey::F.uuid,))
+        .condition(WebAuthnKey::F.local_user.equals(local_user_uuid))
+        .optional()
+        .await?
+        .is_some();
+
+    guard.commit().await?;
+    Ok(MFA {
+        has_totp,
+        has_webauthn,
+    })
+}
+
+/// Checks a TOTP token against all of a local user's keys
+///
+/// A matching token is consumed, so it can't be used again.
+pub async fn consume_totp_token(
+    executor: impl Executor<'_>,
+    local_user_uuid: Uuid,
+    token: &str,
+) -> ApiResult<bool> {
+    let mut guard = executor.ensure_transaction().await?;
+
+    let keys = query!(guard.get_transaction(), TotpKey)
+        .condition(TotpKey::F.local_user.equals(local_user_uuid))
+        .all()
+        .await?;
+
+    let mut is_valid = false;
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    let skew = GLOBAL.settings.get().totp_skew;
+    for key in keys {
+        let totp = totp::totp_from_binary(key.secret, skew)?;
+        let Some(step) = totp::matching_step(&totp, token, now, key.last_used_step as u64) else {
+            continue;
+        };
 
-const MFA_TIMEOUT: Duration = Duration::minutes(10);
+        // Consuming the step in the condition prevents concurrent requests from using the same token
+        let consumed = update!(guard.get_transaction(), TotpKey)
+            .condition(and![
+                TotpKey::F.uuid.equals(key.uuid),
+                TotpKey::F.last_used_step.less_than(step as i64)
+            ])
+            .set(TotpKey::F.last_used_step, step as i64)
+            .exec()
+            .await?;
+        if consumed > 0 {
+            is_valid = true;
+            break;
+        }
+        debug!("TOTP token has already been used");
+    }
+
+    guard.commit().await?;
+    Ok(is_valid)
+}
+
+/// Records that the session's user has just provided a second factor
+pub async fn set_mfa_verified(session: &Session) -> ApiResult<()> {
+    session
+        .insert(SESSION_MFA_VERIFIED, OffsetDateTime::now_utc())
+        .await?;
+    Ok(())
+}
 
-pub async fn get_partial_session_user(session: &Session) -> ApiResult<Uuid> {
+/// Checks whether the session's user has provided a second factor within `max_age`
+pub async fn is_mfa_verified(session: &Session, max_age: Duration) -> ApiResult<bool> {
+    let verified_at: Option<OffsetDateTime> = session.get(SESSION_MFA_VERIFIED).await?;
+    Ok(verified_at.is_some_and(|verified_at| OffsetDateTime::now_utc() - verified_at <= max_age))
+}
+
+/// Get the local user who has provided their first factor
+///
+/// Partial logins expire after the configured MFA timeout and when the user's password changes.
+pub async fn get_partial_session_user(
+    executor: impl Executor<'_>,
+    session: &Session,
+) -> ApiResult<Uuid> {
     let Some(PartiallyAuthedSessionUser {
         timestamp,
         local_user,
@@ -30,15 +138,48 @@ pub async fn get_partial_session_user(session: &Session) -> ApiResult<Uuid> {
         return Err(ApiError::Unauthenticated);
     };
 
-    if OffsetDateTime::now_utc() - timestamp > MFA_TIMEOUT {
+    if OffsetDateTime::now_utc() - timestamp > GLOBAL.settings.get().mfa_timeout {
         trace!("{PARTIALLY_AUTHED_SESSION_USER} expired");
         return Err(ApiError::Unauthenticated);
     }
 
+    let password_changed_at = query!(executor, (LocalUser::F.password_changed_at,))
+        .condition(LocalUser::F.uuid.equals(local_user))
+        .optional()
+        .await?
+        .ok_or(ApiError::Unauthenticated)?
+        .0;
+    if is_password_changed_since(password_changed_at, timestamp) {
+        trace!("The password changed after {PARTIALLY_AUTHED_SESSION_USER} was set");
+        return Err(ApiError::Unauthenticated);
+    }
+
     Ok(local_user)
 }
 
+/// Checks whether a password changed at `changed_at` has been changed after `timestamp`
+///
+/// `None` means the password hasn't been changed since the user was created.
+fn is_password_changed_since(
+    changed_at: Option<OffsetDateTime>,
+    timestamp: OffsetDateTime,
+) -> bool {
+    changed_at.is_some_and(|changed_at| changed_at > timestamp)
+}
+
+/// Stores a local user who has provided their first factor in the session
+///
+/// The login has to be completed using [`set_session_user`] within the configured MFA timeout.
 pub async fn set_partial_session_user(session: &Session, local_user_uuid: Uuid) -> ApiResult<()> {
+    set_partial_session_user_with(session, local_user_uuid, GLOBAL.settings.get().mfa_timeout).await
+}
+
+/// Implementation of [`set_partial_session_user`] with a given MFA timeout
+async fn set_partial_session_user_with(
+    session: &Session,
+    local_user_uuid: Uuid,
+    mfa_timeout: Duration,
+) -> ApiResult<()> {
     session
         .insert(
             PARTIALLY_AUTHED_SESSION_USER,
@@ -48,43 +189,146 @@ pub async fn set_partial_session_user(session: &Session, local_user_uuid: Uuid)
             },
         )
         .await?;
+    // Don't let the cookie outlive the partial login,
+    // `set_logged_in` restores the regular expiry
+    session.set_expiry(Some(Expiry::AtDateTime(
+        OffsetDateTime::now_utc() + mfa_timeout,
+    )));
     Ok(())
 }
 
+/// Completes a local user's login
+///
+/// See [`set_logged_in`].
 pub async fn set_session_user(
     executor: impl Executor<'_>,
     session: &Session,
     local_user_uuid: Uuid,
+    device: &DeviceInfo,
 ) -> ApiResult<()> {
     let mut guard = executor.ensure_transaction().await?;
 
-    let Some((ForeignModelByField::Key(user_uuid),)) =
-        query!(guard.get_transaction(), (LocalUser::F.user,))
-            .condition(LocalUser::F.uuid.equals(local_user_uuid))
-            .optional()
-            .await?
+    let Some((ForeignModelByField::Key(user_uuid), enabled)) = query!(
+        guard.get_transaction(),
+        (LocalUser::F.user, LocalUser::F.user.enabled)
+    )
+    .condition(LocalUser::F.uuid.equals(local_user_uuid))
+    .optional()
+    .await?
     else {
         return Err(ApiError::Unauthenticated);
     };
+    if !enabled {
+        trace!("User {user_uuid} is disabled");
+        return Err(ApiError::Unauthenticated);
+    }
 
     session
         .remove::<serde::de::IgnoredAny>(PARTIALLY_AUTHED_SESSION_USER)
         .await?;
-    session.insert(SESSION_USER, user_uuid).await?;
-    session.save().await?;
-
-    let Some(id) = session.id() else {
-        return Err(ApiError::new_internal_server_error("No ID in session"));
-    };
-    update!(guard.get_transaction(), models::Session)
-        .condition(models::Session::F.id.equals(id.to_string()))
-        .set(
-            models::Session::F.user,
-            Some(ForeignModelByField::Key(user_uuid)),
-        )
-        .exec()
-        .await?;
+    set_logged_in(guard.get_transaction(), session, user_uuid, device).await?;
 
     guard.commit().await?;
     Ok(())
 }
+
+/// Checks whether a WebAuthn authentication verified the user sufficiently
+///
+/// A key used as the only factor has to prove the user's presence and identity (PIN, biometrics, etc.)
+/// while a second factor only has to prove the possession of the key.
+pub fn is_user_verification_sufficient(passwordless: bool, user_verified: bool) -> bool {
+    user_verified || !passwordless
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use time::Duration;
+    use time::OffsetDateTime;
+    use tower_sessions::MemoryStore;
+    use tower_sessions::Session;
+    use uuid::Uuid;
+
+    use super::is_mfa_verified;
+    use super::is_password_changed_since;
+    use super::is_user_verification_sufficient;
+    use super::set_mfa_verified;
+    use super::set_partial_session_user_with;
+    use crate::http::session_keys::PartiallyAuthedSessionUser;
+    use crate::http::session_keys::PARTIALLY_AUTHED_SESSION_USER;
+    use crate::http::session_keys::SESSION_MFA_VERIFIED;
+
+    #[test]
+    fn passwordless_login_requires_user_verification() {
+        assert!(is_user_verification_sufficient(true, true));
+        assert!(!is_user_verification_sufficient(true, false));
+    }
+
+    #[test]
+    fn second_factor_does_not_require_user_verification() {
+        assert!(is_user_verification_sufficient(false, true));
+        assert!(is_user_verification_sufficient(false, false));
+    }
+
+    #[tokio::test]
+    async fn partial_login_expires_with_mfa_timeout() -> Result<(), Box<dyn std::error::Error>> {
+        let session = Session::new(None, Arc::new(MemoryStore::default()), None);
+        let local_user = Uuid::new_v4();
+        let mfa_timeout = Duration::minutes(5);
+
+        let before = OffsetDateTime::now_utc();
+        set_partial_session_user_with(&session, local_user, mfa_timeout).await?;
+        let after = OffsetDateTime::now_utc();
+
+        let expiry = session.expiry_date();
+        assert!(before + mfa_timeout <= expiry && expiry <= after + mfa_timeout);
+
+        let partial: Option<PartiallyAuthedSessionUser> =
+            session.get(PARTIALLY_AUTHED_SESSION_USER).await?;
+        assert_eq!(partial.map(|partial| partial.local_user), Some(local_user));
+        Ok(())
+    }
+
+    #[test]
+    fn partial_login_survives_unchanged_password() {
+        let started = OffsetDateTime::now_utc();
+        assert!(!is_password_changed_since(None, started));
+        assert!(!is_password_changed_since(
+            Some(started - Duration::minutes(1)),
+            started
+        ));
+    }
+
+    #[test]
+    fn partial_login_is_invalidated_by_password_change() {
+        let started = OffsetDateTime::now_utc();
+        assert!(is_password_changed_since(
+            Some(started + Duration::seconds(1)),
+            started
+        ));
+    }
+
+    #[tokio::test]
+    async fn mfa_is_verified_within_max_age() -> Result<(), Box<dyn std::error::Error>> {
+        let session = Session::new(None, Arc::new(MemoryStore::default()), None);
+        assert!(!is_mfa_verified(&session, Duration::minutes(10)).await?);
+
+        set_mfa_verified(&session).await?;
+        assert!(is_mfa_verified(&session, Duration::minutes(10)).await?);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn mfa_verification_expires_after_max_age() -> Result<(), Box<dyn std::error::Error>> {
+        let session = Session::new(None, Arc::new(MemoryStore::default()), None);
+        session
+            .insert(
+                SESSION_MFA_VERIFIED,
+                OffsetDateTime::now_utc() - Duration::minutes(11),
+            )
+            .await?;
+        assert!(!is_mfa_verified(&session, Duration::minutes(10)).await?);
+        Ok(())
+    }
+}
@@ -0,0 +1,213 @@
+//! Admin handlers for internal groups
+
+use axum::extract::Path;
+use rorm::and;
+use rorm::insert;
+use rorm::prelude::ForeignModelByField;
+use rorm::query;
+use rorm::FieldAccess;
+use rorm::Model;
+use serde_json::json;
+use serde_json::Value;
+use swaggapi::delete;
+use swaggapi::get;
+use swaggapi::post;
+use uuid::Uuid;
+
+use crate::global::GLOBAL;
+use crate::http::common::errors::ApiError;
+use crate::http::common::errors::ApiResult;
+use crate::http::common::schemas::FormResult;
+use crate::http::common::schemas::List;
+use crate::http::common::schemas::SingleUuid;
+use crate::http::extractors::api_json::ApiJson;
+use crate::http::extractors::session_user::SessionUser;
+use crate::http::handler_frontend::groups::schema::CreateGroupErrors;
+use crate::http::handler_frontend::groups::schema::CreateGroupRequest;
+use crate::http::handler_frontend::groups::schema::GroupMemberPath;
+use crate::http::handler_frontend::groups::schema::SimpleGroup;
+use crate::models::AuditAction;
+use crate::models::AuditLog;
+use crate::models::InternalGroup;
+use crate::models::InternalGroupInsert;
+use crate::models::User;
+use crate::models::UserGroups;
+use crate::models::UserGroupsInsert;
+use crate::models::UserRole;
+use crate::utils::schemars::SchemaDateTime;
+
+/// Create a new internal group
+#[post("/")]
+pub async fn create_group(
+    SessionUser { user: admin, .. }: SessionUser,
+    ApiJson(request): ApiJson<CreateGroupRequest>,
+) -> ApiResult<ApiJson<FormResult<SingleUuid, CreateGroupErrors>>> {
+    let mut tx = GLOBAL.db.start_transaction().await?;
+
+    let name = request.name.into_inner();
+    if query!(&mut tx, (InternalGroup::F.uuid,))
+        .condition(InternalGroup::F.name.equals(&name))
+        .optional()
+        .await?
+        .is_some()
+    {
+        return Ok(ApiJson(FormResult::err(CreateGroupErrors {
+            name_not_unique: true,
+        })));
+    }
+
+    let uuid = insert!(&mut tx, InternalGroup)
+        .return_primary_key()
+        .single(&InternalGroupInsert {
+            uuid: Uuid::new_v4(),
+            name: name.clone(),
+        })
+        .await?;
+    AuditLog::audit(
+        &mut tx,
+        Some(admin.uuid),
+        AuditAction::GroupCreated,
+        Some(uuid),
+        json!({ "name": name }),
+    )
+    .await?;
+
+    tx.commit().await?;
+    Ok(ApiJson(FormResult::ok(SingleUuid { uuid })))
+}
+
+/// Retrieve all internal groups
+#[get("/")]
+pub async fn get_all_groups() -> ApiResult<ApiJson<List<SimpleGroup>>> {
+    let list = query!(
+        &GLOBAL.db,
+        (
+            InternalGroup::F.uuid,
+            InternalGroup::F.name,
+            InternalGroup::F.created_at
+        )
+    )
+    .all()
+    .await?
+    .into_iter()
+    .map(|(uuid, name, created_at)| SimpleGroup {
+        uuid,
+        name,
+        created_at: SchemaDateTime(created_at),
+    })
+    .collect();
+    Ok(ApiJson(List { list }))
+}
+
+/// Delete an internal group
+///
+/// Its members lose their membership but are kept otherwise.
+#[delete("/:uuid")]
+pub async fn delete_group(
+    SessionUser { user: admin, .. }: SessionUser,
+    Path(SingleUuid { uuid }): Path<SingleUuid>,
+) -> ApiResult<()> {
+    let mut tx = GLOBAL.db.start_transaction().await?;
+
+    let deleted = rorm::delete!(&mut tx, InternalGroup)
+        .condition(InternalGroup::F.uuid.equals(uuid))
+        .await?;
+    if deleted > 0 {
+        AuditLog::audit(
+            &mut tx,
+            Some(admin.uuid),
+            AuditAction::GroupDeleted,
+            Some(uuid),
+            Value::Null,
+        )
+        .await?;
+    }
+
+    tx.commit().await?;
+    Ok(())
+}
+
+/// Add an internal user to a group
+///
+/// Adding a user who is already a member is a no-op.
+#[post("/:uuid/members/:user_uuid")]
+pub async fn add_group_member(
+    SessionUser { user: admin, .. }: SessionUser,
+    Path(GroupMemberPath { uuid, user_uuid }): Path<GroupMemberPath>,
+) -> ApiResult<()> {
+    let mut tx = GLOBAL.db.start_transaction().await?;
+
+    query!(&mut tx, (InternalGroup::F.uuid,))
+        .condition(InternalGroup::F.uuid.equals(uuid))
+        .optional()
+        .await?
+        .ok_or(ApiError::NotFound)?;
+
+    let (role,) = query!(&mut tx, (User::F.role,))
+        .condition(User::F.uuid.equals(user_uuid))
+        .optional()
+        .await?
+        .ok_or(ApiError::NotFound)?;
+    if role.key().parse::<UserRole>()? != UserRole::Internal {
+        return Err(ApiError::BadRequest);
+    }
+
+    let is_member = query!(&mut tx, (UserGroups::F.uuid,))
+        .condition(and![
+            UserGroups::F.user.equals(user_uuid),
+            UserGroups::F.group.equals(uuid)
+        ])
+        .optional()
+        .await?
+        .is_some();
+    if !is_member {
+        insert!(&mut tx, UserGroups)
+            .return_nothing()
+            .single(&UserGroupsInsert {
+                uuid: Uuid::new_v4(),
+                user: ForeignModelByField::Key(user_uuid),
+                group: ForeignModelByField::Key(uuid),
+            })
+            .await?;
+        AuditLog::audit(
+            &mut tx,
+            Some(admin.uuid),
+            AuditAction::GroupMemberAdded,
+            Some(uuid),
+            json!({ "user": user_uuid }),
+        )
+        .await?;
+    }
+
+    tx.commit().await?;
+    Ok(())
+}
+
+/// Remove a user from a group
+#[delete("/:uuid/members/:user_uuid")]
+pub async fn remove_group_member(
+    SessionUser { user: admin, .. }: SessionUser,
+    Path(GroupMemberPath { uuid, user_uuid }): Path<GroupMemberPath>,
+) -> ApiResult<()> {
+    let mut tx = GLOBAL.db.start_transaction().await?;
+
+    let deleted = rorm::delete!(&mut tx, UserGroups)
+        .condition(and![
+            UserGroups::F.user.equals(user_uuid),
+            UserGroups::F.group.equals(uuid)
+        ])
+        .await?;
+    if deleted > 0 {
+        AuditLog::audit(
+            &mut tx,
+            Some(admin.uuid),
+            AuditAction::GroupMemberRemoved,
+            Some(uuid),
+            json!({ "user": user_uuid }),
+        )
+        .await?;
+    }
+
+    tx.commit().await?;
+    Ok(())
+}
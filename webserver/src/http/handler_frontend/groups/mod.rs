@@ -0,0 +1,4 @@
+//! Everything regarding internal groups is defined in this module
+
+pub mod handler_admin;
+pub mod schema;
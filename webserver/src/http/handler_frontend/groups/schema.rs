@@ -0,0 +1,46 @@
+//! The schema for the internal groups
+
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::utils::checked_string::CheckedString;
+use crate::utils::schemars::SchemaDateTime;
+
+/// The request to create a new internal group
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct CreateGroupRequest {
+    /// The group's name
+    pub name: CheckedString<1, 255>,
+}
+
+/// The errors of the create group request
+#[derive(Debug, Copy, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct CreateGroupErrors {
+    /// The name is already taken by another group
+    pub name_not_unique: bool,
+}
+
+/// Simple representation of an internal group
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct SimpleGroup {
+    /// Primary key of the group
+    pub uuid: Uuid,
+
+    /// The group's name
+    pub name: String,
+
+    /// The point in time the group was created
+    pub created_at: SchemaDateTime,
+}
+
+/// The path parameters to address a member of a group
+#[derive(Debug, Copy, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct GroupMemberPath {
+    /// The group's uuid
+    pub uuid: Uuid,
+
+    /// The member's uuid
+    pub user_uuid: Uuid,
+}
@@ -2,6 +2,7 @@
 //!
 //! This included the router as well as the handlers and schemas
 
+use axum::error_handling::HandleErrorLayer;
 use axum::Extension;
 use axum::Router;
 use openidconnect::core::CoreClient;
@@ -9,11 +10,16 @@ use swaggapi::ApiContext;
 use swaggapi::SwaggapiPageBuilder;
 use tower::ServiceBuilder;
 
+use crate::http::common::errors::handle_overloaded;
 use crate::http::middlewares::auth_required::auth_required;
-use crate::http::middlewares::role_required::RoleRequiredLayer;
-use crate::models::UserRole;
+use crate::http::middlewares::csrf::csrf_protection;
+use crate::http::middlewares::permission_required::PermissionRequiredLayer;
+use crate::http::middlewares::rate_limit::rate_limit_logins;
+use crate::models::Permission;
 
+pub mod audit_log;
 pub mod auth;
+pub mod groups;
 pub mod oidc;
 pub mod user_invites;
 pub mod users;
@@ -29,7 +35,8 @@ pub fn initialize(oidc_client: Option<CoreClient>) -> ApiContext<Router> {
     let mut oidc_context = ApiContext::new()
         .tag("OpenId Connect")
         .handler(oidc::handler_common::oidc_login)
-        .handler(oidc::handler_common::finish_login);
+        .handler(oidc::handler_common::finish_login)
+        .handler(oidc::handler_common::get_oidc_access_token);
 
     if let Some(oidc_client) = oidc_client {
         oidc_context =
@@ -50,11 +57,44 @@ pub fn initialize(oidc_client: Option<CoreClient>) -> ApiContext<Router> {
                             .handler(auth::handler_common::get_login_flows)
                             .handler(auth::handler_common::login_webauthn)
                             .handler(auth::handler_common::login_password)
-                            .route_layer(ServiceBuilder::new().concurrency_limit(10))
+                            .route_layer(
+                                ServiceBuilder::new()
+                                    .layer(axum::middleware::from_fn(rate_limit_logins))
+                                    .layer(HandleErrorLayer::new(handle_overloaded))
+                                    .load_shed()
+                                    .concurrency_limit(10),
+                            )
+                            .handler(auth::handler_common::get_mfa_status)
                             .handler(auth::handler_common::verify_webauthn)
                             .handler(auth::handler_common::verify_totp)
                             .handler(auth::handler_common::complete_auth_webauthn)
-                            .handler(auth::handler_common::logout),
+                            .handler(auth::handler_common::login_magic_link)
+                            .handler(auth::handler_common::get_csrf_token)
+                            .handler(auth::handler_common::logout)
+                            // Logging in is protected as well to prevent login csrf
+                            .layer(
+                                ServiceBuilder::new()
+                                    .layer(axum::middleware::from_fn(csrf_protection)),
+                            ),
+                    )
+                    .nest(
+                        "/invites",
+                        ApiContext::new()
+                            .tag("User Invites")
+                            .handler(user_invites::handler_common::get_user_invite)
+                            .handler(user_invites::handler_common::accept_with_password)
+                            .handler(user_invites::handler_common::accept_with_webauthn)
+                            .handler(user_invites::handler_common::complete_invites_webauthn)
+                            .layer(
+                                ServiceBuilder::new()
+                                    .layer(axum::middleware::from_fn(csrf_protection)),
+                            ),
+                    )
+                    // Authenticates by itself to support tickets as alternative to the cookie
+                    .merge(
+                        ApiContext::new()
+                            .tag("Websocket")
+                            .handler(ws::handler_common::websocket),
                     )
                     .merge(
                         ApiContext::new()
@@ -63,11 +103,32 @@ pub fn initialize(oidc_client: Option<CoreClient>) -> ApiContext<Router> {
                                 ApiContext::new()
                                     .tag("users")
                                     .handler(users::handler_common::get_me)
-                                    .handler(users::handler_common::change_password),
+                                    .handler(users::handler_common::update_me)
+                                    .handler(users::handler_common::set_avatar)
+                                    .handler(users::handler_common::delete_avatar)
+                                    .handler(users::handler_common::get_avatar)
+                                    .handler(users::handler_common::resolve_users)
+                                    .handler(users::handler_common::get_auth_methods)
+                                    .handler(users::handler_common::create_ws_ticket)
+                                    .handler(users::handler_common::change_password)
+                                    .handler(users::handler_common::start_step_up_webauthn)
+                                    .handler(users::handler_common::complete_step_up_webauthn)
+                                    .handler(users::handler_common::change_email)
+                                    .handler(users::handler_common::confirm_email)
+                                    .handler(users::handler_common::create_totp_key)
+                                    .handler(users::handler_common::list_totp_keys)
+                                    .handler(users::handler_common::delete_totp_key)
+                                    .handler(users::handler_common::create_webauthn_key)
+                                    .handler(users::handler_common::complete_users_webauthn)
+                                    .handler(users::handler_common::list_webauthn_keys)
+                                    .handler(users::handler_common::delete_webauthn_key)
+                                    .handler(users::handler_common::resend_verification)
+                                    .handler(users::handler_common::verify_email),
                             )
                             .layer(
                                 ServiceBuilder::new()
-                                    .layer(axum::middleware::from_fn(auth_required)),
+                                    .layer(axum::middleware::from_fn(auth_required))
+                                    .layer(axum::middleware::from_fn(csrf_protection)),
                             ),
                     ),
             )
@@ -78,23 +139,78 @@ pub fn initialize(oidc_client: Option<CoreClient>) -> ApiContext<Router> {
                         "/users",
                         ApiContext::new()
                             .tag("Users")
-                            .handler(users::handler_admin::get_all_users)
-                            .handler(users::handler_admin::set_user_permissions)
-                            .handler(users::handler_admin::delete_user),
+                            .merge(
+                                ApiContext::new()
+                                    .handler(users::handler_admin::get_all_users)
+                                    .layer(ServiceBuilder::new().layer(
+                                        PermissionRequiredLayer::new(Permission::ViewUsers),
+                                    )),
+                            )
+                            .merge(
+                                ApiContext::new()
+                                    .handler(users::handler_admin::create_user)
+                                    .handler(users::handler_admin::set_user_permissions)
+                                    .handler(users::handler_admin::set_user_password)
+                                    .handler(users::handler_admin::get_user_methods)
+                                    .handler(users::handler_admin::create_magic_login_link)
+                                    .handler(users::handler_admin::set_user_enabled)
+                                    .handler(users::handler_admin::logout_user)
+                                    .handler(users::handler_admin::delete_user)
+                                    .layer(ServiceBuilder::new().layer(
+                                        PermissionRequiredLayer::new(Permission::ManageUsers),
+                                    )),
+                            ),
                     )
                     .nest(
                         "/user-invites",
                         ApiContext::new()
                             .tag("User Invites")
-                            .handler(user_invites::handler_common::get_user_invite)
-                            .handler(user_invites::handler_common::accept_with_password)
-                            .handler(user_invites::handler_common::accept_with_webauthn)
-                            .handler(user_invites::handler_common::complete_invites_webauthn),
+                            .handler(user_invites::handler_admin::create_user_invite)
+                            .handler(user_invites::handler_admin::get_all_user_invites)
+                            .handler(user_invites::handler_admin::renew_user_invite)
+                            .handler(user_invites::handler_admin::bulk_create_user_invites)
+                            .handler(user_invites::handler_admin::delete_user_invite)
+                            .layer(
+                                ServiceBuilder::new()
+                                    .layer(PermissionRequiredLayer::new(Permission::ManageInvites)),
+                            ),
                     )
-                    .layer(
-                        ServiceBuilder::new()
-                            .layer(RoleRequiredLayer::new(UserRole::Administrator)),
-                    ),
+                    .nest(
+                        "/groups",
+                        ApiContext::new()
+                            .tag("Groups")
+                            .handler(groups::handler_admin::create_group)
+                            .handler(groups::handler_admin::get_all_groups)
+                            .handler(groups::handler_admin::delete_group)
+                            .handler(groups::handler_admin::add_group_member)
+                            .handler(groups::handler_admin::remove_group_member)
+                            .layer(
+                                ServiceBuilder::new()
+                                    .layer(PermissionRequiredLayer::new(Permission::ManageGroups)),
+                            ),
+                    )
+                    .nest(
+                        "/ws",
+                        ApiContext::new()
+                            .tag("Websocket")
+                            .handler(ws::handler_admin::disconnect_all)
+                            .layer(
+                                ServiceBuilder::new().layer(PermissionRequiredLayer::new(
+                                    Permission::ManageWebsockets,
+                                )),
+                            ),
+                    )
+                    .nest(
+                        "/audit-log",
+                        ApiContext::new()
+                            .tag("Audit Log")
+                            .handler(audit_log::handler_admin::get_audit_log)
+                            .layer(
+                                ServiceBuilder::new()
+                                    .layer(PermissionRequiredLayer::new(Permission::ViewAuditLog)),
+                            ),
+                    )
+                    .layer(ServiceBuilder::new().layer(axum::middleware::from_fn(csrf_protection))),
             ),
     )
 }
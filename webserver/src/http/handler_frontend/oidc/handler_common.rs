@@ -7,10 +7,12 @@ use openidconnect::core::CoreAuthenticationFlow;
 use openidconnect::core::CoreClient;
 use openidconnect::reqwest::async_http_client;
 use openidconnect::AccessTokenHash;
+use openidconnect::AuthorizationCode;
 use openidconnect::CsrfToken;
 use openidconnect::Nonce;
 use openidconnect::OAuth2TokenResponse;
 use openidconnect::PkceCodeChallenge;
+use openidconnect::PkceCodeVerifier;
 use openidconnect::Scope;
 use openidconnect::TokenResponse;
 use rorm::insert;
@@ -27,15 +29,24 @@ use uuid::Uuid;
 use crate::global::GLOBAL;
 use crate::http::common::errors::ApiError;
 use crate::http::common::errors::ApiResult;
+use crate::http::extractors::api_json::ApiJson;
+use crate::http::extractors::device_info::DeviceInfo;
+use crate::http::extractors::session_user::SessionUser;
 use crate::http::handler_frontend::oidc::schema::AuthRequest;
 use crate::http::handler_frontend::oidc::schema::AuthState;
+use crate::http::handler_frontend::oidc::schema::OidcAccessTokenResponse;
+use crate::http::handler_frontend::oidc::schema::UserData;
 use crate::http::handler_frontend::users::schema::UserLanguage;
 use crate::http::handler_frontend::users::schema::UserPermissions;
+use crate::http::handler_frontend::users::utils::set_logged_in;
 use crate::http::session_keys::SESSION_OIDC_REQUEST;
-use crate::http::session_keys::SESSION_USER;
 use crate::models::OidcUser;
 use crate::models::User;
 use crate::utils::checked_string::CheckedString;
+use crate::utils::oidc_tokens::get_access_token;
+use crate::utils::oidc_tokens::store_tokens;
+use crate::utils::webhooks;
+use crate::utils::webhooks::WebhookEvent;
 
 /// Handler for OIDC's login endpoint
 #[get("/oidc-login")]
@@ -77,6 +88,7 @@ pub async fn finish_login(
     client: Extension<CoreClient>,
     Query(AuthRequest { code, state }): Query<AuthRequest>,
     session: Session,
+    device: DeviceInfo,
 ) -> ApiResult<Redirect> {
     // Get and remove the state generated in login
     let Some(AuthState {
@@ -95,42 +107,8 @@ pub async fn finish_login(
         return Err(ApiError::Unauthenticated);
     }
 
-    // Exchange the code with a token.
-    let token = client
-        .exchange_code(code.0)
-        .set_pkce_verifier(pkce_code_verifier)
-        .request_async(async_http_client)
-        .await
-        .inspect_err(|e| debug!("Exchange code failed: {e}"))
-        .map_err(|_| ApiError::Unauthenticated)?;
-
-    // Extract the ID token claims after verifying its authenticity and nonce.
-    let Some(id_token) = token.id_token() else {
-        debug!("ID token is missing");
-        return Err(ApiError::Unauthenticated);
-    };
-    let claims = id_token
-        .claims(&client.id_token_verifier(), &nonce)
-        .inspect_err(|e| debug!("ID token is invalid: {e}"))
-        .map_err(|_| ApiError::Unauthenticated)?;
-
-    // Verify the access token hash to ensure that the access token hasn't been substituted for
-    // another user's.
-    if let Some(expected_access_token_hash) = claims.access_token_hash() {
-        let actual_access_token_hash = AccessTokenHash::from_token(
-            token.access_token(),
-            &id_token
-                .signing_alg()
-                .inspect_err(|e| debug!("Retrieving signing alg failed: {e}"))
-                .map_err(|_| ApiError::Unauthenticated)?,
-        )
-        .inspect_err(|e| debug!("Creating access token hash failed: {e}"))
-        .map_err(|_| ApiError::Unauthenticated)?;
-        if actual_access_token_hash != *expected_access_token_hash {
-            debug!("The access token hash is invalid");
-            return Err(ApiError::Unauthenticated);
-        }
-    }
+    let UserData { token, claims } =
+        exchange_code(&client, code.0, pkce_code_verifier, &nonce).await?;
 
     debug!("Got claims: {claims:#?}");
 
@@ -153,42 +131,132 @@ pub async fn finish_login(
         debug!("Missing claim: email");
         return Err(ApiError::Unauthenticated);
     };
+    // The identity provider is trusted to have verified the mail, if it claims so
+    let email_verified = claims.email_verified().unwrap_or(false);
 
     let mut tx = GLOBAL.db.start_transaction().await?;
 
-    let user_uuid = if let Some(oidc_user) = query!(&mut tx, OidcUser)
+    let (oidc_user_uuid, user_uuid, created) =
+        if let Some((oidc_user_uuid, ForeignModelByField::Key(user_uuid), enabled)) = query!(
+            &mut tx,
+            (OidcUser::F.uuid, OidcUser::F.user, OidcUser::F.user.enabled)
+        )
         .condition(OidcUser::F.oidc_id.equals(&username))
         .optional()
         .await?
-    {
-        *oidc_user.user.key()
-    } else {
-        let user_uuid = User::create(
-            &mut tx,
-            CheckedString::new(mail)?,
-            CheckedString::new(display_name)?,
-            UserLanguage::EN,
-            UserPermissions::Internal,
-            None,
-        )
-        .await
-        .map_err(ApiError::new_internal_server_error)?;
-
-        insert!(&mut tx, OidcUser)
-            .return_nothing()
-            .single(&OidcUser {
-                uuid: Uuid::new_v4(),
-                user: ForeignModelByField::Key(user_uuid),
-                oidc_id: username,
-            })
+        {
+            if !enabled {
+                debug!("User {user_uuid} is disabled");
+                return Err(ApiError::Unauthenticated);
+            }
+            (oidc_user_uuid, user_uuid, false)
+        } else {
+            let user_uuid = User::create(
+                &mut tx,
+                CheckedString::new(mail)?,
+                CheckedString::new(display_name)?,
+                UserLanguage::EN,
+                UserPermissions::without_groups(GLOBAL.oidc_default_role),
+                email_verified,
+                None,
+            )
             .await?;
 
-        user_uuid
-    };
+            let oidc_user_uuid = insert!(&mut tx, OidcUser)
+                .return_primary_key()
+                .single(&OidcUser {
+                    uuid: Uuid::new_v4(),
+                    user: ForeignModelByField::Key(user_uuid),
+                    oidc_id: username,
+                    access_token: None,
+                    access_token_expires_at: None,
+                    refresh_token: None,
+                })
+                .await?;
 
-    session.insert(SESSION_USER, user_uuid).await?;
+            (oidc_user_uuid, user_uuid, true)
+        };
+
+    store_tokens(&mut tx, oidc_user_uuid, &token).await?;
+
+    set_logged_in(&mut tx, &session, user_uuid, &device).await?;
 
     tx.commit().await?;
 
+    if created {
+        webhooks::emit(WebhookEvent::UserCreated { user: user_uuid });
+    }
+
     Ok(Redirect::temporary("/"))
 }
+
+/// Retrieve an access token to call downstream APIs on the logged-in user's behalf
+///
+/// An expired access token is silently refreshed using the stored refresh token.
+#[get("/access-token")]
+#[instrument(skip_all, err)]
+pub async fn get_oidc_access_token(
+    SessionUser { user, .. }: SessionUser,
+    client: Extension<CoreClient>,
+) -> ApiResult<ApiJson<OidcAccessTokenResponse>> {
+    let oidc_user_uuid = query!(&GLOBAL.db, (OidcUser::F.uuid,))
+        .condition(OidcUser::F.user.equals(user.uuid))
+        .optional()
+        .await?;
+
+    let access_token = match oidc_user_uuid {
+        Some((oidc_user_uuid,)) => get_access_token(&client, oidc_user_uuid)
+            .await?
+            .map(|access_token| access_token.secret().clone()),
+        None => None,
+    };
+
+    Ok(ApiJson(OidcAccessTokenResponse { access_token }))
+}
+
+/// Exchanges the authorization code for a token and verifies the token's claims
+async fn exchange_code(
+    client: &CoreClient,
+    code: AuthorizationCode,
+    pkce_code_verifier: PkceCodeVerifier,
+    nonce: &Nonce,
+) -> ApiResult<UserData> {
+    let token = client
+        .exchange_code(code)
+        .set_pkce_verifier(pkce_code_verifier)
+        .request_async(async_http_client)
+        .await
+        .inspect_err(|e| debug!("Exchange code failed: {e}"))
+        .map_err(|_| ApiError::Unauthenticated)?;
+
+    // Extract the ID token claims after verifying its authenticity and nonce.
+    let Some(id_token) = token.id_token() else {
+        debug!("ID token is missing");
+        return Err(ApiError::Unauthenticated);
+    };
+    let claims = id_token
+        .claims(&client.id_token_verifier(), nonce)
+        .inspect_err(|e| debug!("ID token is invalid: {e}"))
+        .map_err(|_| ApiError::Unauthenticated)?;
+
+    // Verify the access token hash to ensure that the access token hasn't been substituted for
+    // another user's.
+    if let Some(expected_access_token_hash) = claims.access_token_hash() {
+        let actual_access_token_hash = AccessTokenHash::from_token(
+            token.access_token(),
+            &id_token
+                .signing_alg()
+                .inspect_err(|e| debug!("Retrieving signing alg failed: {e}"))
+                .map_err(|_| ApiError::Unauthenticated)?,
+        )
+        .inspect_err(|e| debug!("Creating access token hash failed: {e}"))
+        .map_err(|_| ApiError::Unauthenticated)?;
+        if actual_access_token_hash != *expected_access_token_hash {
+            debug!("The access token hash is invalid");
+            return Err(ApiError::Unauthenticated);
+        }
+    }
+
+    let claims = claims.clone();
+    Ok(UserData { token, claims })
+}
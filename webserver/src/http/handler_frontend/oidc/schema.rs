@@ -27,7 +27,9 @@ pub struct AuthRequest {
     pub state: SchemaString<CsrfToken>,
 }
 
-/// Data the [`super::handler_common::finish_login`] handler will store in the user's session
+/// The verified result of exchanging the authorization code in [`super::handler_common::finish_login`]
+///
+/// The claims identify the user while the tokens might be stored to call downstream APIs.
 #[derive(Serialize, Deserialize)]
 pub struct UserData {
     /// The oidc token
@@ -36,3 +38,14 @@ pub struct UserData {
     /// The OIDC claims
     pub claims: CoreIdTokenClaims,
 }
+
+/// The current access token issued by the oidc provider
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+pub struct OidcAccessTokenResponse {
+    /// The access token to call downstream APIs on the user's behalf
+    ///
+    /// This is `None` if storing tokens is disabled,
+    /// the user didn't log in using oidc
+    /// or the provider didn't issue a refresh token to replace an expired access token.
+    pub access_token: Option<String>,
+}
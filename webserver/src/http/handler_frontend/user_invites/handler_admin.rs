@@ -1,40 +1,86 @@
 //! Admin handlers for user invites
 
 use axum::extract::Path;
-use futures::TryStreamExt;
+use axum::extract::Query;
+use rorm::conditions::BoxedCondition;
+use rorm::conditions::Condition;
+use rorm::conditions::DynamicCollection;
+use rorm::db::transaction::Transaction;
 use rorm::query;
 use rorm::FieldAccess;
 use rorm::Model;
+use serde_json::json;
+use serde_json::Value;
 use swaggapi::delete;
 use swaggapi::get;
 use swaggapi::post;
+use time::Duration;
+use time::OffsetDateTime;
+use tracing::error;
+use uuid::Uuid;
 
 use crate::global::GLOBAL;
 use crate::http::common::errors::ApiError;
 use crate::http::common::errors::ApiResult;
+use crate::http::common::schemas::Collection;
 use crate::http::common::schemas::FormResult;
 use crate::http::common::schemas::List;
+use crate::http::common::schemas::PageParams;
 use crate::http::common::schemas::SingleUuid;
 use crate::http::extractors::api_json::ApiJson;
+use crate::http::extractors::session_user::SessionUser;
+use crate::http::handler_frontend::user_invites::schema::BulkCreateUserInviteColumn;
+use crate::http::handler_frontend::user_invites::schema::BulkCreateUserInviteResult;
+use crate::http::handler_frontend::user_invites::schema::BulkCreateUserInviteRow;
+use crate::http::handler_frontend::user_invites::schema::BulkCreateUserInvitesRequest;
 use crate::http::handler_frontend::user_invites::schema::CreateUserInviteErrors;
 use crate::http::handler_frontend::user_invites::schema::CreateUserInviteMailError;
 use crate::http::handler_frontend::user_invites::schema::CreateUserInviteRequest;
+use crate::http::handler_frontend::user_invites::schema::GetAllUserInvitesRequest;
+use crate::http::handler_frontend::user_invites::schema::RenewUserInviteRequest;
 use crate::http::handler_frontend::user_invites::schema::SimpleUserInvite;
+use crate::http::handler_frontend::user_invites::utils::invite_validity;
 use crate::http::handler_frontend::user_invites::utils::new_simple_user_invite;
+use crate::http::handler_frontend::user_invites::utils::new_simple_user_invites;
+use crate::http::handler_frontend::user_invites::utils::send_invite_mail;
+use crate::http::handler_frontend::users::schema::UserLanguage;
+use crate::http::handler_frontend::users::schema::UserPermissions;
+use crate::models::AuditAction;
+use crate::models::AuditLog;
 use crate::models::CreateUserInviteError;
 use crate::models::UserInvite;
+use crate::utils::checked_string::CheckedString;
+use crate::utils::schemars::SchemaDateTime;
+use crate::utils::search::like_pattern;
 
 /// Invite a new (local) user
 #[post("/")]
 pub async fn create_user_invite(
+    SessionUser { user: admin, .. }: SessionUser,
     ApiJson(request): ApiJson<CreateUserInviteRequest>,
 ) -> ApiResult<ApiJson<FormResult<SimpleUserInvite, CreateUserInviteErrors>>> {
+    let settings = GLOBAL.settings.get();
+    let Some(valid_for) = invite_validity(
+        request.valid_for_hours,
+        settings.invite_expiry,
+        settings.max_invite_expiry,
+    ) else {
+        return Ok(ApiJson(FormResult::err(CreateUserInviteErrors {
+            valid_for_hours: true,
+            ..Default::default()
+        })));
+    };
+
+    let mut tx = GLOBAL.db.start_transaction().await?;
+
     let invite = match UserInvite::create(
-        &GLOBAL.db,
+        &mut tx,
         request.mail,
         request.display_name,
         request.preferred_lang,
         request.permissions,
+        valid_for,
+        Some(admin.uuid),
     )
     .await
     {
@@ -42,35 +88,534 @@ pub async fn create_user_invite(
         Err(CreateUserInviteError::AlreadyUser) => {
             return Ok(ApiJson(FormResult::err(CreateUserInviteErrors {
                 mail: Some(CreateUserInviteMailError::AlreadyUser),
+                ..Default::default()
             })))
         }
         Err(CreateUserInviteError::AlreadyInvited) => {
             return Ok(ApiJson(FormResult::err(CreateUserInviteErrors {
                 mail: Some(CreateUserInviteMailError::AlreadyInvited),
+                ..Default::default()
+            })))
+        }
+        Err(CreateUserInviteError::InvalidDisplayName(_)) => {
+            return Ok(ApiJson(FormResult::err(CreateUserInviteErrors {
+                display_name: true,
+                ..Default::default()
+            })))
+        }
+        Err(CreateUserInviteError::DisplayNameOccupied) => {
+            return Ok(ApiJson(FormResult::err(CreateUserInviteErrors {
+                display_name_occupied: true,
+                ..Default::default()
             })))
         }
         Err(CreateUserInviteError::Database(error)) => return Err(error.into()),
     };
-    Ok(ApiJson(FormResult::ok(new_simple_user_invite(invite)?)))
+    AuditLog::audit(
+        &mut tx,
+        Some(admin.uuid),
+        AuditAction::InviteCreated,
+        Some(invite.uuid),
+        json!({ "mail": invite.email }),
+    )
+    .await?;
+    let invite = new_simple_user_invite(&mut tx, invite).await?;
+
+    tx.commit().await?;
+
+    send_invite_mail(&invite);
+    Ok(ApiJson(FormResult::ok(invite)))
+}
+
+/// Invite multiple (local) users from a CSV
+///
+/// Invalid rows don't abort the whole batch but are reported in the row's result.
+/// Every row is stored in its own transaction, so a failing row doesn't discard the others.
+#[post("/bulk")]
+pub async fn bulk_create_user_invites(
+    SessionUser { user: admin, .. }: SessionUser,
+    ApiJson(request): ApiJson<BulkCreateUserInvitesRequest>,
+) -> ApiResult<ApiJson<List<BulkCreateUserInviteRow>>> {
+    // Created invites are converted after all rows have been processed
+    let mut rows = Vec::new();
+    let mut reader = csv::ReaderBuilder::new()
+        .flexible(true)
+        .trim(csv::Trim::All)
+        .from_reader(request.csv.as_bytes());
+    for (index, record) in reader.records().enumerate() {
+        // The header is line 1
+        let line = record
+            .as_ref()
+            .ok()
+            .and_then(|record| record.position())
+            .map(|position| position.line())
+            .unwrap_or(index as u64 + 2);
+        let result = match record.ok().and_then(BulkRow::parse) {
+            None => Err(BulkCreateUserInviteResult::Malformed),
+            Some(Err(column)) => Err(BulkCreateUserInviteResult::InvalidColumn { column }),
+            Some(Ok(row)) => match create_bulk_invite(admin.uuid, row).await {
+                Ok(result) => result,
+                Err(error) => {
+                    error!(
+                        line,
+                        error.display = %error,
+                        error.debug = ?error,
+                        "Could not store a row of a bulk invite"
+                    );
+                    Err(BulkCreateUserInviteResult::Failed)
+                }
+            },
+        };
+        rows.push((line, result));
+    }
+
+    let (rows, invites): (Vec<_>, Vec<_>) = rows
+        .into_iter()
+        .map(|(line, result)| match result {
+            Ok(invite) => ((line, None), Some(invite)),
+            Err(result) => ((line, Some(result)), None),
+        })
+        .unzip();
+    let invites =
+        new_simple_user_invites(&GLOBAL.db, invites.into_iter().flatten().collect()).await?;
+
+    let mut invites = invites.into_iter();
+    let mut list = Vec::with_capacity(rows.len());
+    for (line, result) in rows {
+        let result = match result {
+            Some(result) => result,
+            None => {
+                let invite = invites.next().ok_or_else(|| {
+                    ApiError::new_internal_server_error("Conversion lost an invite")
+                })?;
+                send_invite_mail(&invite);
+                BulkCreateUserInviteResult::Created { invite }
+            }
+        };
+        list.push(BulkCreateUserInviteRow { line, result });
+    }
+    Ok(ApiJson(List { list }))
 }
 
-/// Retrieve all outstanding invites (expired or not)
+/// Stores a single row of [`bulk_create_user_invites`] in its own transaction
+///
+/// The outer error is a database error which only fails this row.
+async fn create_bulk_invite(
+    admin: Uuid,
+    row: BulkRow,
+) -> Result<Result<UserInvite, BulkCreateUserInviteResult>, rorm::Error> {
+    let mut tx = GLOBAL.db.start_transaction().await?;
+
+    let invite = match UserInvite::create(
+        &mut tx,
+        row.mail,
+        row.display_name,
+        row.preferred_lang,
+        row.permissions,
+        GLOBAL.settings.get().invite_expiry,
+        Some(admin),
+    )
+    .await
+    {
+        Ok(invite) => invite,
+        Err(CreateUserInviteError::AlreadyUser) => {
+            return Ok(Err(BulkCreateUserInviteResult::MailError {
+                error: CreateUserInviteMailError::AlreadyUser,
+            }))
+        }
+        Err(CreateUserInviteError::AlreadyInvited) => {
+            return Ok(Err(BulkCreateUserInviteResult::MailError {
+                error: CreateUserInviteMailError::AlreadyInvited,
+            }))
+        }
+        Err(CreateUserInviteError::InvalidDisplayName(_)) => {
+            return Ok(Err(BulkCreateUserInviteResult::InvalidColumn {
+                column: BulkCreateUserInviteColumn::DisplayName,
+            }))
+        }
+        Err(CreateUserInviteError::DisplayNameOccupied) => {
+            return Ok(Err(BulkCreateUserInviteResult::DisplayNameOccupied))
+        }
+        Err(CreateUserInviteError::Database(error)) => return Err(error),
+    };
+    AuditLog::audit(
+        &mut tx,
+        Some(admin),
+        AuditAction::InviteCreated,
+        Some(invite.uuid),
+        json!({ "mail": invite.email }),
+    )
+    .await?;
+
+    tx.commit().await?;
+    Ok(Ok(invite))
+}
+
+/// A parsed row of the bulk invite request's CSV
+struct BulkRow {
+    mail: CheckedString<1, 255>,
+    display_name: CheckedString<1, 255>,
+    preferred_lang: UserLanguage,
+    permissions: UserPermissions,
+}
+impl BulkRow {
+    /// Parses a CSV record
+    ///
+    /// Returns `None` if the record doesn't have exactly 4 columns.
+    fn parse(record: csv::StringRecord) -> Option<Result<Self, BulkCreateUserInviteColumn>> {
+        let [mail, display_name, preferred_lang, role] = [0, 1, 2, 3].map(|i| record.get(i));
+        let (Some(mail), Some(display_name), Some(preferred_lang), Some(role), None) =
+            (mail, display_name, preferred_lang, role, record.get(4))
+        else {
+            return None;
+        };
+
+        Some(Ok(Self {
+            mail: match CheckedString::new(mail.to_string()) {
+                Ok(mail) => mail,
+                Err(_) => return Some(Err(BulkCreateUserInviteColumn::Mail)),
+            },
+            display_name: match CheckedString::new(display_name.to_string()) {
+                Ok(display_name) => display_name,
+                Err(_) => return Some(Err(BulkCreateUserInviteColumn::DisplayName)),
+            },
+            preferred_lang: match preferred_lang.parse() {
+                Ok(preferred_lang) => preferred_lang,
+                Err(_) => return Some(Err(BulkCreateUserInviteColumn::PreferredLang)),
+            },
+            permissions: match role.parse() {
+                Ok(role) => UserPermissions::without_groups(role),
+                Err(_) => return Some(Err(BulkCreateUserInviteColumn::Role)),
+            },
+        }))
+    }
+}
+
+/// Retrieves a page of outstanding invites ordered from the newest to the oldest
+///
+/// Expired invites are kept until the cleanup task purges them and can still be renewed.
+/// They are only returned if `include_expired` is set.
 #[get("/")]
-pub async fn get_all_user_invites() -> ApiResult<ApiJson<List<SimpleUserInvite>>> {
-    let list = query!(&GLOBAL.db, UserInvite)
-        .stream()
-        .err_into::<ApiError>()
-        .and_then(|invite| async move { new_simple_user_invite(invite) })
-        .try_collect()
+pub async fn get_all_user_invites(
+    Query(page): Query<PageParams>,
+    Query(filter): Query<GetAllUserInvitesRequest>,
+) -> ApiResult<ApiJson<Collection<SimpleUserInvite, GetAllUserInvitesRequest>>> {
+    let mut tx = GLOBAL.db.start_transaction().await?;
+
+    let mail = filter.mail.as_deref().map(like_pattern);
+    let now = OffsetDateTime::now_utc();
+
+    let (total,) = query!(&mut tx, (UserInvite::F.uuid.count(),))
+        .condition(user_invites_filter(mail.as_deref(), now, &filter))
+        .one()
         .await?;
-    Ok(ApiJson(List { list }))
+
+    let invites = query!(&mut tx, UserInvite)
+        .condition(user_invites_filter(mail.as_deref(), now, &filter))
+        .order_desc(UserInvite::F.created_at)
+        .limit(page.limit())
+        .offset(page.offset)
+        .all()
+        .await?;
+    let items = new_simple_user_invites(&mut tx, invites).await?;
+
+    tx.commit().await?;
+    Ok(ApiJson(Collection::page(
+        items,
+        total as u64,
+        &page,
+        filter,
+    )))
+}
+
+/// Builds the condition selecting the invites matching a [`GetAllUserInvitesRequest`]
+///
+/// `mail` is expected to be the request's mail converted using [`like_pattern`].
+fn user_invites_filter<'a>(
+    mail: Option<&'a str>,
+    now: OffsetDateTime,
+    filter: &GetAllUserInvitesRequest,
+) -> DynamicCollection<BoxedCondition<'a>> {
+    let mut conditions = Vec::new();
+    if let Some(mail) = mail {
+        conditions.push(UserInvite::F.email.ilike(mail).boxed());
+    }
+    if !filter.include_expired {
+        conditions.push(UserInvite::F.expires_at.greater_or_equals(now).boxed());
+    }
+    DynamicCollection::and(conditions)
+}
+
+/// Renew an invite to expire after the configured duration from now
+///
+/// Optionally, the invite's link can be replaced invalidating the old one.
+#[post("/:uuid/renew")]
+pub async fn renew_user_invite(
+    SessionUser { user: admin, .. }: SessionUser,
+    Path(SingleUuid { uuid }): Path<SingleUuid>,
+    ApiJson(request): ApiJson<RenewUserInviteRequest>,
+) -> ApiResult<ApiJson<SimpleUserInvite>> {
+    let mut tx = GLOBAL.db.start_transaction().await?;
+
+    let invite = renew_invite(
+        &mut tx,
+        admin.uuid,
+        uuid,
+        GLOBAL.settings.get().invite_expiry,
+        request.rotate_link,
+    )
+    .await?;
+    let invite = new_simple_user_invite(&mut tx, invite).await?;
+
+    tx.commit().await?;
+    Ok(ApiJson(invite))
+}
+
+/// Implementation of [`renew_user_invite`] without committing the transaction
+///
+/// The renewal is recorded in the invite and the audit log.
+async fn renew_invite(
+    tx: &mut Transaction,
+    admin: Uuid,
+    uuid: Uuid,
+    valid_for: Duration,
+    rotate: bool,
+) -> ApiResult<UserInvite> {
+    let (previous_expires_at,) = query!(&mut *tx, (UserInvite::F.expires_at,))
+        .condition(UserInvite::F.uuid.equals(uuid))
+        .optional()
+        .await?
+        .ok_or(ApiError::NotFound)?;
+    let invite = UserInvite::renew(&mut *tx, uuid, valid_for, rotate, Some(admin))
+        .await?
+        .ok_or(ApiError::NotFound)?;
+    AuditLog::audit(
+        &mut *tx,
+        Some(admin),
+        AuditAction::InviteRenewed,
+        Some(invite.uuid),
+        json!({
+            "previous_uuid": uuid,
+            "previous_expires_at": SchemaDateTime(previous_expires_at),
+            "was_expired": previous_expires_at < OffsetDateTime::now_utc(),
+        }),
+    )
+    .await?;
+    Ok(invite)
 }
 
 /// Delete an outstanding invite
 #[delete("/:uuid")]
-pub async fn delete_user_invite(Path(SingleUuid { uuid }): Path<SingleUuid>) -> ApiResult<()> {
-    rorm::delete!(&GLOBAL.db, UserInvite)
+pub async fn delete_user_invite(
+    SessionUser { user: admin, .. }: SessionUser,
+    Path(SingleUuid { uuid }): Path<SingleUuid>,
+) -> ApiResult<()> {
+    let mut tx = GLOBAL.db.start_transaction().await?;
+
+    let deleted = rorm::delete!(&mut tx, UserInvite)
         .condition(UserInvite::F.uuid.equals(uuid))
         .await?;
+    if deleted > 0 {
+        AuditLog::audit(
+            &mut tx,
+            Some(admin.uuid),
+            AuditAction::InviteDeleted,
+            Some(uuid),
+            Value::Null,
+        )
+        .await?;
+    }
+
+    tx.commit().await?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use rorm::db::Executor;
+    use rorm::query;
+    use rorm::update;
+    use rorm::FieldAccess;
+    use rorm::Model;
+    use time::Duration;
+    use time::OffsetDateTime;
+    use uuid::Uuid;
+
+    use super::renew_invite;
+    use super::user_invites_filter;
+    use super::BulkRow;
+    use crate::http::common::errors::ApiError;
+    use crate::http::handler_frontend::user_invites::schema::BulkCreateUserInviteColumn;
+    use crate::http::handler_frontend::user_invites::schema::GetAllUserInvitesRequest;
+    use crate::http::handler_frontend::users::schema::UserLanguage;
+    use crate::http::handler_frontend::users::schema::UserPermissions;
+    use crate::models::AuditAction;
+    use crate::models::AuditLog;
+    use crate::models::UserInvite;
+    use crate::utils::checked_string::CheckedString;
+    use crate::utils::search::like_pattern;
+    use crate::utils::test_db;
+
+    fn parse(columns: &[&str]) -> Option<Result<BulkRow, BulkCreateUserInviteColumn>> {
+        BulkRow::parse(csv::StringRecord::from(columns.to_vec()))
+    }
+
+    #[test]
+    fn parses_a_valid_row() {
+        let Some(Ok(row)) = parse(&["alice@example.com", "Alice", "DE", "Internal"]) else {
+            panic!("The row should be valid");
+        };
+        assert_eq!(&*row.mail, "alice@example.com");
+        assert_eq!(&*row.display_name, "Alice");
+        assert!(matches!(row.preferred_lang, UserLanguage::DE));
+        assert!(matches!(row.permissions, UserPermissions::Internal { .. }));
+    }
+
+    #[test]
+    fn rejects_rows_with_a_wrong_number_of_columns() {
+        assert!(parse(&["alice@example.com", "Alice", "DE"]).is_none());
+        assert!(parse(&["alice@example.com", "Alice", "DE", "Internal", "extra"]).is_none());
+    }
+
+    #[test]
+    fn reports_the_invalid_column() {
+        assert!(matches!(
+            parse(&["", "Alice", "DE", "Internal"]),
+            Some(Err(BulkCreateUserInviteColumn::Mail))
+        ));
+        assert!(matches!(
+            parse(&["alice@example.com", "Alice", "XX", "Internal"]),
+            Some(Err(BulkCreateUserInviteColumn::PreferredLang))
+        ));
+        assert!(matches!(
+            parse(&["alice@example.com", "Alice", "DE", "Guest"]),
+            Some(Err(BulkCreateUserInviteColumn::Role))
+        ));
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a migrated database"]
+    async fn renewing_an_expired_invite_is_recorded() -> Result<(), Box<dyn std::error::Error>> {
+        let db = test_db::connect().await?;
+        let mut tx = db.start_transaction().await?;
+        let admin = test_db::create_user(&mut tx, "admin", UserPermissions::Administrator).await?;
+        let invite = UserInvite::create(
+            &mut tx,
+            CheckedString::new(format!("{}@test.invalid", Uuid::new_v4()))?,
+            CheckedString::new(format!("Invited-{}", Uuid::new_v4()))?,
+            UserLanguage::EN,
+            UserPermissions::Administrator,
+            Duration::days(1),
+            Some(admin),
+        )
+        .await?;
+        update!(&mut tx, UserInvite)
+            .condition(UserInvite::F.uuid.equals(invite.uuid))
+            .set(
+                UserInvite::F.expires_at,
+                OffsetDateTime::now_utc() - Duration::minutes(1),
+            )
+            .exec()
+            .await?;
+
+        let before = OffsetDateTime::now_utc();
+        let renewed = renew_invite(&mut tx, admin, invite.uuid, Duration::days(1), true).await?;
+        assert_ne!(renewed.uuid, invite.uuid);
+        assert!(renewed.expires_at > before);
+        assert!(renewed
+            .renewed_at
+            .is_some_and(|renewed_at| renewed_at >= before));
+        assert_eq!(
+            renewed
+                .renewed_by
+                .as_ref()
+                .map(|renewed_by| *renewed_by.key()),
+            Some(admin)
+        );
+
+        let entry = rorm::query!(&mut tx, AuditLog)
+            .condition(AuditLog::F.target.equals(renewed.uuid))
+            .one()
+            .await?;
+        assert_eq!(entry.actor, Some(admin));
+        assert_eq!(entry.action, AuditAction::InviteRenewed.to_string());
+        assert_eq!(entry.detail.0["previous_uuid"], invite.uuid.to_string());
+        assert_eq!(entry.detail.0["was_expired"], true);
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a migrated database"]
+    async fn renewing_an_unknown_invite_fails() -> Result<(), Box<dyn std::error::Error>> {
+        let db = test_db::connect().await?;
+        let mut tx = db.start_transaction().await?;
+        let admin = test_db::create_user(&mut tx, "admin", UserPermissions::Administrator).await?;
+
+        let result = renew_invite(&mut tx, admin, Uuid::new_v4(), Duration::days(1), false).await;
+        assert!(matches!(result, Err(ApiError::NotFound)));
+        Ok(())
+    }
+
+    /// The uuids of the invites listed for a mail search
+    async fn listed(
+        executor: impl Executor<'_>,
+        mail: &str,
+        include_expired: bool,
+    ) -> Result<Vec<Uuid>, rorm::Error> {
+        let filter = GetAllUserInvitesRequest {
+            include_expired,
+            mail: Some(mail.to_string()),
+        };
+        let mail = like_pattern(mail);
+        Ok(query!(executor, (UserInvite::F.uuid,))
+            .condition(user_invites_filter(
+                Some(&mail),
+                OffsetDateTime::now_utc(),
+                &filter,
+            ))
+            .all()
+            .await?
+            .into_iter()
+            .map(|(uuid,)| uuid)
+            .collect())
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a migrated database"]
+    async fn expired_invites_are_only_listed_on_request() -> Result<(), Box<dyn std::error::Error>>
+    {
+        let db = test_db::connect().await?;
+        let mut tx = db.start_transaction().await?;
+        let search = Uuid::new_v4().to_string();
+
+        let mut invites = Vec::new();
+        for _ in 0..2 {
+            let invite = UserInvite::create(
+                &mut tx,
+                CheckedString::new(format!("{search}-{}@test.invalid", Uuid::new_v4()))?,
+                CheckedString::new(format!("Invited-{}", Uuid::new_v4()))?,
+                UserLanguage::EN,
+                UserPermissions::Internal { groups: Vec::new() },
+                Duration::days(1),
+                None,
+            )
+            .await?;
+            invites.push(invite.uuid);
+        }
+        update!(&mut tx, UserInvite)
+            .condition(UserInvite::F.uuid.equals(invites[0]))
+            .set(
+                UserInvite::F.expires_at,
+                OffsetDateTime::now_utc() - Duration::minutes(1),
+            )
+            .exec()
+            .await?;
+
+        assert_eq!(listed(&mut tx, &search, false).await?, [invites[1]]);
+        let mut all = listed(&mut tx, &search.to_uppercase(), true).await?;
+        all.sort();
+        invites.sort();
+        assert_eq!(all, invites);
+        Ok(())
+    }
+}
@@ -12,8 +12,8 @@ use swaggapi::utils::SchemalessJson;
 use time::OffsetDateTime;
 use tower_sessions::Session;
 use tracing::debug;
+use tracing::warn;
 use uuid::Uuid;
-use webauthn_rs::prelude::CreationChallengeResponse;
 use webauthn_rs::prelude::RegisterPublicKeyCredential;
 
 use crate::global::GLOBAL;
@@ -21,11 +21,16 @@ use crate::http::common::errors::ApiError;
 use crate::http::common::errors::ApiResult;
 use crate::http::common::schemas::SingleUuid;
 use crate::http::extractors::api_json::ApiJson;
+use crate::http::extractors::device_info::DeviceInfo;
 use crate::http::handler_frontend::user_invites::schema::AcceptWithPwRequest;
 use crate::http::handler_frontend::user_invites::schema::AcceptWithWARequest;
 use crate::http::handler_frontend::user_invites::schema::GetUserInviteResponse;
+use crate::http::handler_frontend::user_invites::schema::UserInviteStatus;
 use crate::http::handler_frontend::user_invites::utils::new_simple_user_invite;
+use crate::http::handler_frontend::users::schema::UserLanguage;
+use crate::http::handler_frontend::users::utils::send_email_verification;
 use crate::http::handler_frontend::users::utils::set_logged_in;
+use crate::http::handler_frontend::users::utils::start_email_verification;
 use crate::http::session_keys::WebAuthnAccept;
 use crate::http::session_keys::SESSION_WEBAUTHN_ACCEPT;
 use crate::models::LocalUser;
@@ -37,7 +42,14 @@ use crate::models::WebAuthnKey;
 use crate::models::WebAuthnKeyInsert;
 use crate::utils::checked_string::CheckedString;
 use crate::utils::hashing::hash_pw;
+use crate::utils::password_policy::meets_password_policy;
+use crate::utils::webauthn::get_registered_credentials;
+use crate::utils::webauthn::is_credential_registered;
+use crate::utils::webauthn::is_enrollment_expired;
+use crate::utils::webauthn::StartWebAuthnRegisterResponse;
 use crate::utils::webauthn::WebAuthnRegisterResult;
+use crate::utils::webhooks;
+use crate::utils::webhooks::WebhookEvent;
 
 /// Gets an invitation's details to display to the user before accepting
 #[get("/:uuid")]
@@ -50,12 +62,10 @@ pub async fn get_user_invite(
             .optional()
             .await?
         {
-            if invite.expires_at < OffsetDateTime::now_utc() {
-                GetUserInviteResponse::Expired
-            } else {
-                GetUserInviteResponse::Valid {
-                    invite: new_simple_user_invite(invite)?,
-                }
+            let invite = new_simple_user_invite(&GLOBAL.db, invite).await?;
+            match invite.status {
+                UserInviteStatus::Active => GetUserInviteResponse::Valid { invite },
+                UserInviteStatus::Expired => GetUserInviteResponse::Expired,
             }
         } else {
             GetUserInviteResponse::NotFound
@@ -67,6 +77,7 @@ pub async fn get_user_invite(
 #[post("/accept/:uuid/with-password")]
 pub async fn accept_with_password(
     session: Session,
+    device: DeviceInfo,
     Path(SingleUuid { uuid }): Path<SingleUuid>,
     ApiJson(request): ApiJson<AcceptWithPwRequest>,
 ) -> ApiResult<()> {
@@ -76,23 +87,32 @@ pub async fn accept_with_password(
         .condition(UserInvite::F.uuid.equals(uuid))
         .optional()
         .await?
-        .ok_or(ApiError::BadRequest)?;
+        .ok_or(ApiError::NotFound)?;
     if invite.expires_at < OffsetDateTime::now_utc() {
         return Err(ApiError::BadRequest);
     }
+    if !meets_password_policy(&request.password) {
+        debug!("The password doesn't meet the password policy");
+        return Err(ApiError::BadRequest);
+    }
     rorm::delete!(&mut tx, UserInvite).single(&invite).await?;
 
+    let verification_required = GLOBAL.settings.get().email_verification_required;
+    let mail = invite.email.clone();
+    let display_name = invite.display_name.clone();
+    let lang = UserLanguage::from_stored(&invite.preferred_lang);
     let user_uuid = User::create(
         &mut tx,
         CheckedString::new(invite.email)?,
         CheckedString::new(invite.display_name)?,
-        invite.preferred_lang.parse()?,
+        lang,
         invite.permissions.0,
+        !verification_required,
         None,
     )
-    .await
-    // CreateUserError::MailOccupied shouldn't happen since the invite should check its uniqueness upon creation
-    .map_err(ApiError::new_internal_server_error)?;
+    // The invite checks the mail's uniqueness upon creation,
+    // but a user might have claimed it since then through another way (e.g. oidc)
+    .await?;
 
     insert!(&mut tx, LocalUser)
         .return_nothing()
@@ -103,9 +123,25 @@ pub async fn accept_with_password(
         })
         .await?;
 
-    set_logged_in(&mut tx, &session, user_uuid).await?;
+    // Holding the invite link doesn't prove the control over the mail
+    let verification_link = if verification_required {
+        Some(start_email_verification(&mut tx, user_uuid, &mail).await?)
+    } else {
+        None
+    };
+
+    set_logged_in(&mut tx, &session, user_uuid, &device).await?;
 
     tx.commit().await?;
+
+    webhooks::emit(WebhookEvent::UserCreated { user: user_uuid });
+    webhooks::emit(WebhookEvent::InviteAccepted {
+        invite: uuid,
+        user: user_uuid,
+    });
+    if let Some(link) = verification_link {
+        send_email_verification(mail, display_name, lang, link);
+    }
     Ok(())
 }
 
@@ -115,25 +151,34 @@ pub async fn accept_with_webauthn(
     session: Session,
     Path(SingleUuid { uuid }): Path<SingleUuid>,
     ApiJson(request): ApiJson<AcceptWithWARequest>,
-) -> ApiResult<SchemalessJson<CreationChallengeResponse>> {
+) -> ApiResult<StartWebAuthnRegisterResponse> {
     let invite = query!(&GLOBAL.db, UserInvite)
         .condition(UserInvite::F.uuid.equals(uuid))
         .optional()
         .await?
-        .ok_or(ApiError::BadRequest)?;
+        .ok_or(ApiError::NotFound)?;
+    let ca_list = GLOBAL.webauthn_attestation_ca_list.get();
+    if ca_list.is_empty() {
+        warn!("Can't register a login key without attestation CAs");
+        return Ok(StartWebAuthnRegisterResponse::Rejected(
+            WebAuthnRegisterResult::AttestationUnavailable,
+        ));
+    }
+
     let user_uuid = Uuid::new_v4();
     let (challenge, state) = GLOBAL.webauthn.start_attested_passkey_registration(
         user_uuid,
         &invite.email,
         &invite.display_name,
-        None,
-        GLOBAL.webauthn_attestation_ca_list.clone(),
+        Some(get_registered_credentials(&GLOBAL.db).await?),
+        ca_list,
         None,
     )?;
     session
         .insert(
             SESSION_WEBAUTHN_ACCEPT,
             WebAuthnAccept {
+                timestamp: OffsetDateTime::now_utc(),
                 label: request.label,
                 user_uuid,
                 invite_uuid: uuid,
@@ -142,16 +187,18 @@ pub async fn accept_with_webauthn(
         )
         .await?;
 
-    Ok(SchemalessJson(challenge))
+    Ok(StartWebAuthnRegisterResponse::Challenge(challenge))
 }
 
 /// Complete the webauthn challenge for accepting the invite by registering a key
 #[post("/complete-webauthn")]
 pub async fn complete_invites_webauthn(
     session: Session,
+    device: DeviceInfo,
     SchemalessJson(request): SchemalessJson<RegisterPublicKeyCredential>,
 ) -> ApiResult<ApiJson<WebAuthnRegisterResult>> {
     let WebAuthnAccept {
+        timestamp,
         label,
         user_uuid,
         invite_uuid,
@@ -160,11 +207,15 @@ pub async fn complete_invites_webauthn(
         .remove(SESSION_WEBAUTHN_ACCEPT)
         .await?
         .ok_or(ApiError::BadRequest)?;
-    let passkey = match GLOBAL
+    if is_enrollment_expired(timestamp) {
+        debug!("{SESSION_WEBAUTHN_ACCEPT} expired");
+        return Err(ApiError::BadRequest);
+    }
+    let key = match GLOBAL
         .webauthn
         .finish_attested_passkey_registration(&request, &state)
     {
-        Ok(passkey) => passkey,
+        Ok(passkey) => MaybeAttestedPasskey::Attested(passkey),
         Err(error) => {
             return if let Some(result) = WebAuthnRegisterResult::parse(&error) {
                 Ok(ApiJson(result))
@@ -174,30 +225,45 @@ pub async fn complete_invites_webauthn(
             }
         }
     };
+    // The key is the user's only way to log in
+    if !key.user_verified() {
+        debug!("WebAuthn key for logging in was registered without user verification");
+        return Ok(ApiJson(WebAuthnRegisterResult::UserNotVerified));
+    }
 
     let mut tx = GLOBAL.db.start_transaction().await?;
 
+    if is_credential_registered(&mut tx, key.cred_id()).await? {
+        debug!("WebAuthn credential is already registered");
+        return Ok(ApiJson(WebAuthnRegisterResult::RejectedDevice));
+    }
+
     let invite = query!(&mut tx, UserInvite)
         .condition(UserInvite::F.uuid.equals(invite_uuid))
         .optional()
         .await?
-        .ok_or(ApiError::BadRequest)?;
+        .ok_or(ApiError::NotFound)?;
     if invite.expires_at < OffsetDateTime::now_utc() {
         return Err(ApiError::BadRequest);
     }
     rorm::delete!(&mut tx, UserInvite).single(&invite).await?;
 
+    let verification_required = GLOBAL.settings.get().email_verification_required;
+    let mail = invite.email.clone();
+    let display_name = invite.display_name.clone();
+    let lang = UserLanguage::from_stored(&invite.preferred_lang);
     User::create(
         &mut tx,
         CheckedString::new(invite.email)?,
         CheckedString::new(invite.display_name)?,
-        invite.preferred_lang.parse()?,
+        lang,
         invite.permissions.0,
+        !verification_required,
         Some(user_uuid),
     )
-    .await
-    // CreateUserError::MailOccupied shouldn't happen since the invite should check its uniqueness upon creation
-    .map_err(ApiError::new_internal_server_error)?;
+    // The invite checks the mail's uniqueness upon creation,
+    // but a user might have claimed it since then through another way (e.g. oidc)
+    .await?;
 
     let local_user_uuid = insert!(&mut tx, LocalUser)
         .return_primary_key()
@@ -213,12 +279,29 @@ pub async fn complete_invites_webauthn(
             uuid: Uuid::new_v4(),
             local_user: ForeignModelByField::Key(local_user_uuid),
             label: label.into_inner(),
-            key: MaybeAttestedPasskey::Attested(passkey).into(),
+            cred_id: Some(key.cred_id().to_vec()),
+            key: key.into(),
         })
         .await?;
 
-    set_logged_in(&mut tx, &session, user_uuid).await?;
+    // Holding the invite link doesn't prove the control over the mail
+    let verification_link = if verification_required {
+        Some(start_email_verification(&mut tx, user_uuid, &mail).await?)
+    } else {
+        None
+    };
+
+    set_logged_in(&mut tx, &session, user_uuid, &device).await?;
 
     tx.commit().await?;
+
+    webhooks::emit(WebhookEvent::UserCreated { user: user_uuid });
+    webhooks::emit(WebhookEvent::InviteAccepted {
+        invite: invite_uuid,
+        user: user_uuid,
+    });
+    if let Some(link) = verification_link {
+        send_email_verification(mail, display_name, lang, link);
+    }
     Ok(ApiJson(WebAuthnRegisterResult::Ok))
 }
@@ -3,6 +3,7 @@
 use schemars::JsonSchema;
 use serde::Deserialize;
 use serde::Serialize;
+use time::OffsetDateTime;
 use uuid::Uuid;
 
 use crate::http::handler_frontend::users::schema::UserLanguage;
@@ -37,6 +38,63 @@ pub struct CreateUserInviteRequest {
     ///
     /// Combination of a `role` and role specific `groups`
     pub permissions: UserPermissions,
+
+    /// The number of hours the invite should be valid for
+    ///
+    /// Defaults to the server's configured duration and may not exceed its configured maximum.
+    pub valid_for_hours: Option<u32>,
+}
+
+/// The request to invite multiple (local) users at once
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct BulkCreateUserInvitesRequest {
+    /// CSV with the columns `mail`, `display_name`, `preferred_lang` and `role`
+    ///
+    /// The first line is expected to be a header and is skipped.
+    pub csv: String,
+}
+
+/// The outcome of a single row of the bulk invite request
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct BulkCreateUserInviteRow {
+    /// The row's line in the CSV (starting at 1)
+    pub line: u64,
+
+    /// The row's outcome
+    pub result: BulkCreateUserInviteResult,
+}
+
+/// The outcome of a single row of the bulk invite request
+///
+/// `Failed` rows couldn't be stored because of a server error, retrying them might succeed.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(tag = "result")]
+#[allow(missing_docs)]
+pub enum BulkCreateUserInviteResult {
+    Created { invite: SimpleUserInvite },
+    MailError { error: CreateUserInviteMailError },
+    InvalidColumn { column: BulkCreateUserInviteColumn },
+    DisplayNameOccupied,
+    Malformed,
+    Failed,
+}
+
+/// The columns of the bulk invite request's CSV
+#[derive(Debug, Copy, Clone, Serialize, Deserialize, JsonSchema)]
+#[allow(missing_docs)]
+pub enum BulkCreateUserInviteColumn {
+    Mail,
+    DisplayName,
+    PreferredLang,
+    Role,
+}
+
+/// The request to renew an invite
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct RenewUserInviteRequest {
+    /// Replace the invite's uuid, invalidating the previously issued link
+    #[serde(default)]
+    pub rotate_link: bool,
 }
 
 /// The errors of the invite user request
@@ -44,6 +102,15 @@ pub struct CreateUserInviteRequest {
 pub struct CreateUserInviteErrors {
     /// The `mail` is not unique
     pub mail: Option<CreateUserInviteMailError>,
+
+    /// The `display_name` is rejected by the server's display name policy
+    pub display_name: bool,
+
+    /// The `display_name` is already used by another user or open invite
+    pub display_name_occupied: bool,
+
+    /// The `valid_for_hours` is zero or exceeds the configured maximum
+    pub valid_for_hours: bool,
 }
 
 /// Reason why `mail` in the invite user request failed
@@ -54,6 +121,38 @@ pub enum CreateUserInviteMailError {
     AlreadyInvited,
 }
 
+/// The state of an outstanding invite
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub enum UserInviteStatus {
+    /// The invite can be accepted
+    Active,
+    /// The invite has expired but hasn't been purged yet
+    ///
+    /// It can't be accepted unless it is renewed.
+    Expired,
+}
+impl UserInviteStatus {
+    /// The status of an invite expiring at `expires_at`
+    pub fn at(expires_at: OffsetDateTime, now: OffsetDateTime) -> Self {
+        if expires_at < now {
+            Self::Expired
+        } else {
+            Self::Active
+        }
+    }
+}
+
+/// The filters for retrieving all user invites
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct GetAllUserInvitesRequest {
+    /// Also return invites which have expired but haven't been purged yet
+    #[serde(default)]
+    pub include_expired: bool,
+
+    /// Only return invites whose mail contains this string (case-insensitive)
+    pub mail: Option<String>,
+}
+
 /// An outstanding user invite
 #[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct SimpleUserInvite {
@@ -80,8 +179,24 @@ pub struct SimpleUserInvite {
     /// Until when is the invite valid
     pub expires_at: SchemaDateTime,
 
+    /// Whether the invite can still be accepted
+    pub status: UserInviteStatus,
+
+    /// The admin who created the invite
+    ///
+    /// `None` if the invite was created using the cli or its creator has been deleted since.
+    pub created_by: Option<InviteCreator>,
+
     /// When was this invite created
     pub created_at: SchemaDateTime,
+
+    /// When was this invite last renewed
+    pub renewed_at: Option<SchemaDateTime>,
+
+    /// The admin who last renewed the invite
+    ///
+    /// `None` if the invite hasn't been renewed or its renewer has been deleted since.
+    pub renewed_by: Option<InviteCreator>,
 }
 
 /// The request to accept an invitation by providing a password
@@ -97,3 +212,49 @@ pub struct AcceptWithWARequest {
     /// A user defined label to identify the login key
     pub label: CheckedString<1, 255>,
 }
+
+/// The admin who created or renewed an invite
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct InviteCreator {
+    /// The admin's primary key
+    pub uuid: Uuid,
+
+    /// The admin's display name
+    pub display_name: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use time::Duration;
+    use time::OffsetDateTime;
+
+    use super::UserInviteStatus;
+
+    #[test]
+    fn invite_is_active_until_it_expires() {
+        let now = OffsetDateTime::now_utc();
+        assert_eq!(
+            UserInviteStatus::at(now + Duration::hours(1), now),
+            UserInviteStatus::Active
+        );
+        assert_eq!(UserInviteStatus::at(now, now), UserInviteStatus::Active);
+    }
+
+    #[test]
+    fn invite_is_expired_afterward() {
+        let now = OffsetDateTime::now_utc();
+        assert_eq!(
+            UserInviteStatus::at(now - Duration::seconds(1), now),
+            UserInviteStatus::Expired
+        );
+    }
+
+    #[test]
+    fn status_is_serialized_as_its_name() -> Result<(), serde_json::Error> {
+        assert_eq!(
+            serde_json::to_string(&UserInviteStatus::Expired)?,
+            "\"Expired\""
+        );
+        Ok(())
+    }
+}
@@ -1,20 +1,165 @@
+//! Utilities for working with [`user_invites::schema`](super::schema)
+
+use std::collections::HashMap;
+use std::collections::HashSet;
+
+use rorm::conditions::DynamicCollection;
+use rorm::db::Executor;
+use rorm::prelude::ForeignModel;
+use rorm::query;
+use rorm::FieldAccess;
+use rorm::Model;
+use time::Duration;
+use time::OffsetDateTime;
+use uuid::Uuid;
+
 use crate::global::GLOBAL;
+use crate::http::common::errors::ApiError;
 use crate::http::common::errors::ApiResult;
+use crate::http::handler_frontend::user_invites::schema::InviteCreator;
 use crate::http::handler_frontend::user_invites::schema::SimpleUserInvite;
+use crate::http::handler_frontend::user_invites::schema::UserInviteStatus;
+use crate::http::handler_frontend::users::schema::UserLanguage;
+use crate::models::User;
 use crate::models::UserInvite;
 use crate::utils::checked_string::CheckedString;
 use crate::utils::links::new_user_invite_link;
 use crate::utils::schemars::SchemaDateTime;
 
-pub fn new_simple_user_invite(invite: UserInvite) -> ApiResult<SimpleUserInvite> {
-    Ok(SimpleUserInvite {
-        uuid: invite.uuid,
-        link: new_user_invite_link(&GLOBAL.origin, invite.uuid),
-        mail: CheckedString::new(invite.email)?,
-        display_name: CheckedString::new(invite.display_name)?,
-        preferred_lang: invite.preferred_lang.parse()?,
-        permissions: invite.permissions.0,
-        expires_at: SchemaDateTime(invite.expires_at),
-        created_at: SchemaDateTime(invite.created_at),
-    })
+/// Sends the invite's link to the invitee in the background
+///
+/// Does nothing if no mailer has been configured.
+pub fn send_invite_mail(invite: &SimpleUserInvite) {
+    let Some(mailer) = GLOBAL.mailer.as_ref() else {
+        return;
+    };
+
+    let mail = invite.mail.to_string();
+    let display_name = invite.display_name.to_string();
+    let lang = invite.preferred_lang;
+    let link = invite.link.clone();
+    tokio::spawn(async move { mailer.send_invite(&mail, &display_name, lang, &link).await });
+}
+
+/// Determines how long a new invite is valid for
+///
+/// Without requested `valid_for_hours`, the configured `default` is used.
+/// Returns `None` if the requested hours are zero or exceed the configured `max`.
+pub fn invite_validity(
+    valid_for_hours: Option<u32>,
+    default: Duration,
+    max: Duration,
+) -> Option<Duration> {
+    match valid_for_hours {
+        None => Some(default),
+        Some(0) => None,
+        Some(hours) => Some(Duration::hours(hours.into())).filter(|valid_for| *valid_for <= max),
+    }
+}
+
+/// Converts a `UserInvite` model into a `SimpleUserInvite` schema.
+///
+/// Use [`new_simple_user_invites`] to convert several invites at once.
+pub async fn new_simple_user_invite(
+    executor: impl Executor<'_>,
+    invite: UserInvite,
+) -> ApiResult<SimpleUserInvite> {
+    new_simple_user_invites(executor, vec![invite])
+        .await?
+        .pop()
+        .ok_or_else(|| ApiError::new_internal_server_error("Conversion lost an invite"))
+}
+
+/// Converts `UserInvite` models into `SimpleUserInvite` schemas preserving their order.
+///
+/// The invites' creators and renewers are retrieved using a single query.
+pub async fn new_simple_user_invites(
+    executor: impl Executor<'_>,
+    invites: Vec<UserInvite>,
+) -> ApiResult<Vec<SimpleUserInvite>> {
+    let creator_uuids: HashSet<Uuid> = invites
+        .iter()
+        .flat_map(|invite| [invite.created_by.as_ref(), invite.renewed_by.as_ref()])
+        .flatten()
+        .map(|user| *user.key())
+        .collect();
+    let creators: HashMap<Uuid, String> = if creator_uuids.is_empty() {
+        HashMap::new()
+    } else {
+        query!(executor, (User::F.uuid, User::F.display_name))
+            .condition(DynamicCollection::or(
+                creator_uuids
+                    .iter()
+                    .map(|uuid| User::F.uuid.equals(*uuid))
+                    .collect(),
+            ))
+            .all()
+            .await?
+            .into_iter()
+            .collect()
+    };
+
+    let get_creator = |user: Option<&ForeignModel<User>>| {
+        let uuid = *user?.key();
+        Some(InviteCreator {
+            uuid,
+            display_name: creators.get(&uuid)?.clone(),
+        })
+    };
+    let now = OffsetDateTime::now_utc();
+    invites
+        .into_iter()
+        .map(|invite| {
+            let created_by = get_creator(invite.created_by.as_ref());
+            let renewed_by = get_creator(invite.renewed_by.as_ref());
+            Ok(SimpleUserInvite {
+                uuid: invite.uuid,
+                link: new_user_invite_link(&GLOBAL.origin, invite.uuid),
+                mail: CheckedString::new(invite.email)?,
+                display_name: CheckedString::new(invite.display_name)?,
+                preferred_lang: UserLanguage::from_stored(&invite.preferred_lang),
+                permissions: invite.permissions.0,
+                expires_at: SchemaDateTime(invite.expires_at),
+                status: UserInviteStatus::at(invite.expires_at, now),
+                created_by,
+                created_at: SchemaDateTime(invite.created_at),
+                renewed_at: invite.renewed_at.map(SchemaDateTime),
+                renewed_by,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use time::Duration;
+
+    use super::invite_validity;
+
+    const DEFAULT: Duration = Duration::hours(24);
+    const MAX: Duration = Duration::hours(72);
+
+    #[test]
+    fn invite_validity_defaults_to_the_configured_duration() {
+        assert_eq!(invite_validity(None, DEFAULT, MAX), Some(DEFAULT));
+    }
+
+    #[test]
+    fn invite_validity_accepts_hours_up_to_the_maximum() {
+        assert_eq!(
+            invite_validity(Some(1), DEFAULT, MAX),
+            Some(Duration::hours(1))
+        );
+        assert_eq!(invite_validity(Some(72), DEFAULT, MAX), Some(MAX));
+    }
+
+    #[test]
+    fn invite_validity_rejects_zero_hours() {
+        assert_eq!(invite_validity(Some(0), DEFAULT, MAX), None);
+    }
+
+    #[test]
+    fn invite_validity_rejects_hours_above_the_maximum() {
+        assert_eq!(invite_validity(Some(73), DEFAULT, MAX), None);
+    }
 }
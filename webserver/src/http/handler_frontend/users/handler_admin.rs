@@ -1,50 +1,1001 @@
 //! The handler for the users
 
+use std::collections::HashSet;
+
 use axum::extract::Path;
+use axum::extract::Query;
+use rorm::and;
+use rorm::conditions::BoxedCondition;
+use rorm::conditions::Condition;
+use rorm::conditions::DynamicCollection;
+use rorm::db::transaction::Transaction;
+use rorm::insert;
+use rorm::or;
+use rorm::prelude::ForeignModelByField;
 use rorm::query;
+use rorm::update;
+use rorm::FieldAccess;
+use rorm::Model;
+use serde_json::json;
+use serde_json::Value;
 use swaggapi::delete;
 use swaggapi::get;
+use swaggapi::post;
 use swaggapi::put;
+use time::OffsetDateTime;
+use tracing::debug;
+use tracing::info;
+use tracing::instrument;
+use uuid::Uuid;
 
+use crate::global::ws::GlobalWs;
 use crate::global::GLOBAL;
+use crate::http::common::errors::ApiError;
 use crate::http::common::errors::ApiResult;
-use crate::http::common::schemas::List;
+use crate::http::common::schemas::Collection;
+use crate::http::common::schemas::FormResult;
+use crate::http::common::schemas::PageParams;
 use crate::http::common::schemas::SingleUuid;
 use crate::http::extractors::api_json::ApiJson;
-use crate::http::handler_frontend::users::schema::FullUser;
+use crate::http::extractors::session_user::SessionUser;
+use crate::http::handler_frontend::user_invites::schema::CreateUserInviteMailError;
+use crate::http::handler_frontend::user_invites::utils::new_simple_user_invite;
+use crate::http::handler_frontend::user_invites::utils::send_invite_mail;
+use crate::http::handler_frontend::users::schema::AdminListUser;
+use crate::http::handler_frontend::users::schema::CreateUserErrors;
+use crate::http::handler_frontend::users::schema::CreateUserRequest;
+use crate::http::handler_frontend::users::schema::CreateUserResponse;
+use crate::http::handler_frontend::users::schema::GetAllUsersRequest;
+use crate::http::handler_frontend::users::schema::LogoutAllResponse;
+use crate::http::handler_frontend::users::schema::MagicLoginLinkResponse;
+use crate::http::handler_frontend::users::schema::SetUserEnabledRequest;
+use crate::http::handler_frontend::users::schema::SetUserPasswordErrors;
+use crate::http::handler_frontend::users::schema::SetUserPasswordRequest;
+use crate::http::handler_frontend::users::schema::UserAuthMethods;
+use crate::http::handler_frontend::users::schema::UserLanguage;
 use crate::http::handler_frontend::users::schema::UserPermissions;
+use crate::http::handler_frontend::users::schema::UsersOrder;
+use crate::http::handler_frontend::users::utils::get_user_auth_methods;
+use crate::http::handler_frontend::users::utils::get_user_permissions;
+use crate::http::handler_frontend::users::utils::new_admin_list_users;
 use crate::http::handler_frontend::users::utils::new_full_user;
+use crate::http::handler_frontend::users::utils::send_email_verification;
+use crate::http::handler_frontend::users::utils::start_email_verification;
+use crate::http::handler_frontend::ws::schema::WsServerMsg;
+use crate::models;
+use crate::models::AuditAction;
+use crate::models::AuditLog;
+use crate::models::CreateUserError;
+use crate::models::CreateUserInviteError;
+use crate::models::InternalGroup;
+use crate::models::LocalUser;
+use crate::models::LocalUserInsert;
+use crate::models::MagicLoginLink;
+use crate::models::MagicLoginLinkInsert;
 use crate::models::User;
+use crate::models::UserInvite;
+use crate::models::UserRole;
+use crate::utils::hashing::hash_pw;
+use crate::utils::links::new_magic_login_link;
+use crate::utils::password_policy::meets_password_policy;
+use crate::utils::schemars::SchemaDateTime;
+use crate::utils::search::like_pattern;
+use crate::utils::webhooks;
+use crate::utils::webhooks::WebhookEvent;
+
+/// Creates a new local user
+///
+/// If no password is provided, an invite is created instead.
+/// Either way, the mail must not belong to another user or open invite.
+#[post("/")]
+#[instrument(skip_all, ret, err)]
+pub async fn create_user(
+    SessionUser { user: admin, .. }: SessionUser,
+    ApiJson(request): ApiJson<CreateUserRequest>,
+) -> ApiResult<ApiJson<FormResult<CreateUserResponse, CreateUserErrors>>> {
+    let mut tx = GLOBAL.db.start_transaction().await?;
+
+    let Some(password) = request.password else {
+        let invite = match UserInvite::create(
+            &mut tx,
+            request.mail,
+            request.display_name,
+            request.preferred_lang,
+            request.permissions,
+            GLOBAL.settings.get().invite_expiry,
+            Some(admin.uuid),
+        )
+        .await
+        {
+            Ok(invite) => invite,
+            Err(CreateUserInviteError::AlreadyUser) => {
+                return Ok(ApiJson(FormResult::err(CreateUserErrors {
+                    mail: Some(CreateUserInviteMailError::AlreadyUser),
+                    ..Default::default()
+                })))
+            }
+            Err(CreateUserInviteError::AlreadyInvited) => {
+                return Ok(ApiJson(FormResult::err(CreateUserErrors {
+                    mail: Some(CreateUserInviteMailError::AlreadyInvited),
+                    ..Default::default()
+                })))
+            }
+            Err(CreateUserInviteError::InvalidDisplayName(_)) => {
+                return Ok(ApiJson(FormResult::err(CreateUserErrors {
+                    display_name: true,
+                    ..Default::default()
+                })))
+            }
+            Err(CreateUserInviteError::DisplayNameOccupied) => {
+                return Ok(ApiJson(FormResult::err(CreateUserErrors {
+                    display_name_occupied: true,
+                    ..Default::default()
+                })))
+            }
+            Err(CreateUserInviteError::Database(error)) => return Err(error.into()),
+        };
+        AuditLog::audit(
+            &mut tx,
+            Some(admin.uuid),
+            AuditAction::InviteCreated,
+            Some(invite.uuid),
+            json!({ "mail": invite.email }),
+        )
+        .await?;
+        let invite = new_simple_user_invite(&mut tx, invite).await?;
+
+        tx.commit().await?;
+
+        send_invite_mail(&invite);
+        return Ok(ApiJson(FormResult::ok(CreateUserResponse::Invited {
+            invite,
+        })));
+    };
+
+    if !meets_password_policy(&password) {
+        return Ok(ApiJson(FormResult::err(CreateUserErrors {
+            password: true,
+            ..Default::default()
+        })));
+    }
+
+    // An open invite could be accepted later on creating a second account with the same mail
+    let invite_with_mail_exists = query!(&mut tx, (UserInvite::F.uuid,))
+        .condition(and![
+            UserInvite::F.email.equals(&request.mail),
+            UserInvite::F
+                .expires_at
+                .greater_than(OffsetDateTime::now_utc())
+        ])
+        .optional()
+        .await?
+        .is_some();
+    if invite_with_mail_exists {
+        return Ok(ApiJson(FormResult::err(CreateUserErrors {
+            mail: Some(CreateUserInviteMailError::AlreadyInvited),
+            ..Default::default()
+        })));
+    }
+
+    let verification_required = GLOBAL.settings.get().email_verification_required;
+    let user_uuid = match User::create(
+        &mut tx,
+        request.mail,
+        request.display_name,
+        request.preferred_lang,
+        request.permissions,
+        !verification_required,
+        None,
+    )
+    .await
+    {
+        Ok(uuid) => uuid,
+        Err(CreateUserError::MailOccupied) => {
+            return Ok(ApiJson(FormResult::err(CreateUserErrors {
+                mail: Some(CreateUserInviteMailError::AlreadyUser),
+                ..Default::default()
+            })))
+        }
+        Err(CreateUserError::InvalidDisplayName(_)) => {
+            return Ok(ApiJson(FormResult::err(CreateUserErrors {
+                display_name: true,
+                ..Default::default()
+            })))
+        }
+        Err(CreateUserError::DisplayNameOccupied) => {
+            return Ok(ApiJson(FormResult::err(CreateUserErrors {
+                display_name_occupied: true,
+                ..Default::default()
+            })))
+        }
+        Err(CreateUserError::Conflict) => return Err(ApiError::Conflict),
+        Err(CreateUserError::Database(error)) => return Err(error.into()),
+    };
+
+    insert!(&mut tx, LocalUser)
+        .return_nothing()
+        .single(&LocalUserInsert {
+            uuid: Uuid::new_v4(),
+            user: ForeignModelByField::Key(user_uuid),
+            password: Some(hash_pw(&password)?),
+        })
+        .await?;
+
+    AuditLog::audit(
+        &mut tx,
+        Some(admin.uuid),
+        AuditAction::UserCreated,
+        Some(user_uuid),
+        Value::Null,
+    )
+    .await?;
+
+    let user = query!(&mut tx, User)
+        .condition(User::F.uuid.equals(user_uuid))
+        .one()
+        .await?;
+    let verification_link = if verification_required {
+        Some(start_email_verification(&mut tx, user_uuid, &user.mail).await?)
+    } else {
+        None
+    };
+    let permissions = get_user_permissions(&mut tx, &user).await?;
+
+    tx.commit().await?;
+
+    webhooks::emit(WebhookEvent::UserCreated { user: user_uuid });
+
+    if let Some(link) = verification_link {
+        send_email_verification(
+            user.mail.clone(),
+            user.display_name.clone(),
+            UserLanguage::from_stored(&user.preferred_lang),
+            link,
+        );
+    }
+    Ok(ApiJson(FormResult::ok(CreateUserResponse::Created {
+        user: new_full_user(user, permissions)?,
+    })))
+}
 
-/// Retrieves an unordered, unsorted list of all users
+/// Retrieves a page of users ordered by their mail or creation
+///
+/// The users can be filtered by a search term, their role and when they were created.
+/// Each user is enriched with some flags which are useful for administration.
 #[get("/")]
-pub async fn get_all_users() -> ApiResult<ApiJson<List<FullUser>>> {
+pub async fn get_all_users(
+    Query(page): Query<PageParams>,
+    Query(filter): Query<GetAllUsersRequest>,
+) -> ApiResult<ApiJson<Collection<AdminListUser, GetAllUsersRequest>>> {
     let mut tx = GLOBAL.db.start_transaction().await?;
 
-    let users = query!(&mut tx, User).all().await?;
+    let (users, total) = query_users(&mut tx, &filter, page.limit(), page.offset).await?;
+    let items = new_admin_list_users(&mut tx, users).await?;
 
     tx.commit().await?;
-    Ok(ApiJson(List {
-        list: users
-            .into_iter()
-            .map(new_full_user)
-            .collect::<Result<_, _>>()?,
-    }))
+    Ok(ApiJson(Collection::page(
+        items,
+        total as u64,
+        &page,
+        filter,
+    )))
+}
+
+/// Retrieves a page of the users matching a [`GetAllUsersRequest`] and the number of all matching users
+async fn query_users(
+    tx: &mut Transaction,
+    filter: &GetAllUsersRequest,
+    limit: u64,
+    offset: u64,
+) -> Result<(Vec<User>, i64), rorm::Error> {
+    let search = filter.search.as_deref().map(like_pattern);
+
+    let (total,) = query!(&mut *tx, (User::F.uuid.count(),))
+        .condition(users_filter(search.as_deref(), filter))
+        .one()
+        .await?;
+
+    let query = query!(&mut *tx, User).condition(users_filter(search.as_deref(), filter));
+    let users = match filter.order {
+        UsersOrder::Mail => query.order_asc(User::F.mail),
+        UsersOrder::Newest => query.order_desc(User::F.created_at),
+        UsersOrder::Oldest => query.order_asc(User::F.created_at),
+    }
+    .limit(limit)
+    .offset(offset)
+    .all()
+    .await?;
+
+    Ok((users, total))
+}
+
+/// Builds the condition selecting the users matching a [`GetAllUsersRequest`]
+///
+/// `search` is expected to be the request's search term converted using [`like_pattern`].
+fn users_filter<'a>(
+    search: Option<&'a str>,
+    filter: &GetAllUsersRequest,
+) -> DynamicCollection<BoxedCondition<'a>> {
+    let mut conditions = Vec::new();
+    if let Some(search) = search {
+        conditions.push(
+            or![
+                User::F.mail.ilike(search),
+                User::F.display_name.ilike(search),
+            ]
+            .boxed(),
+        );
+    }
+    if let Some(role) = filter.role {
+        conditions.push(User::F.role.equals(role.to_string()).boxed());
+    }
+    if let Some(SchemaDateTime(since)) = filter.created_since {
+        conditions.push(User::F.created_at.greater_or_equals(since).boxed());
+    }
+    if let Some(SchemaDateTime(until)) = filter.created_until {
+        conditions.push(User::F.created_at.less_than(until).boxed());
+    }
+    DynamicCollection::and(conditions)
+}
+
+/// Retrieve all login methods of a user
+///
+/// This helps diagnosing a locked-out user before resetting their password or sending a login link.
+/// Only the keys' labels and metadata are returned, never any secret.
+#[get("/:uuid/methods")]
+pub async fn get_user_methods(
+    Path(SingleUuid { uuid }): Path<SingleUuid>,
+) -> ApiResult<ApiJson<UserAuthMethods>> {
+    let mut tx = GLOBAL.db.start_transaction().await?;
+
+    query!(&mut tx, (User::F.uuid,))
+        .condition(User::F.uuid.equals(uuid))
+        .optional()
+        .await?
+        .ok_or(ApiError::NotFound)?;
+    let methods = get_user_auth_methods(&mut tx, uuid).await?;
+
+    tx.commit().await?;
+    Ok(ApiJson(methods))
 }
 
 /// Overwrites a user's permissions
+///
+/// All groups have to exist.
+/// The user's open websockets receive their new permissions.
 #[put("/:uuid/permissions")]
 pub async fn set_user_permissions(
+    SessionUser { user: admin, .. }: SessionUser,
     Path(SingleUuid { uuid }): Path<SingleUuid>,
     ApiJson(new_permissions): ApiJson<UserPermissions>,
 ) -> ApiResult<()> {
-    User::set_permissions(&GLOBAL.db, uuid, new_permissions).await?;
+    let mut tx = GLOBAL.db.start_transaction().await?;
+    let (old_role, permissions) =
+        change_user_permissions(&mut tx, admin.uuid, uuid, new_permissions).await?;
+    tx.commit().await?;
+
+    let new_role = match &permissions {
+        UserPermissions::Administrator => UserRole::Administrator,
+        UserPermissions::Internal { .. } => UserRole::Internal,
+    };
+    if old_role != new_role {
+        webhooks::emit(WebhookEvent::UserRoleChanged {
+            user: uuid,
+            old_role,
+            new_role,
+        });
+    }
+
+    notify_permissions_changed(&GLOBAL.ws, uuid, permissions).await;
+
+    Ok(())
+}
+
+/// Implementation of [`set_user_permissions`] without committing the transaction
+///
+/// Returns the user's old role and their new permissions as they are stored.
+/// Once the transaction has been committed,
+/// the user should be notified using [`notify_permissions_changed`].
+async fn change_user_permissions(
+    tx: &mut Transaction,
+    admin: Uuid,
+    uuid: Uuid,
+    new_permissions: UserPermissions,
+) -> ApiResult<(UserRole, UserPermissions)> {
+    if let UserPermissions::Internal { groups } = &new_permissions {
+        let groups: HashSet<Uuid> = groups.iter().copied().collect();
+        if !groups.is_empty() {
+            let (existing,) = query!(&mut *tx, (InternalGroup::F.uuid.count(),))
+                .condition(DynamicCollection::or(
+                    groups
+                        .iter()
+                        .map(|group| InternalGroup::F.uuid.equals(*group))
+                        .collect(),
+                ))
+                .one()
+                .await?;
+            if existing as usize != groups.len() {
+                return Err(ApiError::BadRequest);
+            }
+        }
+    }
+
+    let (old_role,) = query!(&mut *tx, (User::F.role,))
+        .condition(User::F.uuid.equals(uuid))
+        .optional()
+        .await?
+        .ok_or(ApiError::NotFound)?;
+    let old_role: UserRole = old_role.key().parse()?;
+
+    AuditLog::audit(
+        &mut *tx,
+        Some(admin),
+        AuditAction::UserPermissionsChanged,
+        Some(uuid),
+        serde_json::to_value(&new_permissions)?,
+    )
+    .await?;
+    User::set_permissions(&mut *tx, uuid, new_permissions).await?;
+
+    let user = query!(&mut *tx, User)
+        .condition(User::F.uuid.equals(uuid))
+        .one()
+        .await?;
+    let permissions = get_user_permissions(&mut *tx, &user).await?;
+    Ok((old_role, permissions))
+}
+
+/// Sends a user's new permissions to their open websockets
+async fn notify_permissions_changed(ws: &GlobalWs, uuid: Uuid, permissions: UserPermissions) {
+    ws.send_to_user(uuid, WsServerMsg::PermissionsChanged { permissions })
+        .await;
+}
+
+/// Issue a single-use link logging in a local user without any further factor
+///
+/// This has to be enabled in the config. Otherwise, it responds with a bad request.
+#[post("/:uuid/magic-link")]
+#[instrument(skip_all, ret, err)]
+pub async fn create_magic_login_link(
+    SessionUser { user: admin, .. }: SessionUser,
+    Path(SingleUuid { uuid }): Path<SingleUuid>,
+) -> ApiResult<ApiJson<MagicLoginLinkResponse>> {
+    let Some(valid_for) = GLOBAL.settings.get().magic_link_expiry else {
+        debug!("Magic login links are disabled");
+        return Err(ApiError::BadRequest);
+    };
+
+    let mut tx = GLOBAL.db.start_transaction().await?;
+
+    let (local_user_uuid,) = query!(&mut tx, (LocalUser::F.uuid,))
+        .condition(LocalUser::F.user.equals(uuid))
+        .optional()
+        .await?
+        .ok_or(ApiError::BadRequest)?;
+
+    let expires_at = OffsetDateTime::now_utc() + valid_for;
+    let link_uuid = insert!(&mut tx, MagicLoginLink)
+        .return_primary_key()
+        .single(&MagicLoginLinkInsert {
+            uuid: Uuid::new_v4(),
+            local_user: ForeignModelByField::Key(local_user_uuid),
+            expires_at,
+            created_by: Some(ForeignModelByField::Key(admin.uuid)),
+        })
+        .await?;
+
+    AuditLog::audit(
+        &mut tx,
+        Some(admin.uuid),
+        AuditAction::MagicLinkIssued,
+        Some(uuid),
+        Value::Null,
+    )
+    .await?;
+
+    tx.commit().await?;
+
+    info!(
+        admin.uuid = %admin.uuid,
+        admin.display_name = admin.display_name,
+        user.uuid = %uuid,
+        "Admin issued a magic login link"
+    );
+
+    Ok(ApiJson(MagicLoginLinkResponse {
+        link: new_magic_login_link(&GLOBAL.origin, link_uuid),
+        expires_at: SchemaDateTime(expires_at),
+    }))
+}
+
+/// Overwrites a local user's password
+///
+/// Optionally, the user is logged out from all of their sessions.
+#[put("/:uuid/password")]
+#[instrument(skip_all, ret, err)]
+pub async fn set_user_password(
+    SessionUser { user: admin, .. }: SessionUser,
+    Path(SingleUuid { uuid }): Path<SingleUuid>,
+    ApiJson(request): ApiJson<SetUserPasswordRequest>,
+) -> ApiResult<ApiJson<FormResult<(), SetUserPasswordErrors>>> {
+    let mut tx = GLOBAL.db.start_transaction().await?;
+
+    query!(&mut tx, (User::F.uuid,))
+        .condition(User::F.uuid.equals(uuid))
+        .optional()
+        .await?
+        .ok_or(ApiError::NotFound)?;
+
+    let Some((local_user_uuid,)) = query!(&mut tx, (LocalUser::F.uuid,))
+        .condition(LocalUser::F.user.equals(uuid))
+        .optional()
+        .await?
+    else {
+        return Ok(ApiJson(FormResult::err(SetUserPasswordErrors {
+            not_local: true,
+            ..Default::default()
+        })));
+    };
+
+    if !meets_password_policy(&request.password) {
+        return Ok(ApiJson(FormResult::err(SetUserPasswordErrors {
+            password: true,
+            ..Default::default()
+        })));
+    }
+
+    update!(&mut tx, LocalUser)
+        .condition(LocalUser::F.uuid.equals(local_user_uuid))
+        .set(LocalUser::F.password, Some(hash_pw(&request.password)?))
+        .set(
+            LocalUser::F.password_changed_at,
+            Some(OffsetDateTime::now_utc()),
+        )
+        .exec()
+        .await?;
+
+    if request.logout {
+        models::Session::delete_by_user(&mut tx, uuid).await?;
+    }
+
+    AuditLog::audit(
+        &mut tx,
+        Some(admin.uuid),
+        AuditAction::UserPasswordReset,
+        Some(uuid),
+        json!({ "logout": request.logout }),
+    )
+    .await?;
+
+    tx.commit().await?;
+
+    if request.logout {
+        GLOBAL.ws.close_user(uuid).await;
+    }
+
+    info!(
+        admin.uuid = %admin.uuid,
+        admin.display_name = admin.display_name,
+        user.uuid = %uuid,
+        logout = request.logout,
+        "Admin reset a user's password"
+    );
+
+    Ok(ApiJson(FormResult::ok(())))
+}
+
+/// Enables or disables a user
+///
+/// Disabling a user logs them out from all of their sessions.
+#[put("/:uuid/enabled")]
+#[instrument(skip_all, ret, err)]
+pub async fn set_user_enabled(
+    SessionUser { user: admin, .. }: SessionUser,
+    Path(SingleUuid { uuid }): Path<SingleUuid>,
+    ApiJson(SetUserEnabledRequest { enabled }): ApiJson<SetUserEnabledRequest>,
+) -> ApiResult<()> {
+    let mut tx = GLOBAL.db.start_transaction().await?;
+
+    if !User::set_enabled(&mut tx, uuid, enabled).await? {
+        return Err(ApiError::NotFound);
+    }
+    AuditLog::audit(
+        &mut tx,
+        Some(admin.uuid),
+        AuditAction::UserEnabledChanged,
+        Some(uuid),
+        json!({ "enabled": enabled }),
+    )
+    .await?;
+
+    tx.commit().await?;
+
+    if !enabled {
+        GLOBAL.ws.close_user(uuid).await;
+    }
+
+    info!(
+        admin.uuid = %admin.uuid,
+        admin.display_name = admin.display_name,
+        user.uuid = %uuid,
+        enabled,
+        "Admin changed whether a user is enabled"
+    );
+
     Ok(())
 }
 
+/// Terminates all sessions of a user and closes their websockets
+///
+/// Unlike disabling the user, they can log in again afterward.
+/// This may be used when an account has been compromised.
+#[post("/:uuid/logout-all")]
+#[instrument(skip_all, ret, err)]
+pub async fn logout_user(
+    SessionUser { user: admin, .. }: SessionUser,
+    Path(SingleUuid { uuid }): Path<SingleUuid>,
+) -> ApiResult<ApiJson<LogoutAllResponse>> {
+    let mut tx = GLOBAL.db.start_transaction().await?;
+    let sessions = delete_user_sessions(&mut tx, admin.uuid, uuid).await?;
+    tx.commit().await?;
+
+    GLOBAL.ws.close_user(uuid).await;
+
+    info!(
+        admin.uuid = %admin.uuid,
+        admin.display_name = admin.display_name,
+        user.uuid = %uuid,
+        sessions,
+        "Admin logged out a user"
+    );
+
+    Ok(ApiJson(LogoutAllResponse { sessions }))
+}
+
+/// Implementation of [`logout_user`] without committing the transaction
+///
+/// Returns the number of deleted sessions which is recorded in the audit log.
+/// Once the transaction has been committed, the user's websockets should be closed.
+async fn delete_user_sessions(tx: &mut Transaction, admin: Uuid, uuid: Uuid) -> ApiResult<u64> {
+    query!(&mut *tx, (User::F.uuid,))
+        .condition(User::F.uuid.equals(uuid))
+        .optional()
+        .await?
+        .ok_or(ApiError::NotFound)?;
+    let sessions = models::Session::delete_by_user(&mut *tx, uuid).await?;
+    AuditLog::audit(
+        &mut *tx,
+        Some(admin),
+        AuditAction::UserLoggedOut,
+        Some(uuid),
+        json!({ "sessions": sessions }),
+    )
+    .await?;
+    Ok(sessions)
+}
+
 /// Deletes a user
 #[delete("/:uuid")]
-pub async fn delete_user(Path(SingleUuid { uuid }): Path<SingleUuid>) -> ApiResult<()> {
-    User::delete(&GLOBAL.db, uuid).await?;
+pub async fn delete_user(
+    SessionUser { user: admin, .. }: SessionUser,
+    Path(SingleUuid { uuid }): Path<SingleUuid>,
+) -> ApiResult<()> {
+    let mut tx = GLOBAL.db.start_transaction().await?;
+
+    let deleted = User::delete(&mut tx, uuid)
+        .await?
+        .ok_or(ApiError::NotFound)?;
+    AuditLog::audit(
+        &mut tx,
+        Some(admin.uuid),
+        AuditAction::UserDeleted,
+        Some(uuid),
+        Value::Null,
+    )
+    .await?;
+
+    tx.commit().await?;
+
+    webhooks::emit(WebhookEvent::UserDeleted { user: uuid });
+    GLOBAL.ws.close_user(uuid).await;
+    if let (Some(avatars), Some(avatar)) = (&GLOBAL.avatars, deleted.avatar) {
+        avatars.remove(&avatar).await;
+    }
+
+    info!(
+        admin.uuid = %admin.uuid,
+        admin.display_name = admin.display_name,
+        user.uuid = %uuid,
+        sessions = deleted.sessions,
+        "Admin deleted a user"
+    );
+
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use rorm::fields::types::Json;
+    use rorm::insert;
+    use rorm::prelude::ForeignModelByField;
+    use rorm::query;
+    use rorm::update;
+    use rorm::FieldAccess;
+    use rorm::Model;
+    use time::Duration;
+    use time::OffsetDateTime;
+    use tokio::sync::mpsc;
+    use tower_sessions::session::Id;
+    use uuid::Uuid;
+
+    use super::change_user_permissions;
+    use super::delete_user_sessions;
+    use super::notify_permissions_changed;
+    use super::query_users;
+    use crate::global::ws::GlobalWs;
+    use crate::http::common::errors::ApiError;
+    use crate::http::handler_frontend::users::schema::GetAllUsersRequest;
+    use crate::http::handler_frontend::users::schema::UserPermissions;
+    use crate::http::handler_frontend::users::schema::UsersOrder;
+    use crate::http::handler_frontend::ws::schema::WsServerMsg;
+    use crate::models::AuditAction;
+    use crate::models::AuditLog;
+    use crate::models::InternalGroup;
+    use crate::models::InternalGroupInsert;
+    use crate::models::Session;
+    use crate::models::User;
+    use crate::models::UserRole;
+    use crate::utils::schemars::SchemaDateTime;
+    use crate::utils::test_db;
+
+    fn search(tag: &str) -> GetAllUsersRequest {
+        GetAllUsersRequest {
+            search: Some(tag.to_string()),
+            role: None,
+            created_since: None,
+            created_until: None,
+            order: UsersOrder::Mail,
+        }
+    }
+
+    fn year(year: i64) -> Result<OffsetDateTime, time::error::ComponentRange> {
+        OffsetDateTime::from_unix_timestamp((year - 1970) * 365 * 24 * 60 * 60)
+    }
+
+    fn uuids(users: Vec<User>) -> Vec<Uuid> {
+        users.into_iter().map(|user| user.uuid).collect()
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a migrated database"]
+    async fn search_matches_mail_or_display_name_ignoring_case(
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let db = test_db::connect().await?;
+        let mut tx = db.start_transaction().await?;
+        let tag = Uuid::new_v4().simple().to_string();
+
+        let by_display_name =
+            test_db::create_user(&mut tx, &tag, UserPermissions::Administrator).await?;
+        update!(&mut tx, User)
+            .condition(User::F.uuid.equals(by_display_name))
+            .set(User::F.mail, format!("{}@test.invalid", Uuid::new_v4()))
+            .exec()
+            .await?;
+        let by_mail = test_db::create_user(&mut tx, &tag, UserPermissions::Administrator).await?;
+        update!(&mut tx, User)
+            .condition(User::F.uuid.equals(by_mail))
+            .set(User::F.display_name, Uuid::new_v4().to_string())
+            .exec()
+            .await?;
+        test_db::create_user(&mut tx, "unrelated", UserPermissions::Administrator).await?;
+
+        let (users, total) = query_users(&mut tx, &search(&tag.to_uppercase()), 10, 0).await?;
+        let mut found = uuids(users);
+        found.sort();
+        let mut expected = vec![by_display_name, by_mail];
+        expected.sort();
+        assert_eq!(found, expected);
+        assert_eq!(total, 2);
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a migrated database"]
+    async fn search_without_matches_is_empty() -> Result<(), Box<dyn std::error::Error>> {
+        let db = test_db::connect().await?;
+        let mut tx = db.start_transaction().await?;
+        test_db::create_user(&mut tx, "unrelated", UserPermissions::Administrator).await?;
+
+        let tag = Uuid::new_v4().simple().to_string();
+        let (users, total) = query_users(&mut tx, &search(&tag), 10, 0).await?;
+        assert!(users.is_empty());
+        assert_eq!(total, 0);
+        Ok(())
+    }
+
+    /// Creates the users `a`, `b` and `c` created in 2002, 2003 and 2001
+    ///
+    /// `a` is an administrator, the others are internal users.
+    async fn create_users(
+        tx: &mut rorm::db::transaction::Transaction,
+        tag: &str,
+    ) -> Result<[Uuid; 3], Box<dyn std::error::Error>> {
+        let mut users = [Uuid::nil(); 3];
+        for (index, (name, created_at)) in [("a", 2002), ("b", 2003), ("c", 2001)]
+            .into_iter()
+            .enumerate()
+        {
+            let permissions = if index == 0 {
+                UserPermissions::Administrator
+            } else {
+                UserPermissions::Internal { groups: Vec::new() }
+            };
+            let uuid =
+                test_db::create_user(&mut *tx, &format!("{tag}-{name}"), permissions).await?;
+            update!(&mut *tx, User)
+                .condition(User::F.uuid.equals(uuid))
+                .set(User::F.created_at, year(created_at)?)
+                .exec()
+                .await?;
+            users[index] = uuid;
+        }
+        Ok(users)
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a migrated database"]
+    async fn filters_by_role_and_creation() -> Result<(), Box<dyn std::error::Error>> {
+        let db = test_db::connect().await?;
+        let mut tx = db.start_transaction().await?;
+        let tag = Uuid::new_v4().simple().to_string();
+        let [a, b, c] = create_users(&mut tx, &tag).await?;
+
+        let internal = GetAllUsersRequest {
+            role: Some(UserRole::Internal),
+            ..search(&tag)
+        };
+        let (users, total) = query_users(&mut tx, &internal, 10, 0).await?;
+        assert_eq!(uuids(users), [b, c]);
+        assert_eq!(total, 2);
+
+        let created_in_2002 = GetAllUsersRequest {
+            created_since: Some(SchemaDateTime(year(2002)?)),
+            created_until: Some(SchemaDateTime(year(2003)?)),
+            ..search(&tag)
+        };
+        let (users, total) = query_users(&mut tx, &created_in_2002, 10, 0).await?;
+        assert_eq!(uuids(users), [a]);
+        assert_eq!(total, 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a migrated database"]
+    async fn orders_and_pages_the_users() -> Result<(), Box<dyn std::error::Error>> {
+        let db = test_db::connect().await?;
+        let mut tx = db.start_transaction().await?;
+        let tag = Uuid::new_v4().simple().to_string();
+        let [a, b, c] = create_users(&mut tx, &tag).await?;
+
+        for (order, expected) in [
+            (UsersOrder::Mail, [a, b, c]),
+            (UsersOrder::Newest, [b, a, c]),
+            (UsersOrder::Oldest, [c, a, b]),
+        ] {
+            let filter = GetAllUsersRequest {
+                order,
+                ..search(&tag)
+            };
+            let (users, _) = query_users(&mut tx, &filter, 10, 0).await?;
+            assert_eq!(uuids(users), expected, "{order:?}");
+        }
+
+        let (users, total) = query_users(&mut tx, &search(&tag), 1, 1).await?;
+        assert_eq!(uuids(users), [b]);
+        assert_eq!(total, 3);
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a migrated database"]
+    async fn connected_user_receives_the_new_permissions() -> Result<(), Box<dyn std::error::Error>>
+    {
+        let db = test_db::connect().await?;
+        let mut tx = db.start_transaction().await?;
+        let ws = GlobalWs::new();
+        let user = test_db::create_user(&mut tx, "user", UserPermissions::Administrator).await?;
+        let group = Uuid::new_v4();
+        insert!(&mut tx, InternalGroup)
+            .return_nothing()
+            .single(&InternalGroupInsert {
+                uuid: group,
+                name: group.to_string(),
+            })
+            .await?;
+
+        let (sender, mut rx) = mpsc::channel(1);
+        assert!(
+            ws.register_ws(sender, user, Id::default(), Uuid::new_v4())
+                .await
+        );
+
+        // The duplicate group is only stored once
+        let (old_role, permissions) = change_user_permissions(
+            &mut tx,
+            Uuid::new_v4(),
+            user,
+            UserPermissions::Internal {
+                groups: vec![group, group],
+            },
+        )
+        .await?;
+        assert_eq!(old_role, UserRole::Administrator);
+        notify_permissions_changed(&ws, user, permissions).await;
+
+        assert!(matches!(
+            rx.recv().await,
+            Some(WsServerMsg::PermissionsChanged {
+                permissions: UserPermissions::Internal { groups },
+            }) if groups == [group]
+        ));
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a migrated database"]
+    async fn logging_out_deletes_only_the_users_sessions() -> Result<(), Box<dyn std::error::Error>>
+    {
+        let db = test_db::connect().await?;
+        let mut tx = db.start_transaction().await?;
+        let admin = test_db::create_user(&mut tx, "admin", UserPermissions::Administrator).await?;
+        let user = test_db::create_user(&mut tx, "user", UserPermissions::Administrator).await?;
+
+        for owner in [user, user, admin] {
+            insert!(&mut tx, Session)
+                .return_nothing()
+                .single(&Session {
+                    id: Uuid::new_v4().to_string(),
+                    expires_at: OffsetDateTime::now_utc() + Duration::hours(1),
+                    data: Json(HashMap::new()),
+                    user: Some(ForeignModelByField::Key(owner)),
+                    user_agent: None,
+                    ip: None,
+                })
+                .await?;
+        }
+
+        assert_eq!(delete_user_sessions(&mut tx, admin, user).await?, 2);
+        let remaining = query!(&mut tx, (Session::F.id,))
+            .condition(Session::F.user.equals(user))
+            .all()
+            .await?;
+        assert!(remaining.is_empty());
+        let remaining = query!(&mut tx, (Session::F.id,))
+            .condition(Session::F.user.equals(admin))
+            .all()
+            .await?;
+        assert_eq!(remaining.len(), 1);
+
+        let entry = query!(&mut tx, AuditLog)
+            .condition(AuditLog::F.target.equals(user))
+            .one()
+            .await?;
+        assert_eq!(entry.actor, Some(admin));
+        assert_eq!(entry.action, AuditAction::UserLoggedOut.to_string());
+        assert_eq!(entry.detail.0["sessions"], 2);
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a migrated database"]
+    async fn logging_out_an_unknown_user_fails() -> Result<(), Box<dyn std::error::Error>> {
+        let db = test_db::connect().await?;
+        let mut tx = db.start_transaction().await?;
+
+        let result = delete_user_sessions(&mut tx, Uuid::new_v4(), Uuid::new_v4()).await;
+        assert!(matches!(result, Err(ApiError::NotFound)));
+        Ok(())
+    }
+}
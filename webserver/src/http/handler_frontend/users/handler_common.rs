@@ -1,76 +1,453 @@
 //! The handler for the users
 
+use std::collections::HashMap;
+
 use axum::extract::Path;
+use axum::extract::Query;
+use axum::http::header;
+use axum::response::IntoResponse;
+use axum::response::Response;
 use futures::TryStreamExt;
 use rorm::and;
+use rorm::conditions::DynamicCollection;
 use rorm::insert;
 use rorm::prelude::ForeignModelByField;
 use rorm::query;
 use rorm::update;
 use rorm::FieldAccess;
 use rorm::Model;
+use swaggapi::as_responses::simple_responses;
+use swaggapi::as_responses::AsResponses;
+use swaggapi::as_responses::SimpleResponse;
 use swaggapi::delete;
 use swaggapi::get;
+use swaggapi::internals::SchemaGenerator;
+use swaggapi::patch;
 use swaggapi::post;
+use swaggapi::put;
+use swaggapi::re_exports::mime;
+use swaggapi::re_exports::openapiv3;
+use swaggapi::re_exports::openapiv3::Responses;
 use swaggapi::utils::SchemalessJson;
+use time::Duration;
+use time::OffsetDateTime;
 use tower_sessions::Session;
 use tracing::debug;
+use tracing::info;
 use tracing::instrument;
+use tracing::warn;
 use uuid::Uuid;
-use webauthn_rs::prelude::CreationChallengeResponse;
+use webauthn_rs::prelude::PublicKeyCredential;
 use webauthn_rs::prelude::RegisterPublicKeyCredential;
+use webauthn_rs::prelude::RequestChallengeResponse;
 
 use crate::global::GLOBAL;
 use crate::http::common::errors::ApiError;
 use crate::http::common::errors::ApiResult;
+use crate::http::common::schemas::Collection;
 use crate::http::common::schemas::FormResult;
-use crate::http::common::schemas::List;
+use crate::http::common::schemas::NoFilters;
 use crate::http::common::schemas::SingleUuid;
 use crate::http::extractors::api_json::ApiJson;
+use crate::http::extractors::image_upload::ImageUpload;
 use crate::http::extractors::session_user::SessionUser;
+use crate::http::handler_frontend::auth::schema::WebAuthnAuthenticateResult;
+use crate::http::handler_frontend::auth::utils::consume_totp_token;
+use crate::http::handler_frontend::auth::utils::get_mfa;
+use crate::http::handler_frontend::auth::utils::is_mfa_verified;
+use crate::http::handler_frontend::auth::utils::set_mfa_verified;
+use crate::http::handler_frontend::users::schema::ChangeEmailRequest;
 use crate::http::handler_frontend::users::schema::ChangePwFormErrors;
 use crate::http::handler_frontend::users::schema::ChangePwRequest;
+use crate::http::handler_frontend::users::schema::ConfirmEmailErrors;
 use crate::http::handler_frontend::users::schema::CreateTotpErrors;
 use crate::http::handler_frontend::users::schema::CreateTotpRequest;
 use crate::http::handler_frontend::users::schema::CreateTotpSecretError;
 use crate::http::handler_frontend::users::schema::CreateWebAuthnRequest;
 use crate::http::handler_frontend::users::schema::FullUser;
+use crate::http::handler_frontend::users::schema::GetMeRequest;
+use crate::http::handler_frontend::users::schema::ResolveUsersRequest;
+use crate::http::handler_frontend::users::schema::ResolveUsersResponse;
+use crate::http::handler_frontend::users::schema::SetAvatarErrors;
 use crate::http::handler_frontend::users::schema::SimpleTotpKey;
 use crate::http::handler_frontend::users::schema::SimpleWebAuthnKey;
+use crate::http::handler_frontend::users::schema::UpdateMeErrors;
+use crate::http::handler_frontend::users::schema::UpdateMeRequest;
+use crate::http::handler_frontend::users::schema::UserAuthMethods;
+use crate::http::handler_frontend::users::schema::UserLanguage;
+use crate::http::handler_frontend::users::schema::VerifyEmailErrors;
+use crate::http::handler_frontend::users::utils::get_auth_summary;
+use crate::http::handler_frontend::users::utils::get_user_auth_methods;
 use crate::http::handler_frontend::users::utils::new_full_user;
+use crate::http::handler_frontend::users::utils::new_resolved_user;
+use crate::http::handler_frontend::users::utils::send_email_verification;
+use crate::http::handler_frontend::users::utils::start_email_verification;
+use crate::http::handler_frontend::ws::schema::WsTicketResponse;
+use crate::http::session_keys::WebAuthnAuthentication;
+use crate::http::session_keys::WebAuthnAuthenticationState;
 use crate::http::session_keys::WebAuthnRegistration;
 use crate::http::session_keys::WebAuthnRegistrationState;
 use crate::http::session_keys::SESSION_WEBAUTHN_REGISTRATION;
+use crate::http::session_keys::SESSION_WEBAUTHN_STEP_UP;
+use crate::models::is_unique_violation;
+use crate::models::EmailChange;
+use crate::models::EmailChangeInsert;
+use crate::models::EmailVerification;
 use crate::models::LocalUser;
 use crate::models::MaybeAttestedPasskey;
+use crate::models::Permission;
 use crate::models::TotpKey;
 use crate::models::TotpKeyInsert;
+use crate::models::User;
+use crate::models::UserRole;
 use crate::models::WebAuthnKey;
 use crate::models::WebAuthnKeyInsert;
+use crate::models::WsTicket;
+use crate::utils::avatars::Avatars;
+use crate::utils::avatars::ProcessError;
 use crate::utils::checked_string::CheckedString;
+use crate::utils::display_name::display_name_key;
+use crate::utils::display_name::normalize_display_name;
 use crate::utils::hashing;
 use crate::utils::hashing::hash_pw;
 use crate::utils::hashing::VerifyPwError;
+use crate::utils::links::new_email_change_link;
+use crate::utils::password_policy::meets_password_policy;
+use crate::utils::rate_limit::RateLimiter;
 use crate::utils::schemars::SchemaDateTime;
+use crate::utils::schemars::WebAuthnSchema;
 use crate::utils::totp::totp_from_base32;
 use crate::utils::totp::TotpFromError;
+use crate::utils::webauthn::get_registered_credentials;
+use crate::utils::webauthn::is_credential_registered;
+use crate::utils::webauthn::is_enrollment_expired;
+use crate::utils::webauthn::StartWebAuthnRegisterResponse;
 use crate::utils::webauthn::WebAuthnRegisterResult;
 
 /// Retrieve the currently logged-in user
+///
+/// An overview over the user's login methods and sessions can be requested using `include_auth_summary`.
 #[get("/me")]
 #[instrument(skip_all)]
-pub async fn get_me(SessionUser { user, .. }: SessionUser) -> ApiResult<ApiJson<FullUser>> {
-    new_full_user(user).map(ApiJson)
+pub async fn get_me(
+    SessionUser { user, permissions }: SessionUser,
+    Query(request): Query<GetMeRequest>,
+) -> ApiResult<ApiJson<FullUser>> {
+    let auth_summary = if request.include_auth_summary {
+        Some(get_auth_summary(&GLOBAL.db, user.uuid).await?)
+    } else {
+        None
+    };
+
+    Ok(ApiJson(FullUser {
+        auth_summary,
+        ..new_full_user(user, permissions)?
+    }))
+}
+
+/// Update the own display name and preferred language
+///
+/// Only the provided fields are changed.
+#[patch("/me")]
+pub async fn update_me(
+    SessionUser { user, permissions }: SessionUser,
+    ApiJson(request): ApiJson<UpdateMeRequest>,
+) -> ApiResult<ApiJson<FormResult<FullUser, UpdateMeErrors>>> {
+    let display_name = match request.display_name.map(normalize_display_name).transpose() {
+        Ok(display_name) => display_name,
+        Err(_) => {
+            return Ok(ApiJson(FormResult::err(UpdateMeErrors {
+                display_name: true,
+                ..Default::default()
+            })))
+        }
+    };
+
+    let mut tx = GLOBAL.db.start_transaction().await?;
+
+    if let Some(display_name) = &display_name {
+        if User::is_display_name_taken(&mut tx, display_name, Some(user.uuid)).await? {
+            return Ok(ApiJson(FormResult::err(UpdateMeErrors {
+                display_name_occupied: true,
+                ..Default::default()
+            })));
+        }
+    }
+
+    if let Ok(update) = update!(&mut tx, User)
+        .condition(User::F.uuid.equals(user.uuid))
+        .begin_dyn_set()
+        .set_if(
+            User::F.display_name_key,
+            display_name.as_deref().map(display_name_key),
+        )
+        .set_if(
+            User::F.display_name,
+            display_name.map(CheckedString::into_inner),
+        )
+        .set_if(
+            User::F.preferred_lang,
+            request.preferred_lang.map(|lang| lang.to_string()),
+        )
+        .finish_dyn_set()
+    {
+        // A concurrent request might have taken the display name after it has been checked
+        update.exec().await.map_err(|error| {
+            if is_unique_violation(&error) {
+                ApiError::Conflict
+            } else {
+                error.into()
+            }
+        })?;
+    }
+
+    let user = query!(&mut tx, User)
+        .condition(User::F.uuid.equals(user.uuid))
+        .one()
+        .await?;
+
+    tx.commit().await?;
+    Ok(ApiJson(FormResult::ok(new_full_user(user, permissions)?)))
+}
+
+/// Upload a new avatar replacing the current one
+///
+/// The image is sent as raw body and resized to a square png.
+#[put("/me/avatar")]
+pub async fn set_avatar(
+    SessionUser { user, .. }: SessionUser,
+    ImageUpload { format, data }: ImageUpload,
+) -> ApiResult<ApiJson<FormResult<(), SetAvatarErrors>>> {
+    let Some(avatars) = &GLOBAL.avatars else {
+        return Err(ApiError::BadRequest);
+    };
+
+    if data.len() > avatars.max_upload_bytes {
+        return Ok(ApiJson(FormResult::err(SetAvatarErrors {
+            too_large: true,
+            ..Default::default()
+        })));
+    }
+    let image = match avatars.process(format, data).await {
+        Ok(image) => image,
+        Err(ProcessError::Image(error)) => {
+            debug!(error.display = %error, "Rejected avatar");
+            return Ok(ApiJson(FormResult::err(SetAvatarErrors {
+                invalid_image: true,
+                ..Default::default()
+            })));
+        }
+        Err(error) => return Err(ApiError::new_internal_server_error(error)),
+    };
+
+    let key = Avatars::new_key();
+    avatars.store.store(&key, image).await?;
+
+    let mut tx = GLOBAL.db.start_transaction().await?;
+    let (previous,) = query!(&mut tx, (User::F.avatar,))
+        .condition(User::F.uuid.equals(user.uuid))
+        .one()
+        .await?;
+    update!(&mut tx, User)
+        .condition(User::F.uuid.equals(user.uuid))
+        .set(User::F.avatar, Some(key))
+        .exec()
+        .await?;
+    tx.commit().await?;
+
+    if let Some(previous) = previous {
+        avatars.remove(&previous).await;
+    }
+
+    Ok(ApiJson(FormResult::ok(())))
+}
+
+/// Remove the own avatar
+#[delete("/me/avatar")]
+pub async fn delete_avatar(SessionUser { user, .. }: SessionUser) -> ApiResult<()> {
+    let mut tx = GLOBAL.db.start_transaction().await?;
+    let (previous,) = query!(&mut tx, (User::F.avatar,))
+        .condition(User::F.uuid.equals(user.uuid))
+        .one()
+        .await?;
+    update!(&mut tx, User)
+        .condition(User::F.uuid.equals(user.uuid))
+        .set(User::F.avatar, None)
+        .exec()
+        .await?;
+    tx.commit().await?;
+
+    if let (Some(avatars), Some(previous)) = (&GLOBAL.avatars, previous) {
+        avatars.remove(&previous).await;
+    }
+
+    Ok(())
+}
+
+/// Retrieve a user's avatar as png
+#[get("/:uuid/avatar")]
+pub async fn get_avatar(Path(SingleUuid { uuid }): Path<SingleUuid>) -> ApiResult<AvatarResponse> {
+    let Some(avatars) = &GLOBAL.avatars else {
+        return Err(ApiError::NotFound);
+    };
+
+    let (key,) = query!(&GLOBAL.db, (User::F.avatar,))
+        .condition(User::F.uuid.equals(uuid))
+        .optional()
+        .await?
+        .ok_or(ApiError::NotFound)?;
+    let key = key.ok_or(ApiError::NotFound)?;
+
+    let image = avatars.store.load(&key).await?.ok_or(ApiError::NotFound)?;
+    Ok(AvatarResponse(image))
+}
+
+/// A png image sent as response
+pub struct AvatarResponse(Vec<u8>);
+
+impl IntoResponse for AvatarResponse {
+    fn into_response(self) -> Response {
+        (
+            [
+                (header::CONTENT_TYPE, "image/png"),
+                (header::CACHE_CONTROL, "private, no-cache"),
+            ],
+            self.0,
+        )
+            .into_response()
+    }
+}
+
+impl AsResponses for AvatarResponse {
+    fn responses(_gen: &mut SchemaGenerator) -> Responses {
+        simple_responses([SimpleResponse {
+            status_code: openapiv3::StatusCode::Code(200),
+            mime_type: mime::IMAGE_PNG,
+            description: "The avatar as png".to_string(),
+            media_type: None,
+        }])
+    }
+}
+
+/// Resolve the display information of multiple users at once
+///
+/// At most [`MAX_RESOLVE_USERS`] uuids may be requested.
+#[post("/resolve")]
+pub async fn resolve_users(
+    SessionUser { user, .. }: SessionUser,
+    ApiJson(request): ApiJson<ResolveUsersRequest>,
+) -> ApiResult<ApiJson<ResolveUsersResponse>> {
+    if request.uuids.len() > MAX_RESOLVE_USERS {
+        return Err(ApiError::BadRequest);
+    }
+    if request.uuids.is_empty() {
+        return Ok(ApiJson(ResolveUsersResponse {
+            users: HashMap::new(),
+        }));
+    }
+
+    let show_mail = user
+        .role
+        .key()
+        .parse::<UserRole>()?
+        .has_permission(Permission::ViewUsers);
+
+    let users = query!(
+        &GLOBAL.db,
+        (
+            User::F.uuid,
+            User::F.display_name,
+            User::F.mail,
+            User::F.avatar
+        )
+    )
+    .condition(DynamicCollection::or(
+        request
+            .uuids
+            .iter()
+            .map(|uuid| User::F.uuid.equals(*uuid))
+            .collect(),
+    ))
+    .all()
+    .await?;
+
+    Ok(ApiJson(ResolveUsersResponse {
+        users: users
+            .into_iter()
+            .map(|(uuid, display_name, mail, avatar)| {
+                (
+                    uuid,
+                    new_resolved_user(display_name, mail, avatar, show_mail),
+                )
+            })
+            .collect(),
+    }))
+}
+
+/// The maximum number of users [`resolve_users`] accepts per request
+const MAX_RESOLVE_USERS: usize = 100;
+
+/// Retrieve all login methods of the currently logged-in user
+#[get("/me/auth-methods")]
+#[instrument(skip_all, ret, err)]
+pub async fn get_auth_methods(
+    SessionUser { user, .. }: SessionUser,
+) -> ApiResult<ApiJson<UserAuthMethods>> {
+    Ok(ApiJson(get_user_auth_methods(&GLOBAL.db, user.uuid).await?))
+}
+
+/// The duration a websocket ticket is valid for
+///
+/// The client is expected to open the websocket right after requesting the ticket.
+const WS_TICKET_EXPIRY: Duration = Duration::seconds(30);
+
+/// Issue a ticket to open a websocket without sending the session cookie
+///
+/// This is meant for clients which can't set cookies when opening a websocket.
+/// The ticket is bound to the current session, expires quickly and can only be used once.
+#[post("/me/ws-ticket")]
+#[instrument(skip_all, ret, err)]
+pub async fn create_ws_ticket(
+    SessionUser { .. }: SessionUser,
+    session: Session,
+) -> ApiResult<ApiJson<WsTicketResponse>> {
+    let Some(id) = session.id() else {
+        return Err(ApiError::SessionCorrupt);
+    };
+
+    let expires_at = OffsetDateTime::now_utc() + WS_TICKET_EXPIRY;
+    let ticket = insert!(&GLOBAL.db, WsTicket)
+        .return_primary_key()
+        .single(&WsTicket {
+            uuid: Uuid::new_v4(),
+            session: ForeignModelByField::Key(id.to_string()),
+            expires_at,
+        })
+        .await?;
+
+    Ok(ApiJson(WsTicketResponse {
+        ticket,
+        expires_at: SchemaDateTime(expires_at),
+    }))
 }
 
 /// Change the password of the currently logged-in user
 ///
-/// This may only be called by local users
+/// This may only be called by local users.
+///
+/// Depending on the server's configuration, users with a second factor have to provide it
+/// unless they did so recently.
+/// It is either sent as `totp_token` or proven beforehand using [`start_step_up_webauthn`].
 #[post("/me/change-pw")]
 #[instrument(skip_all, ret, err)]
 pub async fn change_password(
+    session: Session,
     SessionUser { user, .. }: SessionUser,
-    ApiJson(ChangePwRequest { current_pw, new_pw }): ApiJson<ChangePwRequest>,
+    ApiJson(ChangePwRequest {
+        current_pw,
+        new_pw,
+        totp_token,
+    }): ApiJson<ChangePwRequest>,
 ) -> ApiResult<ApiJson<FormResult<(), ChangePwFormErrors>>> {
     let mut tx = GLOBAL.db.start_transaction().await?;
 
@@ -98,16 +475,258 @@ pub async fn change_password(
         };
     }
 
+    if !meets_password_policy(&new_pw) {
+        return Ok(ApiJson(FormResult::err(ChangePwFormErrors {
+            new_pw: true,
+            ..Default::default()
+        })));
+    }
+
+    if let Some(step_up) = GLOBAL.settings.get().password_change_step_up {
+        let mfa = get_mfa(&mut tx, local_user.uuid).await?;
+        if (mfa.has_totp || mfa.has_webauthn) && !is_mfa_verified(&session, step_up).await? {
+            let Some(totp_token) = totp_token else {
+                return Ok(ApiJson(FormResult::err(ChangePwFormErrors {
+                    step_up_required: true,
+                    ..Default::default()
+                })));
+            };
+            if !consume_totp_token(&mut tx, local_user.uuid, &totp_token).await? {
+                return Ok(ApiJson(FormResult::err(ChangePwFormErrors {
+                    totp_token: true,
+                    ..Default::default()
+                })));
+            }
+            set_mfa_verified(&session).await?;
+        }
+    }
+
     let hashed = hash_pw(&new_pw)?;
 
     update!(&mut tx, LocalUser)
         .condition(LocalUser::F.user.equals(user.uuid))
         .set(LocalUser::F.password, Some(hashed))
+        .set(
+            LocalUser::F.password_changed_at,
+            Some(OffsetDateTime::now_utc()),
+        )
+        .exec()
+        .await?;
+
+    tx.commit().await?;
+
+    Ok(ApiJson(FormResult::ok(())))
+}
+
+/// Start proving the second factor using a webauthn key
+///
+/// Completing the challenge with [`complete_step_up_webauthn`] counts as recently provided
+/// second factor, e.g. for [`change_password`].
+///
+/// This may only be called by local users.
+#[post("/me/step-up/webauthn")]
+#[instrument(skip_all, ret, err)]
+pub async fn start_step_up_webauthn(
+    session: Session,
+    SessionUser { user, .. }: SessionUser,
+) -> ApiResult<ApiJson<WebAuthnSchema<RequestChallengeResponse>>> {
+    let mut tx = GLOBAL.db.start_transaction().await?;
+
+    let Some((local_user_uuid,)) = query!(&mut tx, (LocalUser::F.uuid,))
+        .condition(LocalUser::F.user.equals(user.uuid))
+        .optional()
+        .await?
+    else {
+        debug!("WebAuthn step-up was requested from a not-local user");
+        return Err(ApiError::BadRequest);
+    };
+
+    let keys = query!(&mut tx, (WebAuthnKey::F.key,))
+        .condition(WebAuthnKey::F.local_user.equals(local_user_uuid))
+        .stream()
+        .map_ok(|(json,)| json.0.passkey())
+        .try_collect::<Vec<_>>()
+        .await?;
+    if keys.is_empty() {
+        debug!("WebAuthn step-up was requested from a user without webauthn keys");
+        return Err(ApiError::BadRequest);
+    }
+
+    let (challenge, state) = GLOBAL.webauthn.start_passkey_authentication(&keys)?;
+
+    session
+        .insert(
+            SESSION_WEBAUTHN_STEP_UP,
+            WebAuthnAuthentication {
+                local_user: local_user_uuid,
+                state: WebAuthnAuthenticationState::NotAttested(state),
+            },
+        )
+        .await?;
+
+    Ok(ApiJson(WebAuthnSchema(challenge)))
+}
+
+/// Complete the webauthn challenge started by [`start_step_up_webauthn`]
+#[post("/me/step-up/complete-webauthn")]
+#[instrument(skip_all, ret, err)]
+pub async fn complete_step_up_webauthn(
+    session: Session,
+    SessionUser { user, .. }: SessionUser,
+    SchemalessJson(request): SchemalessJson<PublicKeyCredential>,
+) -> ApiResult<ApiJson<WebAuthnAuthenticateResult>> {
+    let Some(WebAuthnAuthentication {
+        local_user,
+        state: WebAuthnAuthenticationState::NotAttested(state),
+    }) = session.remove(SESSION_WEBAUTHN_STEP_UP).await?
+    else {
+        return Err(ApiError::BadRequest);
+    };
+
+    // The challenge has to be answered by the user who requested it
+    query!(&GLOBAL.db, (LocalUser::F.uuid,))
+        .condition(and![
+            LocalUser::F.uuid.equals(local_user),
+            LocalUser::F.user.equals(user.uuid)
+        ])
+        .optional()
+        .await?
+        .ok_or(ApiError::BadRequest)?;
+
+    if let Err(error) = GLOBAL
+        .webauthn
+        .finish_passkey_authentication(&request, &state)
+    {
+        debug!(error.display = %error, error.debug = ?error, "WebAuthn Challenge failed");
+        return Ok(ApiJson(WebAuthnAuthenticateResult::Err));
+    }
+    set_mfa_verified(&session).await?;
+
+    Ok(ApiJson(WebAuthnAuthenticateResult::Ok))
+}
+
+/// How long a requested change of mail can be confirmed
+const EMAIL_CHANGE_EXPIRY: Duration = Duration::hours(24);
+
+/// Request a change of the own mail
+///
+/// A confirmation link is sent to the new address and the old address is notified.
+/// Only one change can be pending at a time, requesting another one replaces it.
+///
+/// This may only be called by local users and requires a configured mailer.
+#[post("/me/change-email")]
+#[instrument(skip_all)]
+pub async fn change_email(
+    SessionUser { user, .. }: SessionUser,
+    ApiJson(request): ApiJson<ChangeEmailRequest>,
+) -> ApiResult<()> {
+    let Some(mailer) = GLOBAL.mailer.as_ref() else {
+        debug!("Change email was requested without a configured mailer");
+        return Err(ApiError::BadRequest);
+    };
+
+    let mut tx = GLOBAL.db.start_transaction().await?;
+
+    // The mail of oidc users is managed by the identity provider
+    if query!(&mut tx, (LocalUser::F.uuid,))
+        .condition(LocalUser::F.user.equals(user.uuid))
+        .optional()
+        .await?
+        .is_none()
+    {
+        debug!("Change email was requested from a not-local user");
+        return Err(ApiError::BadRequest);
+    }
+
+    rorm::delete!(&mut tx, EmailChange)
+        .condition(EmailChange::F.user.equals(user.uuid))
+        .await?;
+    let uuid = insert!(&mut tx, EmailChange)
+        .return_primary_key()
+        .single(&EmailChangeInsert {
+            uuid: Uuid::new_v4(),
+            user: ForeignModelByField::Key(user.uuid),
+            new_mail: request.mail.to_string(),
+            expires_at: OffsetDateTime::now_utc() + EMAIL_CHANGE_EXPIRY,
+        })
+        .await?;
+
+    tx.commit().await?;
+
+    let lang = UserLanguage::from_stored(&user.preferred_lang);
+    let link = new_email_change_link(&GLOBAL.origin, uuid);
+    tokio::spawn(async move {
+        mailer
+            .send_email_change_confirmation(&request.mail, &user.display_name, lang, &link)
+            .await;
+        mailer
+            .send_email_change_notice(&user.mail, &user.display_name, lang, &request.mail)
+            .await;
+    });
+
+    Ok(())
+}
+
+/// Confirm a requested change of the own mail
+///
+/// The uuid is taken from the link sent to the new address.
+#[post("/me/confirm-email/:uuid")]
+#[instrument(skip_all)]
+pub async fn confirm_email(
+    SessionUser { user, .. }: SessionUser,
+    Path(SingleUuid { uuid }): Path<SingleUuid>,
+) -> ApiResult<ApiJson<FormResult<(), ConfirmEmailErrors>>> {
+    let mut tx = GLOBAL.db.start_transaction().await?;
+
+    let (new_mail, expires_at) = query!(
+        &mut tx,
+        (EmailChange::F.new_mail, EmailChange::F.expires_at)
+    )
+    .condition(and![
+        EmailChange::F.uuid.equals(uuid),
+        EmailChange::F.user.equals(user.uuid)
+    ])
+    .optional()
+    .await?
+    .ok_or(ApiError::NotFound)?;
+
+    rorm::delete!(&mut tx, EmailChange)
+        .condition(EmailChange::F.uuid.equals(uuid))
+        .await?;
+
+    if expires_at < OffsetDateTime::now_utc() {
+        tx.commit().await?;
+        return Ok(ApiJson(FormResult::err(ConfirmEmailErrors {
+            expired: true,
+            ..Default::default()
+        })));
+    }
+
+    if query!(&mut tx, (User::F.uuid,))
+        .condition(User::F.mail.equals(&new_mail))
+        .optional()
+        .await?
+        .is_some()
+    {
+        tx.commit().await?;
+        return Ok(ApiJson(FormResult::err(ConfirmEmailErrors {
+            mail_taken: true,
+            ..Default::default()
+        })));
+    }
+
+    update!(&mut tx, User)
+        .condition(User::F.uuid.equals(user.uuid))
+        .set(User::F.mail, new_mail)
+        // The link has been sent to the new address
+        .set(User::F.email_verified, true)
         .exec()
         .await?;
 
     tx.commit().await?;
 
+    info!(user.uuid = %user.uuid, "User changed their mail");
+
     Ok(ApiJson(FormResult::ok(())))
 }
 
@@ -120,7 +739,7 @@ pub async fn create_totp_key(
     SessionUser { user, .. }: SessionUser,
     ApiJson(request): ApiJson<CreateTotpRequest>,
 ) -> ApiResult<ApiJson<FormResult<SingleUuid, CreateTotpErrors>>> {
-    let totp = match totp_from_base32(&request.secret) {
+    let totp = match totp_from_base32(&request.secret, GLOBAL.settings.get().totp_skew) {
         Ok(totp) => totp,
         Err(TotpFromError::InvalidBase32) => {
             return Ok(ApiJson(FormResult::err(CreateTotpErrors {
@@ -178,7 +797,7 @@ pub async fn create_totp_key(
 #[instrument(skip_all, ret, err)]
 pub async fn list_totp_keys(
     SessionUser { user, .. }: SessionUser,
-) -> ApiResult<ApiJson<List<SimpleTotpKey>>> {
+) -> ApiResult<ApiJson<Collection<SimpleTotpKey, NoFilters>>> {
     let mut tx = GLOBAL.db.start_transaction().await?;
 
     let (local_user_uuid,) = query!(&mut tx, (LocalUser::F.uuid,))
@@ -204,7 +823,7 @@ pub async fn list_totp_keys(
     drop(stream);
 
     tx.commit().await?;
-    Ok(ApiJson(List { list }))
+    Ok(ApiJson(Collection::all(list, NoFilters {})))
 }
 
 /// Removes a totp key
@@ -248,7 +867,7 @@ pub async fn create_webauthn_key(
     session: Session,
     SessionUser { user, .. }: SessionUser,
     ApiJson(request): ApiJson<CreateWebAuthnRequest>,
-) -> ApiResult<SchemalessJson<CreationChallengeResponse>> {
+) -> ApiResult<StartWebAuthnRegisterResponse> {
     let mut tx = GLOBAL.db.start_transaction().await?;
 
     let Some((local_user_uuid,)) = query!(&mut tx, (LocalUser::F.uuid,))
@@ -260,20 +879,25 @@ pub async fn create_webauthn_key(
         return Err(ApiError::BadRequest);
     };
 
-    let known_keys = query!(&GLOBAL.db, (WebAuthnKey::F.key,))
-        .condition(WebAuthnKey::F.local_user.equals(local_user_uuid))
-        .stream()
-        .map_ok(|(key,)| key.0.passkey().cred_id().clone())
-        .try_collect()
-        .await?;
+    let known_keys = get_registered_credentials(&mut tx).await?;
 
     let (challenge, state) = if request.can_login {
+        let ca_list = GLOBAL.webauthn_attestation_ca_list.get();
+        if ca_list.is_empty() {
+            warn!("Can't register a login key without attestation CAs");
+            return Ok(StartWebAuthnRegisterResponse::Rejected(
+                WebAuthnRegisterResult::AttestationUnavailable,
+            ));
+        }
+
+        // Attested passkey registrations require user verification,
+        // which is checked again upon every passwordless login
         let (challenge, state) = GLOBAL.webauthn.start_attested_passkey_registration(
             user.uuid,
             &user.mail,
             &user.display_name,
             Some(known_keys),
-            GLOBAL.webauthn_attestation_ca_list.clone(),
+            ca_list,
             None,
         )?;
         (challenge, WebAuthnRegistrationState::Attested(state))
@@ -291,6 +915,7 @@ pub async fn create_webauthn_key(
         .insert(
             SESSION_WEBAUTHN_REGISTRATION,
             WebAuthnRegistration {
+                timestamp: OffsetDateTime::now_utc(),
                 label: request.label,
                 local_user: local_user_uuid,
                 state,
@@ -298,7 +923,7 @@ pub async fn create_webauthn_key(
         )
         .await?;
 
-    Ok(SchemalessJson(challenge))
+    Ok(StartWebAuthnRegisterResponse::Challenge(challenge))
 }
 
 /// Complete the webauthn challenge for registering a new key
@@ -309,6 +934,7 @@ pub async fn complete_users_webauthn(
     SchemalessJson(request): SchemalessJson<RegisterPublicKeyCredential>,
 ) -> ApiResult<ApiJson<WebAuthnRegisterResult>> {
     let WebAuthnRegistration {
+        timestamp,
         label,
         local_user,
         state,
@@ -316,6 +942,10 @@ pub async fn complete_users_webauthn(
         .remove(SESSION_WEBAUTHN_REGISTRATION)
         .await?
         .ok_or(ApiError::BadRequest)?;
+    if is_enrollment_expired(timestamp) {
+        debug!("{SESSION_WEBAUTHN_REGISTRATION} expired");
+        return Err(ApiError::BadRequest);
+    }
 
     let webauthn_result = match state {
         WebAuthnRegistrationState::NotAttested(state) => GLOBAL
@@ -339,15 +969,31 @@ pub async fn complete_users_webauthn(
         }
     };
 
-    insert!(&GLOBAL.db, WebAuthnKey)
+    // webauthn-rs doesn't expose the user verification policy,
+    // so a key for password-less logins is checked explicitly
+    if matches!(key, MaybeAttestedPasskey::Attested(_)) && !key.user_verified() {
+        debug!("WebAuthn key for logging in was registered without user verification");
+        return Ok(ApiJson(WebAuthnRegisterResult::UserNotVerified));
+    }
+
+    let mut tx = GLOBAL.db.start_transaction().await?;
+    if is_credential_registered(&mut tx, key.cred_id()).await? {
+        debug!("WebAuthn credential is already registered");
+        return Ok(ApiJson(WebAuthnRegisterResult::RejectedDevice));
+    }
+
+    insert!(&mut tx, WebAuthnKey)
         .single(&WebAuthnKeyInsert {
             uuid: Uuid::new_v4(),
             local_user: ForeignModelByField::Key(local_user),
             label: label.into_inner(),
+            cred_id: Some(key.cred_id().to_vec()),
             key: key.into(),
         })
         .await?;
 
+    tx.commit().await?;
+
     Ok(ApiJson(WebAuthnRegisterResult::Ok))
 }
 
@@ -357,7 +1003,7 @@ pub async fn complete_users_webauthn(
 #[get("/me/webauthn")]
 pub async fn list_webauthn_keys(
     SessionUser { user, .. }: SessionUser,
-) -> ApiResult<ApiJson<List<SimpleWebAuthnKey>>> {
+) -> ApiResult<ApiJson<Collection<SimpleWebAuthnKey, NoFilters>>> {
     let mut tx = GLOBAL.db.start_transaction().await?;
 
     let (local_user_uuid,) = query!(&mut tx, (LocalUser::F.uuid,))
@@ -383,13 +1029,14 @@ pub async fn list_webauthn_keys(
             uuid,
             label: CheckedString::new(label).unwrap(),
             created_at: SchemaDateTime(created_at),
-            can_login: key.0.attested().is_some(),
+            can_login: key.0.can_login(),
+            user_verified: key.0.user_verified(),
         });
     }
     drop(stream);
 
     tx.commit().await?;
-    Ok(ApiJson(List { list }))
+    Ok(ApiJson(Collection::all(list, NoFilters {})))
 }
 
 /// Removes a totp key
@@ -419,3 +1066,87 @@ pub async fn delete_webauthn_key(
 
     Ok(())
 }
+
+/// Limits how often a user may request another verification link
+static RESEND_VERIFICATION_PER_USER: RateLimiter<Uuid> =
+    RateLimiter::new(3, std::time::Duration::from_secs(60 * 60));
+
+/// Limits how many verification links may be sent to a single address
+static RESEND_VERIFICATION_PER_MAIL: RateLimiter<String> =
+    RateLimiter::new(3, std::time::Duration::from_secs(60 * 60));
+
+/// Send a new link to verify the own mail
+///
+/// A previously sent link becomes invalid.
+///
+/// This requires a configured mailer and an unverified mail.
+/// Only a few links may be requested per hour.
+#[post("/me/resend-verification")]
+#[instrument(skip_all)]
+pub async fn resend_verification(SessionUser { user, .. }: SessionUser) -> ApiResult<()> {
+    if GLOBAL.mailer.is_none() {
+        debug!("Resend verification was requested without a configured mailer");
+        return Err(ApiError::BadRequest);
+    }
+    if user.email_verified {
+        debug!("Resend verification was requested for a verified mail");
+        return Err(ApiError::BadRequest);
+    }
+    RESEND_VERIFICATION_PER_USER
+        .check(user.uuid)
+        .and_then(|()| RESEND_VERIFICATION_PER_MAIL.check(user.mail.to_lowercase()))
+        .map_err(|retry_after_secs| ApiError::TooManyRequests { retry_after_secs })?;
+
+    let lang = UserLanguage::from_stored(&user.preferred_lang);
+    let link = start_email_verification(&GLOBAL.db, user.uuid, &user.mail).await?;
+    send_email_verification(user.mail, user.display_name, lang, link);
+
+    Ok(())
+}
+
+/// Verify the own mail
+///
+/// The uuid is taken from the link sent to the mail.
+#[post("/me/verify-email/:uuid")]
+#[instrument(skip_all)]
+pub async fn verify_email(
+    SessionUser { user, .. }: SessionUser,
+    Path(SingleUuid { uuid }): Path<SingleUuid>,
+) -> ApiResult<ApiJson<FormResult<(), VerifyEmailErrors>>> {
+    let mut tx = GLOBAL.db.start_transaction().await?;
+
+    let (mail, expires_at) = query!(
+        &mut tx,
+        (EmailVerification::F.mail, EmailVerification::F.expires_at)
+    )
+    .condition(and![
+        EmailVerification::F.uuid.equals(uuid),
+        EmailVerification::F.user.equals(user.uuid)
+    ])
+    .optional()
+    .await?
+    .ok_or(ApiError::NotFound)?;
+
+    rorm::delete!(&mut tx, EmailVerification)
+        .condition(EmailVerification::F.uuid.equals(uuid))
+        .await?;
+
+    if expires_at < OffsetDateTime::now_utc() || mail != user.mail {
+        tx.commit().await?;
+        return Ok(ApiJson(FormResult::err(VerifyEmailErrors {
+            expired: true,
+        })));
+    }
+
+    update!(&mut tx, User)
+        .condition(User::F.uuid.equals(user.uuid))
+        .set(User::F.email_verified, true)
+        .exec()
+        .await?;
+
+    tx.commit().await?;
+
+    info!(user.uuid = %user.uuid, "User verified their mail");
+
+    Ok(ApiJson(FormResult::ok(())))
+}
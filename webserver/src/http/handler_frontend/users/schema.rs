@@ -1,10 +1,18 @@
 //! The schema for the users
 
+use std::collections::HashMap;
+
 use schemars::JsonSchema;
+use serde::de::IgnoredAny;
 use serde::Deserialize;
+use serde::Deserializer;
 use serde::Serialize;
+use tracing::warn;
 use uuid::Uuid;
 
+use crate::http::handler_frontend::user_invites::schema::CreateUserInviteMailError;
+use crate::http::handler_frontend::user_invites::schema::SimpleUserInvite;
+use crate::models::UserRole;
 use crate::utils::checked_string::CheckedString;
 use crate::utils::schemars::SchemaDateTime;
 use crate::utils::secure_string::SecureString;
@@ -14,6 +22,16 @@ use crate::utils::secure_string::SecureString;
 pub struct ChangePwFormErrors {
     /// The provided current password was invalid
     pub current_pw: bool,
+
+    /// The new password doesn't meet the server's password policy
+    pub new_pw: bool,
+
+    /// The user has to provide their second factor using `totp_token`,
+    /// the webauthn step-up or by logging in again
+    pub step_up_required: bool,
+
+    /// The provided TOTP token was invalid
+    pub totp_token: bool,
 }
 
 /// The request to change the password
@@ -23,6 +41,100 @@ pub struct ChangePwRequest {
     pub current_pw: CheckedString<1, 255, SecureString>, // TODO replace this with "sudo" mode
     /// The password that should be set
     pub new_pw: CheckedString<1, 255, SecureString>,
+    /// A TOTP token, if the server requires the second factor (see `step_up_required`)
+    #[serde(default)]
+    pub totp_token: Option<CheckedString<6, 6>>,
+}
+
+/// The errors of the verify email request
+#[derive(Debug, Copy, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct VerifyEmailErrors {
+    /// The verification link has expired or the mail has been changed since it was sent
+    pub expired: bool,
+}
+
+/// The request to update the own profile
+///
+/// Omitted fields are left unchanged.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct UpdateMeRequest {
+    /// The new display name
+    #[serde(default)]
+    pub display_name: Option<CheckedString<1, 255>>,
+
+    /// The new preferred language
+    #[serde(default)]
+    pub preferred_lang: Option<UserLanguage>,
+}
+
+/// The request to resolve users' display information
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ResolveUsersRequest {
+    /// The users to resolve
+    ///
+    /// Unknown uuids are omitted from the response.
+    pub uuids: Vec<Uuid>,
+}
+
+/// The display information of users resolved by their uuids
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ResolveUsersResponse {
+    /// Maps the requested uuids to their users
+    pub users: HashMap<Uuid, ResolvedUser>,
+}
+
+/// The display information of a single user
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ResolvedUser {
+    /// The name that is used for displaying purposes
+    pub display_name: String,
+
+    /// The user's mail
+    ///
+    /// Only included if the requesting user is allowed to view users.
+    pub mail: Option<String>,
+
+    /// Has the user uploaded an avatar?
+    pub has_avatar: bool,
+}
+
+/// The errors of the update me request
+#[derive(Debug, Copy, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct UpdateMeErrors {
+    /// The display name was rejected by the configured policy
+    pub display_name: bool,
+
+    /// The display name is already used by another user or open invite
+    pub display_name_occupied: bool,
+}
+
+/// The errors of the set avatar request
+#[derive(Debug, Copy, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct SetAvatarErrors {
+    /// The image could not be decoded
+    pub invalid_image: bool,
+
+    /// The image exceeds the configured size
+    pub too_large: bool,
+}
+
+/// The request to change the own mail
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ChangeEmailRequest {
+    /// The new mail
+    ///
+    /// A confirmation link will be sent to this address.
+    pub mail: CheckedString<1, 255>,
+}
+
+/// The errors of the confirm email request
+#[derive(Debug, Copy, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct ConfirmEmailErrors {
+    /// The confirmation link has expired
+    pub expired: bool,
+
+    /// The new mail has been taken by another user in the meantime
+    pub mail_taken: bool,
 }
 
 /// The request to create a new TOTP key
@@ -94,6 +206,159 @@ pub struct SimpleWebAuthnKey {
 
     /// Can this key be used to log in directly or is it just a 2nd factor?
     pub can_login: bool,
+
+    /// Did the device verify the user (PIN, biometrics, etc.) when the key was registered?
+    pub user_verified: bool,
+}
+
+/// All login methods of a user
+///
+/// Local users may have a password and keys while oidc users are authenticated by the provider.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct UserAuthMethods {
+    /// Has the user set a password?
+    pub has_password: bool,
+
+    /// The user's TOTP keys
+    pub totp_keys: Vec<SimpleTotpKey>,
+
+    /// The user's WebAuthn keys
+    pub webauthn_keys: Vec<SimpleWebAuthnKey>,
+
+    /// Is the user authenticated through the OpenID Connect provider?
+    pub oidc: bool,
+}
+
+/// The filters for retrieving all users
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct GetAllUsersRequest {
+    /// Only return users whose mail or display name contain this string (case-insensitive)
+    pub search: Option<String>,
+
+    /// Only return users with this role
+    pub role: Option<UserRole>,
+
+    /// Only return users created at or after this point in time
+    pub created_since: Option<SchemaDateTime>,
+
+    /// Only return users created before this point in time
+    pub created_until: Option<SchemaDateTime>,
+
+    /// The order to return the users in
+    #[serde(default)]
+    pub order: UsersOrder,
+}
+
+/// The order to retrieve users in
+#[derive(Debug, Copy, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub enum UsersOrder {
+    /// Alphabetically by their mail
+    #[default]
+    Mail,
+    /// From the newest to the oldest account
+    Newest,
+    /// From the oldest to the newest account
+    Oldest,
+}
+
+/// The request to create a new (local) user
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct CreateUserRequest {
+    /// The mail of the user
+    pub mail: CheckedString<1, 255>,
+
+    /// The name that is used for displaying purposes
+    pub display_name: CheckedString<1, 255>,
+
+    /// The preferred language of the user
+    pub preferred_lang: UserLanguage,
+
+    /// The user's permissions
+    pub permissions: UserPermissions,
+
+    /// The user's initial password
+    ///
+    /// If omitted, an invite is created instead.
+    pub password: Option<CheckedString<1, 255, SecureString>>,
+}
+
+/// The response of the create user request
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(tag = "result")]
+pub enum CreateUserResponse {
+    /// The user has been created with the provided password
+    Created {
+        /// The new user
+        user: FullUser,
+    },
+    /// No password has been provided, so an invite has been created instead
+    Invited {
+        /// The new invite
+        invite: SimpleUserInvite,
+    },
+}
+
+/// The errors of the create user request
+#[derive(Debug, Copy, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct CreateUserErrors {
+    /// The `mail` is not unique
+    pub mail: Option<CreateUserInviteMailError>,
+
+    /// The `display_name` is rejected by the server's display name policy
+    pub display_name: bool,
+
+    /// The `display_name` is already used by another user or open invite
+    pub display_name_occupied: bool,
+
+    /// The `password` doesn't meet the server's password policy
+    pub password: bool,
+}
+
+/// The request to overwrite a user's password
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct SetUserPasswordRequest {
+    /// The password that should be set
+    pub password: CheckedString<1, 255, SecureString>,
+
+    /// Should the user be logged out from all of their sessions?
+    #[serde(default)]
+    pub logout: bool,
+}
+
+/// The errors of the set user password request
+#[derive(Debug, Copy, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct SetUserPasswordErrors {
+    /// The user is not a local user and therefore has no password
+    ///
+    /// (i.e. it is authenticated through OpenId Connect)
+    pub not_local: bool,
+
+    /// The `password` doesn't meet the server's password policy
+    pub password: bool,
+}
+
+/// A single-use link logging in a local user
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct MagicLoginLinkResponse {
+    /// The link to hand to the user
+    pub link: String,
+
+    /// Until when is the link valid
+    pub expires_at: SchemaDateTime,
+}
+
+/// The request to enable or disable a user
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, JsonSchema)]
+pub struct SetUserEnabledRequest {
+    /// Should the user be allowed to log in?
+    pub enabled: bool,
+}
+
+/// The response to logging a user out from all of their sessions
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, JsonSchema)]
+pub struct LogoutAllResponse {
+    /// The number of sessions which have been terminated
+    pub sessions: u64,
 }
 
 /// The full representation for the user
@@ -103,6 +368,8 @@ pub struct FullUser {
     pub uuid: Uuid,
     /// The mail of the user
     pub mail: String,
+    /// Has the user verified their mail?
+    pub email_verified: bool,
     /// Used for displaying purposes
     pub display_name: String,
     /// The preferred language of the user
@@ -111,22 +378,117 @@ pub struct FullUser {
     ///
     /// The `role`s of a user
     pub permissions: UserPermissions,
+    /// Is the user allowed to log in?
+    pub enabled: bool,
+    /// The point in time the user logged in the last time
+    pub last_login: Option<SchemaDateTime>,
+    /// The point in time the user was created
+    pub created_at: SchemaDateTime,
+    /// Has the user uploaded an avatar?
+    pub has_avatar: bool,
+    /// An overview over the user's login methods and sessions
+    ///
+    /// Only set by `/me` if requested using `include_auth_summary`.
+    pub auth_summary: Option<UserAuthSummary>,
+}
+
+/// The query parameters for retrieving the currently logged-in user
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, JsonSchema)]
+pub struct GetMeRequest {
+    /// Include the user's `auth_summary`
+    #[serde(default)]
+    pub include_auth_summary: bool,
+}
+
+/// An overview over a user's login methods and sessions
+///
+/// Use `/me/auth-methods` to retrieve the details.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct UserAuthSummary {
+    /// Has the user set a password?
+    pub has_password: bool,
+    /// Has the user registered any TOTP key?
+    pub has_totp: bool,
+    /// Has the user registered any WebAuthn key?
+    pub has_webauthn: bool,
+    /// Can any of the user's WebAuthn keys be used to log in without a password?
+    pub webauthn_can_login: bool,
+    /// The number of the user's sessions which haven't expired
+    pub active_sessions: u64,
+}
+
+/// A user as listed for administrators
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct AdminListUser {
+    /// The user
+    #[serde(flatten)]
+    pub user: FullUser,
+    /// How the user authenticates
+    pub auth_method: UserAuthMethod,
+    /// Has the user registered any 2nd factor (TOTP or WebAuthn key)?
+    pub has_mfa: bool,
+    /// Does the user have an open websocket connection?
+    pub online: bool,
+}
+
+/// How a user authenticates
+#[derive(Debug, Copy, Clone, Serialize, Deserialize, JsonSchema)]
+pub enum UserAuthMethod {
+    /// The user is authenticated locally
+    Local,
+    /// The user is authenticated through OpenId Connect
+    Oidc,
 }
 
 /// The possible languages of a user
-#[derive(PartialEq, Debug, Copy, Clone, Deserialize, Serialize, JsonSchema)]
+///
+/// ## Compatibility
+///
+/// This type is stored by its variant's name in [`User`](crate::models::User)s
+/// and [`UserInvite`](crate::models::UserInvite)s.
+/// Therefore, variants must never be renamed.
+/// Stored values are read using [`UserLanguage::from_stored`]
+/// which falls back to the default for values it doesn't know (e.g. after a downgrade).
+///
+/// Every variant requires translations in [`i18n`](crate::utils::i18n).
+#[derive(PartialEq, Debug, Copy, Clone, Default, Deserialize, Serialize, JsonSchema)]
 // Database conversion
 #[derive(strum::Display, strum::EnumString, strum::IntoStaticStr)]
 #[serde(tag = "type")]
 #[allow(missing_docs)]
 pub enum UserLanguage {
+    #[default]
     EN,
     DE,
+    FR,
+    ES,
+}
+impl UserLanguage {
+    /// Parse a language read from the database
+    ///
+    /// An unknown value is logged and replaced by the default language
+    /// instead of failing the request.
+    pub fn from_stored(value: &str) -> Self {
+        value.parse().unwrap_or_else(|_| {
+            warn!(
+                value,
+                "Unknown stored language, falling back to the default"
+            );
+            Self::default()
+        })
+    }
 }
 
 /// The user's permissions
 ///
 /// The `role`s of a user
+///
+/// ## Compatibility
+///
+/// This type is stored as json in [`UserInvite`](crate::models::UserInvite)s
+/// which have to remain acceptable across upgrades.
+/// Therefore, variants must never be renamed or removed
+/// and fields added to a variant must be `#[serde(default)]`.
 #[derive(PartialEq, Debug, Clone, Deserialize, Serialize, JsonSchema)]
 #[serde(tag = "role")]
 pub enum UserPermissions {
@@ -136,5 +498,197 @@ pub enum UserPermissions {
     Administrator,
 
     /// The internal role is assigned to our employees
-    Internal,
+    Internal {
+        /// The groups the user is a member of
+        ///
+        /// Invites created before groups existed don't have this field.
+        #[serde(default, deserialize_with = "deserialize_groups")]
+        groups: Vec<Uuid>,
+    },
+}
+
+/// Deserializes [`UserPermissions::Internal`]'s groups tolerating shapes stored by older versions
+///
+/// `null` means no groups and a single group may be stored without a list.
+/// A group is either its uuid or an object with a `uuid` field.
+/// Groups of any other shape are dropped, so the invite can still be accepted.
+fn deserialize_groups<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<Uuid>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum StoredGroups {
+        List(Vec<StoredGroup>),
+        Single(StoredGroup),
+    }
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum StoredGroup {
+        Uuid(Uuid),
+        Object { uuid: Uuid },
+        Unknown(IgnoredAny),
+    }
+
+    let groups = match Option::<StoredGroups>::deserialize(deserializer)? {
+        None => Vec::new(),
+        Some(StoredGroups::List(groups)) => groups,
+        Some(StoredGroups::Single(group)) => vec![group],
+    };
+    Ok(groups
+        .into_iter()
+        .filter_map(|group| match group {
+            StoredGroup::Uuid(uuid) | StoredGroup::Object { uuid } => Some(uuid),
+            StoredGroup::Unknown(_) => {
+                warn!("Dropped a group of unknown shape from stored permissions");
+                None
+            }
+        })
+        .collect())
+}
+
+impl UserPermissions {
+    /// The permissions of a user with `role` who isn't a member of any group
+    pub fn without_groups(role: UserRole) -> Self {
+        match role {
+            UserRole::Administrator => Self::Administrator,
+            UserRole::Internal => Self::Internal { groups: Vec::new() },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use time::OffsetDateTime;
+    use uuid::Uuid;
+
+    use super::SimpleTotpKey;
+    use super::UpdateMeRequest;
+    use super::UserAuthMethods;
+    use super::UserLanguage;
+    use super::UserPermissions;
+    use crate::models::UserRole;
+    use crate::utils::checked_string::CheckedString;
+    use crate::utils::schemars::SchemaDateTime;
+
+    #[test]
+    fn auth_methods_only_expose_key_metadata() -> Result<(), Box<dyn std::error::Error>> {
+        let methods = UserAuthMethods {
+            has_password: true,
+            totp_keys: vec![SimpleTotpKey {
+                uuid: Uuid::new_v4(),
+                label: CheckedString::new("Phone".to_string())?,
+                created_at: SchemaDateTime(OffsetDateTime::now_utc()),
+            }],
+            webauthn_keys: Vec::new(),
+            oidc: false,
+        };
+
+        let json = serde_json::to_value(&methods)?;
+        let mut fields: Vec<_> = json["totp_keys"][0]
+            .as_object()
+            .map(|key| key.keys().cloned().collect())
+            .unwrap_or_default();
+        fields.sort();
+        assert_eq!(fields, ["created_at", "label", "uuid"]);
+        assert_eq!(json["has_password"], true);
+        assert_eq!(json["oidc"], false);
+        Ok(())
+    }
+
+    #[test]
+    fn update_me_leaves_omitted_fields_unchanged() -> Result<(), serde_json::Error> {
+        let request: UpdateMeRequest = serde_json::from_str("{}")?;
+        assert!(request.display_name.is_none());
+        assert!(request.preferred_lang.is_none());
+
+        let request: UpdateMeRequest = serde_json::from_str(r#"{"preferred_lang":{"type":"DE"}}"#)?;
+        assert!(request.display_name.is_none());
+        assert_eq!(request.preferred_lang, Some(UserLanguage::DE));
+        Ok(())
+    }
+
+    #[test]
+    fn permissions_without_groups_have_no_groups() -> Result<(), serde_json::Error> {
+        let permissions: UserPermissions = serde_json::from_str(r#"{"role":"Internal"}"#)?;
+        assert_eq!(
+            permissions,
+            UserPermissions::Internal { groups: Vec::new() }
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn permissions_with_null_groups_have_no_groups() -> Result<(), serde_json::Error> {
+        let permissions: UserPermissions =
+            serde_json::from_str(r#"{"role":"Internal","groups":null}"#)?;
+        assert_eq!(
+            permissions,
+            UserPermissions::Internal { groups: Vec::new() }
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn permissions_accept_groups_of_older_shapes() -> Result<(), serde_json::Error> {
+        let uuid = Uuid::new_v4();
+        let permissions: UserPermissions = serde_json::from_str(&format!(
+            r#"{{"role":"Internal","groups":[{{"uuid":"{uuid}","name":"Sales"}}, 42]}}"#
+        ))?;
+        assert_eq!(
+            permissions,
+            UserPermissions::Internal { groups: vec![uuid] }
+        );
+
+        let permissions: UserPermissions =
+            serde_json::from_str(&format!(r#"{{"role":"Internal","groups":"{uuid}"}}"#))?;
+        assert_eq!(
+            permissions,
+            UserPermissions::Internal { groups: vec![uuid] }
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn permissions_round_trip() -> Result<(), serde_json::Error> {
+        let permissions = UserPermissions::Internal {
+            groups: vec![Uuid::new_v4(), Uuid::new_v4()],
+        };
+        let json = serde_json::to_string(&permissions)?;
+        assert_eq!(serde_json::from_str::<UserPermissions>(&json)?, permissions);
+        Ok(())
+    }
+
+    #[test]
+    fn roles_map_to_permissions_without_groups() {
+        assert_eq!(
+            UserPermissions::without_groups(UserRole::Administrator),
+            UserPermissions::Administrator
+        );
+        assert_eq!(
+            UserPermissions::without_groups(UserRole::Internal),
+            UserPermissions::Internal { groups: Vec::new() }
+        );
+    }
+
+    #[test]
+    fn known_stored_language_is_parsed() {
+        assert_eq!(UserLanguage::from_stored("DE"), UserLanguage::DE);
+    }
+
+    #[test]
+    fn unknown_stored_language_falls_back_to_default() {
+        assert_eq!(UserLanguage::from_stored("XX"), UserLanguage::default());
+        assert_eq!(UserLanguage::from_stored(""), UserLanguage::default());
+    }
+
+    #[test]
+    fn stored_language_round_trips() {
+        for lang in [
+            UserLanguage::EN,
+            UserLanguage::DE,
+            UserLanguage::FR,
+            UserLanguage::ES,
+        ] {
+            assert_eq!(UserLanguage::from_stored(&lang.to_string()), lang);
+        }
+    }
 }
@@ -1,71 +1,804 @@
 //! Utilities for working with [`users::schema`](super::schema)
 
+use std::collections::HashMap;
+use std::collections::HashSet;
+
+use rorm::and;
+use rorm::conditions::DynamicCollection;
+use rorm::db::transaction::Transaction;
 use rorm::db::Executor;
+use rorm::insert;
 use rorm::prelude::ForeignModelByField;
+use rorm::query;
 use rorm::update;
 use rorm::FieldAccess;
 use rorm::Model;
+use time::Duration;
+use time::OffsetDateTime;
+use tower_sessions::Expiry;
 use tower_sessions::Session;
+use tracing::debug;
+use tracing::info;
 use uuid::Uuid;
 
+use crate::global::GLOBAL;
 use crate::http::common::errors::ApiError;
 use crate::http::common::errors::ApiResult;
+use crate::http::extractors::device_info::DeviceInfo;
+use crate::http::handler_frontend::users::schema::AdminListUser;
 use crate::http::handler_frontend::users::schema::FullUser;
+use crate::http::handler_frontend::users::schema::ResolvedUser;
+use crate::http::handler_frontend::users::schema::SimpleTotpKey;
+use crate::http::handler_frontend::users::schema::SimpleWebAuthnKey;
+use crate::http::handler_frontend::users::schema::UserAuthMethod;
+use crate::http::handler_frontend::users::schema::UserAuthMethods;
+use crate::http::handler_frontend::users::schema::UserAuthSummary;
+use crate::http::handler_frontend::users::schema::UserLanguage;
 use crate::http::handler_frontend::users::schema::UserPermissions;
+use crate::http::handler_frontend::ws::schema::UserNotification;
+use crate::http::handler_frontend::ws::schema::WsServerMsg;
+use crate::http::session_keys::SESSION_LOGGED_IN_AT;
 use crate::http::session_keys::SESSION_USER;
 use crate::models;
+use crate::models::EmailVerification;
+use crate::models::EmailVerificationInsert;
+use crate::models::LocalUser;
+use crate::models::OidcUser;
+use crate::models::TotpKey;
 use crate::models::User;
+use crate::models::UserGroups;
 use crate::models::UserRole;
+use crate::models::WebAuthnKey;
+use crate::utils::checked_string::CheckedString;
+use crate::utils::links::new_email_verification_link;
+use crate::utils::schemars::SchemaDateTime;
 
-/// Construct the `UserPermissions` schema from a populated `User` model.
+/// Construct the `UserPermissions` schema for a `User` model.
 ///
-/// Errors:
-/// - if `user.groups` is not populated
-/// - if `user.role` is `UserRole::Customer` but `user.customers` is not populated
-#[track_caller]
-pub fn get_user_permissions(user: &User) -> ApiResult<UserPermissions> {
+/// The groups of internal users are queried from the database.
+pub async fn get_user_permissions(
+    executor: impl Executor<'_>,
+    user: &User,
+) -> ApiResult<UserPermissions> {
     Ok(match user.role.key().parse()? {
         UserRole::Administrator => UserPermissions::Administrator,
-        UserRole::Internal => UserPermissions::Internal,
+        UserRole::Internal => UserPermissions::Internal {
+            groups: query!(executor, (UserGroups::F.group,))
+                .condition(UserGroups::F.user.equals(user.uuid))
+                .all()
+                .await?
+                .into_iter()
+                .map(|(group,)| *group.key())
+                .collect(),
+        },
     })
 }
 
-/// Converts the populated `User` model into a `FullUser` schema.
+/// Converts the `User` model and its permissions into a `FullUser` schema.
 ///
-/// Errors:
-/// - if `user.groups` is not populated
-/// - if `user.role` is `UserRole::Customer` but `user.customers` is not populated
+/// The permissions are retrieved using [`get_user_permissions`].
 #[track_caller]
-pub fn new_full_user(user: User) -> ApiResult<FullUser> {
+pub fn new_full_user(user: User, permissions: UserPermissions) -> ApiResult<FullUser> {
     Ok(FullUser {
-        permissions: get_user_permissions(&user)?,
+        permissions,
         uuid: user.uuid,
         mail: user.mail,
+        email_verified: user.email_verified,
         display_name: user.display_name,
-        preferred_lang: user.preferred_lang.parse()?,
+        preferred_lang: UserLanguage::from_stored(&user.preferred_lang),
+        enabled: user.enabled,
+        last_login: user.last_login.map(SchemaDateTime),
+        created_at: SchemaDateTime(user.created_at),
+        has_avatar: user.avatar.is_some(),
+        auth_summary: None,
+    })
+}
+
+/// Retrieve the overview over a user's login methods and sessions
+///
+/// The keys and sessions are counted instead of loaded,
+/// only checking for password-less WebAuthn keys requires their public keys.
+pub async fn get_auth_summary(
+    executor: impl Executor<'_>,
+    user_uuid: Uuid,
+) -> ApiResult<UserAuthSummary> {
+    let mut guard = executor.ensure_transaction().await?;
+
+    let has_password = query!(guard.get_transaction(), (LocalUser::F.password,))
+        .condition(LocalUser::F.user.equals(user_uuid))
+        .optional()
+        .await?
+        .is_some_and(|(password,)| password.is_some());
+
+    let (totp_keys,) = query!(guard.get_transaction(), (TotpKey::F.uuid.count(),))
+        .condition(TotpKey::F.local_user.user.equals(user_uuid))
+        .one()
+        .await?;
+
+    let webauthn_keys = query!(guard.get_transaction(), (WebAuthnKey::F.key,))
+        .condition(WebAuthnKey::F.local_user.user.equals(user_uuid))
+        .all()
+        .await?;
+
+    let (active_sessions,) = query!(guard.get_transaction(), (models::Session::F.id.count(),))
+        .condition(and![
+            models::Session::F.user.equals(user_uuid),
+            models::Session::F
+                .expires_at
+                .greater_than(OffsetDateTime::now_utc())
+        ])
+        .one()
+        .await?;
+
+    guard.commit().await?;
+    Ok(UserAuthSummary {
+        has_password,
+        has_totp: totp_keys > 0,
+        has_webauthn: !webauthn_keys.is_empty(),
+        webauthn_can_login: webauthn_keys.iter().any(|(key,)| key.0.can_login()),
+        active_sessions: active_sessions as u64,
     })
 }
 
-/// Sets the user to logged in after completing an accept option
+/// Converts a user's display information into a `ResolvedUser` schema.
+///
+/// The mail is only included if `show_mail` is set.
+pub fn new_resolved_user(
+    display_name: String,
+    mail: String,
+    avatar: Option<String>,
+    show_mail: bool,
+) -> ResolvedUser {
+    ResolvedUser {
+        display_name,
+        mail: show_mail.then_some(mail),
+        has_avatar: avatar.is_some(),
+    }
+}
+
+/// Retrieve all login methods of a user
+///
+/// Only the keys' labels and metadata are loaded, never any secret.
+pub async fn get_user_auth_methods(
+    executor: impl Executor<'_>,
+    user_uuid: Uuid,
+) -> ApiResult<UserAuthMethods> {
+    let mut guard = executor.ensure_transaction().await?;
+
+    let oidc = query!(guard.get_transaction(), (OidcUser::F.uuid,))
+        .condition(OidcUser::F.user.equals(user_uuid))
+        .optional()
+        .await?
+        .is_some();
+
+    let mut methods = UserAuthMethods {
+        has_password: false,
+        totp_keys: Vec::new(),
+        webauthn_keys: Vec::new(),
+        oidc,
+    };
+
+    if let Some((local_user_uuid, password)) = query!(
+        guard.get_transaction(),
+        (LocalUser::F.uuid, LocalUser::F.password)
+    )
+    .condition(LocalUser::F.user.equals(user_uuid))
+    .optional()
+    .await?
+    {
+        methods.has_password = password.is_some();
+
+        methods.totp_keys = query!(
+            guard.get_transaction(),
+            (TotpKey::F.uuid, TotpKey::F.label, TotpKey::F.created_at)
+        )
+        .condition(TotpKey::F.local_user.equals(local_user_uuid))
+        .all()
+        .await?
+        .into_iter()
+        .map(|(uuid, label, created_at)| {
+            Ok(SimpleTotpKey {
+                uuid,
+                label: CheckedString::new(label)?,
+                created_at: SchemaDateTime(created_at),
+            })
+        })
+        .collect::<ApiResult<_>>()?;
+
+        methods.webauthn_keys = query!(
+            guard.get_transaction(),
+            (
+                WebAuthnKey::F.uuid,
+                WebAuthnKey::F.label,
+                WebAuthnKey::F.created_at,
+                WebAuthnKey::F.key,
+            )
+        )
+        .condition(WebAuthnKey::F.local_user.equals(local_user_uuid))
+        .all()
+        .await?
+        .into_iter()
+        .map(|(uuid, label, created_at, key)| {
+            Ok(SimpleWebAuthnKey {
+                uuid,
+                label: CheckedString::new(label)?,
+                created_at: SchemaDateTime(created_at),
+                can_login: key.0.can_login(),
+                user_verified: key.0.user_verified(),
+            })
+        })
+        .collect::<ApiResult<_>>()?;
+    }
+
+    guard.commit().await?;
+    Ok(methods)
+}
+
+/// Converts a page of `User` models into `AdminListUser` schemas.
+///
+/// The additional flags are retrieved for the whole page at once
+/// using a fixed number of queries, regardless of the number of users.
+pub async fn new_admin_list_users(
+    tx: &mut Transaction,
+    users: Vec<User>,
+) -> ApiResult<Vec<AdminListUser>> {
+    if users.is_empty() {
+        return Ok(Vec::new());
+    }
+    let uuids: Vec<Uuid> = users.iter().map(|user| user.uuid).collect();
+
+    let oidc_users: HashSet<Uuid> = query!(&mut *tx, (OidcUser::F.user,))
+        .condition(DynamicCollection::or(
+            uuids
+                .iter()
+                .map(|uuid| OidcUser::F.user.equals(*uuid))
+                .collect(),
+        ))
+        .all()
+        .await?
+        .into_iter()
+        .map(|(user,)| *user.key())
+        .collect();
+
+    let mut mfa_users: HashSet<Uuid> = query!(&mut *tx, (TotpKey::F.local_user.user,))
+        .condition(DynamicCollection::or(
+            uuids
+                .iter()
+                .map(|uuid| TotpKey::F.local_user.user.equals(*uuid))
+                .collect(),
+        ))
+        .all()
+        .await?
+        .into_iter()
+        .map(|(user,)| *user.key())
+        .collect();
+    mfa_users.extend(
+        query!(&mut *tx, (WebAuthnKey::F.local_user.user,))
+            .condition(DynamicCollection::or(
+                uuids
+                    .iter()
+                    .map(|uuid| WebAuthnKey::F.local_user.user.equals(*uuid))
+                    .collect(),
+            ))
+            .all()
+            .await?
+            .into_iter()
+            .map(|(user,)| *user.key()),
+    );
+
+    let mut groups: HashMap<Uuid, Vec<Uuid>> = HashMap::new();
+    for (user, group) in query!(&mut *tx, (UserGroups::F.user, UserGroups::F.group))
+        .condition(DynamicCollection::or(
+            uuids
+                .iter()
+                .map(|uuid| UserGroups::F.user.equals(*uuid))
+                .collect(),
+        ))
+        .all()
+        .await?
+    {
+        groups.entry(*user.key()).or_default().push(*group.key());
+    }
+
+    let online_users = GLOBAL.ws.online_users(uuids).await;
+
+    users
+        .into_iter()
+        .map(|user| {
+            let uuid = user.uuid;
+            let permissions = match user.role.key().parse()? {
+                UserRole::Administrator => UserPermissions::Administrator,
+                UserRole::Internal => UserPermissions::Internal {
+                    groups: groups.remove(&uuid).unwrap_or_default(),
+                },
+            };
+            Ok(AdminListUser {
+                user: new_full_user(user, permissions)?,
+                auth_method: if oidc_users.contains(&uuid) {
+                    UserAuthMethod::Oidc
+                } else {
+                    UserAuthMethod::Local
+                },
+                has_mfa: mfa_users.contains(&uuid),
+                online: online_users.contains(&uuid),
+            })
+        })
+        .collect()
+}
+
+/// Sets the user to logged in after completing any login method
+///
+/// This associates the session with the user and the device and records the user's `last_login`.
+///
+/// If enabled, the user is notified when they haven't logged in with the device before
+/// (see [`KnownDevice::remember`](models::KnownDevice::remember)).
+/// If the user exceeds the configured number of sessions, their least recently used ones are ended.
 pub async fn set_logged_in(
     executor: impl Executor<'_>,
     session: &Session,
     user_uuid: Uuid,
+    device: &DeviceInfo,
 ) -> ApiResult<()> {
+    let mut guard = executor.ensure_transaction().await?;
+
     session.insert(SESSION_USER, user_uuid).await?;
+    session
+        .insert(SESSION_LOGGED_IN_AT, OffsetDateTime::now_utc())
+        .await?;
+    // The session might have been shortened for a partial login
+    session.set_expiry(Some(Expiry::OnInactivity(GLOBAL.session_idle_timeout)));
     session.save().await?;
 
     let Some(id) = session.id() else {
-        return Err(ApiError::new_internal_server_error("No ID in session"));
+        return Err(ApiError::SessionCorrupt);
     };
-    update!(executor, models::Session)
-        .condition(models::Session::F.id.equals(id.to_string()))
+    let id = id.to_string();
+    let ip = device.ip.map(|ip| ip.to_string());
+
+    let user = query!(guard.get_transaction(), User)
+        .condition(User::F.uuid.equals(user_uuid))
+        .one()
+        .await?;
+    let settings = GLOBAL.settings.get();
+
+    let unknown_device = models::KnownDevice::remember(
+        guard.get_transaction(),
+        user_uuid,
+        device.user_agent.as_deref(),
+        ip.as_deref(),
+    )
+    .await?;
+    // A user's first login can't come from a known device
+    let is_new_device = settings.notify_new_devices && user.last_login.is_some() && unknown_device;
+
+    let evicted = end_excess_sessions(
+        guard.get_transaction(),
+        user_uuid,
+        &id,
+        settings.max_sessions_per_user,
+    )
+    .await?;
+
+    update!(guard.get_transaction(), models::Session)
+        .condition(models::Session::F.id.equals(id.as_str()))
         .set(
             models::Session::F.user,
             Some(ForeignModelByField::Key(user_uuid)),
         )
+        .set(models::Session::F.user_agent, device.user_agent.clone())
+        .set(models::Session::F.ip, ip.clone())
+        .exec()
+        .await?;
+
+    update!(guard.get_transaction(), User)
+        .condition(User::F.uuid.equals(user_uuid))
+        .set(User::F.last_login, Some(OffsetDateTime::now_utc()))
         .exec()
         .await?;
 
+    guard.commit().await?;
+
+    for id in evicted {
+        debug!(user.uuid = %user_uuid, "Ended a session exceeding the limit");
+        if let Ok(id) = id.parse() {
+            GLOBAL.ws.close_session(user_uuid, id).await;
+        }
+    }
+    if is_new_device {
+        info!(user.uuid = %user_uuid, user_agent = ?device.user_agent, ip = ?ip, "Login from a new device");
+        notify_new_device(user, device.user_agent.clone(), ip);
+    }
     Ok(())
 }
+
+/// Ends a user's least recently used sessions exceeding `max_sessions`
+///
+/// The session `current_id` is kept and counts towards the limit.
+/// `0` means unlimited.
+/// Returns the ids of the ended sessions whose websockets still have to be closed.
+async fn end_excess_sessions(
+    executor: impl Executor<'_>,
+    user_uuid: Uuid,
+    current_id: &str,
+    max_sessions: u32,
+) -> Result<Vec<String>, rorm::Error> {
+    if max_sessions == 0 {
+        return Ok(Vec::new());
+    }
+    let mut guard = executor.ensure_transaction().await?;
+
+    let other_sessions = query!(guard.get_transaction(), (models::Session::F.id,))
+        .condition(and![
+            models::Session::F.user.equals(user_uuid),
+            models::Session::F.id.not_equals(current_id)
+        ])
+        // Sessions expire on inactivity, so this orders them by their last use
+        .order_asc(models::Session::F.expires_at)
+        .all()
+        .await?;
+    let excess = (other_sessions.len() + 1).saturating_sub(max_sessions as usize);
+    let evicted: Vec<_> = other_sessions
+        .into_iter()
+        .take(excess)
+        .map(|(id,)| id)
+        .collect();
+    if !evicted.is_empty() {
+        rorm::delete!(guard.get_transaction(), models::Session)
+            .condition(DynamicCollection::or(
+                evicted
+                    .iter()
+                    .map(|id| models::Session::F.id.equals(id.as_str()))
+                    .collect(),
+            ))
+            .await?;
+    }
+
+    guard.commit().await?;
+    Ok(evicted)
+}
+
+/// Notifies a user about a login from a new device in the background
+fn notify_new_device(user: User, user_agent: Option<String>, ip: Option<String>) {
+    tokio::spawn(async move {
+        GLOBAL
+            .ws
+            .send_to_user(
+                user.uuid,
+                WsServerMsg::Notification {
+                    notification: UserNotification::NewDeviceLogin {
+                        user_agent: user_agent.clone(),
+                        ip: ip.clone(),
+                    },
+                },
+            )
+            .await;
+
+        let Some(mailer) = GLOBAL.mailer.as_ref() else {
+            return;
+        };
+        let lang = UserLanguage::from_stored(&user.preferred_lang);
+        mailer
+            .send_new_device_login(
+                &user.mail,
+                &user.display_name,
+                lang,
+                user_agent.as_deref().unwrap_or("-"),
+                ip.as_deref().unwrap_or("-"),
+            )
+            .await;
+    });
+}
+
+/// Starts the verification of a user's mail
+///
+/// A pending verification of the user is replaced.
+/// Returns the link which has to be sent to `mail` using [`send_email_verification`]
+/// after the transaction has been committed.
+pub async fn start_email_verification(
+    executor: impl Executor<'_>,
+    user_uuid: Uuid,
+    mail: &str,
+) -> Result<String, rorm::Error> {
+    let valid_for = GLOBAL.settings.get().email_verification_expiry;
+    let uuid = start_email_verification_with(executor, user_uuid, mail, valid_for).await?;
+    Ok(new_email_verification_link(&GLOBAL.origin, uuid))
+}
+
+/// Implementation of [`start_email_verification`] returning the new verification's uuid
+async fn start_email_verification_with(
+    executor: impl Executor<'_>,
+    user_uuid: Uuid,
+    mail: &str,
+    valid_for: Duration,
+) -> Result<Uuid, rorm::Error> {
+    let mut guard = executor.ensure_transaction().await?;
+
+    rorm::delete!(guard.get_transaction(), EmailVerification)
+        .condition(EmailVerification::F.user.equals(user_uuid))
+        .await?;
+    let uuid = insert!(guard.get_transaction(), EmailVerification)
+        .return_primary_key()
+        .single(&EmailVerificationInsert {
+            uuid: Uuid::new_v4(),
+            user: ForeignModelByField::Key(user_uuid),
+            mail: mail.to_string(),
+            expires_at: OffsetDateTime::now_utc() + valid_for,
+        })
+        .await?;
+
+    guard.commit().await?;
+    Ok(uuid)
+}
+
+/// Sends the link created by [`start_email_verification`] in the background
+///
+/// Does nothing if no mailer is configured.
+pub fn send_email_verification(
+    mail: String,
+    display_name: String,
+    lang: UserLanguage,
+    link: String,
+) {
+    let Some(mailer) = GLOBAL.mailer.as_ref() else {
+        return;
+    };
+    tokio::spawn(async move {
+        mailer
+            .send_email_verification(&mail, &display_name, lang, &link)
+            .await
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use rorm::fields::types::Json;
+    use rorm::insert;
+    use rorm::prelude::ForeignModelByField;
+    use rorm::query;
+    use rorm::FieldAccess;
+    use rorm::Model;
+    use time::Duration;
+    use time::OffsetDateTime;
+    use uuid::Uuid;
+
+    use super::end_excess_sessions;
+    use super::get_auth_summary;
+    use super::get_user_auth_methods;
+    use super::get_user_permissions;
+    use super::new_resolved_user;
+    use super::start_email_verification_with;
+    use crate::http::handler_frontend::users::schema::UserPermissions;
+    use crate::models::EmailVerification;
+    use crate::models::InternalGroup;
+    use crate::models::InternalGroupInsert;
+    use crate::models::LocalUser;
+    use crate::models::LocalUserInsert;
+    use crate::models::Session;
+    use crate::models::TotpKey;
+    use crate::models::TotpKeyInsert;
+    use crate::models::User;
+    use crate::utils::test_db;
+
+    #[tokio::test]
+    #[ignore = "requires a migrated database"]
+    async fn internal_users_are_only_assigned_existing_groups(
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let db = test_db::connect().await?;
+        let mut tx = db.start_transaction().await?;
+        let group = insert!(&mut tx, InternalGroup)
+            .return_primary_key()
+            .single(&InternalGroupInsert {
+                uuid: Uuid::new_v4(),
+                name: format!("group-{}", Uuid::new_v4()),
+            })
+            .await?;
+        let user = test_db::create_user(
+            &mut tx,
+            "member",
+            UserPermissions::Internal {
+                groups: vec![group, Uuid::new_v4()],
+            },
+        )
+        .await?;
+
+        let user = query!(&mut tx, User)
+            .condition(User::F.uuid.equals(user))
+            .one()
+            .await?;
+        let UserPermissions::Internal { groups } = get_user_permissions(&mut tx, &user).await?
+        else {
+            panic!("An internal user should have internal permissions");
+        };
+        assert_eq!(groups, [group]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a migrated database"]
+    async fn resending_replaces_the_previous_verification() -> Result<(), Box<dyn std::error::Error>>
+    {
+        let db = test_db::connect().await?;
+        let mut tx = db.start_transaction().await?;
+        let user = test_db::create_user(&mut tx, "verify", UserPermissions::Administrator).await?;
+
+        let valid_for = Duration::hours(1);
+        let first =
+            start_email_verification_with(&mut tx, user, "a@test.invalid", valid_for).await?;
+        let second =
+            start_email_verification_with(&mut tx, user, "b@test.invalid", valid_for).await?;
+        assert_ne!(first, second);
+
+        let verifications = query!(
+            &mut tx,
+            (
+                EmailVerification::F.uuid,
+                EmailVerification::F.mail,
+                EmailVerification::F.expires_at
+            )
+        )
+        .condition(EmailVerification::F.user.equals(user))
+        .all()
+        .await?;
+        assert_eq!(verifications.len(), 1);
+        let (uuid, mail, expires_at) = &verifications[0];
+        assert_eq!(*uuid, second);
+        assert_eq!(mail, "b@test.invalid");
+        assert!(*expires_at > OffsetDateTime::now_utc());
+        Ok(())
+    }
+
+    #[test]
+    fn resolved_user_includes_mail_if_allowed() {
+        let user = new_resolved_user(
+            "Jane Doe".to_string(),
+            "jane@example.com".to_string(),
+            None,
+            true,
+        );
+        assert_eq!(user.display_name, "Jane Doe");
+        assert_eq!(user.mail.as_deref(), Some("jane@example.com"));
+    }
+
+    #[test]
+    fn resolved_user_hides_mail_otherwise() {
+        let user = new_resolved_user(
+            "Jane Doe".to_string(),
+            "jane@example.com".to_string(),
+            None,
+            false,
+        );
+        assert_eq!(user.mail, None);
+    }
+
+    #[test]
+    fn resolved_user_reports_avatar() {
+        let user = new_resolved_user(
+            "Jane Doe".to_string(),
+            "jane@example.com".to_string(),
+            Some("avatar-key".to_string()),
+            false,
+        );
+        assert!(user.has_avatar);
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a migrated database"]
+    async fn least_recently_used_sessions_are_ended() -> Result<(), Box<dyn std::error::Error>> {
+        let db = test_db::connect().await?;
+        let mut tx = db.start_transaction().await?;
+        let user =
+            test_db::create_user(&mut tx, "sessions", UserPermissions::Administrator).await?;
+
+        let now = OffsetDateTime::now_utc();
+        let mut ids = Vec::new();
+        for hours in 1..=4 {
+            let id = Uuid::new_v4().to_string();
+            insert!(&mut tx, Session)
+                .return_nothing()
+                .single(&Session {
+                    id: id.clone(),
+                    expires_at: now + Duration::hours(hours),
+                    data: Json(HashMap::new()),
+                    user: Some(ForeignModelByField::Key(user)),
+                    user_agent: None,
+                    ip: None,
+                })
+                .await?;
+            ids.push(id);
+        }
+        // The current session is the oldest one to check that it is never ended
+        let current = &ids[0];
+
+        assert!(end_excess_sessions(&mut tx, user, current, 0)
+            .await?
+            .is_empty());
+        let evicted = end_excess_sessions(&mut tx, user, current, 2).await?;
+        assert_eq!(evicted, [ids[1].clone(), ids[2].clone()]);
+
+        let mut remaining: Vec<_> = query!(&mut tx, (Session::F.id,))
+            .condition(Session::F.user.equals(user))
+            .all()
+            .await?
+            .into_iter()
+            .map(|(id,)| id)
+            .collect();
+        remaining.sort();
+        let mut expected = [ids[0].clone(), ids[3].clone()];
+        expected.sort();
+        assert_eq!(remaining, expected);
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a migrated database"]
+    async fn auth_summary_counts_only_active_sessions() -> Result<(), Box<dyn std::error::Error>> {
+        let db = test_db::connect().await?;
+        let mut tx = db.start_transaction().await?;
+        let user = test_db::create_user(&mut tx, "summary", UserPermissions::Administrator).await?;
+
+        let now = OffsetDateTime::now_utc();
+        for expires_at in [now + Duration::hours(1), now - Duration::hours(1)] {
+            insert!(&mut tx, Session)
+                .return_nothing()
+                .single(&Session {
+                    id: Uuid::new_v4().to_string(),
+                    expires_at,
+                    data: Json(HashMap::new()),
+                    user: Some(ForeignModelByField::Key(user)),
+                    user_agent: None,
+                    ip: None,
+                })
+                .await?;
+        }
+
+        let summary = get_auth_summary(&mut tx, user).await?;
+        assert!(!summary.has_password);
+        assert!(!summary.has_totp);
+        assert!(!summary.has_webauthn);
+        assert!(!summary.webauthn_can_login);
+        assert_eq!(summary.active_sessions, 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a migrated database"]
+    async fn auth_methods_list_the_users_keys() -> Result<(), Box<dyn std::error::Error>> {
+        let db = test_db::connect().await?;
+        let mut tx = db.start_transaction().await?;
+        let user = test_db::create_user(&mut tx, "methods", UserPermissions::Administrator).await?;
+
+        let methods = get_user_auth_methods(&mut tx, user).await?;
+        assert!(!methods.has_password);
+        assert!(methods.totp_keys.is_empty());
+        assert!(!methods.oidc);
+
+        let local_user = insert!(&mut tx, LocalUser)
+            .return_primary_key()
+            .single(&LocalUserInsert {
+                uuid: Uuid::new_v4(),
+                user: ForeignModelByField::Key(user),
+                password: Some("hash".to_string()),
+            })
+            .await?;
+        let totp_key = insert!(&mut tx, TotpKey)
+            .return_primary_key()
+            .single(&TotpKeyInsert {
+                uuid: Uuid::new_v4(),
+                local_user: ForeignModelByField::Key(local_user),
+                secret: vec![0; 20],
+                label: "Phone".to_string(),
+            })
+            .await?;
+
+        let methods = get_user_auth_methods(&mut tx, user).await?;
+        assert!(methods.has_password);
+        assert_eq!(methods.totp_keys.len(), 1);
+        assert_eq!(methods.totp_keys[0].uuid, totp_key);
+        assert_eq!(&*methods.totp_keys[0].label, "Phone");
+        assert!(methods.webauthn_keys.is_empty());
+        assert!(!methods.oidc);
+        Ok(())
+    }
+}
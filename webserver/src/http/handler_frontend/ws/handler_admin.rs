@@ -0,0 +1,110 @@
+//! Admin handlers for websockets
+
+use rorm::db::Executor;
+use serde_json::json;
+use swaggapi::post;
+use tracing::info;
+use uuid::Uuid;
+
+use crate::global::ws::GlobalWs;
+use crate::global::GLOBAL;
+use crate::http::common::errors::ApiResult;
+use crate::http::extractors::api_json::ApiJson;
+use crate::http::extractors::session_user::SessionUser;
+use crate::http::handler_frontend::ws::schema::DisconnectAllRequest;
+use crate::models::AuditAction;
+use crate::models::AuditLog;
+
+/// Close the websocket connections of all users
+///
+/// This may be used before a deploy to make clients reconnect to the new instance.
+#[post("/disconnect-all")]
+pub async fn disconnect_all(
+    SessionUser { user: admin, .. }: SessionUser,
+    ApiJson(DisconnectAllRequest { reason }): ApiJson<DisconnectAllRequest>,
+) -> ApiResult<()> {
+    disconnect_all_with(&GLOBAL.db, &GLOBAL.ws, admin.uuid, reason).await?;
+    info!(
+        admin.uuid = %admin.uuid,
+        admin.display_name = admin.display_name,
+        "Disconnected all websockets"
+    );
+
+    Ok(())
+}
+
+/// Implementation of [`disconnect_all`] with a given database and websocket manager
+async fn disconnect_all_with(
+    executor: impl Executor<'_>,
+    ws: &GlobalWs,
+    admin: Uuid,
+    reason: Option<String>,
+) -> Result<(), rorm::Error> {
+    AuditLog::audit(
+        executor,
+        Some(admin),
+        AuditAction::WebsocketsDisconnected,
+        None,
+        json!({ "reason": reason }),
+    )
+    .await?;
+
+    ws.close_all(reason).await;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use rorm::FieldAccess;
+    use rorm::Model;
+    use tokio::sync::mpsc;
+    use tower_sessions::session::Id;
+    use uuid::Uuid;
+
+    use super::disconnect_all_with;
+    use crate::global::ws::GlobalWs;
+    use crate::http::handler_frontend::ws::schema::WsServerMsg;
+    use crate::models::AuditAction;
+    use crate::models::AuditLog;
+    use crate::utils::test_db;
+
+    #[tokio::test]
+    #[ignore = "requires a migrated database"]
+    async fn closes_the_connections_and_audits_it() -> Result<(), Box<dyn std::error::Error>> {
+        let db = test_db::connect().await?;
+        let mut tx = db.start_transaction().await?;
+        let ws = GlobalWs::new();
+        let (admin, user) = (Uuid::new_v4(), Uuid::new_v4());
+
+        let (sender, mut rx) = mpsc::channel(2);
+        assert!(
+            ws.register_ws(sender, user, Id::default(), Uuid::new_v4())
+                .await
+        );
+
+        let reason = Some("Deploying a new version".to_string());
+        disconnect_all_with(&mut tx, &ws, admin, reason.clone()).await?;
+
+        assert!(ws.online_users(vec![user]).await.is_empty());
+        assert!(matches!(
+            rx.recv().await,
+            Some(WsServerMsg::Disconnect { reason: received }) if received == reason
+        ));
+        assert!(matches!(rx.recv().await, Some(WsServerMsg::Close)));
+
+        let entries = rorm::query!(&mut tx, AuditLog)
+            .condition(
+                AuditLog::F
+                    .action
+                    .equals(AuditAction::WebsocketsDisconnected.to_string()),
+            )
+            .all()
+            .await?;
+        let entry = entries
+            .into_iter()
+            .find(|entry| entry.actor == Some(admin))
+            .ok_or("The disconnect wasn't audited")?;
+        assert_eq!(entry.detail.0["reason"], "Deploying a new version");
+        Ok(())
+    }
+}
@@ -1,11 +1,13 @@
 //! The handler for the websocket
 
+use std::str::FromStr;
 use std::sync::Arc;
 use std::time::Duration;
 use std::time::Instant;
 
 use axum::extract::ws::Message;
 use axum::extract::ws::WebSocket;
+use axum::extract::Query;
 use axum::extract::WebSocketUpgrade;
 use axum::response::IntoResponse;
 use axum::response::Response;
@@ -14,6 +16,11 @@ use futures::stream::SplitStream;
 use futures::StreamExt;
 use futures_util::SinkExt;
 use futures_util::TryStreamExt;
+use rorm::db::Executor;
+use rorm::prelude::ForeignModelByField;
+use rorm::query;
+use rorm::FieldAccess;
+use rorm::Model;
 use swaggapi::as_responses::simple_responses;
 use swaggapi::as_responses::AsResponses;
 use swaggapi::as_responses::SimpleResponse;
@@ -21,17 +28,25 @@ use swaggapi::get;
 use swaggapi::internals::SchemaGenerator;
 use swaggapi::re_exports::mime::APPLICATION_OCTET_STREAM;
 use swaggapi::re_exports::openapiv3::Responses;
+use time::OffsetDateTime;
 use tokio::sync::mpsc;
 use tokio::sync::Mutex;
+use tokio::task::JoinSet;
+use tower_sessions::session::Id;
 use tower_sessions::Session;
 use tracing::debug;
 use tracing::trace;
+use uuid::Uuid;
 
 use crate::global::GLOBAL;
 use crate::http::common::errors::ApiError;
+use crate::http::common::errors::ApiResult;
 use crate::http::extractors::session_user::SessionUser;
+use crate::http::handler_frontend::ws::schema::WebsocketQuery;
 use crate::http::handler_frontend::ws::schema::WsClientMsg;
 use crate::http::handler_frontend::ws::schema::WsServerMsg;
+use crate::models::User;
+use crate::models::WsTicket;
 
 struct WsResponse(Response);
 
@@ -52,17 +67,31 @@ impl AsResponses for WsResponse {
     }
 }
 
+/// Retrieves the id of a session which has already been persisted
+///
+/// A logged-in user's session has always been persisted,
+/// so a missing id means the session is corrupt.
+fn persisted_session_id(session: &Session) -> Result<Id, ApiError> {
+    session.id().ok_or(ApiError::SessionCorrupt)
+}
+
+/// Upgrade the connection to a websocket
+///
+/// The user is authenticated by the session cookie
+/// or by a `ticket` issued by `/users/me/ws-ticket` for clients which can't send the cookie.
 #[get("/ws")]
 pub async fn websocket(
     ws: WebSocketUpgrade,
-    SessionUser { user, .. }: SessionUser,
     session: Session,
+    Query(WebsocketQuery { ticket }): Query<WebsocketQuery>,
 ) -> WsResponse {
-    let Some(id) = session.id() else {
-        return WsResponse(
-            ApiError::new_internal_server_error("Couldn't retrieve session".to_string())
-                .into_response(),
-        );
+    let authenticated = match ticket {
+        Some(ticket) => redeem_ticket(ticket).await,
+        None => authenticate_session(&session).await,
+    };
+    let (user, id) = match authenticated {
+        Ok(authenticated) => authenticated,
+        Err(error) => return WsResponse(error.into_response()),
     };
 
     WsResponse(ws.on_upgrade(move |ws| async move {
@@ -73,20 +102,82 @@ pub async fn websocket(
 
         let last_hb = Arc::new(Mutex::new(Instant::now()));
 
-        let convert_handle = tokio::spawn(convert_to_send(sender_tx.clone(), tx_rx));
-        let send_handle = tokio::spawn(handle_send(sender, sender_rx));
-        let recv_handle = tokio::spawn(handle_recv(sender_tx.clone(), receiver));
-        let heartbeat_handle = tokio::spawn(heartbeat(last_hb.clone(), sender_tx));
+        let mut tasks = JoinSet::new();
+        tasks.spawn(convert_to_send(sender_tx.clone(), tx_rx));
+        tasks.spawn(handle_send(sender, sender_rx));
+        tasks.spawn(handle_recv(sender_tx.clone(), receiver, last_hb.clone()));
+        tasks.spawn(heartbeat(last_hb, sender_tx));
 
-        if !GLOBAL.ws.register_ws(tx_tx, user.uuid, id).await {
-            heartbeat_handle.abort();
-            convert_handle.abort();
-            send_handle.abort();
-            recv_handle.abort();
+        let conn_id = Uuid::new_v4();
+        if !GLOBAL.ws.register_ws(tx_tx, user.uuid, id, conn_id).await {
+            tasks.abort_all();
+            return;
         }
+
+        // As soon as any of the tasks finishes, the connection is considered dead.
+        // Cancel the remaining ones and remove the connection from the manager.
+        tasks.join_next().await;
+        tasks.abort_all();
+        GLOBAL.ws.deregister(user.uuid, id, conn_id).await;
+        debug!("Websocket connection closed");
     }))
 }
 
+/// Authenticates the websocket using the session cookie
+async fn authenticate_session(session: &Session) -> ApiResult<(User, Id)> {
+    let SessionUser { user, .. } = SessionUser::from_session(session).await?;
+    let id = persisted_session_id(session)?;
+    Ok((user, id))
+}
+
+/// Authenticates the websocket using a [`WsTicket`]
+///
+/// The ticket is consumed, even if it has expired.
+async fn redeem_ticket(ticket: Uuid) -> ApiResult<(User, Id)> {
+    let (user, id) = consume_ticket(&GLOBAL.db, ticket).await?;
+    let SessionUser { user, .. } = SessionUser::load(user).await?;
+    Ok((user, id))
+}
+
+/// Consumes a [`WsTicket`] returning the user and session it was issued for
+///
+/// The ticket is consumed, even if it has expired.
+async fn consume_ticket(executor: impl Executor<'_>, ticket: Uuid) -> ApiResult<(Uuid, Id)> {
+    let mut guard = executor.ensure_transaction().await?;
+
+    let Some((ForeignModelByField::Key(id), expires_at, user)) = query!(
+        guard.get_transaction(),
+        (
+            WsTicket::F.session,
+            WsTicket::F.expires_at,
+            WsTicket::F.session.user,
+        )
+    )
+    .condition(WsTicket::F.uuid.equals(ticket))
+    .optional()
+    .await?
+    else {
+        debug!("Unknown websocket ticket");
+        return Err(ApiError::Unauthenticated);
+    };
+
+    rorm::delete!(guard.get_transaction(), WsTicket)
+        .condition(WsTicket::F.uuid.equals(ticket))
+        .await?;
+    guard.commit().await?;
+
+    if expires_at < OffsetDateTime::now_utc() {
+        debug!("Websocket ticket expired");
+        return Err(ApiError::Unauthenticated);
+    }
+    let Some(ForeignModelByField::Key(user)) = user else {
+        debug!("The websocket ticket's session has been logged out");
+        return Err(ApiError::Unauthenticated);
+    };
+    let id = Id::from_str(&id).map_err(ApiError::new_internal_server_error)?;
+    Ok((user, id))
+}
+
 #[derive(Debug, Clone)]
 enum SendInstruction {
     Message(Message),
@@ -170,6 +261,7 @@ async fn handle_send(
 async fn handle_recv(
     sender_tx: mpsc::Sender<SendInstruction>,
     mut receiver: SplitStream<WebSocket>,
+    last_hb: Arc<Mutex<Instant>>,
 ) {
     while let Ok(Some(msg)) = receiver.try_next().await {
         match msg {
@@ -189,6 +281,10 @@ async fn handle_recv(
                     return;
                 }
             }
+            Message::Pong(_) => {
+                trace!("Received WS pong");
+                *last_hb.lock().await = Instant::now();
+            }
             Message::Close(_) => {
                 debug!("Client sent ws close");
                 return;
@@ -197,3 +293,128 @@ async fn handle_recv(
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    use rorm::fields::types::Json;
+    use rorm::insert;
+    use rorm::prelude::ForeignModelByField;
+    use rorm::FieldAccess;
+    use rorm::Model;
+    use time::Duration;
+    use time::OffsetDateTime;
+    use tower_sessions::session::Id;
+    use tower_sessions::MemoryStore;
+    use uuid::Uuid;
+
+    use super::consume_ticket;
+    use super::persisted_session_id;
+    use crate::http::common::errors::ApiError;
+    use crate::http::handler_frontend::users::schema::UserPermissions;
+    use crate::models::Session;
+    use crate::models::WsTicket;
+    use crate::utils::test_db;
+
+    #[test]
+    fn session_without_id_is_corrupt() {
+        let session = tower_sessions::Session::new(None, Arc::new(MemoryStore::default()), None);
+        assert!(matches!(
+            persisted_session_id(&session),
+            Err(ApiError::SessionCorrupt)
+        ));
+    }
+
+    /// Inserts a session of `user` and a ticket for it expiring in `expires_in`
+    async fn issue_ticket(
+        tx: &mut rorm::db::transaction::Transaction,
+        user: Option<Uuid>,
+        expires_in: Duration,
+    ) -> Result<(Id, Uuid), Box<dyn std::error::Error>> {
+        let id = Id::default();
+        let now = OffsetDateTime::now_utc();
+        insert!(&mut *tx, Session)
+            .return_nothing()
+            .single(&Session {
+                id: id.to_string(),
+                expires_at: now + Duration::hours(1),
+                data: Json(HashMap::new()),
+                user: user.map(ForeignModelByField::Key),
+                user_agent: None,
+                ip: None,
+            })
+            .await?;
+        let ticket = insert!(&mut *tx, WsTicket)
+            .return_primary_key()
+            .single(&WsTicket {
+                uuid: Uuid::new_v4(),
+                session: ForeignModelByField::Key(id.to_string()),
+                expires_at: now + expires_in,
+            })
+            .await?;
+        Ok((id, ticket))
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a migrated database"]
+    async fn tickets_are_consumed_exactly_once() -> Result<(), Box<dyn std::error::Error>> {
+        let db = test_db::connect().await?;
+        let mut tx = db.start_transaction().await?;
+        let user = test_db::create_user(&mut tx, "ws", UserPermissions::Administrator).await?;
+        let (id, ticket) = issue_ticket(&mut tx, Some(user), Duration::seconds(30)).await?;
+
+        assert_eq!(consume_ticket(&mut tx, ticket).await?, (user, id));
+        assert!(matches!(
+            consume_ticket(&mut tx, ticket).await,
+            Err(ApiError::Unauthenticated)
+        ));
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a migrated database"]
+    async fn expired_tickets_are_rejected_and_consumed() -> Result<(), Box<dyn std::error::Error>> {
+        let db = test_db::connect().await?;
+        let mut tx = db.start_transaction().await?;
+        let user = test_db::create_user(&mut tx, "ws", UserPermissions::Administrator).await?;
+        let (_, ticket) = issue_ticket(&mut tx, Some(user), Duration::seconds(-1)).await?;
+
+        assert!(matches!(
+            consume_ticket(&mut tx, ticket).await,
+            Err(ApiError::Unauthenticated)
+        ));
+        let (remaining,) = rorm::query!(&mut tx, (WsTicket::F.uuid.count(),))
+            .condition(WsTicket::F.uuid.equals(ticket))
+            .one()
+            .await?;
+        assert_eq!(remaining, 0);
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a migrated database"]
+    async fn tickets_of_logged_out_sessions_are_rejected() -> Result<(), Box<dyn std::error::Error>>
+    {
+        let db = test_db::connect().await?;
+        let mut tx = db.start_transaction().await?;
+        let user = test_db::create_user(&mut tx, "ws", UserPermissions::Administrator).await?;
+
+        // Logging out deletes the session
+        let (_, ticket) = issue_ticket(&mut tx, Some(user), Duration::seconds(30)).await?;
+        Session::delete_by_user(&mut tx, user).await?;
+        assert!(matches!(
+            consume_ticket(&mut tx, ticket).await,
+            Err(ApiError::Unauthenticated)
+        ));
+
+        // A session which isn't logged in anymore
+        let (_, ticket) = issue_ticket(&mut tx, None, Duration::seconds(30)).await?;
+        assert!(matches!(
+            consume_ticket(&mut tx, ticket).await,
+            Err(ApiError::Unauthenticated)
+        ));
+        Ok(())
+    }
+}
@@ -1,4 +1,5 @@
 //! Handler and schema for the websocket
 
+pub mod handler_admin;
 pub mod handler_common;
 pub mod schema;
@@ -3,6 +3,10 @@
 use schemars::JsonSchema;
 use serde::Deserialize;
 use serde::Serialize;
+use uuid::Uuid;
+
+use crate::http::handler_frontend::users::schema::UserPermissions;
+use crate::utils::schemars::SchemaDateTime;
 
 /// Websocket messages that originate from the server
 #[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
@@ -13,9 +17,111 @@ pub enum WsServerMsg {
     /// This variant is used to close the websocket connection
     #[serde(skip_serializing, skip_deserializing)]
     Close,
+    /// The server is about to close the connection
+    ///
+    /// The client may reconnect afterward.
+    Disconnect {
+        /// A reason which may be displayed to the user
+        reason: Option<String>,
+    },
+    /// An admin changed the user's permissions
+    ///
+    /// The frontend should re-evaluate what it shows.
+    PermissionsChanged {
+        /// The user's new permissions
+        permissions: UserPermissions,
+    },
+    /// Something the user should be made aware of
+    Notification {
+        /// The notification to display
+        notification: UserNotification,
+    },
+}
+
+/// Notifications pushed to a user's open websockets
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(tag = "type")]
+pub enum UserNotification {
+    /// The user's account has been logged into from a device they haven't used before
+    NewDeviceLogin {
+        /// The new device's user agent
+        user_agent: Option<String>,
+        /// The new device's ip address
+        ip: Option<String>,
+    },
+}
+
+/// The request to close all websocket connections
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+pub struct DisconnectAllRequest {
+    /// A reason which is sent to the clients before closing their connections
+    pub reason: Option<String>,
 }
 
 /// Websocket messages that originate from the client
 #[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
 #[serde(tag = "type")]
 pub enum WsClientMsg {}
+
+/// The query parameters when opening a websocket
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+pub struct WebsocketQuery {
+    /// A ticket issued by `/users/me/ws-ticket`
+    ///
+    /// Authenticates clients which can't send the session cookie when opening the websocket.
+    pub ticket: Option<Uuid>,
+}
+
+/// A ticket to open a websocket without sending the session cookie
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+pub struct WsTicketResponse {
+    /// The ticket to pass as `ticket` query parameter to `/ws`
+    ///
+    /// It can only be used once.
+    pub ticket: Uuid,
+
+    /// Until when is the ticket valid
+    pub expires_at: SchemaDateTime,
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use uuid::Uuid;
+
+    use super::WsServerMsg;
+    use crate::http::handler_frontend::users::schema::UserPermissions;
+
+    #[test]
+    fn permissions_changed_contains_the_new_permissions() -> Result<(), serde_json::Error> {
+        let group = Uuid::new_v4();
+        let msg = WsServerMsg::PermissionsChanged {
+            permissions: UserPermissions::Internal {
+                groups: vec![group],
+            },
+        };
+        assert_eq!(
+            serde_json::to_value(&msg)?,
+            json!({
+                "type": "PermissionsChanged",
+                "permissions": { "role": "Internal", "groups": [group] },
+            })
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn permissions_changed_to_administrator() -> Result<(), serde_json::Error> {
+        let msg = WsServerMsg::PermissionsChanged {
+            permissions: UserPermissions::Administrator,
+        };
+        assert_eq!(
+            serde_json::to_value(&msg)?,
+            json!({
+                "type": "PermissionsChanged",
+                "permissions": { "role": "Administrator" },
+            })
+        );
+        Ok(())
+    }
+}
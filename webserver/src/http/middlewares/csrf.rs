@@ -0,0 +1,48 @@
+//! Cross-site request forgery protection middleware
+
+use axum::extract::Request;
+use axum::http::HeaderName;
+use axum::middleware::Next;
+use axum::response::Response;
+use tower_sessions::Session;
+
+use crate::config::CsrfMode;
+use crate::global::GLOBAL;
+use crate::http::common::errors::ApiError;
+use crate::http::common::errors::ApiResult;
+use crate::http::session_keys::SESSION_CSRF_TOKEN;
+use crate::utils::constant_time::constant_time_eq;
+
+/// The header state-changing requests have to carry the csrf token in
+pub static X_CSRF_TOKEN: HeaderName = HeaderName::from_static("x-csrf-token");
+
+/// Rejects state-changing requests which might have been issued by another site
+///
+/// Requests using a safe method (i.e. `GET`, `HEAD` and `OPTIONS`) are always let through.
+/// What is required of other requests depends on the configured [`CsrfMode`].
+pub async fn csrf_protection(session: Session, req: Request, next: Next) -> ApiResult<Response> {
+    if req.method().is_safe() {
+        return Ok(next.run(req).await);
+    }
+
+    let header = req.headers().get(&X_CSRF_TOKEN);
+    match GLOBAL.settings.get().csrf_mode {
+        CsrfMode::Token => {
+            let token = session.get::<String>(SESSION_CSRF_TOKEN).await?;
+            let matches = token.zip(header).is_some_and(|(token, header)| {
+                constant_time_eq(token.as_bytes(), header.as_bytes())
+            });
+            if !matches {
+                return Err(ApiError::CsrfFailed);
+            }
+        }
+        CsrfMode::Header => {
+            if header.is_none() {
+                return Err(ApiError::CsrfFailed);
+            }
+        }
+        CsrfMode::Disabled => {}
+    }
+
+    Ok(next.run(req).await)
+}
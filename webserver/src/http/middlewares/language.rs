@@ -0,0 +1,35 @@
+//! Language middleware
+
+use std::cell::Cell;
+
+use axum::extract::Request;
+use axum::middleware::Next;
+use axum::response::Response;
+
+use crate::http::handler_frontend::users::schema::UserLanguage;
+
+tokio::task_local! {
+    static LANGUAGE: Cell<UserLanguage>;
+}
+
+/// Get the language the current request's messages should be presented in
+///
+/// This is the session user's preferred language once it has been extracted
+/// and english for anonymous requests or when called outside of [`request_language`].
+pub fn current_language() -> UserLanguage {
+    LANGUAGE.try_with(Cell::get).unwrap_or(UserLanguage::EN)
+}
+
+/// Set the language the current request's messages should be presented in
+///
+/// Does nothing when called outside of [`request_language`].
+pub fn set_current_language(lang: UserLanguage) {
+    let _ = LANGUAGE.try_with(|language| language.set(lang));
+}
+
+/// Provides the scope for [`current_language`] and [`set_current_language`] to every request
+pub async fn request_language(req: Request, next: Next) -> Response {
+    LANGUAGE
+        .scope(Cell::new(UserLanguage::EN), next.run(req))
+        .await
+}
@@ -1,7 +1,11 @@
 //! Middlewares are defined in this module
 
 pub mod auth_required;
-pub mod role_required;
+pub mod csrf;
+pub mod language;
+pub mod permission_required;
+pub mod rate_limit;
+pub mod request_id;
 
 /// Very simple macro which produces the boilerplate required to implement a layer (middleware) for axum.
 ///
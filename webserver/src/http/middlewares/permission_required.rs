@@ -0,0 +1,69 @@
+//! Permission required middleware
+
+use std::convert::Infallible;
+use std::ops::ControlFlow;
+use std::task::Poll;
+
+use axum::extract::FromRequestParts;
+use axum::extract::Request;
+use axum::response::IntoResponse;
+use axum::response::Response;
+use tracing::trace;
+
+use crate::global::GLOBAL;
+use crate::http::common::errors::ApiError;
+use crate::http::extractors::session_user::SessionUser;
+use crate::impl_axum_layer;
+use crate::models::Permission;
+use crate::models::UserRole;
+
+/// Middleware which checks the [`SessionUser`]'s role to grant a certain [`Permission`]
+///
+/// If email verification is required, the user's mail has to be verified as well.
+#[derive(Copy, Clone, Debug)]
+pub struct PermissionRequiredLayer {
+    permission: Permission,
+}
+impl PermissionRequiredLayer {
+    /// Constructs a new `PermissionRequiredLayer`
+    pub const fn new(required_permission: Permission) -> Self {
+        Self {
+            permission: required_permission,
+        }
+    }
+}
+impl_axum_layer!(PermissionRequiredLayer => PermissionRequiredService);
+impl PermissionRequiredLayer {
+    async fn call(self, req: Request) -> ControlFlow<Response, Request> {
+        let (mut parts, body) = req.into_parts();
+        let user = match SessionUser::from_request_parts(&mut parts, &()).await {
+            Ok(user) => user.user,
+            Err(error) => return ControlFlow::Break(error.into_response()),
+        };
+
+        let user_role = match user.role.key().parse::<UserRole>() {
+            Ok(role) => role,
+            Err(error) => return ControlFlow::Break(ApiError::from(error).into_response()),
+        };
+
+        if !user_role.has_permission(self.permission) {
+            trace!(
+                user = user.display_name,
+                user_role = %user_role,
+                required_permission = %self.permission,
+                "Missing privileges due to missing permission"
+            );
+            return ControlFlow::Break(ApiError::MissingPrivileges.into_response());
+        }
+
+        if GLOBAL.settings.get().email_verification_required && !user.email_verified {
+            trace!(
+                user = user.display_name,
+                "Missing privileges due to an unverified mail"
+            );
+            return ControlFlow::Break(ApiError::EmailUnverified.into_response());
+        }
+
+        ControlFlow::Continue(Request::from_parts(parts, body))
+    }
+}
@@ -0,0 +1,158 @@
+//! Rate limiting of clients by their ip address
+
+use std::net::IpAddr;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use axum::extract::ConnectInfo;
+use axum::extract::Request;
+use axum::http::HeaderName;
+use axum::middleware::Next;
+use axum::response::Response;
+
+use crate::global::GLOBAL;
+use crate::http::common::errors::ApiError;
+use crate::http::common::errors::ApiResult;
+use crate::utils::ip_network::IpNetwork;
+use crate::utils::rate_limit::RateLimiter;
+
+/// The header reverse proxies name the clients they forward requests for in
+static X_FORWARDED_FOR: HeaderName = HeaderName::from_static("x-forwarded-for");
+
+/// Limits how often a client may attempt to log in
+static LOGINS_PER_IP: RateLimiter<IpAddr> = RateLimiter::new(30, Duration::from_secs(60));
+
+/// Rejects login attempts of clients exceeding [`LOGINS_PER_IP`]
+///
+/// Clients from the configured trusted networks aren't limited.
+/// See [`resolve_client`] for how the client is determined.
+pub async fn rate_limit_logins(
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    req: Request,
+    next: Next,
+) -> ApiResult<Response> {
+    let trusted = &GLOBAL.trusted_networks;
+    let forwarded_for = req
+        .headers()
+        .get(&X_FORWARDED_FOR)
+        .and_then(|value| value.to_str().ok());
+    let client = resolve_client(peer.ip(), forwarded_for, trusted);
+
+    check_client(&LOGINS_PER_IP, trusted, client)
+        .map_err(|retry_after_secs| ApiError::TooManyRequests { retry_after_secs })?;
+
+    Ok(next.run(req).await)
+}
+
+/// Determines the client a request has been sent by
+///
+/// This is the connection's peer unless it is part of a `trusted` network.
+/// Trusted peers, like a reverse proxy, may name the client they forward for in `X-Forwarded-For`.
+/// Every proxy appends the address it received the request from,
+/// so the header is read from the end as long as the addresses are trusted.
+/// Entries before the first untrusted address might have been spoofed and are ignored.
+fn resolve_client(peer: IpAddr, forwarded_for: Option<&str>, trusted: &[IpNetwork]) -> IpAddr {
+    let is_trusted = |address: IpAddr| trusted.iter().any(|network| network.contains(address));
+
+    let mut client = peer.to_canonical();
+    let mut forwarded = forwarded_for.unwrap_or_default().rsplit(',');
+    while is_trusted(client) {
+        let Some(Ok(address)) = forwarded
+            .next()
+            .map(|address| address.trim().parse::<IpAddr>())
+        else {
+            break;
+        };
+        client = address.to_canonical();
+    }
+    client
+}
+
+/// Records a request of `client` unless it is part of a `trusted` network
+///
+/// Returns the seconds until the client may send a request again if it exceeded the limit.
+fn check_client(
+    limiter: &RateLimiter<IpAddr>,
+    trusted: &[IpNetwork],
+    client: IpAddr,
+) -> Result<(), u64> {
+    if trusted.iter().any(|network| network.contains(client)) {
+        return Ok(());
+    }
+    limiter.check(client)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::IpAddr;
+    use std::time::Duration;
+
+    use super::check_client;
+    use super::resolve_client;
+    use crate::utils::ip_network::IpNetwork;
+    use crate::utils::rate_limit::RateLimiter;
+
+    #[test]
+    fn trusted_clients_bypass_the_limit() -> Result<(), Box<dyn std::error::Error>> {
+        let limiter = RateLimiter::new(1, Duration::from_secs(60));
+        let trusted: [IpNetwork; 1] = ["10.0.0.0/8".parse()?];
+        let monitoring: IpAddr = "10.1.2.3".parse()?;
+
+        for _ in 0..3 {
+            assert_eq!(check_client(&limiter, &trusted, monitoring), Ok(()));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn untrusted_clients_are_limited() -> Result<(), Box<dyn std::error::Error>> {
+        let limiter = RateLimiter::new(1, Duration::from_secs(60));
+        let trusted: [IpNetwork; 1] = ["10.0.0.0/8".parse()?];
+        let client: IpAddr = "203.0.113.7".parse()?;
+
+        assert_eq!(check_client(&limiter, &trusted, client), Ok(()));
+        assert!(check_client(&limiter, &trusted, client).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn untrusted_peers_cant_spoof_their_address() -> Result<(), Box<dyn std::error::Error>> {
+        let trusted: [IpNetwork; 1] = ["10.0.0.0/8".parse()?];
+        let peer: IpAddr = "203.0.113.7".parse()?;
+
+        assert_eq!(resolve_client(peer, Some("10.1.2.3"), &trusted), peer);
+        assert_eq!(resolve_client(peer, None, &trusted), peer);
+        Ok(())
+    }
+
+    #[test]
+    fn trusted_peers_name_the_forwarded_client() -> Result<(), Box<dyn std::error::Error>> {
+        let trusted: [IpNetwork; 1] = ["10.0.0.0/8".parse()?];
+        let proxy: IpAddr = "10.0.0.2".parse()?;
+
+        assert_eq!(
+            resolve_client(proxy, Some("203.0.113.7"), &trusted),
+            "203.0.113.7".parse::<IpAddr>()?
+        );
+        // The client prepended a trusted address which the proxy passed on
+        assert_eq!(
+            resolve_client(proxy, Some("10.1.2.3, 203.0.113.7, 10.0.0.3"), &trusted),
+            "203.0.113.7".parse::<IpAddr>()?
+        );
+        // Requests by the proxy itself
+        assert_eq!(resolve_client(proxy, None, &trusted), proxy);
+        assert_eq!(resolve_client(proxy, Some("unknown"), &trusted), proxy);
+        Ok(())
+    }
+
+    #[test]
+    fn mapped_addresses_are_resolved_to_ipv4() -> Result<(), Box<dyn std::error::Error>> {
+        let peer: IpAddr = "::ffff:203.0.113.7".parse()?;
+
+        assert_eq!(
+            resolve_client(peer, None, &[]),
+            "203.0.113.7".parse::<IpAddr>()?
+        );
+        Ok(())
+    }
+}
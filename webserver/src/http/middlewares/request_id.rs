@@ -0,0 +1,127 @@
+//! Request id middleware
+
+use axum::extract::Request;
+use axum::http::HeaderName;
+use axum::http::HeaderValue;
+use axum::middleware::Next;
+use axum::response::Response;
+use tracing::info_span;
+use tracing::Instrument;
+use uuid::Uuid;
+
+/// The header a request id is read from and echoed in
+pub static X_REQUEST_ID: HeaderName = HeaderName::from_static("x-request-id");
+
+/// Upper bound for the length of request ids provided by clients
+const MAX_REQUEST_ID_LEN: usize = 64;
+
+tokio::task_local! {
+    static REQUEST_ID: String;
+}
+
+/// The id of the request currently being handled
+///
+/// It is also available as request extension.
+#[derive(Debug, Clone)]
+pub struct RequestId(pub String);
+
+/// Get the id of the request currently being handled
+///
+/// Returns `None` when called outside of [`request_id`].
+pub fn current_request_id() -> Option<String> {
+    REQUEST_ID.try_with(Clone::clone).ok()
+}
+
+/// Assigns an id to every request
+///
+/// The id is taken from the `X-Request-Id` header or generated if the header is missing or malformed.
+/// It is recorded in a span around the request and echoed in the response's `X-Request-Id` header.
+pub async fn request_id(mut req: Request, next: Next) -> Response {
+    let request_id = req
+        .headers()
+        .get(&X_REQUEST_ID)
+        .and_then(|value| value.to_str().ok())
+        .filter(|value| {
+            // Restrict the id to avoid passing arbitrary user input into logs
+            !value.is_empty()
+                && value.len() <= MAX_REQUEST_ID_LEN
+                && value
+                    .bytes()
+                    .all(|c| c.is_ascii_alphanumeric() || c == b'-' || c == b'_')
+        })
+        .map(String::from)
+        .unwrap_or_else(|| Uuid::new_v4().to_string());
+
+    req.extensions_mut().insert(RequestId(request_id.clone()));
+
+    let span = info_span!("request", request_id = %request_id);
+    let mut response = REQUEST_ID
+        .scope(request_id.clone(), next.run(req).instrument(span))
+        .await;
+
+    if let Ok(value) = HeaderValue::from_str(&request_id) {
+        response.headers_mut().insert(X_REQUEST_ID.clone(), value);
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+
+    use axum::body::to_bytes;
+    use axum::body::Body;
+    use axum::extract::Request;
+    use axum::middleware::from_fn;
+    use axum::routing::get;
+    use axum::Router;
+    use tower::ServiceExt;
+    use uuid::Uuid;
+
+    use super::current_request_id;
+    use super::request_id;
+    use super::X_REQUEST_ID;
+
+    /// Sends a request with an optional `X-Request-Id` and returns the echoed and the handler's id
+    async fn send(header: Option<&str>) -> Result<(String, String), Box<dyn Error>> {
+        let router = Router::new()
+            .route(
+                "/",
+                get(|| async { current_request_id().unwrap_or_default() }),
+            )
+            .layer(from_fn(request_id));
+
+        let mut request = Request::builder().uri("/");
+        if let Some(header) = header {
+            request = request.header(&X_REQUEST_ID, header);
+        }
+        let response = router.oneshot(request.body(Body::empty())?).await?;
+
+        let echoed = response.headers()[&X_REQUEST_ID].to_str()?.to_string();
+        let seen = String::from_utf8(to_bytes(response.into_body(), usize::MAX).await?.to_vec())?;
+        Ok((echoed, seen))
+    }
+
+    #[tokio::test]
+    async fn provided_request_id_is_used() -> Result<(), Box<dyn Error>> {
+        let (echoed, seen) = send(Some("frontend-42_a")).await?;
+        assert_eq!(echoed, "frontend-42_a");
+        assert_eq!(seen, "frontend-42_a");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn malformed_request_id_is_replaced() -> Result<(), Box<dyn Error>> {
+        for header in [None, Some(""), Some("no spaces"), Some(&*"a".repeat(65))] {
+            let (echoed, seen) = send(header).await?;
+            assert!(Uuid::parse_str(&echoed).is_ok());
+            assert_eq!(echoed, seen);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn no_request_id_outside_of_requests() {
+        assert_eq!(current_request_id(), None);
+    }
+}
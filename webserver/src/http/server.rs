@@ -5,8 +5,16 @@ use std::net::AddrParseError;
 use std::net::IpAddr;
 use std::net::SocketAddr;
 use std::str::FromStr;
+use std::time::Duration;
 
+use axum::extract::DefaultBodyLimit;
+use axum::http::header;
+use axum::http::HeaderName;
+use axum::http::HeaderValue;
+use axum::http::Method;
 use axum::Router;
+use axum_server::tls_rustls::RustlsConfig;
+use axum_server::Handle;
 use futures::StreamExt;
 use openidconnect::core::CoreClient;
 use openidconnect::core::CoreProviderMetadata;
@@ -21,11 +29,14 @@ use swaggapi::SwaggerUi;
 use thiserror::Error;
 use tokio::net::TcpListener;
 use tower::ServiceBuilder;
+use tower_http::cors::AllowOrigin;
+use tower_http::cors::CorsLayer;
+use tower_http::set_header::SetResponseHeaderLayer;
 use tower_http::trace::TraceLayer;
 use tower_sessions::cookie::SameSite;
 use tower_sessions::Expiry;
 use tower_sessions::SessionManagerLayer;
-use tower_sessions_rorm_store::RormStore;
+use tower_sessions_redis_store::fred::prelude::RedisError;
 use tracing::error;
 use tracing::info;
 use tracing::info_span;
@@ -33,12 +44,18 @@ use tracing::instrument;
 use tracing::Instrument;
 
 use crate::config::Config;
+use crate::config::CookieSameSite;
+use crate::config::CorsConfig;
+use crate::config::SecurityHeadersConfig;
 use crate::global::GLOBAL;
+use crate::http::connection_limit::ConnectionLimitAcceptor;
 use crate::http::handler_frontend;
 use crate::http::handler_frontend::ws::schema::WsClientMsg;
 use crate::http::handler_frontend::ws::schema::WsServerMsg;
 use crate::http::handler_frontend::FRONTEND_API_V1;
-use crate::models;
+use crate::http::middlewares::language::request_language;
+use crate::http::middlewares::request_id::request_id;
+use crate::http::session_store::ConfiguredStore;
 
 /// Start the http server
 #[instrument(skip_all, ret)]
@@ -59,43 +76,189 @@ pub async fn run(config: &Config) -> Result<(), StartServerError> {
         None
     };
 
+    let mut router = build_api(oidc_client);
+    if config.server.serve_docs {
+        let mut swaggui = SwaggerUi::without_everything().page("Frontend", &FRONTEND_API_V1);
+        swaggui.path = "/docs";
+        router = router.merge(swaggui);
+    } else {
+        info!("Docs are disabled");
+    }
+
+    let session_store = ConfiguredStore::new(&config.sessions, GLOBAL.db.clone()).await?;
+    router = router.layer(
+        ServiceBuilder::new()
+            .layer(axum::middleware::from_fn(request_id))
+            .layer(axum::middleware::from_fn(request_language))
+            .layer(TraceLayer::new_for_http())
+            .layer(cors_layer(&config.cors)?)
+            .layer(session_layer(config, session_store))
+            .layer(DefaultBodyLimit::max(config.server.body_limit_bytes)),
+    );
+    for (name, value) in security_headers(&config.security_headers, config.tls.is_some())? {
+        router = router.layer(SetResponseHeaderLayer::if_not_present(name, value));
+    }
+
+    let ip_addr = IpAddr::from_str(&config.server.listen_address).map_err(|source| {
+        StartServerError::InvalidAddress {
+            address: config.server.listen_address.clone(),
+            source,
+        }
+    })?;
+    let socket_addr = SocketAddr::new(ip_addr, config.server.listen_port);
+
+    let listener =
+        TcpListener::bind(socket_addr)
+            .await
+            .map_err(|source| StartServerError::Bind {
+                address: socket_addr,
+                source,
+            })?;
+
+    // Websockets would keep the server from shutting down, so they are closed explicitly.
+    // Other requests get some time to finish before they are dropped.
+    let drain_timeout = Duration::from_secs(config.server.shutdown_timeout_secs);
+    let handle = Handle::new();
+    let shutdown_handle = handle.clone();
+    tokio::spawn(async move {
+        handle_signals().instrument(info_span!("signals")).await;
+        GLOBAL
+            .ws
+            .close_all(Some("The server is shutting down".to_string()))
+            .await;
+        shutdown_handle.graceful_shutdown(Some(drain_timeout));
+    });
+
+    let listener = listener.into_std()?;
+    let service = router.into_make_service_with_connect_info::<SocketAddr>();
+    let max_connections = config.server.max_connections;
+    if let Some(tls) = &config.tls {
+        let tls_config = RustlsConfig::from_pem_file(&tls.cert_path, &tls.key_path)
+            .await
+            .map_err(StartServerError::InvalidTls)?;
+
+        info!("Start to listen on https://{socket_addr}");
+        axum_server::from_tcp_rustls(listener, tls_config)
+            .map(|acceptor| ConnectionLimitAcceptor::new(acceptor, max_connections))
+            .handle(handle)
+            .serve(service)
+            .await?;
+    } else {
+        info!("Start to listen on http://{socket_addr}");
+        axum_server::from_tcp(listener)
+            .map(|acceptor| ConnectionLimitAcceptor::new(acceptor, max_connections))
+            .handle(handle)
+            .serve(service)
+            .await?;
+    }
+
+    Ok(())
+}
+
+/// Assemble the api's router
+///
+/// This also registers all handlers and schemas in [`FRONTEND_API_V1`]
+/// which is why it has to be called before exporting the OpenAPI document.
+pub fn build_api(oidc_client: Option<CoreClient>) -> Router {
     // Register models that are not used in handlers
     (&FRONTEND_API_V1)
         .add_schema::<WsServerMsg>()
         .add_schema::<WsClientMsg>();
 
-    let mut swaggui = SwaggerUi::without_everything().page("Frontend", &FRONTEND_API_V1);
-    swaggui.path = "/docs";
+    Router::new().merge(
+        ApiContext::new()
+            .page(&FRONTEND_API_V1)
+            .nest("/api/frontend", handler_frontend::initialize(oidc_client)),
+    )
+}
 
-    let router = Router::new()
-        .merge(
-            ApiContext::new()
-                .page(&FRONTEND_API_V1)
-                .nest("/api/frontend", handler_frontend::initialize(oidc_client)),
+/// Build the session layer from its config
+fn session_layer(config: &Config, store: ConfiguredStore) -> SessionManagerLayer<ConfiguredStore> {
+    let sessions = &config.sessions;
+    let layer = SessionManagerLayer::new(store)
+        .with_expiry(Expiry::OnInactivity(GLOBAL.session_idle_timeout))
+        .with_name(sessions.cookie_name.clone())
+        .with_secure(config.session_cookie_secure())
+        .with_path(sessions.cookie_path.clone())
+        .with_same_site(match sessions.cookie_same_site {
+            CookieSameSite::Strict => SameSite::Strict,
+            CookieSameSite::Lax => SameSite::Lax,
+            CookieSameSite::None => SameSite::None,
+        });
+    match &sessions.cookie_domain {
+        Some(domain) => layer.with_domain(domain.clone()),
+        None => layer,
+    }
+}
+
+/// Build the cors layer from its config
+///
+/// Origins default to [`GlobalEntities::origin`](crate::global::GlobalEntities::origin).
+fn cors_layer(config: &CorsConfig) -> Result<CorsLayer, StartServerError> {
+    let invalid = |value: &str| StartServerError::InvalidCors(value.to_string());
+
+    let allow_origin = if config.allows_any_origin() {
+        AllowOrigin::any()
+    } else if config.allowed_origins.is_empty() {
+        AllowOrigin::exact(
+            HeaderValue::from_str(&GLOBAL.origin).map_err(|_| invalid(&GLOBAL.origin))?,
         )
-        .merge(swaggui)
-        .layer(
-            ServiceBuilder::new()
-                .layer(TraceLayer::new_for_http())
-                .layer(
-                    SessionManagerLayer::new(RormStore::<models::Session>::new(GLOBAL.db.clone()))
-                        .with_expiry(Expiry::OnInactivity(time::Duration::hours(24)))
-                        .with_same_site(SameSite::Lax),
-                ),
-        );
-
-    let socket_addr = SocketAddr::new(
-        IpAddr::from_str(&config.server.listen_address)?,
-        config.server.listen_port,
-    );
+    } else {
+        AllowOrigin::list(
+            config
+                .allowed_origins
+                .iter()
+                .map(|origin| HeaderValue::from_str(origin).map_err(|_| invalid(origin)))
+                .collect::<Result<Vec<_>, _>>()?,
+        )
+    };
+    let allow_methods = config
+        .allowed_methods
+        .iter()
+        .map(|method| Method::from_str(method).map_err(|_| invalid(method)))
+        .collect::<Result<Vec<_>, _>>()?;
+    let allow_headers = config
+        .allowed_headers
+        .iter()
+        .map(|header| HeaderName::from_str(header).map_err(|_| invalid(header)))
+        .collect::<Result<Vec<_>, _>>()?;
 
-    info!("Start to listen on http://{socket_addr}");
-    let listener = TcpListener::bind(socket_addr).await?;
-    axum::serve(listener, router)
-        .with_graceful_shutdown(handle_signals().instrument(info_span!("signals")))
-        .await?;
+    Ok(CorsLayer::new()
+        .allow_origin(allow_origin)
+        .allow_methods(allow_methods)
+        .allow_headers(allow_headers)
+        .allow_credentials(config.allow_credentials))
+}
 
-    Ok(())
+/// Collect the enabled security headers from their config
+fn security_headers(
+    config: &SecurityHeadersConfig,
+    tls: bool,
+) -> Result<Vec<(HeaderName, HeaderValue)>, StartServerError> {
+    let mut headers = vec![
+        (header::X_CONTENT_TYPE_OPTIONS, &config.content_type_options),
+        (header::X_FRAME_OPTIONS, &config.frame_options),
+        (header::REFERRER_POLICY, &config.referrer_policy),
+        (
+            header::CONTENT_SECURITY_POLICY,
+            &config.content_security_policy,
+        ),
+    ];
+    if tls {
+        headers.push((
+            header::STRICT_TRANSPORT_SECURITY,
+            &config.strict_transport_security,
+        ));
+    }
+
+    headers
+        .into_iter()
+        .filter(|(_, value)| !value.is_empty())
+        .map(|(name, value)| match HeaderValue::from_str(value) {
+            Ok(value) => Ok((name, value)),
+            Err(_) => Err(StartServerError::InvalidHeader(name)),
+        })
+        .collect()
 }
 
 async fn handle_signals() {
@@ -118,8 +281,130 @@ async fn handle_signals() {
 pub enum StartServerError {
     #[error("{0}")]
     Io(#[from] io::Error),
-    #[error("Invalid address: {0}")]
-    InvalidAddress(#[from] AddrParseError),
+    #[error("Invalid listen address {address}: {source}")]
+    InvalidAddress {
+        address: String,
+        source: AddrParseError,
+    },
+    #[error("Could not bind to {address}: {source}")]
+    Bind {
+        address: SocketAddr,
+        source: io::Error,
+    },
+    #[error("Invalid value for the security header {0}")]
+    InvalidHeader(HeaderName),
+    #[error("Invalid value in the cors config: {0}")]
+    InvalidCors(String),
+    #[error("Could not connect to the session store: {0}")]
+    SessionStore(#[from] RedisError),
+    #[error("Could not load the TLS certificate or key: {0}")]
+    InvalidTls(io::Error),
     #[error("Connection to oidc failed: {0}")]
     OidcConnectionFailed(#[from] DiscoveryError<HttpClientError>),
 }
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+
+    use axum::body::Body;
+    use axum::extract::Request;
+    use axum::http::header;
+    use axum::http::header::ACCESS_CONTROL_ALLOW_ORIGIN;
+    use axum::http::header::ORIGIN;
+    use axum::http::HeaderName;
+    use axum::http::HeaderValue;
+    use axum::routing::get;
+    use axum::Router;
+    use tower::ServiceExt;
+
+    use super::cors_layer;
+    use super::security_headers;
+    use super::StartServerError;
+    use crate::config::CorsConfig;
+    use crate::config::SecurityHeadersConfig;
+
+    #[tokio::test]
+    async fn only_allowed_origins_may_make_cross_origin_requests() -> Result<(), Box<dyn Error>> {
+        let config = CorsConfig {
+            allowed_origins: vec!["https://frontend.example".to_string()],
+            ..CorsConfig::default()
+        };
+        let router = Router::new()
+            .route("/", get(|| async {}))
+            .layer(cors_layer(&config)?);
+
+        for (origin, allowed) in [
+            ("https://frontend.example", true),
+            ("https://other.example", false),
+        ] {
+            let request = Request::builder()
+                .uri("/")
+                .header(ORIGIN, origin)
+                .body(Body::empty())?;
+            let response = router.clone().oneshot(request).await?;
+            assert_eq!(
+                response
+                    .headers()
+                    .get(ACCESS_CONTROL_ALLOW_ORIGIN)
+                    .is_some(),
+                allowed
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_cors_values_are_rejected() {
+        let config = CorsConfig {
+            allowed_origins: vec!["https://frontend.example".to_string()],
+            allowed_methods: vec!["NOT A METHOD".to_string()],
+            ..CorsConfig::default()
+        };
+        assert!(matches!(
+            cors_layer(&config),
+            Err(StartServerError::InvalidCors(value)) if value == "NOT A METHOD"
+        ));
+    }
+
+    #[test]
+    fn strict_transport_security_is_only_sent_with_tls() -> Result<(), Box<dyn Error>> {
+        let config = SecurityHeadersConfig::default();
+        let has_hsts = |headers: Vec<(HeaderName, HeaderValue)>| {
+            headers
+                .iter()
+                .any(|(name, _)| name == header::STRICT_TRANSPORT_SECURITY)
+        };
+        assert!(!has_hsts(security_headers(&config, false)?));
+        assert!(has_hsts(security_headers(&config, true)?));
+        Ok(())
+    }
+
+    #[test]
+    fn empty_security_headers_are_omitted() -> Result<(), Box<dyn Error>> {
+        let config = SecurityHeadersConfig {
+            frame_options: String::new(),
+            ..SecurityHeadersConfig::default()
+        };
+        let headers = security_headers(&config, false)?;
+        assert!(!headers
+            .iter()
+            .any(|(name, _)| name == header::X_FRAME_OPTIONS));
+        assert!(headers
+            .iter()
+            .any(|(name, _)| name == header::X_CONTENT_TYPE_OPTIONS));
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_security_headers_are_rejected() {
+        let config = SecurityHeadersConfig {
+            referrer_policy: "no\nnewlines".to_string(),
+            ..SecurityHeadersConfig::default()
+        };
+        assert!(matches!(
+            security_headers(&config, false),
+            Err(StartServerError::InvalidHeader(name)) if name == header::REFERRER_POLICY
+        ));
+    }
+}
@@ -21,6 +21,25 @@ pub const SESSION_USER: &str = "user";
 /// Value is of type [`PartiallyAuthedSessionUser`]
 pub const PARTIALLY_AUTHED_SESSION_USER: &str = "missing2fa";
 
+/// The key for accessing when the user logged in
+///
+/// Used to check the configured maximum lifetime of sessions.
+///
+/// Value is of type `OffsetDateTime`
+pub const SESSION_LOGGED_IN_AT: &str = "logged_in_at";
+
+/// The key for accessing when the user last provided a second factor
+///
+/// Value is of type `OffsetDateTime`
+pub const SESSION_MFA_VERIFIED: &str = "mfa_verified";
+
+/// The key for accessing the token state-changing requests have to echo
+///
+/// See [`csrf_protection`](crate::http::middlewares::csrf::csrf_protection).
+///
+/// Value is of type `String`
+pub const SESSION_CSRF_TOKEN: &str = "csrf_token";
+
 /// The key for accessing and storing the data required for a secure OIDC request
 ///
 /// I.e. csrf token, some nonce, etc.
@@ -33,6 +52,12 @@ pub const SESSION_OIDC_REQUEST: &str = "oidc_request";
 /// Value is of type [`WebAuthnAuthentication`]
 pub const SESSION_WEBAUTHN_AUTHENTICATION: &str = "webauthn_authentication";
 
+/// The key for accessing and storing the data required for proving the second factor
+/// of a logged-in user using webauthn
+///
+/// Value is of type [`WebAuthnAuthentication`]
+pub const SESSION_WEBAUTHN_STEP_UP: &str = "webauthn_step_up";
+
 /// The key for accessing and storing the data required for a webauthn registration request
 ///
 /// Value is of type [`WebAuthnRegistration`]
@@ -48,7 +73,7 @@ pub const SESSION_WEBAUTHN_ACCEPT: &str = "webauthn_accept";
 pub struct PartiallyAuthedSessionUser {
     /// When the user tried to log in.
     ///
-    /// Use to check the [`MFA_TIMEOUT`](crate::http::handler_frontend::auth::utils::MFA_TIMEOUT).
+    /// Use to check the configured MFA timeout.
     pub timestamp: OffsetDateTime,
 
     /// The `LocalUser` who tries to log in
@@ -79,6 +104,11 @@ pub enum WebAuthnAuthenticationState {
 /// Stored in a session under the key [`SESSION_WEBAUTHN_REGISTRATION`]
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct WebAuthnRegistration {
+    /// When the registration was started
+    ///
+    /// Used to check the configured enrollment timeout.
+    pub timestamp: OffsetDateTime,
+
     /// The label to be given to the new key in registration
     pub label: CheckedString<1, 255>,
 
@@ -101,6 +131,11 @@ pub enum WebAuthnRegistrationState {
 /// Stored in a session under the key [`SESSION_WEBAUTHN_ACCEPT`]
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct WebAuthnAccept {
+    /// When the registration was started
+    ///
+    /// Used to check the configured enrollment timeout.
+    pub timestamp: OffsetDateTime,
+
     /// The label to be given to the new key in registration
     pub label: CheckedString<1, 255>,
 
@@ -0,0 +1,243 @@
+//! The session store selected by the config
+//!
+//! Regardless of the backend storing the sessions' data,
+//! every session has a row in the [`models::Session`] table.
+//! It maps the session to its user and is used to end all of a user's sessions.
+//! Deleting a row therefore ends the session even if its data is stored elsewhere.
+
+use std::collections::HashMap;
+
+use axum::async_trait;
+use rorm::and;
+use rorm::fields::types::Json;
+use rorm::insert;
+use rorm::query;
+use rorm::update;
+use rorm::Database;
+use rorm::FieldAccess;
+use rorm::Model;
+use time::OffsetDateTime;
+use tower_sessions::session::Id;
+use tower_sessions::session::Record;
+use tower_sessions::session_store;
+use tower_sessions::MemoryStore;
+use tower_sessions::SessionStore;
+use tower_sessions_redis_store::fred::prelude::ClientLike;
+use tower_sessions_redis_store::fred::prelude::RedisConfig;
+use tower_sessions_redis_store::fred::prelude::RedisError;
+use tower_sessions_redis_store::fred::prelude::RedisPool;
+use tower_sessions_redis_store::RedisStore;
+use tower_sessions_rorm_store::RormStore;
+
+use crate::config::SessionStoreKind;
+use crate::config::SessionsConfig;
+use crate::models;
+
+/// The number of connections to redis
+const REDIS_POOL_SIZE: usize = 6;
+
+/// The session store used by the server
+#[derive(Debug, Clone)]
+pub enum ConfiguredStore {
+    /// Keeps the data in the session table itself
+    Database(RormStore<models::Session>),
+    /// Keeps the data in memory
+    Memory(MappedStore<MemoryStore>),
+    /// Keeps the data in redis
+    Redis(MappedStore<RedisStore<RedisPool>>),
+}
+
+impl ConfiguredStore {
+    /// Constructs the store selected by the config
+    ///
+    /// Connects to redis if it is selected.
+    pub async fn new(config: &SessionsConfig, db: Database) -> Result<Self, RedisError> {
+        Ok(match config.store {
+            SessionStoreKind::Database => Self::Database(RormStore::new(db)),
+            SessionStoreKind::Memory => Self::Memory(MappedStore::new(MemoryStore::default(), db)),
+            SessionStoreKind::Redis => {
+                let pool = RedisPool::new(
+                    RedisConfig::from_url(&config.redis_url)?,
+                    None,
+                    None,
+                    None,
+                    REDIS_POOL_SIZE,
+                )?;
+                pool.init().await?;
+                Self::Redis(MappedStore::new(RedisStore::new(pool), db))
+            }
+        })
+    }
+}
+
+#[async_trait]
+impl SessionStore for ConfiguredStore {
+    async fn create(&self, record: &mut Record) -> session_store::Result<()> {
+        match self {
+            Self::Database(store) => store.create(record).await,
+            Self::Memory(store) => store.create(record).await,
+            Self::Redis(store) => store.create(record).await,
+        }
+    }
+
+    async fn save(&self, record: &Record) -> session_store::Result<()> {
+        match self {
+            Self::Database(store) => store.save(record).await,
+            Self::Memory(store) => store.save(record).await,
+            Self::Redis(store) => store.save(record).await,
+        }
+    }
+
+    async fn load(&self, id: &Id) -> session_store::Result<Option<Record>> {
+        match self {
+            Self::Database(store) => store.load(id).await,
+            Self::Memory(store) => store.load(id).await,
+            Self::Redis(store) => store.load(id).await,
+        }
+    }
+
+    async fn delete(&self, id: &Id) -> session_store::Result<()> {
+        match self {
+            Self::Database(store) => store.delete(id).await,
+            Self::Memory(store) => store.delete(id).await,
+            Self::Redis(store) => store.delete(id).await,
+        }
+    }
+}
+
+/// Wraps a store to maintain the [`models::Session`] rows for the sessions it stores
+///
+/// A session is only loaded from the wrapped store if its row exists and hasn't expired.
+#[derive(Debug, Clone)]
+pub struct MappedStore<S> {
+    inner: S,
+    db: Database,
+}
+
+impl<S> MappedStore<S> {
+    /// Wraps a store
+    pub fn new(inner: S, db: Database) -> Self {
+        Self { inner, db }
+    }
+
+    /// Creates or updates the row of a session
+    ///
+    /// Only its expiry is updated, the mapping to its user is left untouched.
+    async fn save_row(&self, record: &Record) -> session_store::Result<()> {
+        let id = record.id.to_string();
+
+        let updated = update!(&self.db, models::Session)
+            .condition(models::Session::F.id.equals(id.as_str()))
+            .set(models::Session::F.expires_at, record.expiry_date)
+            .exec()
+            .await
+            .map_err(backend_error)?;
+        if updated == 0 {
+            insert!(&self.db, models::Session)
+                .return_nothing()
+                .single(&models::Session {
+                    id,
+                    expires_at: record.expiry_date,
+                    data: Json(HashMap::new()),
+                    user: None,
+                    user_agent: None,
+                    ip: None,
+                })
+                .await
+                .map_err(backend_error)?;
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl<S: SessionStore> SessionStore for MappedStore<S> {
+    async fn create(&self, record: &mut Record) -> session_store::Result<()> {
+        self.inner.create(record).await?;
+        self.save_row(record).await
+    }
+
+    async fn save(&self, record: &Record) -> session_store::Result<()> {
+        self.inner.save(record).await?;
+        self.save_row(record).await
+    }
+
+    async fn load(&self, id: &Id) -> session_store::Result<Option<Record>> {
+        let row_id = id.to_string();
+        let exists = query!(&self.db, (models::Session::F.id,))
+            .condition(and![
+                models::Session::F.id.equals(row_id.as_str()),
+                models::Session::F
+                    .expires_at
+                    .greater_than(OffsetDateTime::now_utc())
+            ])
+            .optional()
+            .await
+            .map_err(backend_error)?
+            .is_some();
+        if !exists {
+            // The row has been deleted to end the session
+            self.inner.delete(id).await?;
+            return Ok(None);
+        }
+
+        self.inner.load(id).await
+    }
+
+    async fn delete(&self, id: &Id) -> session_store::Result<()> {
+        self.inner.delete(id).await?;
+        let row_id = id.to_string();
+        rorm::delete!(&self.db, models::Session)
+            .condition(models::Session::F.id.equals(row_id.as_str()))
+            .await
+            .map_err(backend_error)?;
+        Ok(())
+    }
+}
+
+fn backend_error(error: rorm::Error) -> session_store::Error {
+    session_store::Error::Backend(error.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use rorm::FieldAccess;
+    use rorm::Model;
+    use time::Duration;
+    use time::OffsetDateTime;
+    use tower_sessions::session::Id;
+    use tower_sessions::session::Record;
+    use tower_sessions::MemoryStore;
+    use tower_sessions::SessionStore;
+
+    use super::MappedStore;
+    use crate::models;
+    use crate::utils::test_db;
+
+    /// The store writes its rows outside any transaction,
+    /// so the test removes its session again through the store.
+    #[tokio::test]
+    #[ignore = "requires a migrated database"]
+    async fn deleting_the_row_ends_the_session() -> Result<(), Box<dyn std::error::Error>> {
+        let db = test_db::connect().await?;
+        let store = MappedStore::new(MemoryStore::default(), db.clone());
+
+        let mut record = Record {
+            id: Id::default(),
+            data: Default::default(),
+            expiry_date: OffsetDateTime::now_utc() + Duration::hours(1),
+        };
+        store.create(&mut record).await?;
+        assert!(store.load(&record.id).await?.is_some());
+
+        let row_id = record.id.to_string();
+        rorm::delete!(&db, models::Session)
+            .condition(models::Session::F.id.equals(row_id.as_str()))
+            .await?;
+        assert!(store.load(&record.id).await?.is_none());
+        assert!(store.inner.load(&record.id).await?.is_none());
+
+        store.delete(&record.id).await?;
+        Ok(())
+    }
+}
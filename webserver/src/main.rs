@@ -10,74 +10,239 @@ use std::io::Write;
 use clap::Parser;
 use rorm::cli as rorm_cli;
 use rorm::config::DatabaseConfig;
+use rorm::db::Executor;
+use rorm::query;
+use rorm::update;
 use rorm::Database;
 use rorm::DatabaseConfiguration;
+use rorm::FieldAccess;
+use rorm::Model;
+use swaggapi::SwaggapiPage;
+use time::Duration;
+use time::OffsetDateTime;
+use tracing::error;
+use tracing::info;
 use tracing::instrument;
+use tracing::warn;
 use webauthn_rs::WebauthnBuilder;
 
 use crate::cli::Cli;
 use crate::cli::Command;
 use crate::config::Config;
+use crate::config::CookieSameSite;
+use crate::config::CsrfMode;
 use crate::global::ws::GlobalWs;
 use crate::global::GlobalEntities;
+use crate::global::Settings;
 use crate::global::GLOBAL;
 use crate::http::handler_frontend::users::schema::UserLanguage;
 use crate::http::handler_frontend::users::schema::UserPermissions;
+use crate::http::handler_frontend::FRONTEND_API_V1;
+use crate::models::AuditAction;
+use crate::models::AuditLog;
+use crate::models::LocalUser;
+use crate::models::Role;
+use crate::models::TotpKey;
+use crate::models::User;
+use crate::models::UserGroups;
 use crate::models::UserInvite;
+use crate::models::UserRole;
+use crate::models::WebAuthnKey;
+use crate::utils::avatars::Avatars;
 use crate::utils::checked_string::CheckedString;
+use crate::utils::display_name::init_display_name_policy;
+use crate::utils::hashing;
+use crate::utils::hashing::hash_pw;
 use crate::utils::links::new_user_invite_link;
+use crate::utils::mailer::Mailer;
+use crate::utils::migrations::check_migrations;
+use crate::utils::migrations::latest_applied_migration;
+use crate::utils::oidc_tokens::TokenCipher;
+use crate::utils::swap_lock::SwapLock;
+use crate::utils::webauthn::load_attestation_ca_list;
+use crate::utils::webhooks::Webhooks;
 
 mod cli;
 pub mod config;
 pub mod global;
 pub mod http;
 pub mod models;
+pub mod tasks;
 pub mod utils;
 
 #[instrument(skip_all)]
-async fn start(config: &Config) -> Result<(), Box<dyn std::error::Error>> {
+async fn start(
+    config: &Config,
+    config_path: &str,
+    migrations_dir: &str,
+    skip_migration_check: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
     // Connect to the database
     let mut conf = DatabaseConfiguration::new(config.database.clone().into());
     conf.disable_logging = Some(true);
     let db = Database::connect(conf).await?;
 
+    if skip_migration_check {
+        warn!("Skipping the migration check");
+    } else {
+        check_migrations(&db, migrations_dir).await?;
+    }
+
+    let seeded = Role::ensure_seeded(&db).await?;
+    if seeded > 0 {
+        info!("Created {seeded} missing roles");
+    }
+    if let Some(oidc) = &config.openid_connect {
+        if !Role::exists(&db, oidc.default_role).await? {
+            return Err(format!(
+                "The role {} for new oidc users doesn't exist",
+                oidc.default_role
+            )
+            .into());
+        }
+    }
+
+    if matches!(config.sessions.cookie_same_site, CookieSameSite::None)
+        && matches!(config.csrf.mode, CsrfMode::Disabled)
+    {
+        warn!("The session cookie is sent cross-site while csrf protection is disabled");
+    }
+
     let ws = GlobalWs::new();
 
+    let mailer = config.smtp.as_ref().map(Mailer::new).transpose()?;
+
+    let avatars = config.avatars.as_ref().map(Avatars::new).transpose()?;
+
+    let webhooks = config.webhooks.as_ref().map(Webhooks::new).transpose()?;
+
+    let oidc_token_cipher = config
+        .openid_connect
+        .as_ref()
+        .and_then(|oidc| oidc.token_encryption_key.as_ref())
+        .map(TokenCipher::new);
+
     let webauthn = WebauthnBuilder::new(&config.webauthn.id, &config.webauthn.origin)?
         .rp_name(&config.webauthn.name)
         .build()?;
-    let webauthn_attestation_ca_list = serde_json::from_reader(io::BufReader::new(
-        fs::File::open(&config.webauthn.attestation_ca_list)?,
-    ))?;
+    let webauthn_attestation_ca_list =
+        load_attestation_ca_list(&config.webauthn.attestation_ca_list)?;
 
     // Initialize Globals
     GLOBAL.init(GlobalEntities {
         db,
         ws,
+        mailer,
+        avatars,
+        webhooks,
+        oidc_token_cipher,
+        oidc_default_role: config
+            .openid_connect
+            .as_ref()
+            .map_or(UserRole::Internal, |oidc| oidc.default_role),
         webauthn,
-        webauthn_attestation_ca_list,
+        webauthn_attestation_ca_list: SwapLock::new(webauthn_attestation_ca_list),
+        session_idle_timeout: Duration::minutes(config.sessions.idle_timeout_minutes.into()),
+        trusted_networks: config.server.trusted_networks.clone(),
+        settings: SwapLock::new(Settings::from_config(config)),
         origin: config.server.origin.trim_end_matches('/').to_string(),
     });
 
+    if let Some(minutes) = config.webauthn.attestation_ca_list_reload_minutes {
+        tasks::reload_ca_list::spawn_ca_list_reload(
+            config.webauthn.attestation_ca_list.clone(),
+            std::time::Duration::from_secs(u64::from(minutes) * 60),
+        );
+    }
+
+    tasks::reload_config::spawn_config_reload(config_path.to_string());
+
+    if config.cleanup.enabled {
+        tasks::cleanup::spawn_cleanup(
+            std::time::Duration::from_secs(u64::from(config.cleanup.interval_minutes.max(1)) * 60),
+            Duration::hours(config.cleanup.expired_invite_retention_hours.into()),
+        );
+    }
+
     // Start the webserver
     http::server::run(config).await?;
 
+    // Closing affects all clones of the database handle
+    GLOBAL.db.clone().close().await;
+
     Ok(())
 }
 
+/// Decides whether to format logs as json based on the `LOG_FORMAT` environment variable
+///
+/// It selects between `json` and `pretty` logs.
+/// Otherwise, release builds which are expected to run behind a log aggregator use json.
+fn use_json_logs(log_format: Option<&str>) -> bool {
+    match log_format {
+        Some("json") => true,
+        Some("pretty") => false,
+        _ => !cfg!(debug_assertions),
+    }
+}
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     if env::var("RUST_LOG").is_err() {
         env::set_var("RUST_LOG", "INFO");
     }
-    tracing_subscriber::fmt::init();
+    if use_json_logs(env::var("LOG_FORMAT").ok().as_deref()) {
+        tracing_subscriber::fmt()
+            .json()
+            .flatten_event(true)
+            .with_current_span(true)
+            .with_span_list(true)
+            .init();
+    } else {
+        tracing_subscriber::fmt::init();
+    }
 
     let cli = Cli::parse();
 
-    let config = Config::try_from_path(&cli.config_path)?;
+    if let Command::ExportOpenApi { output } = &cli.command {
+        http::server::build_api(None);
+        let document = serde_json::to_string_pretty(&*(&FRONTEND_API_V1).build())?;
+        fs::write(output, document)?;
+        return Ok(());
+    }
+    if let Command::PrintConfigTemplate = &cli.command {
+        print!("{}", Config::template()?);
+        return Ok(());
+    }
+
+    let config = match Config::try_from_path(&cli.config_path) {
+        Ok(config) => config,
+        Err(error) => {
+            // Returning the error would print its `Debug` representation
+            // which buries the parse location in nested structs
+            error!("{error}");
+            std::process::exit(1);
+        }
+    };
+
+    hashing::init_pepper(config.auth.password_pepper.as_deref())?;
+    init_display_name_policy(
+        config.users.display_name_policy,
+        config.users.unique_display_names,
+    )?;
 
     match cli.command {
-        Command::Start => start(&config).await?,
+        Command::Start {
+            migrations_dir,
+            skip_migration_check,
+        } => {
+            start(
+                &config,
+                &cli.config_path,
+                &migrations_dir,
+                skip_migration_check,
+            )
+            .await?
+        }
         #[cfg(debug_assertions)]
         Command::MakeMigrations { migrations_dir } => {
             use std::io::Write;
@@ -103,17 +268,43 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         Command::Migrate { migrations_dir } => {
             rorm_cli::migrate::run_migrate_custom(
                 DatabaseConfig {
-                    driver: config.database.into(),
+                    driver: config.database.clone().into(),
                     last_migration_table_name: None,
                 },
                 migrations_dir,
                 false,
                 None,
             )
-            .await?
+            .await?;
+
+            let db = connect_db(&config).await?;
+            Role::ensure_seeded(&db).await?;
+            db.close().await;
+        }
+        Command::MigrationStatus { migrations_dir } => {
+            migration_status(&config, &migrations_dir).await?
         }
-        Command::CreateAdminUser => {
-            create_admin_user(config).await?;
+        Command::ExportOpenApi { .. } | Command::PrintConfigTemplate => {
+            unreachable!("handled before loading the config")
+        }
+        Command::CreateAdminUser { no_email } => {
+            create_admin_user(config, no_email).await?;
+        }
+        Command::CreateInvite {
+            mail,
+            display_name,
+            lang,
+            role,
+            no_email,
+        } => {
+            let permissions = UserPermissions::without_groups(role);
+            create_invite(&config, mail, display_name, lang, permissions, no_email).await?;
+        }
+        Command::ListUsers => list_users(&config).await?,
+        Command::DeleteUser { mail } => delete_user(&config, &mail).await?,
+        Command::SetRole { mail, role } => set_role(&config, &mail, role).await?,
+        Command::ResetPassword { mail, remove_mfa } => {
+            reset_password(&config, &mail, remove_mfa).await?
         }
     }
 
@@ -121,12 +312,12 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 }
 
 /// Creates an invitation for an admin user
-async fn create_admin_user(config: Config) -> Result<(), Box<dyn std::error::Error>> {
-    // Connect to the database
-    let mut conf = DatabaseConfiguration::new(config.database.clone().into());
-    conf.disable_logging = Some(true);
-    let db = Database::connect(conf).await?;
-
+///
+/// The invite link is sent via mail, if SMTP has been configured and `no_email` isn't set.
+async fn create_admin_user(
+    config: Config,
+    no_email: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
     let stdin = io::stdin();
     let mut stdout = io::stdout();
 
@@ -143,20 +334,279 @@ async fn create_admin_user(config: Config) -> Result<(), Box<dyn std::error::Err
     stdin.read_line(&mut display_name)?;
     let display_name = display_name.trim().to_string();
 
-    let invite = UserInvite::create(
-        &db,
-        CheckedString::new(mail.to_string()).map_err(|e| format!("Invalid mail: {e}"))?,
-        CheckedString::new(display_name).map_err(|e| format!("Invalid display_name: {e}"))?,
+    create_invite(
+        &config,
+        mail.to_string(),
+        display_name,
         UserLanguage::EN,
         UserPermissions::Administrator,
+        no_email,
+    )
+    .await
+}
+
+/// Creates an invite, prints its link and sends it via mail if SMTP has been configured
+async fn create_invite(
+    config: &Config,
+    mail: String,
+    display_name: String,
+    lang: UserLanguage,
+    permissions: UserPermissions,
+    no_email: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let db = connect_db(config).await?;
+    let mut tx = db.start_transaction().await?;
+
+    let invite = UserInvite::create(
+        &mut tx,
+        CheckedString::new(mail).map_err(|e| format!("Invalid mail: {e}"))?,
+        CheckedString::new(display_name).map_err(|e| format!("Invalid display_name: {e}"))?,
+        lang,
+        permissions,
+        Duration::hours(config.invites.default_expiry_hours.into()),
+        None,
+    )
+    .await?;
+    AuditLog::audit(
+        &mut tx,
+        None,
+        AuditAction::InviteCreated,
+        Some(invite.uuid),
+        serde_json::json!({ "mail": invite.email }),
     )
     .await?;
 
+    tx.commit().await?;
+
+    let link = new_user_invite_link(config.server.origin.trim_end_matches('/'), invite.uuid);
     println!(
-        "Created invitation for {mail}, please go to {}",
-        new_user_invite_link(config.server.origin.trim_end_matches('/'), invite.uuid)
+        "Created invitation for {}, please go to {link}",
+        invite.email
     );
 
+    if let Some(smtp) = config.smtp.as_ref().filter(|_| !no_email) {
+        Mailer::new(smtp)?
+            .send_invite(&invite.email, &invite.display_name, lang, &link)
+            .await;
+    }
+
     db.close().await;
     Ok(())
 }
+
+/// Connects to the database for a cli command
+async fn connect_db(config: &Config) -> Result<Database, rorm::Error> {
+    let mut conf = DatabaseConfiguration::new(config.database.clone().into());
+    conf.disable_logging = Some(true);
+    Database::connect(conf).await
+}
+
+/// Looks up a user by their mail
+async fn user_by_mail(
+    executor: impl Executor<'_>,
+    mail: &str,
+) -> Result<User, Box<dyn std::error::Error>> {
+    query!(executor, User)
+        .condition(User::F.mail.equals(mail))
+        .optional()
+        .await?
+        .ok_or_else(|| format!("There is no user with the mail {mail}").into())
+}
+
+/// Prints all users
+async fn list_users(config: &Config) -> Result<(), Box<dyn std::error::Error>> {
+    let db = connect_db(config).await?;
+
+    let users = query!(&db, User).all().await?;
+    for user in &users {
+        println!(
+            "{}\t{}\t{}\t{}\t{}\t{}",
+            user.uuid,
+            user.mail,
+            user.display_name,
+            user.role.key(),
+            if user.enabled { "enabled" } else { "disabled" },
+            user.created_at.date(),
+        );
+    }
+    println!("{} users", users.len());
+
+    db.close().await;
+    Ok(())
+}
+
+/// Deletes a user
+async fn delete_user(config: &Config, mail: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let db = connect_db(config).await?;
+    let mut tx = db.start_transaction().await?;
+
+    let user = user_by_mail(&mut tx, mail).await?;
+    let deleted = User::delete(&mut tx, user.uuid)
+        .await?
+        .ok_or("The user has been deleted concurrently")?;
+    AuditLog::audit(
+        &mut tx,
+        None,
+        AuditAction::UserDeleted,
+        Some(user.uuid),
+        serde_json::Value::Null,
+    )
+    .await?;
+
+    tx.commit().await?;
+
+    if let (Some(config), Some(avatar)) = (&config.avatars, deleted.avatar) {
+        Avatars::new(config)?.remove(&avatar).await;
+    }
+    println!("Deleted user {mail}, ended {} sessions", deleted.sessions);
+
+    db.close().await;
+    Ok(())
+}
+
+/// Changes a user's role
+async fn set_role(
+    config: &Config,
+    mail: &str,
+    role: UserRole,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let db = connect_db(config).await?;
+    let mut tx = db.start_transaction().await?;
+
+    let user = user_by_mail(&mut tx, mail).await?;
+    let permissions = match role {
+        UserRole::Administrator => UserPermissions::Administrator,
+        UserRole::Internal => UserPermissions::Internal {
+            groups: query!(&mut tx, (UserGroups::F.group,))
+                .condition(UserGroups::F.user.equals(user.uuid))
+                .all()
+                .await?
+                .into_iter()
+                .map(|(group,)| *group.key())
+                .collect(),
+        },
+    };
+    AuditLog::audit(
+        &mut tx,
+        None,
+        AuditAction::UserPermissionsChanged,
+        Some(user.uuid),
+        serde_json::to_value(&permissions)?,
+    )
+    .await?;
+    User::set_permissions(&mut tx, user.uuid, permissions).await?;
+
+    tx.commit().await?;
+    println!("Changed the role of {mail} to {role}");
+
+    db.close().await;
+    Ok(())
+}
+
+/// Overwrites a local user's password reading the new one from stdin
+///
+/// The user is logged out from all of their sessions.
+async fn reset_password(
+    config: &Config,
+    mail: &str,
+    remove_mfa: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let db = connect_db(config).await?;
+    let mut tx = db.start_transaction().await?;
+
+    let user = user_by_mail(&mut tx, mail).await?;
+    let (local_user_uuid,) = query!(&mut tx, (LocalUser::F.uuid,))
+        .condition(LocalUser::F.user.equals(user.uuid))
+        .optional()
+        .await?
+        .ok_or_else(|| format!("{mail} is not a local user"))?;
+
+    let password = rpassword::prompt_password("Enter the new password: ")?;
+    let min_length = config.auth.min_password_length;
+    if password.chars().count() < min_length {
+        return Err(format!("The password must be at least {min_length} characters long").into());
+    }
+
+    update!(&mut tx, LocalUser)
+        .condition(LocalUser::F.uuid.equals(local_user_uuid))
+        .set(LocalUser::F.password, Some(hash_pw(&password)?))
+        .set(
+            LocalUser::F.password_changed_at,
+            Some(OffsetDateTime::now_utc()),
+        )
+        .exec()
+        .await?;
+    if remove_mfa {
+        rorm::delete!(&mut tx, TotpKey)
+            .condition(TotpKey::F.local_user.equals(local_user_uuid))
+            .await?;
+        rorm::delete!(&mut tx, WebAuthnKey)
+            .condition(WebAuthnKey::F.local_user.equals(local_user_uuid))
+            .await?;
+    }
+    models::Session::delete_by_user(&mut tx, user.uuid).await?;
+    AuditLog::audit(
+        &mut tx,
+        None,
+        AuditAction::UserPasswordReset,
+        Some(user.uuid),
+        serde_json::json!({ "logout": true, "remove_mfa": remove_mfa }),
+    )
+    .await?;
+
+    tx.commit().await?;
+    println!("Reset the password of {mail}");
+
+    db.close().await;
+    Ok(())
+}
+
+/// Prints which migrations have been applied and fails if any are pending
+async fn migration_status(
+    config: &Config,
+    migrations_dir: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let migrations = rorm_cli::utils::migrations::get_existing_migrations(migrations_dir)?;
+
+    let db = connect_db(config).await?;
+    let applied = latest_applied_migration(&db).await;
+    db.close().await;
+    let applied = applied?;
+
+    let mut pending = 0;
+    for migration in &migrations {
+        let is_applied = applied.is_some_and(|applied| migration.id <= applied);
+        if !is_applied {
+            pending += 1;
+        }
+        println!(
+            "[{}] {:04}_{}",
+            if is_applied { "x" } else { " " },
+            migration.id,
+            migration.name
+        );
+    }
+
+    if pending > 0 {
+        return Err(format!("{pending} migrations are pending").into());
+    }
+    println!("All migrations have been applied");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::use_json_logs;
+
+    #[test]
+    fn log_format_can_be_selected() {
+        assert!(use_json_logs(Some("json")));
+        assert!(!use_json_logs(Some("pretty")));
+    }
+
+    #[test]
+    fn log_format_defaults_to_the_build_profile() {
+        assert_eq!(use_json_logs(None), !cfg!(debug_assertions));
+        assert_eq!(use_json_logs(Some("xml")), !cfg!(debug_assertions));
+    }
+}
@@ -0,0 +1,83 @@
+use rorm::db::Executor;
+use rorm::fields::types::Json;
+use rorm::insert;
+use serde_json::Value;
+use uuid::Uuid;
+
+use crate::models::AuditAction;
+use crate::models::AuditLog;
+use crate::models::AuditLogInsert;
+
+impl AuditLog {
+    /// Records an action in the audit log
+    ///
+    /// This should be called using the transaction which performs the action,
+    /// so the entry is only written if the action succeeds.
+    ///
+    /// `actor` is `None` for actions performed by the system.
+    pub async fn audit(
+        executor: impl Executor<'_>,
+        actor: Option<Uuid>,
+        action: AuditAction,
+        target: Option<Uuid>,
+        detail: Value,
+    ) -> Result<(), rorm::Error> {
+        insert!(executor, AuditLog)
+            .return_nothing()
+            .single(&AuditLogInsert {
+                uuid: Uuid::new_v4(),
+                actor,
+                action: action.to_string(),
+                target,
+                detail: Json(detail),
+            })
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rorm::query;
+    use rorm::FieldAccess;
+    use rorm::Model;
+    use serde_json::json;
+    use uuid::Uuid;
+
+    use crate::http::handler_frontend::users::schema::UserPermissions;
+    use crate::models::AuditAction;
+    use crate::models::AuditLog;
+    use crate::utils::test_db;
+
+    #[tokio::test]
+    #[ignore = "requires a migrated database"]
+    async fn audited_actions_are_recorded() -> Result<(), Box<dyn std::error::Error>> {
+        let db = test_db::connect().await?;
+        let mut tx = db.start_transaction().await?;
+        let admin =
+            test_db::create_user(&mut tx, "auditor", UserPermissions::Administrator).await?;
+        let target = Uuid::new_v4();
+
+        AuditLog::audit(
+            &mut tx,
+            Some(admin),
+            AuditAction::UserPasswordReset,
+            Some(target),
+            json!({ "logout": true }),
+        )
+        .await?;
+
+        let entries = query!(&mut tx, AuditLog)
+            .condition(AuditLog::F.actor.equals(admin))
+            .all()
+            .await?;
+        assert_eq!(entries.len(), 1);
+        let entry = &entries[0];
+        assert_eq!(
+            entry.action.parse::<AuditAction>()?,
+            AuditAction::UserPasswordReset
+        );
+        assert_eq!(entry.target, Some(target));
+        assert_eq!(entry.detail.0, json!({ "logout": true }));
+        Ok(())
+    }
+}
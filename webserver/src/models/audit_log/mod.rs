@@ -0,0 +1,73 @@
+//! The audit log of sensitive actions is defined in this module
+
+use rorm::fields::types::Json;
+use rorm::Model;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde::Serialize;
+use serde_json::Value;
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+mod impls;
+mod patches;
+
+pub use self::impls::*;
+pub use self::patches::*;
+
+/// An entry in the audit log
+///
+/// Entries are written using [`AuditLog::audit`] and never modified afterward.
+#[derive(Model)]
+pub struct AuditLog {
+    /// Primary key of an entry
+    #[rorm(primary_key)]
+    pub uuid: Uuid,
+
+    /// The user who performed the action
+    ///
+    /// `None` if it was performed by the system, for example through the cli.
+    /// This is no foreign key to retain the entry when the user is deleted.
+    pub actor: Option<Uuid>,
+
+    /// The action which was performed
+    ///
+    /// The value should only be used in conversions to and from [`AuditAction`].
+    #[rorm(max_length = 255)]
+    pub action: String,
+
+    /// The entity the action was performed on
+    ///
+    /// Which kind of entity depends on the `action`.
+    pub target: Option<Uuid>,
+
+    /// Additional details depending on the `action`
+    pub detail: Json<Value>,
+
+    /// The point in time the action was performed
+    #[rorm(auto_create_time)]
+    pub created_at: OffsetDateTime,
+}
+
+/// The actions recorded in the audit log
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Deserialize, Serialize, JsonSchema)]
+// Database conversion
+#[derive(strum::Display, strum::EnumString, strum::IntoStaticStr)]
+#[allow(missing_docs)]
+pub enum AuditAction {
+    UserCreated,
+    UserDeleted,
+    UserPermissionsChanged,
+    UserPasswordReset,
+    UserEnabledChanged,
+    UserLoggedOut,
+    MagicLinkIssued,
+    InviteCreated,
+    InviteRenewed,
+    InviteDeleted,
+    GroupCreated,
+    GroupDeleted,
+    GroupMemberAdded,
+    GroupMemberRemoved,
+    WebsocketsDisconnected,
+}
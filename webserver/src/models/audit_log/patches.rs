@@ -0,0 +1,26 @@
+use rorm::fields::types::Json;
+use rorm::Patch;
+use serde_json::Value;
+use uuid::Uuid;
+
+use crate::models::AuditLog;
+
+/// Insert patch for [`AuditLog`]
+#[derive(Patch)]
+#[rorm(model = "AuditLog")]
+pub struct AuditLogInsert {
+    /// Primary key of an entry
+    pub uuid: Uuid,
+
+    /// The user who performed the action
+    pub actor: Option<Uuid>,
+
+    /// The action which was performed
+    pub action: String,
+
+    /// The entity the action was performed on
+    pub target: Option<Uuid>,
+
+    /// Additional details depending on the `action`
+    pub detail: Json<Value>,
+}
@@ -0,0 +1,27 @@
+//! All group related models are defined here
+
+use rorm::Model;
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+mod patches;
+
+pub use self::patches::*;
+
+/// A group of internal users
+///
+/// Users are assigned to groups using [`UserGroups`](crate::models::UserGroups).
+#[derive(Model, Clone)]
+pub struct InternalGroup {
+    /// Primary key of a group
+    #[rorm(primary_key)]
+    pub uuid: Uuid,
+
+    /// The group's name
+    #[rorm(max_length = 255, unique)]
+    pub name: String,
+
+    /// The point in time the group was created
+    #[rorm(auto_create_time)]
+    pub created_at: OffsetDateTime,
+}
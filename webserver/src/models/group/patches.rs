@@ -0,0 +1,15 @@
+use rorm::Patch;
+use uuid::Uuid;
+
+use crate::models::InternalGroup;
+
+/// Insert patch for [`InternalGroup`]
+#[derive(Patch)]
+#[rorm(model = "InternalGroup")]
+pub struct InternalGroupInsert {
+    /// Primary key of a group
+    pub uuid: Uuid,
+
+    /// The group's name
+    pub name: String,
+}
@@ -1,9 +1,26 @@
 //! All database models are defined in this module
 
+pub use audit_log::*;
+pub use group::*;
 pub use role::*;
 pub use session::*;
 pub use user::*;
 
+pub mod audit_log;
+pub mod group;
 pub mod role;
 pub mod session;
 pub mod user;
+
+/// Checks whether a query failed because it violated a unique constraint
+///
+/// Use it to detect concurrent requests which passed the same uniqueness check.
+pub fn is_unique_violation(error: &rorm::Error) -> bool {
+    match error {
+        rorm::Error::SqlxError(error) => error
+            .as_database_error()
+            .and_then(|error| error.code())
+            .is_some_and(|code| code == "23505"),
+        _ => false,
+    }
+}
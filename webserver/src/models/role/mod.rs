@@ -1,10 +1,19 @@
 //! The role management for users is defined in this module
 
+use rorm::db::Executor;
+use rorm::insert;
+use rorm::query;
+use rorm::FieldAccess;
 use rorm::Model;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde::Serialize;
+use strum::IntoEnumIterator;
 
 /// The role of a user
 ///
 /// It should not be modified as it is created at start of the application
+/// by [`Role::ensure_seeded`]
 #[derive(Model, Clone)]
 pub struct Role {
     /// The value should only be used in conversions to and from [`UserRole`]
@@ -12,8 +21,56 @@ pub struct Role {
     pub identifier: String,
 }
 
+impl Role {
+    /// Inserts the rows for all [`UserRole`]s which don't exist yet
+    ///
+    /// This is idempotent and has to run before users are created,
+    /// because users reference their role by its identifier.
+    ///
+    /// Returns the number of inserted roles.
+    pub async fn ensure_seeded(executor: impl Executor<'_>) -> Result<usize, rorm::Error> {
+        let mut guard = executor.ensure_transaction().await?;
+
+        let existing: Vec<String> = query!(guard.get_transaction(), (Role::F.identifier,))
+            .all()
+            .await?
+            .into_iter()
+            .map(|(identifier,)| identifier)
+            .collect();
+        let missing = Self::missing(&existing);
+
+        if !missing.is_empty() {
+            insert!(guard.get_transaction(), Role)
+                .return_nothing()
+                .bulk(&missing)
+                .await?;
+        }
+
+        guard.commit().await?;
+        Ok(missing.len())
+    }
+
+    /// The rows of all [`UserRole`]s whose identifier is not in `existing`
+    fn missing(existing: &[String]) -> Vec<Role> {
+        UserRole::iter()
+            .map(|role| role.to_string())
+            .filter(|identifier| !existing.contains(identifier))
+            .map(|identifier| Role { identifier })
+            .collect()
+    }
+
+    /// Checks whether the row for a [`UserRole`] exists
+    pub async fn exists(executor: impl Executor<'_>, role: UserRole) -> Result<bool, rorm::Error> {
+        Ok(query!(executor, (Role::F.identifier,))
+            .condition(Role::F.identifier.equals(role.to_string()))
+            .optional()
+            .await?
+            .is_some())
+    }
+}
+
 /// The roles of a user
-#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Deserialize, Serialize, JsonSchema)]
 // Database conversion
 #[derive(strum::Display, strum::EnumString, strum::IntoStaticStr, strum::EnumIter)]
 #[allow(missing_docs)]
@@ -21,3 +78,105 @@ pub enum UserRole {
     Administrator,
     Internal,
 }
+
+impl UserRole {
+    /// The permissions granted to users with this role
+    pub const fn permissions(self) -> &'static [Permission] {
+        match self {
+            UserRole::Administrator => &[
+                Permission::ViewUsers,
+                Permission::ManageUsers,
+                Permission::ManageInvites,
+                Permission::ManageGroups,
+                Permission::ViewAuditLog,
+                Permission::ManageWebsockets,
+            ],
+            UserRole::Internal => &[Permission::ViewUsers],
+        }
+    }
+
+    /// Checks whether this role grants a certain permission
+    pub fn has_permission(self, permission: Permission) -> bool {
+        self.permissions().contains(&permission)
+    }
+}
+
+/// Fine-grained permissions granted to users through their [`UserRole`]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Deserialize, Serialize, JsonSchema, strum::Display)]
+pub enum Permission {
+    /// Retrieve the list of all users
+    ViewUsers,
+    /// Create, modify and delete users
+    ManageUsers,
+    /// Create, renew and delete user invites
+    ManageInvites,
+    /// Create and delete internal groups and assign their members
+    ManageGroups,
+    /// Retrieve the audit log
+    ViewAuditLog,
+    /// Close the websocket connections of all users
+    ManageWebsockets,
+}
+
+#[cfg(test)]
+mod tests {
+    use strum::IntoEnumIterator;
+
+    use super::Permission;
+    use super::Role;
+    use super::UserRole;
+
+    #[test]
+    fn administrators_have_every_permission() {
+        for permission in [
+            Permission::ViewUsers,
+            Permission::ManageUsers,
+            Permission::ManageInvites,
+            Permission::ManageGroups,
+            Permission::ViewAuditLog,
+            Permission::ManageWebsockets,
+        ] {
+            assert!(UserRole::Administrator.has_permission(permission));
+        }
+    }
+
+    #[test]
+    fn internal_users_may_only_view_users() {
+        assert!(UserRole::Internal.has_permission(Permission::ViewUsers));
+        assert!(!UserRole::Internal.has_permission(Permission::ManageUsers));
+        assert!(!UserRole::Internal.has_permission(Permission::ManageInvites));
+        assert!(!UserRole::Internal.has_permission(Permission::ManageGroups));
+        assert!(!UserRole::Internal.has_permission(Permission::ViewAuditLog));
+        assert!(!UserRole::Internal.has_permission(Permission::ManageWebsockets));
+    }
+
+    /// The identifiers of some rows
+    fn identifiers(roles: Vec<Role>) -> Vec<String> {
+        roles.into_iter().map(|role| role.identifier).collect()
+    }
+
+    #[test]
+    fn all_roles_are_missing_in_an_empty_table() {
+        assert_eq!(
+            identifiers(Role::missing(&[])),
+            ["Administrator", "Internal"]
+        );
+    }
+
+    #[test]
+    fn only_missing_roles_are_seeded() {
+        assert_eq!(
+            identifiers(Role::missing(&["Administrator".to_string()])),
+            ["Internal"]
+        );
+        assert!(Role::missing(&["Administrator".to_string(), "Internal".to_string()]).is_empty());
+    }
+
+    #[test]
+    fn identifiers_parse_back_into_roles() -> Result<(), strum::ParseError> {
+        for role in UserRole::iter() {
+            assert_eq!(role.to_string().parse::<UserRole>()?, role);
+        }
+        Ok(())
+    }
+}
@@ -1,14 +1,24 @@
 use std::collections::HashMap;
 
+use rorm::and;
+use rorm::db::Executor;
 use rorm::fields::types::Json;
+use rorm::insert;
 use rorm::internal::field::Field;
 use rorm::internal::field::FieldProxy;
+use rorm::prelude::ForeignModelByField;
+use rorm::update;
+use rorm::FieldAccess;
 use rorm::Model;
 use rorm::Patch;
 use serde_json::Value;
+use sha2::Digest;
+use sha2::Sha256;
 use time::OffsetDateTime;
 use tower_sessions_rorm_store::SessionModel;
+use uuid::Uuid;
 
+use crate::models::KnownDevice;
 use crate::models::Session;
 
 impl SessionModel for Session {
@@ -31,6 +41,8 @@ impl SessionModel for Session {
             expires_at,
             data,
             user: None,
+            user_agent: None,
+            ip: None,
         }
     }
 
@@ -38,3 +50,140 @@ impl SessionModel for Session {
         (self.id.clone(), self.expires_at, self.data.clone())
     }
 }
+
+impl Session {
+    /// Deletes all sessions of a user, effectively logging them out
+    ///
+    /// Returns the number of deleted sessions.
+    ///
+    /// The caller should also close the user's websockets using [`GlobalWs::close_user`](crate::global::ws::GlobalWs::close_user).
+    pub async fn delete_by_user(
+        executor: impl Executor<'_>,
+        user_uuid: Uuid,
+    ) -> Result<u64, rorm::Error> {
+        rorm::delete!(executor, Session)
+            .condition(Session::F.user.equals(user_uuid))
+            .await
+    }
+}
+
+impl KnownDevice {
+    /// Records a login of a user with a device and checks whether they used it before
+    ///
+    /// Returns `true` if the device is new to the user.
+    /// A device without a user agent can't be recognized and is always new.
+    pub async fn remember(
+        executor: impl Executor<'_>,
+        user_uuid: Uuid,
+        user_agent: Option<&str>,
+        ip: Option<&str>,
+    ) -> Result<bool, rorm::Error> {
+        let Some(fingerprint) = Self::fingerprint(user_agent, ip) else {
+            return Ok(true);
+        };
+        let mut guard = executor.ensure_transaction().await?;
+
+        let now = OffsetDateTime::now_utc();
+        let updated = update!(guard.get_transaction(), KnownDevice)
+            .condition(and![
+                KnownDevice::F.user.equals(user_uuid),
+                KnownDevice::F.fingerprint.equals(fingerprint.as_str())
+            ])
+            .set(KnownDevice::F.last_seen, now)
+            .exec()
+            .await?;
+        if updated == 0 {
+            insert!(guard.get_transaction(), KnownDevice)
+                .return_nothing()
+                .single(&KnownDevice {
+                    uuid: Uuid::new_v4(),
+                    user: ForeignModelByField::Key(user_uuid),
+                    fingerprint,
+                    last_seen: now,
+                })
+                .await?;
+        }
+
+        guard.commit().await?;
+        Ok(updated == 0)
+    }
+
+    /// Identifies a device by its user agent and ip address
+    ///
+    /// Only the hash is stored as the user agent may be up to a few kilobytes long.
+    /// Returns `None` if there is no user agent.
+    fn fingerprint(user_agent: Option<&str>, ip: Option<&str>) -> Option<String> {
+        let user_agent = user_agent?;
+        let mut hasher = Sha256::new();
+        hasher.update(user_agent.as_bytes());
+        // Separates the fields as neither may contain a line break
+        hasher.update(b"\n");
+        hasher.update(ip.unwrap_or_default().as_bytes());
+        Some(format!("{:x}", hasher.finalize()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rorm::FieldAccess;
+    use rorm::Model;
+    use time::OffsetDateTime;
+
+    use crate::http::handler_frontend::users::schema::UserPermissions;
+    use crate::models::KnownDevice;
+    use crate::models::Session;
+    use crate::utils::test_db;
+
+    const FIREFOX: &str = "Mozilla/5.0 (X11; Linux x86_64; rv:131.0) Gecko/20100101 Firefox/131.0";
+
+    #[test]
+    fn fingerprint_depends_on_user_agent_and_ip() {
+        let home = KnownDevice::fingerprint(Some(FIREFOX), Some("192.0.2.1"));
+        assert_eq!(
+            home,
+            KnownDevice::fingerprint(Some(FIREFOX), Some("192.0.2.1"))
+        );
+        assert_ne!(
+            home,
+            KnownDevice::fingerprint(Some(FIREFOX), Some("192.0.2.2"))
+        );
+        assert_ne!(
+            home,
+            KnownDevice::fingerprint(Some("curl/8.0"), Some("192.0.2.1"))
+        );
+        assert_ne!(home, KnownDevice::fingerprint(Some(FIREFOX), None));
+    }
+
+    #[test]
+    fn devices_without_user_agent_have_no_fingerprint() {
+        assert_eq!(KnownDevice::fingerprint(None, Some("192.0.2.1")), None);
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a migrated database"]
+    async fn devices_are_known_after_logging_out() -> Result<(), Box<dyn std::error::Error>> {
+        let db = test_db::connect().await?;
+        let mut tx = db.start_transaction().await?;
+        let user = test_db::create_user(&mut tx, "device", UserPermissions::Administrator).await?;
+
+        let ip = Some("192.0.2.1");
+        assert!(KnownDevice::remember(&mut tx, user, Some(FIREFOX), ip).await?);
+        Session::delete_by_user(&mut tx, user).await?;
+
+        let before = OffsetDateTime::now_utc();
+        assert!(!KnownDevice::remember(&mut tx, user, Some(FIREFOX), ip).await?);
+        let (last_seen,) = rorm::query!(&mut tx, (KnownDevice::F.last_seen,))
+            .condition(KnownDevice::F.user.equals(user))
+            .one()
+            .await?;
+        assert!(last_seen >= before);
+
+        assert!(KnownDevice::remember(&mut tx, user, Some(FIREFOX), Some("192.0.2.2")).await?);
+        assert!(KnownDevice::remember(&mut tx, user, None, ip).await?);
+        assert!(KnownDevice::remember(&mut tx, user, None, ip).await?);
+
+        let other = test_db::create_user(&mut tx, "device", UserPermissions::Administrator).await?;
+        assert!(KnownDevice::remember(&mut tx, other, Some(FIREFOX), ip).await?);
+        Ok(())
+    }
+}
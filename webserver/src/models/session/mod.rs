@@ -7,11 +7,14 @@ use rorm::prelude::ForeignModel;
 use rorm::Model;
 use serde_json::Value;
 use time::OffsetDateTime;
+use uuid::Uuid;
 
 use crate::models::user::User;
 
 mod impls;
 
+pub use self::impls::*;
+
 /// The representation of a session in the database
 #[derive(Model)]
 pub struct Session {
@@ -28,4 +31,56 @@ pub struct Session {
     /// The relation to an optional user
     #[rorm(on_delete = "Cascade", on_update = "Cascade")]
     pub user: Option<ForeignModel<User>>,
+
+    /// The user agent of the device the user logged in with
+    #[rorm(max_length = 255)]
+    pub user_agent: Option<String>,
+
+    /// The ip address the user logged in from
+    #[rorm(max_length = 255)]
+    pub ip: Option<String>,
+}
+
+/// A device a user has logged in with before
+///
+/// Unlike the [`Session`]s, it survives logging out to recognize the device on the next login.
+/// A device is identified by its user agent and ip address,
+/// so a login from a known browser on a new network counts as a new device.
+#[derive(Model)]
+pub struct KnownDevice {
+    /// Primary key of a known device
+    #[rorm(primary_key)]
+    pub uuid: Uuid,
+
+    /// The user who logged in with the device
+    #[rorm(on_delete = "Cascade", on_update = "Cascade")]
+    pub user: ForeignModel<User>,
+
+    /// SHA-256 of the device's user agent and ip address
+    ///
+    /// See [`KnownDevice::fingerprint`].
+    #[rorm(max_length = 255)]
+    pub fingerprint: String,
+
+    /// The last time the user logged in with the device
+    pub last_seen: OffsetDateTime,
+}
+
+/// A short-lived ticket to open a websocket without sending the session cookie
+///
+/// It authenticates as the session it was issued for and is consumed when opening the websocket.
+#[derive(Model)]
+pub struct WsTicket {
+    /// Primary key which is also the secret ticket
+    #[rorm(primary_key)]
+    pub uuid: Uuid,
+
+    /// The session the ticket was issued for
+    ///
+    /// Logging out invalidates the session's unused tickets.
+    #[rorm(on_delete = "Cascade", on_update = "Cascade")]
+    pub session: ForeignModel<Session>,
+
+    /// Until when is the ticket valid
+    pub expires_at: OffsetDateTime,
 }
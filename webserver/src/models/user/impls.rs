@@ -1,3 +1,7 @@
+use std::collections::HashSet;
+
+use rorm::and;
+use rorm::conditions::DynamicCollection;
 use rorm::db::Executor;
 use rorm::delete;
 use rorm::insert;
@@ -11,17 +15,29 @@ use time::Duration;
 use time::OffsetDateTime;
 use uuid::Uuid;
 use webauthn_rs::prelude::AttestedPasskey;
+use webauthn_rs::prelude::Credential;
+use webauthn_rs::prelude::CredentialID;
 use webauthn_rs::prelude::Passkey;
 
 use crate::http::handler_frontend::users::schema::UserLanguage;
 use crate::http::handler_frontend::users::schema::UserPermissions;
+use crate::models::is_unique_violation;
+use crate::models::InternalGroup;
 use crate::models::MaybeAttestedPasskey;
+use crate::models::Session;
 use crate::models::User;
+use crate::models::UserGroups;
+use crate::models::UserGroupsInsert;
 use crate::models::UserInsert;
 use crate::models::UserInvite;
 use crate::models::UserInviteInsert;
 use crate::models::UserRole;
 use crate::utils::checked_string::CheckedString;
+use crate::utils::display_name::display_name_key;
+use crate::utils::display_name::normalize_display_name;
+use crate::utils::display_name::unique_display_names;
+use crate::utils::display_name::InvalidDisplayName;
+use crate::utils::search::escape_like;
 
 impl MaybeAttestedPasskey {
     /// Shorthand to access the `Passkey`
@@ -34,6 +50,14 @@ impl MaybeAttestedPasskey {
         }
     }
 
+    /// The id of the credential
+    pub fn cred_id(&self) -> &CredentialID {
+        match self {
+            Self::NotAttested(passkey) => passkey.cred_id(),
+            Self::Attested(attested) => attested.cred_id(),
+        }
+    }
+
     /// Shorthand to access the `AttestedPasskey`
     pub fn attested(self) -> Option<AttestedPasskey> {
         match self {
@@ -41,6 +65,22 @@ impl MaybeAttestedPasskey {
             Self::Attested(attested) => Some(attested),
         }
     }
+
+    /// Did the authenticator verify the user (PIN, biometrics, etc.) while registering the key?
+    pub fn user_verified(&self) -> bool {
+        let passkey = match self {
+            Self::NotAttested(passkey) => passkey.clone(),
+            Self::Attested(attested) => attested.clone().into(),
+        };
+        Credential::from(passkey).user_verified
+    }
+
+    /// Can the key be used to log in without any other factor?
+    ///
+    /// This requires an attested device which verified the user upon registration.
+    pub fn can_login(&self) -> bool {
+        matches!(self, Self::Attested(_)) && self.user_verified()
+    }
 }
 
 impl User {
@@ -49,6 +89,8 @@ impl User {
     /// This function only creates the `User` model and the association required to store its permissions.
     /// The caller has to ensure a `LocalUser` or `OidcUser` is created after calling this function.
     ///
+    /// The `display_name` is normalized using [`normalize_display_name`].
+    ///
     /// The returned `Uuid` is the new `User`'s primary key.
     /// If it has been specified by the caller using the `uuid` argument,
     /// this will simply return the same value.
@@ -58,8 +100,11 @@ impl User {
         display_name: CheckedString<1, 255>,
         preferred_lang: UserLanguage,
         permissions: UserPermissions,
+        email_verified: bool,
         uuid: Option<Uuid>,
     ) -> Result<Uuid, CreateUserError> {
+        let display_name = normalize_display_name(display_name)?;
+
         let mut guard = executor.ensure_transaction().await?;
 
         let uuid = uuid.unwrap_or_else(Uuid::new_v4);
@@ -71,6 +116,9 @@ impl User {
         if mail_exists {
             return Err(CreateUserError::MailOccupied);
         }
+        if Self::is_display_name_taken(guard.get_transaction(), &display_name, None).await? {
+            return Err(CreateUserError::DisplayNameOccupied);
+        }
 
         let role = match &permissions {
             UserPermissions::Administrator => UserRole::Administrator,
@@ -81,12 +129,21 @@ impl User {
             .return_nothing()
             .single(&UserInsert {
                 uuid,
+                display_name_key: display_name_key(&display_name),
                 display_name: display_name.into_inner(),
                 preferred_lang: preferred_lang.to_string(),
                 role: ForeignModelByField::Key(role.to_string()),
                 mail: mail.into_inner(),
+                email_verified,
             })
-            .await?;
+            .await
+            .map_err(|error| {
+                if is_unique_violation(&error) {
+                    CreateUserError::Conflict
+                } else {
+                    CreateUserError::Database(error)
+                }
+            })?;
 
         Self::set_permissions_internal::<false>(guard.get_transaction(), uuid, permissions).await?;
 
@@ -94,7 +151,62 @@ impl User {
         Ok(uuid)
     }
 
+    /// Checks whether a display name is already used by a user or an open invite
+    ///
+    /// The comparison is case-insensitive and the user `except` as well as expired invites are ignored.
+    /// This always returns `false` unless [`unique_display_names`] is enabled.
+    ///
+    /// Concurrent requests might both pass this check.
+    /// Storing the name's [`display_name_key`] rejects the second one with a unique violation.
+    pub async fn is_display_name_taken(
+        executor: impl Executor<'_>,
+        display_name: &str,
+        except: Option<Uuid>,
+    ) -> Result<bool, rorm::Error> {
+        Self::is_display_name_taken_with(executor, display_name, except, unique_display_names())
+            .await
+    }
+
+    /// Implementation of [`User::is_display_name_taken`] with a given setting
+    async fn is_display_name_taken_with(
+        executor: impl Executor<'_>,
+        display_name: &str,
+        except: Option<Uuid>,
+        unique: bool,
+    ) -> Result<bool, rorm::Error> {
+        if !unique {
+            return Ok(false);
+        }
+
+        let mut guard = executor.ensure_transaction().await?;
+
+        let pattern = escape_like(display_name);
+        let users = query!(guard.get_transaction(), (User::F.uuid,))
+            .condition(User::F.display_name.ilike(&pattern))
+            .all()
+            .await?;
+        let taken_by_user = users
+            .into_iter()
+            .any(|(uuid,)| except.map_or(true, |except| uuid != except));
+        let taken = taken_by_user
+            || query!(guard.get_transaction(), (UserInvite::F.uuid,))
+                .condition(and![
+                    UserInvite::F.display_name.ilike(&pattern),
+                    UserInvite::F
+                        .expires_at
+                        .greater_than(OffsetDateTime::now_utc())
+                ])
+                .optional()
+                .await?
+                .is_some();
+
+        guard.commit().await?;
+        Ok(taken)
+    }
+
     /// Sets a user's permission overwriting old ones
+    ///
+    /// Groups which don't exist are ignored.
     pub async fn set_permissions(
         executor: impl Executor<'_>,
         user_uuid: Uuid,
@@ -124,26 +236,113 @@ impl User {
                 .set(User::F.role, ForeignModelByField::Key(role.to_string()))
                 .condition(User::F.uuid.equals(user_uuid))
                 .await?;
+
+            delete!(guard.get_transaction(), UserGroups)
+                .condition(UserGroups::F.user.equals(user_uuid))
+                .await?;
+        }
+
+        if let UserPermissions::Internal { groups } = permissions {
+            let groups: HashSet<Uuid> = groups.into_iter().collect();
+            if !groups.is_empty() {
+                let existing = query!(guard.get_transaction(), (InternalGroup::F.uuid,))
+                    .condition(DynamicCollection::or(
+                        groups
+                            .iter()
+                            .map(|group| InternalGroup::F.uuid.equals(*group))
+                            .collect(),
+                    ))
+                    .all()
+                    .await?;
+                let patches: Vec<_> = existing
+                    .into_iter()
+                    .map(|(group,)| UserGroupsInsert {
+                        uuid: Uuid::new_v4(),
+                        user: ForeignModelByField::Key(user_uuid),
+                        group: ForeignModelByField::Key(group),
+                    })
+                    .collect();
+                if !patches.is_empty() {
+                    insert!(guard.get_transaction(), UserGroups)
+                        .return_nothing()
+                        .bulk(&patches)
+                        .await?;
+                }
+            }
         }
 
         guard.commit().await
     }
 
-    /// Deletes an existing user
+    /// Enables or disables a user
+    ///
+    /// Disabling a user deletes all of their sessions.
+    /// The caller should close the user's websockets afterwards.
     ///
     /// Returns `false`, if the user didn't exist.
-    pub async fn delete(executor: impl Executor<'_>, user_uuid: Uuid) -> Result<bool, rorm::Error> {
+    pub async fn set_enabled(
+        executor: impl Executor<'_>,
+        user_uuid: Uuid,
+        enabled: bool,
+    ) -> Result<bool, rorm::Error> {
         let mut guard = executor.ensure_transaction().await?;
 
-        let num_deleted = delete!(guard.get_transaction(), User)
+        let num_updated = update!(guard.get_transaction(), User)
+            .set(User::F.enabled, enabled)
+            .condition(User::F.uuid.equals(user_uuid))
+            .await?;
+
+        if !enabled {
+            Session::delete_by_user(guard.get_transaction(), user_uuid).await?;
+        }
+
+        guard.commit().await?;
+        Ok(num_updated > 0)
+    }
+
+    /// Deletes an existing user and ends all their sessions
+    ///
+    /// Returns `None`, if the user didn't exist.
+    ///
+    /// The caller should also close the user's websockets using [`GlobalWs::close_user`](crate::global::ws::GlobalWs::close_user)
+    /// and remove the [`DeletedUser::avatar`] from the avatar store.
+    pub async fn delete(
+        executor: impl Executor<'_>,
+        user_uuid: Uuid,
+    ) -> Result<Option<DeletedUser>, rorm::Error> {
+        let mut guard = executor.ensure_transaction().await?;
+
+        let Some((avatar,)) = query!(guard.get_transaction(), (User::F.avatar,))
+            .condition(User::F.uuid.equals(user_uuid))
+            .optional()
+            .await?
+        else {
+            return Ok(None);
+        };
+
+        // Local and oidc users, totp and webauthn keys are removed by `on_delete = "Cascade"`.
+        // Sessions are deleted explicitly to report their number.
+        let sessions = Session::delete_by_user(guard.get_transaction(), user_uuid).await?;
+
+        delete!(guard.get_transaction(), User)
             .condition(User::F.uuid.equals(user_uuid))
             .await?;
 
         guard.commit().await?;
-        Ok(num_deleted > 0)
+        Ok(Some(DeletedUser { sessions, avatar }))
     }
 }
 
+/// Information about a user deleted by [`User::delete`]
+#[derive(Debug, Clone)]
+pub struct DeletedUser {
+    /// The number of sessions which have been ended
+    pub sessions: u64,
+
+    /// The key of the user's avatar which is no longer referenced
+    pub avatar: Option<String>,
+}
+
 /// The error that might occur when creating a user
 #[derive(Debug, Error)]
 #[allow(missing_docs)]
@@ -152,19 +351,39 @@ pub enum CreateUserError {
     Database(#[from] rorm::Error),
     #[error("There's already a user with the chosen mail")]
     MailOccupied,
+    #[error("There's already a user or invite with the chosen display name")]
+    DisplayNameOccupied,
+    #[error("Invalid display name: {0}")]
+    InvalidDisplayName(#[from] InvalidDisplayName),
+    /// A concurrent request took the mail or display name after they have been checked
+    #[error("The mail or display name has just been taken")]
+    Conflict,
 }
 
 impl UserInvite {
     /// Creates a new user invite checking if the mail is already used (either by user or open invite).
+    /// An expired invite for the same mail is replaced.
+    ///
+    /// The invite will expire after `valid_for`.
+    /// The `display_name` is normalized using [`normalize_display_name`].
+    ///
+    /// `created_by` is the admin issuing the invite or `None` if it is issued by the cli.
     pub async fn create(
         executor: impl Executor<'_>,
         mail: CheckedString<1, 255>,
         display_name: CheckedString<1, 255>,
         preferred_lang: UserLanguage,
         permissions: UserPermissions,
+        valid_for: Duration,
+        created_by: Option<Uuid>,
     ) -> Result<Self, CreateUserInviteError> {
+        let display_name = normalize_display_name(display_name)?;
+
         let mut guard = executor.ensure_transaction().await?;
 
+        if User::is_display_name_taken(guard.get_transaction(), &display_name, None).await? {
+            return Err(CreateUserInviteError::DisplayNameOccupied);
+        }
         let user_with_mail_exists = query!(guard.get_transaction(), (User::F.uuid,))
             .condition(User::F.mail.equals(&mail))
             .optional()
@@ -173,14 +392,22 @@ impl UserInvite {
         if user_with_mail_exists {
             return Err(CreateUserInviteError::AlreadyUser);
         }
+        let now = OffsetDateTime::now_utc();
         let invite_with_mail_exists = query!(guard.get_transaction(), (UserInvite::F.uuid,))
-            .condition(UserInvite::F.email.equals(&mail))
+            .condition(and![
+                UserInvite::F.email.equals(&mail),
+                UserInvite::F.expires_at.greater_than(now)
+            ])
             .optional()
             .await?
             .is_some();
         if invite_with_mail_exists {
             return Err(CreateUserInviteError::AlreadyInvited);
         }
+        // An expired invite is replaced, so it can't be renewed next to the new one
+        delete!(guard.get_transaction(), UserInvite)
+            .condition(UserInvite::F.email.equals(&mail))
+            .await?;
 
         let invite = insert!(guard.get_transaction(), UserInvite)
             .single(&UserInviteInsert {
@@ -189,13 +416,54 @@ impl UserInvite {
                 preferred_lang: preferred_lang.to_string(),
                 email: mail.into_inner(),
                 permissions: permissions.into(),
-                expires_at: OffsetDateTime::now_utc() + Duration::days(1),
+                expires_at: now + valid_for,
+                created_by: created_by.map(ForeignModelByField::Key),
             })
             .await?;
 
         guard.commit().await?;
         Ok(invite)
     }
+
+    /// Extends an invite to expire `valid_for` from now
+    ///
+    /// If `rotate` is set, the invite's `uuid` is replaced invalidating its old link.
+    /// Expired invites which haven't been purged yet can be renewed as well.
+    ///
+    /// Returns `None`, if the invite didn't exist.
+    pub async fn renew(
+        executor: impl Executor<'_>,
+        invite_uuid: Uuid,
+        valid_for: Duration,
+        rotate: bool,
+        renewed_by: Option<Uuid>,
+    ) -> Result<Option<Self>, rorm::Error> {
+        let mut guard = executor.ensure_transaction().await?;
+
+        let now = OffsetDateTime::now_utc();
+        let new_uuid = if rotate { Uuid::new_v4() } else { invite_uuid };
+        let num_updated = update!(guard.get_transaction(), UserInvite)
+            .set(UserInvite::F.uuid, new_uuid)
+            .set(UserInvite::F.expires_at, now + valid_for)
+            .set(UserInvite::F.renewed_at, Some(now))
+            .set(
+                UserInvite::F.renewed_by,
+                renewed_by.map(ForeignModelByField::Key),
+            )
+            .condition(UserInvite::F.uuid.equals(invite_uuid))
+            .await?;
+        if num_updated == 0 {
+            return Ok(None);
+        }
+
+        let invite = query!(guard.get_transaction(), UserInvite)
+            .condition(UserInvite::F.uuid.equals(new_uuid))
+            .one()
+            .await?;
+
+        guard.commit().await?;
+        Ok(Some(invite))
+    }
 }
 /// The error that might occur when creating a user invite
 #[derive(Debug, Error)]
@@ -207,4 +475,180 @@ pub enum CreateUserInviteError {
     AlreadyUser,
     #[error("There's already an open invite with the chosen mail")]
     AlreadyInvited,
+    #[error("There's already a user or invite with the chosen display name")]
+    DisplayNameOccupied,
+    #[error("Invalid display name: {0}")]
+    InvalidDisplayName(#[from] InvalidDisplayName),
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use rorm::fields::types::Json;
+    use rorm::insert;
+    use rorm::prelude::ForeignModelByField;
+    use rorm::query;
+    use rorm::update;
+    use rorm::FieldAccess;
+    use rorm::Model;
+    use time::Duration;
+    use time::OffsetDateTime;
+    use uuid::Uuid;
+    use webauthn_rs::prelude::AttestationFormat;
+    use webauthn_rs::prelude::AttestationMetadata;
+    use webauthn_rs::prelude::COSEAlgorithm;
+    use webauthn_rs::prelude::COSEEC2Key;
+    use webauthn_rs::prelude::COSEKey;
+    use webauthn_rs::prelude::COSEKeyType;
+    use webauthn_rs::prelude::Credential;
+    use webauthn_rs::prelude::ECDSACurve;
+    use webauthn_rs::prelude::ParsedAttestation;
+    use webauthn_rs::prelude::ParsedAttestationData;
+    use webauthn_rs::prelude::RegisteredExtensions;
+    use webauthn_rs::prelude::UserVerificationPolicy;
+
+    use crate::http::handler_frontend::users::schema::UserLanguage;
+    use crate::http::handler_frontend::users::schema::UserPermissions;
+    use crate::models::MaybeAttestedPasskey;
+    use crate::models::Session;
+    use crate::models::User;
+    use crate::models::UserInvite;
+    use crate::utils::checked_string::CheckedString;
+    use crate::utils::test_db;
+
+    #[tokio::test]
+    #[ignore = "requires a migrated database"]
+    async fn display_names_of_users_are_taken_ignoring_case(
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let db = test_db::connect().await?;
+        let mut tx = db.start_transaction().await?;
+        let user = test_db::create_user(&mut tx, "Taken", UserPermissions::Administrator).await?;
+        let (name,) = rorm::query!(&mut tx, (User::F.display_name,))
+            .condition(User::F.uuid.equals(user))
+            .one()
+            .await?;
+
+        assert!(User::is_display_name_taken_with(&mut tx, &name, None, true).await?);
+        assert!(User::is_display_name_taken_with(&mut tx, &name.to_uppercase(), None, true).await?);
+        assert!(!User::is_display_name_taken_with(&mut tx, &name, Some(user), true).await?);
+        assert!(
+            !User::is_display_name_taken_with(&mut tx, &format!("{name}-2"), None, true).await?
+        );
+
+        assert!(!User::is_display_name_taken_with(&mut tx, &name, None, false).await?);
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a migrated database"]
+    async fn display_names_of_open_invites_are_taken() -> Result<(), Box<dyn std::error::Error>> {
+        let db = test_db::connect().await?;
+        let mut tx = db.start_transaction().await?;
+        let name = format!("Invited-{}", Uuid::new_v4());
+        let invite = UserInvite::create(
+            &mut tx,
+            CheckedString::new(format!("{}@test.invalid", Uuid::new_v4()))?,
+            CheckedString::new(name.clone())?,
+            UserLanguage::EN,
+            UserPermissions::Administrator,
+            Duration::days(1),
+            None,
+        )
+        .await?;
+
+        assert!(User::is_display_name_taken_with(&mut tx, &name.to_lowercase(), None, true).await?);
+        assert!(!User::is_display_name_taken_with(&mut tx, &name, None, false).await?);
+
+        update!(&mut tx, UserInvite)
+            .condition(UserInvite::F.uuid.equals(invite.uuid))
+            .set(
+                UserInvite::F.expires_at,
+                OffsetDateTime::now_utc() - Duration::minutes(1),
+            )
+            .exec()
+            .await?;
+        assert!(!User::is_display_name_taken_with(&mut tx, &name, None, true).await?);
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a migrated database"]
+    async fn deleting_a_user_ends_their_sessions() -> Result<(), Box<dyn std::error::Error>> {
+        let db = test_db::connect().await?;
+        let mut tx = db.start_transaction().await?;
+        let user = test_db::create_user(&mut tx, "deleted", UserPermissions::Administrator).await?;
+        for _ in 0..2 {
+            insert!(&mut tx, Session)
+                .return_nothing()
+                .single(&Session {
+                    id: Uuid::new_v4().to_string(),
+                    expires_at: OffsetDateTime::now_utc() + Duration::hours(1),
+                    data: Json(HashMap::new()),
+                    user: Some(ForeignModelByField::Key(user)),
+                    user_agent: None,
+                    ip: None,
+                })
+                .await?;
+        }
+
+        let deleted = User::delete(&mut tx, user).await?;
+        assert!(matches!(
+            deleted,
+            Some(ref deleted) if deleted.sessions == 2 && deleted.avatar.is_none()
+        ));
+        let remaining = query!(&mut tx, (Session::F.id,))
+            .condition(Session::F.user.equals(user))
+            .all()
+            .await?;
+        assert!(remaining.is_empty());
+
+        assert!(User::delete(&mut tx, user).await?.is_none());
+        Ok(())
+    }
+
+    /// A credential whose key material is never used
+    fn credential(user_verified: bool) -> Credential {
+        Credential {
+            cred_id: vec![1, 2, 3, 4].into(),
+            cred: COSEKey {
+                type_: COSEAlgorithm::ES256,
+                key: COSEKeyType::EC_EC2(COSEEC2Key {
+                    curve: ECDSACurve::SECP256R1,
+                    x: vec![0; 32].into(),
+                    y: vec![0; 32].into(),
+                }),
+            },
+            counter: 0,
+            transports: None,
+            user_verified,
+            backup_eligible: false,
+            backup_state: false,
+            registration_policy: UserVerificationPolicy::Required,
+            extensions: RegisteredExtensions::none(),
+            attestation: ParsedAttestation {
+                data: ParsedAttestationData::None,
+                metadata: AttestationMetadata::None,
+            },
+            attestation_format: AttestationFormat::None,
+        }
+    }
+
+    #[test]
+    fn attested_keys_require_user_verification_to_log_in() {
+        let verified = MaybeAttestedPasskey::Attested(credential(true).into());
+        assert!(verified.user_verified());
+        assert!(verified.can_login());
+
+        let unverified = MaybeAttestedPasskey::Attested(credential(false).into());
+        assert!(!unverified.user_verified());
+        assert!(!unverified.can_login());
+    }
+
+    #[test]
+    fn unattested_keys_can_not_log_in() {
+        let key = MaybeAttestedPasskey::NotAttested(credential(true).into());
+        assert!(key.user_verified());
+        assert!(!key.can_login());
+    }
 }
@@ -13,6 +13,7 @@ use webauthn_rs::prelude::AttestedPasskey;
 use webauthn_rs::prelude::Passkey;
 
 use crate::http::handler_frontend::users::schema::UserPermissions;
+use crate::models::InternalGroup;
 use crate::models::Role;
 
 mod impls;
@@ -32,6 +33,13 @@ pub struct User {
     #[rorm(max_length = 255)]
     pub display_name: String,
 
+    /// The lowercase display name if display names have to be unique
+    ///
+    /// Its unique constraint prevents concurrent requests from taking the same display name.
+    /// See [`display_name_key`](crate::utils::display_name::display_name_key).
+    #[rorm(max_length = 255, unique)]
+    pub display_name_key: Option<String>,
+
     /// The preferred language of the user
     #[rorm(max_length = 255)]
     pub preferred_lang: String,
@@ -47,6 +55,29 @@ pub struct User {
     /// The mail of the user
     #[rorm(max_length = 255, unique)]
     pub mail: String,
+
+    /// Is the user allowed to log in?
+    ///
+    /// Disabled users are treated as unauthenticated.
+    #[rorm(default = true)]
+    pub enabled: bool,
+
+    /// Has the user proven to control their mail?
+    ///
+    /// Users from before the verification was introduced count as verified.
+    #[rorm(default = true)]
+    pub email_verified: bool,
+
+    /// The point in time the user logged in the last time
+    pub last_login: Option<OffsetDateTime>,
+
+    /// The point in time the user was created
+    #[rorm(auto_create_time)]
+    pub created_at: OffsetDateTime,
+
+    /// The key of the user's avatar in the configured [`AvatarStore`](crate::utils::avatars::AvatarStore)
+    #[rorm(max_length = 255)]
+    pub avatar: Option<String>,
 }
 
 /// A user that is identified though an IDM server
@@ -63,6 +94,17 @@ pub struct OidcUser {
     /// The ID provided by the openid server
     #[rorm(max_length = 255)]
     pub oidc_id: String,
+
+    /// The encrypted access token issued by the openid server
+    ///
+    /// Tokens are only stored if `OpenidConnect.TokenEncryptionKey` is configured.
+    pub access_token: Option<Vec<u8>>,
+
+    /// The point in time the access token expires
+    pub access_token_expires_at: Option<OffsetDateTime>,
+
+    /// The encrypted refresh token issued by the openid server
+    pub refresh_token: Option<Vec<u8>>,
 }
 
 /// A locally authenticated user
@@ -82,6 +124,11 @@ pub struct LocalUser {
     #[rorm(max_length = 1024)]
     pub password: Option<String>,
 
+    /// The last time the password was changed or reset
+    ///
+    /// Partial logins started before this point are rejected.
+    pub password_changed_at: Option<OffsetDateTime>,
+
     /// TOTP keys registers for this user
     pub totp: BackRef<field!(TotpKey::F.local_user)>,
 
@@ -108,6 +155,12 @@ pub struct TotpKey {
     #[rorm(max_length = 32)]
     pub secret: Vec<u8>,
 
+    /// The time step of the last token used to log in
+    ///
+    /// Tokens of this or earlier steps are rejected to prevent their replay.
+    #[rorm(default = 0)]
+    pub last_used_step: i64,
+
     /// The point in time the TOTP was added to the account
     #[rorm(auto_create_time)]
     pub created_at: OffsetDateTime,
@@ -115,7 +168,7 @@ pub struct TotpKey {
 
 /// A WebAuthn key registered by the user.
 ///
-/// If the key is `user_verified`, it can be used a password-less login.
+/// If the key is attested and `user_verified`, it can be used a password-less login.
 #[derive(Model)]
 pub struct WebAuthnKey {
     /// Primary key
@@ -133,6 +186,13 @@ pub struct WebAuthnKey {
     /// Cryptographic public key
     pub key: Json<MaybeAttestedPasskey>,
 
+    /// The id of the key's credential
+    ///
+    /// It guards against registering the same credential twice.
+    /// `None` for keys registered before this column was added.
+    #[rorm(unique)]
+    pub cred_id: Option<Vec<u8>>,
+
     /// The point in time the TOTP was added to the account
     #[rorm(auto_create_time)]
     pub created_at: OffsetDateTime,
@@ -148,6 +208,79 @@ pub enum MaybeAttestedPasskey {
     Attested(AttestedPasskey),
 }
 
+/// A pending verification of a user's mail
+#[derive(Model)]
+pub struct EmailVerification {
+    /// Primary key which is also the secret part of the verification link
+    #[rorm(primary_key)]
+    pub uuid: Uuid,
+
+    /// The user whose mail should be verified
+    #[rorm(on_delete = "Cascade", on_update = "Cascade")]
+    pub user: ForeignModel<User>,
+
+    /// The mail the link has been sent to
+    ///
+    /// The verification only succeeds if the user's mail hasn't changed since.
+    #[rorm(max_length = 255)]
+    pub mail: String,
+
+    /// Until when can the mail be verified
+    pub expires_at: OffsetDateTime,
+
+    /// When was the verification requested
+    #[rorm(auto_create_time)]
+    pub created_at: OffsetDateTime,
+}
+
+/// A single-use link logging in a local user without any further factor
+///
+/// It is issued by an admin, for example after recovering a user's account.
+#[derive(Model)]
+pub struct MagicLoginLink {
+    /// Primary key which is also the secret part of the link
+    #[rorm(primary_key)]
+    pub uuid: Uuid,
+
+    /// The user to log in
+    #[rorm(on_delete = "Cascade", on_update = "Cascade")]
+    pub local_user: ForeignModel<LocalUser>,
+
+    /// Until when is the link valid
+    pub expires_at: OffsetDateTime,
+
+    /// The admin who issued this link
+    #[rorm(on_delete = "SetNull", on_update = "Cascade")]
+    pub created_by: Option<ForeignModel<User>>,
+
+    /// When was this link issued
+    #[rorm(auto_create_time)]
+    pub created_at: OffsetDateTime,
+}
+
+/// A pending change of a local user's mail awaiting confirmation through the new address
+#[derive(Model)]
+pub struct EmailChange {
+    /// Primary key which is also the secret part of the confirmation link
+    #[rorm(primary_key)]
+    pub uuid: Uuid,
+
+    /// The user whose mail should be changed
+    #[rorm(on_delete = "Cascade", on_update = "Cascade")]
+    pub user: ForeignModel<User>,
+
+    /// The new mail
+    #[rorm(max_length = 255)]
+    pub new_mail: String,
+
+    /// Until when can the change be confirmed
+    pub expires_at: OffsetDateTime,
+
+    /// When was the change requested
+    #[rorm(auto_create_time)]
+    pub created_at: OffsetDateTime,
+}
+
 /// M2M model between [`User`] and [`InternalGroup`]
 #[derive(Model, Clone)]
 pub struct UserGroups {
@@ -158,6 +291,10 @@ pub struct UserGroups {
     /// The user
     #[rorm(on_update = "Cascade", on_delete = "Cascade")]
     pub user: ForeignModel<User>,
+
+    /// The group
+    #[rorm(on_update = "Cascade", on_delete = "Cascade")]
+    pub group: ForeignModel<InternalGroup>,
 }
 
 /// An outstanding invite link for a new local user to register himself
@@ -180,6 +317,8 @@ pub struct UserInvite {
     pub email: String,
 
     /// The `role` and associated relations to set for the new user
+    ///
+    /// See [`UserPermissions`]'s compatibility section before changing its shape.
     pub permissions: Json<UserPermissions>,
 
     /// Until when is the invite valid
@@ -188,4 +327,21 @@ pub struct UserInvite {
     /// When was this invite created
     #[rorm(auto_create_time)]
     pub created_at: OffsetDateTime,
+
+    /// The admin who created this invite
+    ///
+    /// `None` if the invite was created using the cli or its creator has been deleted since.
+    #[rorm(on_delete = "SetNull", on_update = "Cascade")]
+    pub created_by: Option<ForeignModel<User>>,
+
+    /// When was this invite last renewed
+    ///
+    /// Previous renewals are recorded in the audit log.
+    pub renewed_at: Option<OffsetDateTime>,
+
+    /// The admin who last renewed this invite
+    ///
+    /// `None` if the invite hasn't been renewed or its renewer has been deleted since.
+    #[rorm(on_delete = "SetNull", on_update = "Cascade")]
+    pub renewed_by: Option<ForeignModel<User>>,
 }
@@ -5,11 +5,16 @@ use time::OffsetDateTime;
 use uuid::Uuid;
 
 use crate::http::handler_frontend::users::schema::UserPermissions;
+use crate::models::EmailChange;
+use crate::models::EmailVerification;
+use crate::models::InternalGroup;
 use crate::models::LocalUser;
+use crate::models::MagicLoginLink;
 use crate::models::MaybeAttestedPasskey;
 use crate::models::Role;
 use crate::models::TotpKey;
 use crate::models::User;
+use crate::models::UserGroups;
 use crate::models::UserInvite;
 use crate::models::WebAuthnKey;
 
@@ -23,6 +28,9 @@ pub struct UserInsert {
     /// The name that is used for displaying purposes
     pub display_name: String,
 
+    /// The lowercase display name if display names have to be unique
+    pub display_name_key: Option<String>,
+
     /// The preferred language of the user
     pub preferred_lang: String,
 
@@ -35,6 +43,9 @@ pub struct UserInsert {
 
     /// The mail of the user
     pub mail: String,
+
+    /// Has the user proven to control their mail?
+    pub email_verified: bool,
 }
 
 /// Insert patch for [`LocalUser`]
@@ -83,6 +94,60 @@ pub struct WebAuthnKeyInsert {
 
     /// Cryptographic public key
     pub key: Json<MaybeAttestedPasskey>,
+
+    /// The id of the key's credential
+    pub cred_id: Option<Vec<u8>>,
+}
+
+/// Insert patch for [`EmailVerification`]
+#[derive(Patch)]
+#[rorm(model = "EmailVerification")]
+pub struct EmailVerificationInsert {
+    /// Primary key which is also the secret part of the verification link
+    pub uuid: Uuid,
+
+    /// The user whose mail should be verified
+    pub user: ForeignModel<User>,
+
+    /// The mail the link has been sent to
+    pub mail: String,
+
+    /// Until when can the mail be verified
+    pub expires_at: OffsetDateTime,
+}
+
+/// Insert patch for [`MagicLoginLink`]
+#[derive(Patch)]
+#[rorm(model = "MagicLoginLink")]
+pub struct MagicLoginLinkInsert {
+    /// Primary key which is also the secret part of the link
+    pub uuid: Uuid,
+
+    /// The user to log in
+    pub local_user: ForeignModel<LocalUser>,
+
+    /// Until when is the link valid
+    pub expires_at: OffsetDateTime,
+
+    /// The admin who issued this link
+    pub created_by: Option<ForeignModel<User>>,
+}
+
+/// Insert patch for [`EmailChange`]
+#[derive(Patch)]
+#[rorm(model = "EmailChange")]
+pub struct EmailChangeInsert {
+    /// Primary key which is also the secret part of the confirmation link
+    pub uuid: Uuid,
+
+    /// The user whose mail should be changed
+    pub user: ForeignModel<User>,
+
+    /// The new mail
+    pub new_mail: String,
+
+    /// Until when can the change be confirmed
+    pub expires_at: OffsetDateTime,
 }
 
 /// Insert patch for [`UserInvite`]
@@ -106,4 +171,21 @@ pub struct UserInviteInsert {
 
     /// Until when is the invite valid
     pub expires_at: OffsetDateTime,
+
+    /// The admin who created this invite
+    pub created_by: Option<ForeignModel<User>>,
+}
+
+/// Insert patch for [`UserGroups`]
+#[derive(Patch)]
+#[rorm(model = "UserGroups")]
+pub struct UserGroupsInsert {
+    /// A primary key
+    pub uuid: Uuid,
+
+    /// The user
+    pub user: ForeignModel<User>,
+
+    /// The group
+    pub group: ForeignModel<InternalGroup>,
 }
@@ -0,0 +1,102 @@
+//! Periodic removal of expired database rows
+
+use std::time::Duration;
+
+use rorm::Database;
+use rorm::FieldAccess;
+use rorm::Model;
+use time::OffsetDateTime;
+use tokio::time::interval;
+use tokio::time::MissedTickBehavior;
+use tracing::debug;
+use tracing::error;
+use tracing::info;
+
+use crate::global::GLOBAL;
+use crate::models::EmailChange;
+use crate::models::EmailVerification;
+use crate::models::KnownDevice;
+use crate::models::MagicLoginLink;
+use crate::models::Session;
+use crate::models::UserInvite;
+use crate::models::WsTicket;
+
+/// How long a device is remembered after the user last logged in with it
+const KNOWN_DEVICE_RETENTION: time::Duration = time::Duration::days(365);
+
+/// Spawn a task which purges expired invites, mail verifications and changes, login links
+/// and sessions every `period`
+///
+/// Invites are only purged once they have been expired for `invite_retention`.
+///
+/// [`GLOBAL`] has to be initialized before calling this function.
+pub fn spawn_cleanup(period: Duration, invite_retention: time::Duration) {
+    tokio::spawn(async move {
+        let mut interval = interval(period);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            interval.tick().await;
+
+            // Errors are most likely transient, so just try again next time
+            if let Err(error) = purge(&GLOBAL.db, invite_retention).await {
+                error!(error.display = %error, error.debug = ?error, "Cleanup failed");
+            }
+        }
+    });
+}
+
+/// Delete all expired invites, mail verifications and changes, login links, sessions and websocket tickets
+///
+/// Known devices are forgotten after [`KNOWN_DEVICE_RETENTION`].
+async fn purge(db: &Database, invite_retention: time::Duration) -> Result<(), rorm::Error> {
+    let now = OffsetDateTime::now_utc();
+
+    let invites = rorm::delete!(db, UserInvite)
+        .condition(UserInvite::F.expires_at.less_than(now - invite_retention))
+        .await?;
+    let email_verifications = rorm::delete!(db, EmailVerification)
+        .condition(EmailVerification::F.expires_at.less_than(now))
+        .await?;
+    let magic_links = rorm::delete!(db, MagicLoginLink)
+        .condition(MagicLoginLink::F.expires_at.less_than(now))
+        .await?;
+    let email_changes = rorm::delete!(db, EmailChange)
+        .condition(EmailChange::F.expires_at.less_than(now))
+        .await?;
+    let sessions = rorm::delete!(db, Session)
+        .condition(Session::F.expires_at.less_than(now))
+        .await?;
+    let known_devices = rorm::delete!(db, KnownDevice)
+        .condition(
+            KnownDevice::F
+                .last_seen
+                .less_than(now - KNOWN_DEVICE_RETENTION),
+        )
+        .await?;
+    let ws_tickets = rorm::delete!(db, WsTicket)
+        .condition(WsTicket::F.expires_at.less_than(now))
+        .await?;
+
+    if invites > 0
+        || email_verifications > 0
+        || magic_links > 0
+        || email_changes > 0
+        || sessions > 0
+        || known_devices > 0
+        || ws_tickets > 0
+    {
+        info!(
+            invites,
+            email_verifications,
+            magic_links,
+            email_changes,
+            sessions,
+            known_devices,
+            ws_tickets,
+            "Purged expired rows"
+        );
+    } else {
+        debug!("Nothing to purge");
+    }
+    Ok(())
+}
@@ -0,0 +1,5 @@
+//! Background tasks running alongside the webserver
+
+pub mod cleanup;
+pub mod reload_ca_list;
+pub mod reload_config;
@@ -0,0 +1,46 @@
+//! Periodic reload of the webauthn attestation CA list
+
+use std::path::PathBuf;
+use std::time::Duration;
+
+use tokio::time::interval;
+use tokio::time::MissedTickBehavior;
+use tracing::debug;
+use tracing::error;
+
+use crate::global::GLOBAL;
+use crate::utils::webauthn::load_attestation_ca_list;
+
+/// Spawn a task which reloads the attestation CA list from `path` every `period`
+///
+/// If the file can't be read, the current list is kept.
+///
+/// [`GLOBAL`] has to be initialized before calling this function.
+pub fn spawn_ca_list_reload(path: PathBuf, period: Duration) {
+    tokio::spawn(async move {
+        let mut interval = interval(period);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        // The first tick completes immediately and the list has just been loaded
+        interval.tick().await;
+        loop {
+            interval.tick().await;
+
+            let result = {
+                let path = path.clone();
+                tokio::task::spawn_blocking(move || load_attestation_ca_list(&path)).await
+            };
+            match result {
+                Ok(Ok(ca_list)) => {
+                    GLOBAL.webauthn_attestation_ca_list.swap(ca_list);
+                    debug!("Reloaded the attestation CA list");
+                }
+                Ok(Err(error)) => {
+                    error!(error.display = %error, "Keeping the current attestation CA list");
+                }
+                Err(error) => {
+                    error!(error.display = %error, "Reloading the attestation CA list panicked");
+                }
+            }
+        }
+    });
+}
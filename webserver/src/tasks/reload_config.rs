@@ -0,0 +1,63 @@
+//! Reload of the live-reloadable settings upon `SIGHUP`
+
+use futures::StreamExt;
+use signal_hook::consts::SIGHUP;
+use signal_hook_tokio::Signals;
+use tracing::error;
+use tracing::info;
+
+use crate::config::Config;
+use crate::global::Settings;
+use crate::global::GLOBAL;
+use crate::utils::webauthn::load_attestation_ca_list;
+
+/// Spawn a task which re-reads the config file at `path` whenever the process receives `SIGHUP`
+///
+/// Only the [`Settings`] and the attestation CA list are swapped,
+/// every other change requires a restart.
+/// If the file can't be read or is invalid, the current settings are kept.
+///
+/// [`GLOBAL`] has to be initialized before calling this function.
+pub fn spawn_config_reload(path: String) {
+    let mut signals = match Signals::new([SIGHUP]) {
+        Ok(signals) => signals,
+        Err(error) => {
+            error!(error.display = %error, "Could not register SIGHUP, the config can't be reloaded");
+            return;
+        }
+    };
+
+    tokio::spawn(async move {
+        while signals.next().await.is_some() {
+            info!("Received SIGHUP, reloading the config ..");
+
+            let config = match Config::try_from_path(&path) {
+                Ok(config) => config,
+                Err(error) => {
+                    error!(error.display = %error, "Keeping the current config");
+                    continue;
+                }
+            };
+
+            GLOBAL.settings.swap(Settings::from_config(&config));
+
+            let result = {
+                let path = config.webauthn.attestation_ca_list.clone();
+                tokio::task::spawn_blocking(move || load_attestation_ca_list(&path)).await
+            };
+            match result {
+                Ok(Ok(ca_list)) => {
+                    GLOBAL.webauthn_attestation_ca_list.swap(ca_list);
+                }
+                Ok(Err(error)) => {
+                    error!(error.display = %error, "Keeping the current attestation CA list");
+                }
+                Err(error) => {
+                    error!(error.display = %error, "Reloading the attestation CA list panicked");
+                }
+            }
+
+            info!("Reloaded the config, changes to other settings require a restart");
+        }
+    });
+}
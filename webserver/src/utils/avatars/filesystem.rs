@@ -0,0 +1,50 @@
+//! An [`AvatarStore`] using a local directory
+
+use std::io;
+use std::path::PathBuf;
+
+use axum::async_trait;
+
+use crate::utils::avatars::AvatarStore;
+use crate::utils::avatars::AvatarStoreError;
+
+/// Stores every avatar as a file in a local directory
+///
+/// Only suited for deployments running a single instance
+/// unless the directory is shared among them.
+pub struct FilesystemStore {
+    directory: PathBuf,
+}
+
+impl FilesystemStore {
+    /// Constructs a store using a directory
+    ///
+    /// The directory is created when storing the first avatar.
+    pub fn new(directory: PathBuf) -> Self {
+        Self { directory }
+    }
+}
+
+#[async_trait]
+impl AvatarStore for FilesystemStore {
+    async fn store(&self, key: &str, image: Vec<u8>) -> Result<(), AvatarStoreError> {
+        tokio::fs::create_dir_all(&self.directory).await?;
+        tokio::fs::write(self.directory.join(key), image).await?;
+        Ok(())
+    }
+
+    async fn load(&self, key: &str) -> Result<Option<Vec<u8>>, AvatarStoreError> {
+        match tokio::fs::read(self.directory.join(key)).await {
+            Ok(image) => Ok(Some(image)),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(error) => Err(error.into()),
+        }
+    }
+
+    async fn remove(&self, key: &str) -> Result<(), AvatarStoreError> {
+        match tokio::fs::remove_file(self.directory.join(key)).await {
+            Err(error) if error.kind() != io::ErrorKind::NotFound => Err(error.into()),
+            _ => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,231 @@
+//! Processing and storing the users' avatars
+//!
+//! The [`Avatars`] are optional and only available if the config contains an `Avatars` section.
+
+use std::io::Cursor;
+
+use axum::async_trait;
+use axum::body::Bytes;
+use image::imageops::FilterType;
+use image::ImageFormat;
+use image::ImageReader;
+use image::Limits;
+use thiserror::Error;
+use tracing::warn;
+
+use crate::config::AvatarStorageConfig;
+use crate::config::AvatarsConfig;
+
+mod filesystem;
+mod s3;
+
+pub use self::filesystem::FilesystemStore;
+pub use self::s3::S3Store;
+
+/// Upper bound for the width and height of uploaded images
+///
+/// Protects against images which are small in size but huge once decoded.
+const MAX_SOURCE_DIMENSION: u32 = 8192;
+
+/// A backend storing the processed avatars
+///
+/// The keys are generated by [`Avatars::new_key`] and safe to be used as file names.
+#[async_trait]
+pub trait AvatarStore: Send + Sync {
+    /// Stores an avatar under a key
+    async fn store(&self, key: &str, image: Vec<u8>) -> Result<(), AvatarStoreError>;
+
+    /// Loads the avatar stored under a key
+    ///
+    /// Returns `None` if there is none.
+    async fn load(&self, key: &str) -> Result<Option<Vec<u8>>, AvatarStoreError>;
+
+    /// Removes the avatar stored under a key
+    ///
+    /// Removing a missing avatar is not an error.
+    async fn remove(&self, key: &str) -> Result<(), AvatarStoreError>;
+}
+
+/// The configured avatar store and processing options
+pub struct Avatars {
+    /// The backend storing the processed avatars
+    pub store: Box<dyn AvatarStore>,
+
+    /// The maximum size of uploaded images in bytes
+    pub max_upload_bytes: usize,
+
+    /// The width and height avatars are resized to
+    size: u32,
+}
+
+impl Avatars {
+    /// Constructs the avatar store from the config
+    pub fn new(config: &AvatarsConfig) -> Result<Self, AvatarStoreError> {
+        let store: Box<dyn AvatarStore> = match &config.storage {
+            AvatarStorageConfig::Filesystem { directory } => {
+                Box::new(FilesystemStore::new(directory.clone()))
+            }
+            AvatarStorageConfig::S3 {
+                bucket,
+                region,
+                endpoint,
+                access_key_id,
+                secret_access_key,
+            } => Box::new(S3Store::new(
+                bucket,
+                region,
+                endpoint.as_ref(),
+                access_key_id,
+                secret_access_key,
+            )?),
+        };
+
+        Ok(Self {
+            store,
+            max_upload_bytes: config.max_upload_bytes,
+            size: config.size,
+        })
+    }
+
+    /// Generates a new unique key to store an avatar under
+    pub fn new_key() -> String {
+        format!("{}.png", uuid::Uuid::new_v4())
+    }
+
+    /// Removes an avatar which is no longer referenced from the store
+    ///
+    /// Failing to do so is not fatal and only logged.
+    pub async fn remove(&self, key: &str) {
+        if let Err(error) = self.store.remove(key).await {
+            warn!(error.display = %error, avatar = key, "Failed to remove an avatar");
+        }
+    }
+
+    /// Converts an uploaded image into a square png thumbnail
+    ///
+    /// The image is cropped to its center and re-encoded which drops any metadata like EXIF.
+    pub async fn process(&self, format: ImageFormat, data: Bytes) -> Result<Vec<u8>, ProcessError> {
+        let size = self.size;
+        tokio::task::spawn_blocking(move || {
+            let mut limits = Limits::default();
+            limits.max_image_width = Some(MAX_SOURCE_DIMENSION);
+            limits.max_image_height = Some(MAX_SOURCE_DIMENSION);
+
+            let mut reader = ImageReader::with_format(Cursor::new(data), format);
+            reader.limits(limits);
+            let image = reader.decode()?;
+
+            let thumbnail = image.resize_to_fill(size, size, FilterType::Lanczos3);
+            let mut png = Vec::new();
+            thumbnail.write_to(&mut Cursor::new(&mut png), ImageFormat::Png)?;
+            Ok(png)
+        })
+        .await?
+    }
+}
+
+/// The error that might occur when accessing an [`AvatarStore`]
+#[derive(Debug, Error)]
+#[allow(missing_docs)]
+pub enum AvatarStoreError {
+    #[error("Filesystem error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Object store error: {0}")]
+    ObjectStore(#[from] object_store::Error),
+}
+
+/// The error that might occur in [`Avatars::process`]
+#[derive(Debug, Error)]
+#[allow(missing_docs)]
+pub enum ProcessError {
+    #[error("Invalid image: {0}")]
+    Image(#[from] image::ImageError),
+    #[error("The processing task failed: {0}")]
+    Join(#[from] tokio::task::JoinError),
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::io::Cursor;
+
+    use image::ImageFormat;
+    use image::RgbImage;
+    use uuid::Uuid;
+
+    use super::AvatarStore;
+    use super::Avatars;
+    use super::FilesystemStore;
+
+    #[tokio::test]
+    async fn filesystem_store_roundtrip() -> Result<(), Box<dyn std::error::Error>> {
+        let directory = env::temp_dir().join(format!("avatars-{}", Uuid::new_v4()));
+        let store = FilesystemStore::new(directory.clone());
+        let key = Avatars::new_key();
+
+        assert_eq!(store.load(&key).await?, None);
+        store.store(&key, vec![1, 2, 3]).await?;
+        assert_eq!(store.load(&key).await?, Some(vec![1, 2, 3]));
+
+        store.remove(&key).await?;
+        assert_eq!(store.load(&key).await?, None);
+
+        // Removing it again is no error
+        store.remove(&key).await?;
+
+        tokio::fs::remove_dir(&directory).await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn images_are_cropped_to_squares() -> Result<(), Box<dyn std::error::Error>> {
+        let avatars = Avatars {
+            store: Box::new(FilesystemStore::new(env::temp_dir())),
+            max_upload_bytes: 1024 * 1024,
+            size: 16,
+        };
+
+        let mut jpeg = Vec::new();
+        RgbImage::new(64, 32).write_to(&mut Cursor::new(&mut jpeg), ImageFormat::Jpeg)?;
+
+        let png = avatars.process(ImageFormat::Jpeg, jpeg.into()).await?;
+        let thumbnail = image::load_from_memory_with_format(&png, ImageFormat::Png)?;
+        assert_eq!((thumbnail.width(), thumbnail.height()), (16, 16));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn invalid_images_are_rejected() {
+        let avatars = Avatars {
+            store: Box::new(FilesystemStore::new(env::temp_dir())),
+            max_upload_bytes: 1024 * 1024,
+            size: 16,
+        };
+
+        let result = avatars
+            .process(ImageFormat::Png, vec![1, 2, 3].into())
+            .await;
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn remove_deletes_the_avatar() -> Result<(), Box<dyn std::error::Error>> {
+        let directory = env::temp_dir().join(format!("avatars-{}", Uuid::new_v4()));
+        let avatars = Avatars {
+            store: Box::new(FilesystemStore::new(directory.clone())),
+            max_upload_bytes: 1024,
+            size: 64,
+        };
+        let key = Avatars::new_key();
+
+        avatars.store.store(&key, vec![1, 2, 3]).await?;
+        avatars.remove(&key).await;
+        assert_eq!(avatars.store.load(&key).await?, None);
+
+        // Removing it again is no error
+        avatars.remove(&key).await;
+
+        tokio::fs::remove_dir(&directory).await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,70 @@
+//! An [`AvatarStore`] using an S3 compatible object store
+
+use axum::async_trait;
+use object_store::aws::AmazonS3;
+use object_store::aws::AmazonS3Builder;
+use object_store::path::Path;
+use object_store::ObjectStore;
+use webauthn_rs::prelude::Url;
+
+use crate::utils::avatars::AvatarStore;
+use crate::utils::avatars::AvatarStoreError;
+
+/// Stores every avatar as an object in an S3 bucket
+///
+/// Suited for deployments running multiple instances.
+pub struct S3Store {
+    bucket: AmazonS3,
+}
+
+impl S3Store {
+    /// Constructs a store using a bucket
+    ///
+    /// The `endpoint` is only required for object stores other than AWS.
+    pub fn new(
+        bucket: &str,
+        region: &str,
+        endpoint: Option<&Url>,
+        access_key_id: &str,
+        secret_access_key: &str,
+    ) -> Result<Self, AvatarStoreError> {
+        let mut builder = AmazonS3Builder::new()
+            .with_bucket_name(bucket)
+            .with_region(region)
+            .with_access_key_id(access_key_id)
+            .with_secret_access_key(secret_access_key);
+        if let Some(endpoint) = endpoint {
+            builder = builder
+                .with_endpoint(endpoint.as_str())
+                .with_allow_http(endpoint.scheme() == "http");
+        }
+        Ok(Self {
+            bucket: builder.build()?,
+        })
+    }
+}
+
+#[async_trait]
+impl AvatarStore for S3Store {
+    async fn store(&self, key: &str, image: Vec<u8>) -> Result<(), AvatarStoreError> {
+        self.bucket.put(&Path::from(key), image.into()).await?;
+        Ok(())
+    }
+
+    async fn load(&self, key: &str) -> Result<Option<Vec<u8>>, AvatarStoreError> {
+        match self.bucket.get(&Path::from(key)).await {
+            Ok(result) => Ok(Some(result.bytes().await?.to_vec())),
+            Err(object_store::Error::NotFound { .. }) => Ok(None),
+            Err(error) => Err(error.into()),
+        }
+    }
+
+    async fn remove(&self, key: &str) -> Result<(), AvatarStoreError> {
+        match self.bucket.delete(&Path::from(key)).await {
+            Err(error) if !matches!(error, object_store::Error::NotFound { .. }) => {
+                Err(error.into())
+            }
+            _ => Ok(()),
+        }
+    }
+}
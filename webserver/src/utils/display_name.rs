@@ -0,0 +1,204 @@
+//! Normalization and validation of user's display names
+//!
+//! Display names are free text chosen by admins, invitees or identity providers.
+//! To prevent users from impersonating each other using invisible or direction changing characters,
+//! every display name passes through [`normalize_display_name`] before being stored.
+//!
+//! How strict this is can be configured using [`init_display_name_policy`].
+//! It also configures whether display names have to be unique (see [`unique_display_names`]).
+
+use std::sync::OnceLock;
+
+use serde::Deserialize;
+use serde::Serialize;
+use thiserror::Error;
+
+use crate::utils::checked_string::CheckedString;
+
+/// The policy applied by [`normalize_display_name`]
+static POLICY: OnceLock<DisplayNamePolicy> = OnceLock::new();
+
+/// Whether display names have to be unique
+static UNIQUE: OnceLock<bool> = OnceLock::new();
+
+/// How strictly display names should be normalized and validated
+#[derive(Deserialize, Serialize, Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum DisplayNamePolicy {
+    /// Store display names as they are
+    Off,
+
+    /// Strip control and invisible characters and collapse whitespace
+    #[default]
+    Normalize,
+
+    /// Like [`DisplayNamePolicy::Normalize`] but additionally reject names
+    /// mixing latin, greek and cyrillic letters
+    Strict,
+}
+
+/// Sets the policy used by [`normalize_display_name`] and whether display names have to be unique
+///
+/// This function should be called once at startup before any user is created.
+pub fn init_display_name_policy(
+    policy: DisplayNamePolicy,
+    unique: bool,
+) -> Result<(), AlreadyInitialized> {
+    POLICY.set(policy).map_err(|_| AlreadyInitialized)?;
+    UNIQUE.set(unique).map_err(|_| AlreadyInitialized)
+}
+
+/// Checks whether display names have to be unique (case-insensitive)
+///
+/// Defaults to `false` if [`init_display_name_policy`] hasn't been called.
+pub fn unique_display_names() -> bool {
+    UNIQUE.get().copied().unwrap_or_default()
+}
+
+/// The key stored in [`User::display_name_key`](crate::models::User::display_name_key) for a display name
+///
+/// `None` unless [`unique_display_names`] is enabled.
+pub fn display_name_key(display_name: &str) -> Option<String> {
+    unique_display_names().then(|| display_name.to_lowercase())
+}
+
+/// The display name policy has already been initialized
+#[derive(Debug, Error)]
+#[error("The display name policy has already been initialized")]
+pub struct AlreadyInitialized;
+
+/// Normalizes a display name according to the configured [`DisplayNamePolicy`]
+pub fn normalize_display_name(
+    display_name: CheckedString<1, 255>,
+) -> Result<CheckedString<1, 255>, InvalidDisplayName> {
+    normalize_with(POLICY.get().copied().unwrap_or_default(), display_name)
+}
+
+/// Implementation of [`normalize_display_name`] with a given policy
+fn normalize_with(
+    policy: DisplayNamePolicy,
+    display_name: CheckedString<1, 255>,
+) -> Result<CheckedString<1, 255>, InvalidDisplayName> {
+    if policy == DisplayNamePolicy::Off {
+        return Ok(display_name);
+    }
+
+    let normalized = display_name
+        .split_whitespace()
+        .map(|word| {
+            word.chars()
+                .filter(|c| !c.is_control() && !is_invisible(*c))
+                .collect::<String>()
+        })
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    if policy == DisplayNamePolicy::Strict && mixes_scripts(&normalized) {
+        return Err(InvalidDisplayName::MixedScripts);
+    }
+
+    CheckedString::new(normalized).map_err(|_| InvalidDisplayName::Empty)
+}
+
+/// The error returned by [`normalize_display_name`]
+#[derive(Debug, Error)]
+pub enum InvalidDisplayName {
+    /// Nothing remained after normalization
+    #[error("The display name is empty after normalization")]
+    Empty,
+
+    /// The display name mixes confusable scripts
+    #[error("The display name mixes latin, greek or cyrillic letters")]
+    MixedScripts,
+}
+
+/// Checks for zero-width, bidirectional control and other invisible formatting characters
+fn is_invisible(c: char) -> bool {
+    matches!(
+        c,
+        '\u{00AD}'
+            | '\u{034F}'
+            | '\u{061C}'
+            | '\u{115F}'
+            | '\u{1160}'
+            | '\u{17B4}'
+            | '\u{17B5}'
+            | '\u{180E}'
+            | '\u{200B}'..='\u{200F}'
+            | '\u{202A}'..='\u{202E}'
+            | '\u{2060}'..='\u{206F}'
+            | '\u{3164}'
+            | '\u{FE00}'..='\u{FE0F}'
+            | '\u{FEFF}'
+            | '\u{FFA0}'
+    )
+}
+
+/// Checks whether a string contains letters from more than one of the scripts
+/// latin, greek and cyrillic, which contain many look-alike characters
+fn mixes_scripts(s: &str) -> bool {
+    let (mut latin, mut greek, mut cyrillic) = (false, false, false);
+    for c in s.chars().filter(|c| c.is_alphabetic()) {
+        match c {
+            'a'..='z' | 'A'..='Z' | '\u{00C0}'..='\u{024F}' | '\u{1E00}'..='\u{1EFF}' => {
+                latin = true
+            }
+            '\u{0370}'..='\u{03FF}' | '\u{1F00}'..='\u{1FFF}' => greek = true,
+            '\u{0400}'..='\u{052F}' => cyrillic = true,
+            _ => {}
+        }
+    }
+    [latin, greek, cyrillic].into_iter().filter(|x| *x).count() > 1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::normalize_with;
+    use super::DisplayNamePolicy;
+    use super::InvalidDisplayName;
+    use crate::utils::checked_string::CheckedString;
+    use crate::utils::checked_string::ConstraintsViolated;
+
+    fn name(name: &str) -> Result<CheckedString<1, 255>, ConstraintsViolated> {
+        CheckedString::new(name.to_string())
+    }
+
+    #[test]
+    fn normalize_strips_invisible_characters_and_whitespace() -> Result<(), ConstraintsViolated> {
+        let normalized = normalize_with(
+            DisplayNamePolicy::Normalize,
+            name("  Jane\u{200B}\u{202E}  Doe\t")?,
+        );
+        assert_eq!(normalized.ok().as_deref(), Some("Jane Doe"));
+        Ok(())
+    }
+
+    #[test]
+    fn normalize_rejects_names_without_visible_characters() -> Result<(), ConstraintsViolated> {
+        assert!(matches!(
+            normalize_with(DisplayNamePolicy::Normalize, name("\u{200B}\u{FEFF}")?),
+            Err(InvalidDisplayName::Empty)
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn off_keeps_names_as_they_are() -> Result<(), ConstraintsViolated> {
+        let normalized = normalize_with(DisplayNamePolicy::Off, name(" Jane\u{200B} ")?);
+        assert_eq!(normalized.ok().as_deref(), Some(" Jane\u{200B} "));
+        Ok(())
+    }
+
+    #[test]
+    fn strict_rejects_mixed_scripts() -> Result<(), ConstraintsViolated> {
+        // The second letter is a cyrillic "a"
+        let mixed = "P\u{0430}ypal";
+        assert!(matches!(
+            normalize_with(DisplayNamePolicy::Strict, name(mixed)?),
+            Err(InvalidDisplayName::MixedScripts)
+        ));
+        assert!(normalize_with(DisplayNamePolicy::Normalize, name(mixed)?).is_ok());
+        assert!(normalize_with(DisplayNamePolicy::Strict, name("Jürgen Müller")?).is_ok());
+        Ok(())
+    }
+}
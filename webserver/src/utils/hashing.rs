@@ -1,16 +1,77 @@
 //! Helper methods for hashing are defined in this module
+//!
+//! Passwords may be hashed using an application wide secret ("pepper")
+//! which has to be set once at startup using [`init_pepper`].
+//! Changing or removing the pepper invalidates all existing password hashes.
+
+use std::sync::OnceLock;
 
 use argon2::password_hash::Error;
 use argon2::password_hash::SaltString;
+use argon2::Algorithm;
 use argon2::Argon2;
+use argon2::Params;
 use argon2::PasswordHash;
 use argon2::PasswordHasher;
 use argon2::PasswordVerifier;
+use argon2::Version;
 use thiserror::Error;
 
+/// The application wide secret mixed into every password hash
+static PEPPER: OnceLock<Option<Vec<u8>>> = OnceLock::new();
+
+/// The minimum length of a pepper in bytes
+pub const MIN_PEPPER_LEN: usize = 16;
+
+/// The hash [`verify_dummy`] verifies against
+///
+/// `None` if hashing the decoy failed.
+static DECOY_HASH: OnceLock<Option<String>> = OnceLock::new();
+
+/// Sets the pepper used by [`hash_pw`] and [`verify_pw`]
+///
+/// This function should be called once at startup before any password is hashed.
+pub fn init_pepper(pepper: Option<&str>) -> Result<(), InitPepperError> {
+    if let Some(pepper) = pepper {
+        if pepper.len() < MIN_PEPPER_LEN {
+            return Err(InitPepperError::TooShort);
+        }
+    }
+    PEPPER
+        .set(pepper.map(|pepper| pepper.as_bytes().to_vec()))
+        .map_err(|_| InitPepperError::AlreadyInitialized)?;
+
+    // Hash the decoy now instead of slowing down the first call to `verify_dummy`
+    decoy_hash();
+    Ok(())
+}
+
+/// Get the pepper set by [`init_pepper`]
+fn pepper() -> Option<&'static [u8]> {
+    PEPPER.get().and_then(|pepper| pepper.as_deref())
+}
+
+/// Constructs the [`Argon2`] context using a pepper
+fn argon2(pepper: Option<&[u8]>) -> Result<Argon2<'_>, Error> {
+    match pepper {
+        Some(pepper) => Ok(Argon2::new_with_secret(
+            pepper,
+            Algorithm::default(),
+            Version::default(),
+            Params::default(),
+        )?),
+        None => Ok(Argon2::default()),
+    }
+}
+
 /// Hash a password
 pub fn hash_pw(pw: &str) -> Result<String, argon2::password_hash::Error> {
-    Argon2::default()
+    hash_pw_with(pw, pepper())
+}
+
+/// Implementation of [`hash_pw`] with a given pepper
+fn hash_pw_with(pw: &str, pepper: Option<&[u8]>) -> Result<String, argon2::password_hash::Error> {
+    argon2(pepper)?
         .hash_password(
             pw.as_bytes(),
             &SaltString::generate(&mut rand::thread_rng()),
@@ -20,7 +81,12 @@ pub fn hash_pw(pw: &str) -> Result<String, argon2::password_hash::Error> {
 
 /// Verify a password
 pub fn verify_pw(pw: &str, hash: &str) -> Result<(), VerifyPwError> {
-    Argon2::default()
+    verify_pw_with(pw, hash, pepper())
+}
+
+/// Implementation of [`verify_pw`] with a given pepper
+fn verify_pw_with(pw: &str, hash: &str, pepper: Option<&[u8]>) -> Result<(), VerifyPwError> {
+    argon2(pepper)?
         .verify_password(pw.as_bytes(), &PasswordHash::new(hash)?)
         .map_err(|e| match e {
             Error::Password => VerifyPwError::Mismatch,
@@ -30,6 +96,25 @@ pub fn verify_pw(pw: &str, hash: &str) -> Result<(), VerifyPwError> {
     Ok(())
 }
 
+/// Verify a password against a decoy hash discarding the result
+///
+/// Call this when there is no hash to verify against (e.g. the user doesn't exist)
+/// to take as long as [`verify_pw`] and not reveal the absence through the response time.
+pub fn verify_dummy(pw: &str) {
+    if let Some(hash) = decoy_hash() {
+        let _ = verify_pw(pw, hash);
+    }
+}
+
+/// Get the hash [`verify_dummy`] verifies against
+///
+/// It is created using the same parameters and pepper as every other hash.
+fn decoy_hash() -> Option<&'static str> {
+    DECOY_HASH
+        .get_or_init(|| hash_pw("decoy password").ok())
+        .as_deref()
+}
+
 /// The possible outcomes of a verify_pw operation
 #[derive(Debug, Error)]
 #[allow(missing_docs)]
@@ -39,3 +124,78 @@ pub enum VerifyPwError {
     #[error("Password mismatched hash")]
     Mismatch,
 }
+
+/// The error returned by [`init_pepper`]
+#[derive(Debug, Error)]
+#[allow(missing_docs)]
+pub enum InitPepperError {
+    #[error("The pepper has to be at least {MIN_PEPPER_LEN} bytes long")]
+    TooShort,
+    #[error("The pepper has already been initialized")]
+    AlreadyInitialized,
+}
+
+#[cfg(test)]
+mod tests {
+    use argon2::password_hash::Error;
+    use argon2::PasswordHash;
+
+    use super::decoy_hash;
+    use super::hash_pw;
+    use super::hash_pw_with;
+    use super::init_pepper;
+    use super::verify_pw_with;
+    use super::InitPepperError;
+    use super::VerifyPwError;
+
+    const PEPPER: &[u8] = b"0123456789abcdef";
+
+    #[test]
+    fn verifies_password_hashed_with_pepper() -> Result<(), VerifyPwError> {
+        let hash = hash_pw_with("secret", Some(PEPPER))?;
+        verify_pw_with("secret", &hash, Some(PEPPER))
+    }
+
+    #[test]
+    fn rejects_wrong_password() -> Result<(), VerifyPwError> {
+        let hash = hash_pw_with("secret", Some(PEPPER))?;
+        assert!(matches!(
+            verify_pw_with("other", &hash, Some(PEPPER)),
+            Err(VerifyPwError::Mismatch)
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_password_after_pepper_changed() -> Result<(), VerifyPwError> {
+        let hash = hash_pw_with("secret", Some(PEPPER))?;
+        assert!(matches!(
+            verify_pw_with("secret", &hash, Some(b"fedcba9876543210")),
+            Err(VerifyPwError::Mismatch)
+        ));
+        assert!(matches!(
+            verify_pw_with("secret", &hash, None),
+            Err(VerifyPwError::Mismatch)
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_short_pepper() {
+        assert!(matches!(
+            init_pepper(Some("too short")),
+            Err(InitPepperError::TooShort)
+        ));
+    }
+
+    #[test]
+    fn decoy_is_hashed_like_passwords() -> Result<(), Error> {
+        let decoy = PasswordHash::new(decoy_hash().ok_or(Error::Password)?)?;
+        let hash = hash_pw("secret")?;
+        let hash = PasswordHash::new(&hash)?;
+        assert_eq!(decoy.algorithm, hash.algorithm);
+        assert_eq!(decoy.version, hash.version);
+        assert_eq!(decoy.params, hash.params);
+        Ok(())
+    }
+}
@@ -0,0 +1,363 @@
+//! Translations of the messages presented to users
+//!
+//! Every [`Message`] has to be translated into every [`UserLanguage`].
+//! Adding a language to [`UserLanguage`] therefore doesn't compile
+//! until a translation function has been added to [`Message::template`].
+
+use crate::http::handler_frontend::users::schema::UserLanguage;
+
+/// The name used to refer to this application in messages
+///
+/// It is available in every template as `{app}`.
+pub const APPLICATION_NAME: &str = "{{project-name}}";
+
+/// The keys of all translated messages
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[allow(missing_docs)]
+pub enum Message {
+    // Api errors
+    Unauthenticated,
+    BadRequest,
+    NotFound,
+    Conflict,
+    SessionCorrupt,
+    MissingPrivileges,
+    PayloadTooLarge,
+    CsrfFailed,
+    TooManyRequests,
+    EmailUnverified,
+    InternalServerError,
+
+    // Mails
+    /// Placeholders: `display_name`, `link`
+    InviteSubject,
+    /// Placeholders: `display_name`, `link`
+    InviteBody,
+    /// Placeholders: `display_name`, `link`
+    EmailChangeConfirmationSubject,
+    /// Placeholders: `display_name`, `link`
+    EmailChangeConfirmationBody,
+    /// Placeholders: `display_name`, `link`
+    EmailVerificationSubject,
+    /// Placeholders: `display_name`, `link`
+    EmailVerificationBody,
+    /// Placeholders: `display_name`, `new_mail`
+    EmailChangeNoticeSubject,
+    /// Placeholders: `display_name`, `new_mail`
+    EmailChangeNoticeBody,
+    /// Placeholders: `display_name`, `user_agent`, `ip`
+    NewDeviceLoginSubject,
+    /// Placeholders: `display_name`, `user_agent`, `ip`
+    NewDeviceLoginBody,
+}
+
+impl Message {
+    /// Get the message's raw template in a language
+    pub fn template(self, lang: UserLanguage) -> &'static str {
+        match lang {
+            UserLanguage::EN => self.en(),
+            UserLanguage::DE => self.de(),
+            UserLanguage::FR => self.fr(),
+            UserLanguage::ES => self.es(),
+        }
+    }
+
+    /// Get the message in a language
+    ///
+    /// Use [`Message::format`] for messages with placeholders.
+    pub fn translate(self, lang: UserLanguage) -> String {
+        self.format(lang, &[])
+    }
+
+    /// Get the message in a language and fill in its placeholders
+    ///
+    /// A placeholder `{name}` is replaced by the value of the pair whose key is `name`.
+    /// Unknown placeholders are left as is.
+    /// Values are inserted verbatim, i.e. they are not searched for placeholders themselves.
+    pub fn format(self, lang: UserLanguage, args: &[(&str, &str)]) -> String {
+        let mut template = self.template(lang);
+        let mut output = String::with_capacity(template.len());
+        while let Some(start) = template.find('{') {
+            output.push_str(&template[..start]);
+            template = &template[start..];
+
+            let value = template.find('}').and_then(|end| {
+                let key = &template[1..end];
+                let value = if key == "app" {
+                    Some(APPLICATION_NAME)
+                } else {
+                    args.iter().find(|(name, _)| *name == key).map(|(_, v)| *v)
+                };
+                value.map(|value| (value, end))
+            });
+            match value {
+                Some((value, end)) => {
+                    output.push_str(value);
+                    template = &template[end + 1..];
+                }
+                None => {
+                    output.push('{');
+                    template = &template[1..];
+                }
+            }
+        }
+        output.push_str(template);
+        output
+    }
+
+    fn en(self) -> &'static str {
+        match self {
+            Message::Unauthenticated => "Unauthenticated",
+            Message::BadRequest => "Bad Request",
+            Message::NotFound => "Not Found",
+            Message::Conflict => "Conflict",
+            Message::SessionCorrupt => "The session is corrupt, please log in again",
+            Message::MissingPrivileges => "Missing Privileges",
+            Message::PayloadTooLarge => "The request's body is too large",
+            Message::CsrfFailed => "The csrf token is missing or invalid",
+            Message::TooManyRequests => "Too many requests, please try again later",
+            Message::EmailUnverified => "Your mail address has to be verified first",
+            Message::InternalServerError => "Internal server error occurred",
+
+            Message::InviteSubject => "You have been invited to {app}",
+            Message::InviteBody => {
+                "Hello {display_name},\n\n\
+                you have been invited to {app}.\n\
+                Please follow the link below to set up your account:\n\n\
+                {link}\n"
+            }
+            Message::EmailChangeConfirmationSubject => "Confirm your new mail address for {app}",
+            Message::EmailChangeConfirmationBody => {
+                "Hello {display_name},\n\n\
+                please follow the link below to confirm this address \
+                for your {app} account:\n\n\
+                {link}\n"
+            }
+            Message::EmailVerificationSubject => "Verify your mail address for {app}",
+            Message::EmailVerificationBody => {
+                "Hello {display_name},\n\n\
+                please follow the link below to verify your mail address \
+                for your {app} account:\n\n\
+                {link}\n"
+            }
+            Message::EmailChangeNoticeSubject => "Your mail address for {app} is being changed",
+            Message::EmailChangeNoticeBody => {
+                "Hello {display_name},\n\n\
+                a change of your {app} account's mail address \
+                to {new_mail} has been requested.\n\
+                If this wasn't you, please contact your administrator.\n"
+            }
+            Message::NewDeviceLoginSubject => "New login to {app}",
+            Message::NewDeviceLoginBody => {
+                "Hello {display_name},\n\n\
+                your {app} account has been logged into from a new device:\n\n\
+                Device: {user_agent}\n\
+                IP address: {ip}\n\n\
+                If this wasn't you, please contact your administrator.\n"
+            }
+        }
+    }
+
+    fn de(self) -> &'static str {
+        match self {
+            Message::Unauthenticated => "Nicht angemeldet",
+            Message::BadRequest => "Ungültige Anfrage",
+            Message::NotFound => "Nicht gefunden",
+            Message::Conflict => "Konflikt",
+            Message::SessionCorrupt => {
+                "Die Sitzung ist beschädigt, bitte melden Sie sich erneut an"
+            }
+            Message::MissingPrivileges => "Fehlende Berechtigungen",
+            Message::PayloadTooLarge => "Der Inhalt der Anfrage ist zu groß",
+            Message::CsrfFailed => "Das CSRF-Token fehlt oder ist ungültig",
+            Message::TooManyRequests => "Zu viele Anfragen, bitte versuchen Sie es später erneut",
+            Message::EmailUnverified => "Ihre E-Mail-Adresse muss zuerst bestätigt werden",
+            Message::InternalServerError => "Ein interner Serverfehler ist aufgetreten",
+
+            Message::InviteSubject => "Sie wurden zu {app} eingeladen",
+            Message::InviteBody => {
+                "Hallo {display_name},\n\n\
+                Sie wurden zu {app} eingeladen.\n\
+                Bitte folgen Sie dem Link, um Ihren Account einzurichten:\n\n\
+                {link}\n"
+            }
+            Message::EmailChangeConfirmationSubject => {
+                "Bestätigen Sie Ihre neue E-Mail-Adresse für {app}"
+            }
+            Message::EmailChangeConfirmationBody => {
+                "Hallo {display_name},\n\n\
+                bitte folgen Sie dem Link, um diese Adresse \
+                für Ihren {app} Account zu bestätigen:\n\n\
+                {link}\n"
+            }
+            Message::EmailVerificationSubject => "Bestätigen Sie Ihre E-Mail-Adresse für {app}",
+            Message::EmailVerificationBody => {
+                "Hallo {display_name},\n\n\
+                bitte folgen Sie dem Link, um Ihre E-Mail-Adresse \
+                für Ihren {app} Account zu bestätigen:\n\n\
+                {link}\n"
+            }
+            Message::EmailChangeNoticeSubject => "Ihre E-Mail-Adresse für {app} wird geändert",
+            Message::EmailChangeNoticeBody => {
+                "Hallo {display_name},\n\n\
+                für Ihren {app} Account wurde eine Änderung \
+                der E-Mail-Adresse zu {new_mail} angefordert.\n\
+                Falls Sie das nicht waren, wenden Sie sich bitte an Ihren Administrator.\n"
+            }
+            Message::NewDeviceLoginSubject => "Neue Anmeldung bei {app}",
+            Message::NewDeviceLoginBody => {
+                "Hallo {display_name},\n\n\
+                bei Ihrem {app} Account hat sich ein neues Gerät angemeldet:\n\n\
+                Gerät: {user_agent}\n\
+                IP-Adresse: {ip}\n\n\
+                Falls Sie das nicht waren, wenden Sie sich bitte an Ihren Administrator.\n"
+            }
+        }
+    }
+
+    fn fr(self) -> &'static str {
+        match self {
+            Message::Unauthenticated => "Non authentifié",
+            Message::BadRequest => "Requête invalide",
+            Message::NotFound => "Introuvable",
+            Message::Conflict => "Conflit",
+            Message::SessionCorrupt => "La session est corrompue, veuillez vous reconnecter",
+            Message::MissingPrivileges => "Privilèges insuffisants",
+            Message::PayloadTooLarge => "Le corps de la requête est trop volumineux",
+            Message::CsrfFailed => "Le jeton CSRF est manquant ou invalide",
+            Message::TooManyRequests => "Trop de requêtes, veuillez réessayer plus tard",
+            Message::EmailUnverified => "Votre adresse e-mail doit d'abord être vérifiée",
+            Message::InternalServerError => "Une erreur interne du serveur s'est produite",
+
+            Message::InviteSubject => "Vous avez été invité à {app}",
+            Message::InviteBody => {
+                "Bonjour {display_name},\n\n\
+                vous avez été invité à {app}.\n\
+                Veuillez suivre le lien ci-dessous pour configurer votre compte :\n\n\
+                {link}\n"
+            }
+            Message::EmailChangeConfirmationSubject => {
+                "Confirmez votre nouvelle adresse e-mail pour {app}"
+            }
+            Message::EmailChangeConfirmationBody => {
+                "Bonjour {display_name},\n\n\
+                veuillez suivre le lien ci-dessous pour confirmer cette adresse \
+                pour votre compte {app} :\n\n\
+                {link}\n"
+            }
+            Message::EmailVerificationSubject => "Vérifiez votre adresse e-mail pour {app}",
+            Message::EmailVerificationBody => {
+                "Bonjour {display_name},\n\n\
+                veuillez suivre le lien ci-dessous pour vérifier votre adresse e-mail \
+                pour votre compte {app} :\n\n\
+                {link}\n"
+            }
+            Message::EmailChangeNoticeSubject => "Votre adresse e-mail pour {app} va être modifiée",
+            Message::EmailChangeNoticeBody => {
+                "Bonjour {display_name},\n\n\
+                une modification de l'adresse e-mail de votre compte {app} \
+                vers {new_mail} a été demandée.\n\
+                Si ce n'était pas vous, veuillez contacter votre administrateur.\n"
+            }
+            Message::NewDeviceLoginSubject => "Nouvelle connexion à {app}",
+            Message::NewDeviceLoginBody => {
+                "Bonjour {display_name},\n\n\
+                une connexion à votre compte {app} a eu lieu depuis un nouvel appareil :\n\n\
+                Appareil : {user_agent}\n\
+                Adresse IP : {ip}\n\n\
+                Si ce n'était pas vous, veuillez contacter votre administrateur.\n"
+            }
+        }
+    }
+
+    fn es(self) -> &'static str {
+        match self {
+            Message::Unauthenticated => "No autenticado",
+            Message::BadRequest => "Solicitud incorrecta",
+            Message::NotFound => "No encontrado",
+            Message::Conflict => "Conflicto",
+            Message::SessionCorrupt => "La sesión está dañada, por favor inicie sesión de nuevo",
+            Message::MissingPrivileges => "Faltan privilegios",
+            Message::PayloadTooLarge => "El cuerpo de la solicitud es demasiado grande",
+            Message::CsrfFailed => "El token CSRF falta o no es válido",
+            Message::TooManyRequests => "Demasiadas solicitudes, inténtelo de nuevo más tarde",
+            Message::EmailUnverified => "Primero debe verificar su dirección de correo",
+            Message::InternalServerError => "Se ha producido un error interno del servidor",
+
+            Message::InviteSubject => "Ha sido invitado a {app}",
+            Message::InviteBody => {
+                "Hola {display_name}:\n\n\
+                ha sido invitado a {app}.\n\
+                Por favor, siga el enlace de abajo para configurar su cuenta:\n\n\
+                {link}\n"
+            }
+            Message::EmailChangeConfirmationSubject => {
+                "Confirme su nueva dirección de correo para {app}"
+            }
+            Message::EmailChangeConfirmationBody => {
+                "Hola {display_name}:\n\n\
+                por favor, siga el enlace de abajo para confirmar esta dirección \
+                para su cuenta de {app}:\n\n\
+                {link}\n"
+            }
+            Message::EmailVerificationSubject => "Verifique su dirección de correo para {app}",
+            Message::EmailVerificationBody => {
+                "Hola {display_name}:\n\n\
+                por favor, siga el enlace de abajo para verificar su dirección de correo \
+                para su cuenta de {app}:\n\n\
+                {link}\n"
+            }
+            Message::EmailChangeNoticeSubject => {
+                "Su dirección de correo para {app} se está cambiando"
+            }
+            Message::EmailChangeNoticeBody => {
+                "Hola {display_name}:\n\n\
+                se ha solicitado un cambio de la dirección de correo de su cuenta de {app} \
+                a {new_mail}.\n\
+                Si no fue usted, por favor contacte a su administrador.\n"
+            }
+            Message::NewDeviceLoginSubject => "Nuevo inicio de sesión en {app}",
+            Message::NewDeviceLoginBody => {
+                "Hola {display_name}:\n\n\
+                se ha iniciado sesión en su cuenta de {app} desde un nuevo dispositivo:\n\n\
+                Dispositivo: {user_agent}\n\
+                Dirección IP: {ip}\n\n\
+                Si no fue usted, por favor contacte a su administrador.\n"
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Message;
+    use super::APPLICATION_NAME;
+    use crate::http::handler_frontend::users::schema::UserLanguage;
+
+    #[test]
+    fn placeholders_are_filled_in() {
+        let subject = Message::InviteSubject.format(UserLanguage::EN, &[]);
+        assert_eq!(
+            subject,
+            format!("You have been invited to {APPLICATION_NAME}")
+        );
+
+        let body = Message::NewDeviceLoginBody.format(
+            UserLanguage::DE,
+            &[
+                ("display_name", "Jane"),
+                ("user_agent", "curl"),
+                ("ip", "127.0.0.1"),
+            ],
+        );
+        assert!(body.starts_with("Hallo Jane,"));
+        assert!(body.contains("Gerät: curl\nIP-Adresse: 127.0.0.1\n"));
+    }
+
+    #[test]
+    fn unknown_placeholders_and_braces_in_values_are_kept() {
+        let body = Message::InviteBody.format(UserLanguage::EN, &[("link", "{display_name}")]);
+        assert!(body.starts_with("Hello {display_name},"));
+        assert!(body.ends_with("\n\n{display_name}\n"));
+    }
+}
@@ -0,0 +1,191 @@
+//! Networks of ip addresses written in CIDR notation
+
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+use serde::Deserialize;
+use serde::Serialize;
+use thiserror::Error;
+
+/// A network of ip addresses like `10.0.0.0/8` or `fd00::/8`
+///
+/// A single address without a prefix length is a network containing only itself.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Deserialize, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct IpNetwork {
+    /// The network's address with all bits after the prefix cleared
+    address: IpAddr,
+
+    /// The number of leading bits identifying the network
+    prefix_len: u8,
+}
+
+impl IpNetwork {
+    /// Checks whether an address is part of the network
+    ///
+    /// IPv4 addresses mapped to IPv6 are treated as the IPv4 address they map to.
+    pub fn contains(&self, address: IpAddr) -> bool {
+        match (self.address, address.to_canonical()) {
+            (IpAddr::V4(network), IpAddr::V4(address)) => {
+                let mask = mask_u32(self.prefix_len);
+                u32::from(network) == u32::from(address) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(address)) => {
+                let mask = mask_u128(self.prefix_len);
+                u128::from(network) == u128::from(address) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+/// The mask selecting the first `prefix_len` bits of an IPv4 address
+fn mask_u32(prefix_len: u8) -> u32 {
+    u32::MAX
+        .checked_shl(32 - u32::from(prefix_len))
+        .unwrap_or(0)
+}
+
+/// The mask selecting the first `prefix_len` bits of an IPv6 address
+fn mask_u128(prefix_len: u8) -> u128 {
+    u128::MAX
+        .checked_shl(128 - u32::from(prefix_len))
+        .unwrap_or(0)
+}
+
+impl FromStr for IpNetwork {
+    type Err = InvalidIpNetwork;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (address, prefix_len) = match s.split_once('/') {
+            Some((address, prefix_len)) => (address, Some(prefix_len)),
+            None => (s, None),
+        };
+        let address: IpAddr = address.parse().map_err(|_| InvalidIpNetwork)?;
+        let max_prefix_len = if address.is_ipv4() { 32 } else { 128 };
+        let prefix_len = match prefix_len {
+            Some(prefix_len) => prefix_len.parse().map_err(|_| InvalidIpNetwork)?,
+            None => max_prefix_len,
+        };
+        if prefix_len > max_prefix_len {
+            return Err(InvalidIpNetwork);
+        }
+
+        // Clearing the host bits lets `contains` compare the addresses directly
+        let address = match address {
+            IpAddr::V4(address) => {
+                let mask = mask_u32(prefix_len);
+                IpAddr::V4((u32::from(address) & mask).into())
+            }
+            IpAddr::V6(address) => {
+                let mask = mask_u128(prefix_len);
+                IpAddr::V6((u128::from(address) & mask).into())
+            }
+        };
+        Ok(Self {
+            address,
+            prefix_len,
+        })
+    }
+}
+
+impl TryFrom<String> for IpNetwork {
+    type Error = InvalidIpNetwork;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<IpNetwork> for String {
+    fn from(value: IpNetwork) -> Self {
+        value.to_string()
+    }
+}
+
+impl fmt::Display for IpNetwork {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.address, self.prefix_len)
+    }
+}
+
+/// The string is no ip address optionally followed by a `/` and a valid prefix length
+#[derive(Debug, Error)]
+#[error("Expected an ip network like 10.0.0.0/8 or fd00::/8")]
+pub struct InvalidIpNetwork;
+
+#[cfg(test)]
+mod tests {
+    use std::net::IpAddr;
+
+    use super::IpNetwork;
+
+    fn ip(address: &str) -> Result<IpAddr, std::net::AddrParseError> {
+        address.parse()
+    }
+
+    #[test]
+    fn ipv4_network_contains_its_addresses() -> Result<(), Box<dyn std::error::Error>> {
+        let network: IpNetwork = "10.0.0.0/8".parse()?;
+        assert!(network.contains(ip("10.0.0.1")?));
+        assert!(network.contains(ip("10.255.255.255")?));
+        assert!(!network.contains(ip("11.0.0.1")?));
+        assert!(!network.contains(ip("::1")?));
+        Ok(())
+    }
+
+    #[test]
+    fn ipv6_network_contains_its_addresses() -> Result<(), Box<dyn std::error::Error>> {
+        let network: IpNetwork = "fd00::/8".parse()?;
+        assert!(network.contains(ip("fd12:3456::1")?));
+        assert!(!network.contains(ip("fe80::1")?));
+        assert!(!network.contains(ip("10.0.0.1")?));
+        Ok(())
+    }
+
+    #[test]
+    fn mapped_ipv4_addresses_are_treated_as_ipv4() -> Result<(), Box<dyn std::error::Error>> {
+        let network: IpNetwork = "192.168.0.0/16".parse()?;
+        assert!(network.contains(ip("::ffff:192.168.1.1")?));
+        Ok(())
+    }
+
+    #[test]
+    fn host_bits_are_ignored() -> Result<(), Box<dyn std::error::Error>> {
+        let network: IpNetwork = "192.168.1.1/24".parse()?;
+        assert_eq!(network.to_string(), "192.168.1.0/24");
+        assert!(network.contains(ip("192.168.1.200")?));
+        Ok(())
+    }
+
+    #[test]
+    fn single_address_is_a_network_of_itself() -> Result<(), Box<dyn std::error::Error>> {
+        let network: IpNetwork = "192.0.2.1".parse()?;
+        assert_eq!(network.to_string(), "192.0.2.1/32");
+        assert!(network.contains(ip("192.0.2.1")?));
+        assert!(!network.contains(ip("192.0.2.2")?));
+        Ok(())
+    }
+
+    #[test]
+    fn zero_prefix_contains_everything() -> Result<(), Box<dyn std::error::Error>> {
+        let network: IpNetwork = "0.0.0.0/0".parse()?;
+        assert!(network.contains(ip("203.0.113.7")?));
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_networks_are_rejected() {
+        for invalid in [
+            "",
+            "10.0.0.0/33",
+            "fd00::/129",
+            "10.0.0.0/",
+            "10.0.0/8",
+            "example.com",
+        ] {
+            assert!(invalid.parse::<IpNetwork>().is_err(), "{invalid}");
+        }
+    }
+}
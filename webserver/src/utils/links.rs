@@ -11,3 +11,48 @@ use uuid::Uuid;
 pub fn new_user_invite_link(origin: &str, user_invite_uuid: Uuid) -> String {
     format!("{origin}/invite/{user_invite_uuid}")
 }
+
+/// Constructs a new link to verify a user's mail.
+///
+/// The link resolves to a view in the frontend where the logged-in user completes the verification.
+pub fn new_email_verification_link(origin: &str, email_verification_uuid: Uuid) -> String {
+    format!("{origin}/verify-email/{email_verification_uuid}")
+}
+
+/// Constructs a new link to confirm a change of a user's mail.
+///
+/// The link resolves to a view in the frontend where the logged-in user confirms the change.
+pub fn new_email_change_link(origin: &str, email_change_uuid: Uuid) -> String {
+    format!("{origin}/confirm-email/{email_change_uuid}")
+}
+
+/// Constructs a new link for a magic login link.
+///
+/// The link points directly to the api which establishes the session and redirects to the frontend.
+pub fn new_magic_login_link(origin: &str, magic_login_link_uuid: Uuid) -> String {
+    format!("{origin}/api/frontend/v1/common/auth/magic/{magic_login_link_uuid}")
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use super::new_email_change_link;
+    use super::new_magic_login_link;
+
+    #[test]
+    fn magic_login_link_points_to_the_api() {
+        assert_eq!(
+            new_magic_login_link("https://example.com", Uuid::nil()),
+            "https://example.com/api/frontend/v1/common/auth/magic/00000000-0000-0000-0000-000000000000"
+        );
+    }
+
+    #[test]
+    fn email_change_link_points_to_the_frontend() {
+        assert_eq!(
+            new_email_change_link("https://example.com", Uuid::nil()),
+            "https://example.com/confirm-email/00000000-0000-0000-0000-000000000000"
+        );
+    }
+}
@@ -0,0 +1,198 @@
+//! Sending mails to users via SMTP
+//!
+//! The [`Mailer`] is optional and only available if the config contains a `Smtp` section.
+
+use lettre::address::AddressError;
+use lettre::message::header::ContentType;
+use lettre::message::Mailbox;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::AsyncSmtpTransport;
+use lettre::AsyncTransport;
+use lettre::Message;
+use lettre::Tokio1Executor;
+use thiserror::Error;
+use tracing::info;
+use tracing::warn;
+
+use crate::config::SmtpConfig;
+use crate::config::SmtpEncryption;
+use crate::http::handler_frontend::users::schema::UserLanguage;
+use crate::utils::i18n;
+
+/// Connection to a SMTP server used to send mails
+#[derive(Debug, Clone)]
+pub struct Mailer {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+}
+
+impl Mailer {
+    /// Constructs a new mailer from the config
+    ///
+    /// The connection is established lazily when sending the first mail.
+    pub fn new(config: &SmtpConfig) -> Result<Self, MailerError> {
+        let builder = match config.encryption {
+            SmtpEncryption::Tls => AsyncSmtpTransport::<Tokio1Executor>::relay(&config.host)?,
+            SmtpEncryption::StartTls => {
+                AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&config.host)?
+            }
+            SmtpEncryption::None => {
+                AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&config.host)
+            }
+        };
+        let builder = match config.port {
+            Some(port) => builder.port(port),
+            None => builder,
+        };
+        let transport = builder
+            .credentials(Credentials::new(
+                config.user.clone(),
+                config.password.clone().into_inner(),
+            ))
+            .build();
+
+        Ok(Self {
+            transport,
+            from: config.from.parse()?,
+        })
+    }
+
+    /// Sends a mail containing the link to accept an invite
+    ///
+    /// Failing to send the mail is not fatal and only logged.
+    pub async fn send_invite(
+        &self,
+        mail: &str,
+        display_name: &str,
+        lang: UserLanguage,
+        link: &str,
+    ) {
+        let args = [("display_name", display_name), ("link", link)];
+        let subject = i18n::Message::InviteSubject.format(lang, &args);
+        let body = i18n::Message::InviteBody.format(lang, &args);
+
+        match self.send(mail, display_name, subject, body).await {
+            Ok(()) => info!(mail, "Sent invite mail"),
+            Err(error) => warn!(mail, error = %error, "Failed to send invite mail"),
+        }
+    }
+
+    /// Sends a mail containing the link to verify a user's mail
+    ///
+    /// Failing to send the mail is not fatal and only logged.
+    pub async fn send_email_verification(
+        &self,
+        mail: &str,
+        display_name: &str,
+        lang: UserLanguage,
+        link: &str,
+    ) {
+        let args = [("display_name", display_name), ("link", link)];
+        let subject = i18n::Message::EmailVerificationSubject.format(lang, &args);
+        let body = i18n::Message::EmailVerificationBody.format(lang, &args);
+
+        match self.send(mail, display_name, subject, body).await {
+            Ok(()) => info!(mail, "Sent mail verification"),
+            Err(error) => warn!(mail, error = %error, "Failed to send mail verification"),
+        }
+    }
+
+    /// Sends a mail to a user's new address containing the link to confirm the change
+    ///
+    /// Failing to send the mail is not fatal and only logged.
+    pub async fn send_email_change_confirmation(
+        &self,
+        new_mail: &str,
+        display_name: &str,
+        lang: UserLanguage,
+        link: &str,
+    ) {
+        let args = [("display_name", display_name), ("link", link)];
+        let subject = i18n::Message::EmailChangeConfirmationSubject.format(lang, &args);
+        let body = i18n::Message::EmailChangeConfirmationBody.format(lang, &args);
+
+        match self.send(new_mail, display_name, subject, body).await {
+            Ok(()) => info!(mail = new_mail, "Sent mail change confirmation"),
+            Err(error) => {
+                warn!(mail = new_mail, error = %error, "Failed to send mail change confirmation")
+            }
+        }
+    }
+
+    /// Notifies a user's old address about a requested change of their mail
+    ///
+    /// Failing to send the mail is not fatal and only logged.
+    pub async fn send_email_change_notice(
+        &self,
+        old_mail: &str,
+        display_name: &str,
+        lang: UserLanguage,
+        new_mail: &str,
+    ) {
+        let args = [("display_name", display_name), ("new_mail", new_mail)];
+        let subject = i18n::Message::EmailChangeNoticeSubject.format(lang, &args);
+        let body = i18n::Message::EmailChangeNoticeBody.format(lang, &args);
+
+        match self.send(old_mail, display_name, subject, body).await {
+            Ok(()) => info!(mail = old_mail, "Sent mail change notice"),
+            Err(error) => {
+                warn!(mail = old_mail, error = %error, "Failed to send mail change notice")
+            }
+        }
+    }
+
+    /// Notifies a user about a login from a device they haven't used before
+    ///
+    /// Failing to send the mail is not fatal and only logged.
+    pub async fn send_new_device_login(
+        &self,
+        mail: &str,
+        display_name: &str,
+        lang: UserLanguage,
+        user_agent: &str,
+        ip: &str,
+    ) {
+        let args = [
+            ("display_name", display_name),
+            ("user_agent", user_agent),
+            ("ip", ip),
+        ];
+        let subject = i18n::Message::NewDeviceLoginSubject.format(lang, &args);
+        let body = i18n::Message::NewDeviceLoginBody.format(lang, &args);
+
+        match self.send(mail, display_name, subject, body).await {
+            Ok(()) => info!(mail, "Sent new device notice"),
+            Err(error) => warn!(mail, error = %error, "Failed to send new device notice"),
+        }
+    }
+
+    /// Sends a plain text mail
+    async fn send(
+        &self,
+        mail: &str,
+        display_name: &str,
+        subject: String,
+        body: String,
+    ) -> Result<(), MailerError> {
+        let message = Message::builder()
+            .from(self.from.clone())
+            .to(Mailbox::new(Some(display_name.to_string()), mail.parse()?))
+            .subject(subject)
+            .header(ContentType::TEXT_PLAIN)
+            .body(body)?;
+        self.transport.send(message).await?;
+        Ok(())
+    }
+}
+
+/// The errors which might occur while constructing a [`Mailer`] or sending a mail
+#[derive(Debug, Error)]
+#[allow(missing_docs)]
+pub enum MailerError {
+    #[error("Invalid mail address: {0}")]
+    Address(#[from] AddressError),
+    #[error("Invalid message: {0}")]
+    Message(#[from] lettre::error::Error),
+    #[error("SMTP error: {0}")]
+    Smtp(#[from] lettre::transport::smtp::Error),
+}
@@ -0,0 +1,192 @@
+//! Comparison of the migrations applied to the database with the ones on disk
+
+use std::path::Path;
+
+use rorm::cli::utils::migrations::get_existing_migrations;
+use rorm::Database;
+use thiserror::Error;
+
+/// The table rorm's migrator stores the last applied migration in
+const LAST_MIGRATION_TABLE: &str = "_rorm__last_migration";
+
+/// Ensure the database has all migrations from `migrations_dir` applied
+pub async fn check_migrations(
+    db: &Database,
+    migrations_dir: &str,
+) -> Result<(), MigrationCheckError> {
+    let available = latest_available_migration(migrations_dir)?;
+    let applied = latest_applied_migration(db).await?;
+    ensure_applied(applied, available, migrations_dir)
+}
+
+/// Compare the last applied migration with the latest available one
+fn ensure_applied(
+    applied: Option<u16>,
+    available: Option<u16>,
+    migrations_dir: &str,
+) -> Result<(), MigrationCheckError> {
+    if let Some(available) = available {
+        if applied.map_or(true, |applied| applied < available) {
+            return Err(MigrationCheckError::Pending {
+                applied,
+                available,
+                migrations_dir: migrations_dir.to_string(),
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Get the highest migration id from a directory of migration files
+pub fn latest_available_migration(
+    migrations_dir: &str,
+) -> Result<Option<u16>, MigrationCheckError> {
+    // A missing directory must not pass as one without migrations
+    if !Path::new(migrations_dir).is_dir() {
+        return Err(MigrationCheckError::Read {
+            migrations_dir: migrations_dir.to_string(),
+            source: "The directory doesn't exist".into(),
+        });
+    }
+    let migrations =
+        get_existing_migrations(migrations_dir).map_err(|source| MigrationCheckError::Read {
+            migrations_dir: migrations_dir.to_string(),
+            source: source.into(),
+        })?;
+    Ok(migrations.iter().map(|migration| migration.id).max())
+}
+
+/// Get the id of the last migration applied to the database
+///
+/// Returns `None` if no migration has been applied yet.
+pub async fn latest_applied_migration(db: &Database) -> Result<Option<u16>, MigrationCheckError> {
+    let rows = match db
+        .raw_sql(
+            &format!("SELECT migration_id FROM {LAST_MIGRATION_TABLE} ORDER BY id DESC LIMIT 1;"),
+            None,
+            None,
+        )
+        .await
+    {
+        Ok(rows) => rows,
+        // The table is created by the first migration run,
+        // so querying it fails on a fresh database
+        Err(error) if is_undefined_table(&error) => return Ok(None),
+        Err(error) => return Err(MigrationCheckError::Database(error)),
+    };
+
+    let Some(row) = rows.first() else {
+        return Ok(None);
+    };
+    let id: i32 = row
+        .get("migration_id")
+        .map_err(MigrationCheckError::Database)?;
+    Ok(Some(id as u16))
+}
+
+/// Checks whether a query failed because it referenced a table which doesn't exist
+fn is_undefined_table(error: &rorm::Error) -> bool {
+    match error {
+        rorm::Error::SqlxError(error) => error
+            .as_database_error()
+            .and_then(|error| error.code())
+            .is_some_and(|code| code == "42P01"),
+        _ => false,
+    }
+}
+
+/// Errors which might occur while checking the migrations
+#[derive(Debug, Error)]
+#[allow(missing_docs)]
+pub enum MigrationCheckError {
+    #[error("Could not read the migrations in {migrations_dir}: {source}")]
+    Read {
+        migrations_dir: String,
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+    #[error("Could not query the applied migrations: {0}")]
+    Database(rorm::Error),
+    #[error(
+        "The database is behind (applied: {}, available: {available}), run `Migrate {}` first",
+        applied.map_or("none".to_string(), |id| id.to_string()),
+        migrations_dir,
+    )]
+    Pending {
+        applied: Option<u16>,
+        available: u16,
+        migrations_dir: String,
+    },
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+
+    use uuid::Uuid;
+
+    use super::ensure_applied;
+    use super::latest_available_migration;
+    use super::MigrationCheckError;
+
+    #[test]
+    fn unmigrated_database_is_behind() {
+        assert!(matches!(
+            ensure_applied(None, Some(3), "migrations"),
+            Err(MigrationCheckError::Pending {
+                applied: None,
+                available: 3,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn partially_migrated_database_is_behind() {
+        assert!(matches!(
+            ensure_applied(Some(2), Some(3), "migrations"),
+            Err(MigrationCheckError::Pending {
+                applied: Some(2),
+                available: 3,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn migrated_database_passes() {
+        assert!(ensure_applied(Some(3), Some(3), "migrations").is_ok());
+    }
+
+    #[test]
+    fn pending_error_names_the_directory() -> Result<(), Box<dyn std::error::Error>> {
+        let error = ensure_applied(None, Some(1), "/srv/migrations")
+            .err()
+            .ok_or("expected the database to be behind")?;
+        assert_eq!(
+            error.to_string(),
+            "The database is behind (applied: none, available: 1), run `Migrate /srv/migrations` first"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn empty_migrations_dir_requires_nothing() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = env::temp_dir().join(format!("migrations-{}", Uuid::new_v4()));
+        fs::create_dir(&dir)?;
+        let available = latest_available_migration(dir.to_str().ok_or("temp dir is not unicode")?);
+        fs::remove_dir(&dir)?;
+
+        assert_eq!(available?, None);
+        assert!(ensure_applied(None, None, "migrations").is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn missing_migrations_dir_is_reported() {
+        assert!(matches!(
+            latest_available_migration("/nonexistent/migrations"),
+            Err(MigrationCheckError::Read { .. })
+        ));
+    }
+}
@@ -1,11 +1,25 @@
 //! Utility modules that may be used throughout multiple handlers or from a task
 //! within the webserver are defined here
 
+pub mod avatars;
 pub mod checked_string;
+pub mod constant_time;
+pub mod display_name;
 pub mod hashing;
+pub mod i18n;
+pub mod ip_network;
 pub mod links;
+pub mod mailer;
+pub mod migrations;
+pub mod oidc_tokens;
+pub mod password_policy;
+pub mod rate_limit;
 pub mod schemars;
+pub mod search;
 pub mod secure_string;
 pub mod swap_lock;
+#[cfg(test)]
+pub mod test_db;
 pub mod totp;
 pub mod webauthn;
+pub mod webhooks;
@@ -0,0 +1,238 @@
+//! Storing the tokens issued by the oidc provider
+//!
+//! Storing them is opt-in by configuring `OpenidConnect.TokenEncryptionKey`.
+//! The tokens are encrypted using AES-256-GCM before being written to the database.
+
+use aes_gcm::aead::Aead;
+use aes_gcm::aead::OsRng;
+use aes_gcm::AeadCore;
+use aes_gcm::Aes256Gcm;
+use aes_gcm::KeyInit;
+use aes_gcm::Nonce;
+use openidconnect::core::CoreClient;
+use openidconnect::core::CoreTokenResponse;
+use openidconnect::reqwest::async_http_client;
+use openidconnect::AccessToken;
+use openidconnect::OAuth2TokenResponse;
+use openidconnect::RefreshToken;
+use rorm::db::Executor;
+use rorm::query;
+use rorm::update;
+use rorm::FieldAccess;
+use rorm::Model;
+use sha2::Digest;
+use sha2::Sha256;
+use thiserror::Error;
+use time::Duration;
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+use crate::global::GLOBAL;
+use crate::models::OidcUser;
+use crate::utils::secure_string::SecureString;
+
+/// The length of the nonce prepended to every encrypted token
+const NONCE_LEN: usize = 12;
+
+/// Access tokens expiring within this duration are refreshed ahead of time
+///
+/// This accounts for the time the request using the token takes.
+const REFRESH_MARGIN: Duration = Duration::seconds(30);
+
+/// Encrypts and decrypts the stored tokens
+pub struct TokenCipher(Aes256Gcm);
+
+impl TokenCipher {
+    /// Derives the cipher's key from the configured secret
+    pub fn new(secret: &SecureString) -> Self {
+        let key = Sha256::digest(secret.as_bytes());
+        Self(Aes256Gcm::new(&key))
+    }
+
+    /// Encrypts a token prepending the random nonce
+    fn encrypt(&self, token: &str) -> Result<Vec<u8>, OidcTokenError> {
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let ciphertext = self
+            .0
+            .encrypt(&nonce, token.as_bytes())
+            .map_err(|_| OidcTokenError::Encryption)?;
+
+        let mut data = nonce.to_vec();
+        data.extend(ciphertext);
+        Ok(data)
+    }
+
+    /// Decrypts a token produced by [`TokenCipher::encrypt`]
+    fn decrypt(&self, data: &[u8]) -> Result<String, OidcTokenError> {
+        if data.len() < NONCE_LEN {
+            return Err(OidcTokenError::Decryption);
+        }
+        let (nonce, ciphertext) = data.split_at(NONCE_LEN);
+        let plaintext = self
+            .0
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .map_err(|_| OidcTokenError::Decryption)?;
+        String::from_utf8(plaintext).map_err(|_| OidcTokenError::Decryption)
+    }
+}
+
+/// Stores the tokens of a token response for an oidc user
+///
+/// The stored refresh token is only replaced if the response contains a new one,
+/// because providers usually omit it on later logins and refreshes.
+///
+/// Does nothing if storing tokens is disabled.
+pub async fn store_tokens(
+    executor: impl Executor<'_>,
+    oidc_user_uuid: Uuid,
+    token: &CoreTokenResponse,
+) -> Result<(), OidcTokenError> {
+    let Some(cipher) = GLOBAL.oidc_token_cipher.as_ref() else {
+        return Ok(());
+    };
+
+    let access_token = cipher.encrypt(token.access_token().secret())?;
+    let expires_at = token
+        .expires_in()
+        .map(|expires_in| OffsetDateTime::now_utc() + expires_in);
+    let refresh_token = token
+        .refresh_token()
+        .map(|refresh_token| cipher.encrypt(refresh_token.secret()))
+        .transpose()?;
+
+    if let Ok(update) = update!(executor, OidcUser)
+        .condition(OidcUser::F.uuid.equals(oidc_user_uuid))
+        .begin_dyn_set()
+        .set_if(OidcUser::F.access_token, Some(Some(access_token)))
+        .set_if(OidcUser::F.access_token_expires_at, Some(expires_at))
+        .set_if(OidcUser::F.refresh_token, refresh_token.map(Some))
+        .finish_dyn_set()
+    {
+        update.exec().await?;
+    }
+    Ok(())
+}
+
+/// Retrieves a valid access token for an oidc user to call downstream APIs on their behalf
+///
+/// An expired access token is refreshed using the stored refresh token.
+///
+/// Returns `None` if there is no usable token,
+/// for example because storing tokens is disabled or the provider didn't issue a refresh token.
+pub async fn get_access_token(
+    client: &CoreClient,
+    oidc_user_uuid: Uuid,
+) -> Result<Option<AccessToken>, OidcTokenError> {
+    let Some(cipher) = GLOBAL.oidc_token_cipher.as_ref() else {
+        return Ok(None);
+    };
+
+    let Some((access_token, expires_at, refresh_token)) = query!(
+        &GLOBAL.db,
+        (
+            OidcUser::F.access_token,
+            OidcUser::F.access_token_expires_at,
+            OidcUser::F.refresh_token,
+        )
+    )
+    .condition(OidcUser::F.uuid.equals(oidc_user_uuid))
+    .optional()
+    .await?
+    else {
+        return Ok(None);
+    };
+
+    if let Some(access_token) = access_token {
+        if expires_at.map_or(true, |expires_at| {
+            expires_at - REFRESH_MARGIN > OffsetDateTime::now_utc()
+        }) {
+            return Ok(Some(AccessToken::new(cipher.decrypt(&access_token)?)));
+        }
+    }
+
+    let Some(refresh_token) = refresh_token else {
+        return Ok(None);
+    };
+    let refresh_token = RefreshToken::new(cipher.decrypt(&refresh_token)?);
+
+    let token = client
+        .exchange_refresh_token(&refresh_token)
+        .request_async(async_http_client)
+        .await
+        .map_err(|error| OidcTokenError::Refresh(error.to_string()))?;
+
+    store_tokens(&GLOBAL.db, oidc_user_uuid, &token).await?;
+
+    Ok(Some(token.access_token().clone()))
+}
+
+/// The errors which might occur while storing or retrieving oidc tokens
+#[derive(Debug, Error)]
+#[allow(missing_docs)]
+pub enum OidcTokenError {
+    #[error("Database error: {0}")]
+    Database(#[from] rorm::Error),
+    #[error("Could not encrypt token")]
+    Encryption,
+    /// The stored token has been tampered with or the key has changed
+    #[error("Could not decrypt stored token")]
+    Decryption,
+    #[error("Could not refresh the access token: {0}")]
+    Refresh(String),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::OidcTokenError;
+    use super::TokenCipher;
+    use crate::utils::secure_string::SecureString;
+
+    fn cipher(secret: &str) -> TokenCipher {
+        TokenCipher::new(&SecureString::new(secret.to_string()))
+    }
+
+    #[test]
+    fn tokens_are_decrypted_again() -> Result<(), OidcTokenError> {
+        let cipher = cipher("secret");
+        let data = cipher.encrypt("access-token")?;
+        assert_eq!(cipher.decrypt(&data)?, "access-token");
+        Ok(())
+    }
+
+    #[test]
+    fn every_encryption_uses_a_new_nonce() -> Result<(), OidcTokenError> {
+        let cipher = cipher("secret");
+        assert_ne!(
+            cipher.encrypt("access-token")?,
+            cipher.encrypt("access-token")?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn tampered_tokens_are_rejected() -> Result<(), OidcTokenError> {
+        let cipher = cipher("secret");
+        let mut data = cipher.encrypt("access-token")?;
+        let last = data.len() - 1;
+        data[last] ^= 1;
+        assert!(matches!(
+            cipher.decrypt(&data),
+            Err(OidcTokenError::Decryption)
+        ));
+        assert!(matches!(
+            cipher.decrypt(&[0; 4]),
+            Err(OidcTokenError::Decryption)
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn tokens_are_bound_to_the_secret() -> Result<(), OidcTokenError> {
+        let data = cipher("secret").encrypt("access-token")?;
+        assert!(matches!(
+            cipher("other secret").decrypt(&data),
+            Err(OidcTokenError::Decryption)
+        ));
+        Ok(())
+    }
+}
@@ -0,0 +1,37 @@
+//! The policy every password set through the api has to meet
+//!
+//! It is applied regardless of who sets the password,
+//! i.e. to self-service changes as well as to passwords set by admins or invitees.
+
+use crate::global::GLOBAL;
+
+/// Checks whether a password meets the configured policy
+pub fn meets_password_policy(password: &str) -> bool {
+    meets_min_length(password, GLOBAL.settings.get().min_password_length)
+}
+
+/// Checks whether a password is at least `min_length` characters long
+fn meets_min_length(password: &str, min_length: usize) -> bool {
+    password.chars().count() >= min_length
+}
+
+#[cfg(test)]
+mod tests {
+    use super::meets_min_length;
+
+    #[test]
+    fn accepts_passwords_of_min_length() {
+        assert!(meets_min_length("12345678", 8));
+        assert!(meets_min_length("123456789", 8));
+    }
+
+    #[test]
+    fn rejects_shorter_passwords() {
+        assert!(!meets_min_length("1234567", 8));
+    }
+
+    #[test]
+    fn counts_characters_instead_of_bytes() {
+        assert!(!meets_min_length("äöüäöüä", 8));
+    }
+}
@@ -0,0 +1,124 @@
+//! A simple in-memory rate limiter for actions which are costly or may be abused
+//!
+//! Use it for limits which depend on the request's content, like the user or mail.
+//! Limits which only bound the server's load are better expressed as tower layers.
+
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+
+/// Limits how often an action may be performed per key within a fixed window
+///
+/// Its state is not shared between multiple instances of the server.
+pub struct RateLimiter<K> {
+    /// How often the action may be performed per window
+    max: u32,
+
+    /// The duration of a window starting with the first action
+    window: Duration,
+
+    /// The currently running windows
+    windows: Mutex<BTreeMap<K, RunningWindow>>,
+}
+
+/// The state of a key's running window
+struct RunningWindow {
+    /// When did the window start
+    started: Instant,
+
+    /// How often has the action been performed in this window
+    count: u32,
+}
+
+impl<K: Ord> RateLimiter<K> {
+    /// Constructs a rate limiter allowing `max` actions per key in every `window`
+    pub const fn new(max: u32, window: Duration) -> Self {
+        Self {
+            max,
+            window,
+            windows: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Records an action for `key` if the limit allows it
+    ///
+    /// Returns the seconds until the action is allowed again otherwise.
+    /// These are meant to be returned in [`ApiError::TooManyRequests`](crate::http::common::errors::ApiError::TooManyRequests).
+    pub fn check(&self, key: K) -> Result<(), u64> {
+        self.check_at(key, Instant::now())
+    }
+
+    /// Implementation of [`RateLimiter::check`] with a given current time
+    fn check_at(&self, key: K, now: Instant) -> Result<(), u64> {
+        let mut windows = self
+            .windows
+            .lock()
+            .unwrap_or_else(|error| error.into_inner());
+
+        windows.retain(|_, window| now.duration_since(window.started) < self.window);
+
+        let window = windows.entry(key).or_insert(RunningWindow {
+            started: now,
+            count: 0,
+        });
+        if window.count >= self.max {
+            let remaining = self.window - now.duration_since(window.started);
+            return Err(remaining.as_secs() + u64::from(remaining.subsec_nanos() > 0));
+        }
+        window.count += 1;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use std::time::Instant;
+
+    use super::RateLimiter;
+
+    #[test]
+    fn allows_max_actions_per_window() {
+        let limiter = RateLimiter::new(2, Duration::from_secs(60));
+        let now = Instant::now();
+
+        assert_eq!(limiter.check_at("a", now), Ok(()));
+        assert_eq!(limiter.check_at("a", now), Ok(()));
+        assert_eq!(limiter.check_at("a", now), Err(60));
+    }
+
+    #[test]
+    fn reports_remaining_seconds_rounded_up() {
+        let limiter = RateLimiter::new(1, Duration::from_secs(60));
+        let now = Instant::now();
+
+        assert_eq!(limiter.check_at("a", now), Ok(()));
+        assert_eq!(
+            limiter.check_at("a", now + Duration::from_millis(10_500)),
+            Err(50)
+        );
+    }
+
+    #[test]
+    fn keys_are_limited_independently() {
+        let limiter = RateLimiter::new(1, Duration::from_secs(60));
+        let now = Instant::now();
+
+        assert_eq!(limiter.check_at("a", now), Ok(()));
+        assert_eq!(limiter.check_at("b", now), Ok(()));
+        assert!(limiter.check_at("a", now).is_err());
+    }
+
+    #[test]
+    fn allows_actions_again_after_the_window() {
+        let limiter = RateLimiter::new(1, Duration::from_secs(60));
+        let now = Instant::now();
+
+        assert_eq!(limiter.check_at("a", now), Ok(()));
+        assert!(limiter
+            .check_at("a", now + Duration::from_secs(59))
+            .is_err());
+        assert_eq!(limiter.check_at("a", now + Duration::from_secs(60)), Ok(()));
+    }
+}
@@ -0,0 +1,59 @@
+//! Utilities for searching the database
+
+/// Converts a search term into a `LIKE` pattern matching any string containing the term
+///
+/// Use it with an `ilike` condition for a case-insensitive search.
+pub fn like_pattern(search: &str) -> String {
+    format!("%{}%", escape_like(search))
+}
+
+/// Escapes a string's wildcards to make a `LIKE` pattern matching only the string itself
+///
+/// Use it with an `ilike` condition for a case-insensitive comparison.
+pub fn escape_like(value: &str) -> String {
+    let mut pattern = String::with_capacity(value.len());
+    for char in value.chars() {
+        if matches!(char, '%' | '_' | '\\') {
+            pattern.push('\\');
+        }
+        pattern.push(char);
+    }
+    pattern
+}
+
+#[cfg(test)]
+mod tests {
+    use super::escape_like;
+    use super::like_pattern;
+
+    #[test]
+    fn like_pattern_matches_containing_strings() {
+        assert_eq!(like_pattern("doe"), "%doe%");
+    }
+
+    #[test]
+    fn like_pattern_escapes_the_search_term() {
+        assert_eq!(like_pattern("100%"), "%100\\%%");
+        assert_eq!(like_pattern("a_b\\c"), "%a\\_b\\\\c%");
+    }
+
+    #[test]
+    fn like_pattern_of_empty_search_matches_everything() {
+        assert_eq!(like_pattern(""), "%%");
+    }
+
+    #[test]
+    fn escape_like_escapes_wildcards() {
+        assert_eq!(escape_like("50%_off"), "50\\%\\_off");
+    }
+
+    #[test]
+    fn escape_like_escapes_the_escape_character() {
+        assert_eq!(escape_like("a\\b"), "a\\\\b");
+    }
+
+    #[test]
+    fn escape_like_keeps_other_characters() {
+        assert_eq!(escape_like("Jane Doe"), "Jane Doe");
+    }
+}
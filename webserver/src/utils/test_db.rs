@@ -0,0 +1,55 @@
+//! Access to a database for tests
+//!
+//! Tests using it are marked `#[ignore = "requires a migrated database"]`
+//! and run using `cargo test -- --ignored`.
+//! `TEST_CONFIG_PATH` has to point to a config whose database is migrated.
+//! Tests should only use a transaction which is never committed to leave the database as it was.
+
+use std::env;
+use std::error::Error;
+
+use rorm::db::Executor;
+use rorm::Database;
+use rorm::DatabaseConfiguration;
+use uuid::Uuid;
+
+use crate::config::Config;
+use crate::http::handler_frontend::users::schema::UserLanguage;
+use crate::http::handler_frontend::users::schema::UserPermissions;
+use crate::models::Role;
+use crate::models::User;
+use crate::utils::checked_string::CheckedString;
+
+/// Connects to the database configured by `TEST_CONFIG_PATH`
+///
+/// Missing roles are created because users can't be created without them.
+pub async fn connect() -> Result<Database, Box<dyn Error>> {
+    let path = env::var("TEST_CONFIG_PATH")?;
+    let config = Config::try_from_path(&path)?;
+
+    let mut conf = DatabaseConfiguration::new(config.database.into());
+    conf.disable_logging = Some(true);
+    let db = Database::connect(conf).await?;
+
+    Role::ensure_seeded(&db).await?;
+    Ok(db)
+}
+
+/// Creates a user whose mail and display name start with `prefix` followed by a random suffix
+pub async fn create_user(
+    executor: impl Executor<'_>,
+    prefix: &str,
+    permissions: UserPermissions,
+) -> Result<Uuid, Box<dyn Error>> {
+    let name = format!("{prefix}-{}", Uuid::new_v4());
+    Ok(User::create(
+        executor,
+        CheckedString::new(format!("{name}@test.invalid"))?,
+        CheckedString::new(name)?,
+        UserLanguage::EN,
+        permissions,
+        true,
+        None,
+    )
+    .await?)
+}
@@ -7,21 +7,42 @@ use totp_rs::TotpUrlError;
 use totp_rs::TOTP;
 
 use crate::utils::checked_string::CheckedString;
+use crate::utils::constant_time::constant_time_eq;
 use crate::utils::secure_string::SecureString;
 
 /// Constructs a [`TOTP`] from an unencoded secret
-pub fn totp_from_binary(secret: Vec<u8>) -> Result<TOTP, TotpFromError> {
-    Ok(TOTP::from_rfc6238(Rfc6238::with_defaults(secret)?)?)
+///
+/// The `skew` is the number of time steps a token may lie in the past or future
+/// (see `Auth.TotpSkew` in the [`Config`](crate::config::Config)).
+pub fn totp_from_binary(secret: Vec<u8>, skew: u8) -> Result<TOTP, TotpFromError> {
+    let mut totp = TOTP::from_rfc6238(Rfc6238::with_defaults(secret)?)?;
+    totp.skew = skew;
+    Ok(totp)
 }
 
 /// Constructs a [`TOTP`] from a base32 encoded secret
+///
+/// See [`totp_from_binary`] for the `skew`.
 pub fn totp_from_base32(
     secret: &CheckedString<32, 64, SecureString>,
+    skew: u8,
 ) -> Result<TOTP, TotpFromError> {
     let Some(secret) = base32::decode(base32::Alphabet::Rfc4648 { padding: false }, &secret) else {
         return Err(TotpFromError::InvalidBase32);
     };
-    totp_from_binary(secret)
+    totp_from_binary(secret, skew)
+}
+
+/// Checks a token like [`TOTP::check`] but returns the time step the token belongs to
+///
+/// Tokens of `last_used_step` or earlier are rejected to prevent their replay.
+/// The returned step has to be stored as the new `last_used_step` afterwards.
+pub fn matching_step(totp: &TOTP, token: &str, time: u64, last_used_step: u64) -> Option<u64> {
+    let current = time / totp.step;
+    let skew = u64::from(totp.skew);
+    let first = current.saturating_sub(skew).max(last_used_step + 1);
+    (first..=current + skew)
+        .find(|step| constant_time_eq(totp.generate(step * totp.step).as_bytes(), token.as_bytes()))
 }
 
 /// Error returned by [`totp_from_binary`] and [`totp_from_base32`]
@@ -41,3 +62,61 @@ pub enum TotpFromError {
     #[error("This should never happen: creating `TOTP` from valid `Rfc6238` cannot fail")]
     Unreachable(#[from] TotpUrlError),
 }
+
+#[cfg(test)]
+mod tests {
+    use super::matching_step;
+    use super::totp_from_binary;
+    use super::TotpFromError;
+
+    const SECRET: &[u8] = b"12345678901234567890";
+
+    /// A point in time at the start of a time step
+    const NOW: u64 = 1_000_020;
+
+    #[test]
+    fn accepts_token_of_current_step() -> Result<(), TotpFromError> {
+        let totp = totp_from_binary(SECRET.to_vec(), 1)?;
+        let token = totp.generate(NOW);
+        assert_eq!(matching_step(&totp, &token, NOW, 0), Some(NOW / 30));
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_replayed_token() -> Result<(), TotpFromError> {
+        let totp = totp_from_binary(SECRET.to_vec(), 1)?;
+        let token = totp.generate(NOW);
+        let step = matching_step(&totp, &token, NOW, 0);
+        assert_eq!(step, Some(NOW / 30));
+        assert_eq!(matching_step(&totp, &token, NOW, NOW / 30), None);
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_token_of_step_before_last_used() -> Result<(), TotpFromError> {
+        let totp = totp_from_binary(SECRET.to_vec(), 1)?;
+        let token = totp.generate(NOW - 30);
+        assert_eq!(matching_step(&totp, &token, NOW, NOW / 30), None);
+        Ok(())
+    }
+
+    #[test]
+    fn accepts_tokens_one_step_inside_skew() -> Result<(), TotpFromError> {
+        let totp = totp_from_binary(SECRET.to_vec(), 1)?;
+        let past = totp.generate(NOW - 30);
+        let future = totp.generate(NOW + 30);
+        assert_eq!(matching_step(&totp, &past, NOW, 0), Some(NOW / 30 - 1));
+        assert_eq!(matching_step(&totp, &future, NOW, 0), Some(NOW / 30 + 1));
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_tokens_one_step_outside_skew() -> Result<(), TotpFromError> {
+        let totp = totp_from_binary(SECRET.to_vec(), 1)?;
+        let past = totp.generate(NOW - 60);
+        let future = totp.generate(NOW + 60);
+        assert_eq!(matching_step(&totp, &past, NOW, 0), None);
+        assert_eq!(matching_step(&totp, &future, NOW, 0), None);
+        Ok(())
+    }
+}
@@ -1,21 +1,139 @@
 //! Utilities for working with webauthn which are shared among multiple groups of handlers
 
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::path::PathBuf;
+
+use axum::response::IntoResponse;
+use axum::response::Response;
+use futures::TryStreamExt;
+use rorm::db::Executor;
+use rorm::query;
+use rorm::FieldAccess;
+use rorm::Model;
 use schemars::JsonSchema;
 use serde::Deserialize;
 use serde::Serialize;
+use swaggapi::as_responses::AsResponses;
+use swaggapi::internals::SchemaGenerator;
+use swaggapi::re_exports::openapiv3::Responses;
+use swaggapi::utils::SchemalessJson;
+use thiserror::Error;
+use time::Duration;
+use time::OffsetDateTime;
+use webauthn_rs::prelude::AttestationCaList;
+use webauthn_rs::prelude::CreationChallengeResponse;
+use webauthn_rs::prelude::CredentialID;
 use webauthn_rs::prelude::WebauthnError;
 
+use crate::global::GLOBAL;
+use crate::http::extractors::api_json::ApiJson;
+use crate::models::WebAuthnKey;
+
+/// Checks whether a registration started at `timestamp` exceeded the configured enrollment timeout
+pub fn is_enrollment_expired(timestamp: OffsetDateTime) -> bool {
+    is_enrollment_expired_at(
+        timestamp,
+        OffsetDateTime::now_utc(),
+        GLOBAL.settings.get().enrollment_timeout,
+    )
+}
+
+/// Implementation of [`is_enrollment_expired`] with a given current time and timeout
+fn is_enrollment_expired_at(
+    timestamp: OffsetDateTime,
+    now: OffsetDateTime,
+    timeout: Duration,
+) -> bool {
+    now - timestamp > timeout
+}
+
+/// Gathers the credential ids of all registered webauthn keys
+///
+/// A credential may only be registered by a single user,
+/// so all of them are excluded when registering a new key.
+pub async fn get_registered_credentials(
+    executor: impl Executor<'_>,
+) -> Result<Vec<CredentialID>, rorm::Error> {
+    query!(executor, (WebAuthnKey::F.key,))
+        .stream()
+        .map_ok(|(key,)| key.0.cred_id().clone())
+        .try_collect()
+        .await
+}
+
+/// Checks whether a credential has already been registered as webauthn key by any user
+///
+/// Call this before any other change when completing a registration.
+/// The exclusion list of [`get_registered_credentials`] is enforced by the browser only.
+pub async fn is_credential_registered(
+    executor: impl Executor<'_>,
+    cred_id: &CredentialID,
+) -> Result<bool, rorm::Error> {
+    Ok(query!(executor, (WebAuthnKey::F.uuid,))
+        .condition(WebAuthnKey::F.cred_id.equals(cred_id.to_vec()))
+        .optional()
+        .await?
+        .is_some())
+}
+
+/// Reads the list of attestation CAs generated by `fido-mds-tool query`
+///
+/// An empty list is rejected because it would make every registration of a login key fail.
+pub fn load_attestation_ca_list(path: &Path) -> Result<AttestationCaList, LoadCaListError> {
+    let file = fs::File::open(path).map_err(|source| LoadCaListError::Io {
+        path: path.to_path_buf(),
+        source,
+    })?;
+    let ca_list: AttestationCaList =
+        serde_json::from_reader(io::BufReader::new(file)).map_err(|source| {
+            LoadCaListError::Malformed {
+                path: path.to_path_buf(),
+                source,
+            }
+        })?;
+    if ca_list.is_empty() {
+        return Err(LoadCaListError::Empty {
+            path: path.to_path_buf(),
+        });
+    }
+    Ok(ca_list)
+}
+
+/// The error returned by [`load_attestation_ca_list`]
+#[derive(Debug, Error)]
+#[allow(missing_docs)]
+pub enum LoadCaListError {
+    #[error("Could not read the attestation CA list {}: {source}", path.display())]
+    Io { path: PathBuf, source: io::Error },
+    /// The json error's message contains the line and column
+    #[error("The attestation CA list {} is malformed: {source}", path.display())]
+    Malformed {
+        path: PathBuf,
+        source: serde_json::Error,
+    },
+    #[error("The attestation CA list {} doesn't contain any CA", path.display())]
+    Empty { path: PathBuf },
+}
+
 /// The result when registering a new webauthn key
 #[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 #[serde(tag = "result")]
 pub enum WebAuthnRegisterResult {
     /// The key was registered successfully
     Ok,
-    /// The used device is rejected to be used with attestation
+    /// The used device is rejected to be used with attestation or is already registered
     RejectedDevice,
     /// The browser denied the access to device information which is required to check attestation
     MissingDevice,
-    // Other errors are mapped to `ApiError::InternalServerError`
+    /// The device didn't verify the user (PIN, biometrics, etc.) although it was required
+    UserNotVerified,
+    /// The device only supports algorithms which are not accepted
+    UnsupportedAlgorithm,
+    /// The server has no attestation CAs configured, so keys able to log in can't be registered
+    AttestationUnavailable,
+    // Other errors are mapped to `ApiError::BadRequest`
 }
 impl WebAuthnRegisterResult {
     /// Maps some variants of `WebauthnError` into a `WebAuthnRegisterResult`
@@ -24,7 +142,172 @@ impl WebAuthnRegisterResult {
     pub fn parse(error: &WebauthnError) -> Option<Self> {
         Some(match error {
             WebauthnError::AttestationNotVerifiable => Self::MissingDevice,
+            WebauthnError::AttestationTrustFailure
+            | WebauthnError::AttestationChainNotTrusted(_)
+            | WebauthnError::CredentialExcludedFromRequest => Self::RejectedDevice,
+            WebauthnError::UserNotVerified | WebauthnError::UserNotPresent => Self::UserNotVerified,
+            WebauthnError::CredentialAlteredAlgFromRequest
+            | WebauthnError::CredentialInsecureCryptography => Self::UnsupportedAlgorithm,
             _ => return None,
         })
     }
 }
+
+/// The response when starting to register a new webauthn key
+///
+/// The registration is either started by sending a challenge to the browser
+/// or rejected with a [`WebAuthnRegisterResult`] before contacting the device.
+pub enum StartWebAuthnRegisterResponse {
+    /// The challenge the device has to answer
+    Challenge(CreationChallengeResponse),
+    /// The registration can't be started
+    Rejected(WebAuthnRegisterResult),
+}
+
+impl IntoResponse for StartWebAuthnRegisterResponse {
+    fn into_response(self) -> Response {
+        match self {
+            Self::Challenge(challenge) => SchemalessJson(challenge).into_response(),
+            Self::Rejected(result) => ApiJson(result).into_response(),
+        }
+    }
+}
+
+impl AsResponses for StartWebAuthnRegisterResponse {
+    fn responses(gen: &mut SchemaGenerator) -> Responses {
+        // The challenge has no schema, so it can't be combined with the result's
+        SchemalessJson::<CreationChallengeResponse>::responses(gen)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::error::Error;
+    use std::fs;
+    use std::path::Path;
+    use std::path::PathBuf;
+
+    use axum::body::to_bytes;
+    use axum::http::StatusCode;
+    use axum::response::IntoResponse;
+    use serde_json::json;
+    use serde_json::Value;
+    use time::Duration;
+    use time::OffsetDateTime;
+    use uuid::Uuid;
+    use webauthn_rs::prelude::AttestationCaList;
+    use webauthn_rs::prelude::WebauthnError;
+
+    use super::is_enrollment_expired_at;
+    use super::load_attestation_ca_list;
+    use super::LoadCaListError;
+    use super::StartWebAuthnRegisterResponse;
+    use super::WebAuthnRegisterResult;
+
+    #[tokio::test]
+    async fn rejected_registration_responds_with_result() -> Result<(), Box<dyn Error>> {
+        let response =
+            StartWebAuthnRegisterResponse::Rejected(WebAuthnRegisterResult::AttestationUnavailable)
+                .into_response();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body: Value =
+            serde_json::from_slice(&to_bytes(response.into_body(), usize::MAX).await?)?;
+        assert_eq!(body, json!({ "result": "AttestationUnavailable" }));
+        Ok(())
+    }
+
+    #[test]
+    fn enrollment_within_timeout_is_not_expired() {
+        let started = OffsetDateTime::now_utc();
+        let timeout = Duration::minutes(5);
+        assert!(!is_enrollment_expired_at(started, started, timeout));
+        assert!(!is_enrollment_expired_at(
+            started,
+            started + timeout,
+            timeout
+        ));
+    }
+
+    #[test]
+    fn enrollment_exceeding_timeout_is_expired() {
+        let started = OffsetDateTime::now_utc();
+        let timeout = Duration::minutes(5);
+        assert!(is_enrollment_expired_at(
+            started,
+            started + timeout + Duration::seconds(1),
+            timeout
+        ));
+    }
+
+    #[test]
+    fn missing_user_verification_is_reported() {
+        assert!(matches!(
+            WebAuthnRegisterResult::parse(&WebauthnError::UserNotVerified),
+            Some(WebAuthnRegisterResult::UserNotVerified)
+        ));
+        assert!(matches!(
+            WebAuthnRegisterResult::parse(&WebauthnError::UserNotPresent),
+            Some(WebAuthnRegisterResult::UserNotVerified)
+        ));
+    }
+
+    #[test]
+    fn unsupported_algorithms_are_reported() {
+        assert!(matches!(
+            WebAuthnRegisterResult::parse(&WebauthnError::CredentialAlteredAlgFromRequest),
+            Some(WebAuthnRegisterResult::UnsupportedAlgorithm)
+        ));
+        assert!(matches!(
+            WebAuthnRegisterResult::parse(&WebauthnError::CredentialInsecureCryptography),
+            Some(WebAuthnRegisterResult::UnsupportedAlgorithm)
+        ));
+    }
+
+    #[test]
+    fn excluded_credentials_are_rejected() {
+        assert!(matches!(
+            WebAuthnRegisterResult::parse(&WebauthnError::CredentialExcludedFromRequest),
+            Some(WebAuthnRegisterResult::RejectedDevice)
+        ));
+    }
+
+    #[test]
+    fn other_errors_are_not_mapped() {
+        assert!(WebAuthnRegisterResult::parse(&WebauthnError::MismatchedChallenge).is_none());
+    }
+
+    /// Writes `contents` to a new file in the temp dir
+    fn temp_file(contents: &[u8]) -> Result<PathBuf, Box<dyn Error>> {
+        let path = env::temp_dir().join(format!("attestation-ca-list-{}.json", Uuid::new_v4()));
+        fs::write(&path, contents)?;
+        Ok(path)
+    }
+
+    #[test]
+    fn missing_ca_list_is_reported() {
+        assert!(matches!(
+            load_attestation_ca_list(Path::new("/nonexistent/ca-list.json")),
+            Err(LoadCaListError::Io { .. })
+        ));
+    }
+
+    #[test]
+    fn malformed_ca_list_is_reported() -> Result<(), Box<dyn Error>> {
+        let path = temp_file(b"not json")?;
+        let result = load_attestation_ca_list(&path);
+        fs::remove_file(&path)?;
+        assert!(matches!(result, Err(LoadCaListError::Malformed { .. })));
+        Ok(())
+    }
+
+    #[test]
+    fn empty_ca_list_is_rejected() -> Result<(), Box<dyn Error>> {
+        let path = temp_file(&serde_json::to_vec(&AttestationCaList::default())?)?;
+        let result = load_attestation_ca_list(&path);
+        fs::remove_file(&path)?;
+        assert!(matches!(result, Err(LoadCaListError::Empty { .. })));
+        Ok(())
+    }
+}
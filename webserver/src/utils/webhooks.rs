@@ -0,0 +1,328 @@
+//! Notifying downstream systems about events via webhooks
+//!
+//! The [`Webhooks`] are optional and only available if the config contains a `Webhooks` section.
+//!
+//! Events are posted as json to every configured endpoint.
+//! The body is signed using HMAC-SHA256 and the hex encoded signature is sent in the
+//! [`SIGNATURE_HEADER`] header.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use hmac::Hmac;
+use hmac::Mac;
+use reqwest::header::CONTENT_TYPE;
+use serde::Serialize;
+use sha2::Sha256;
+use time::OffsetDateTime;
+use tokio::sync::mpsc;
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::sync::Semaphore;
+use tracing::debug;
+use tracing::error;
+use tracing::warn;
+use uuid::Uuid;
+use webauthn_rs::prelude::Url;
+
+use crate::config::WebhooksConfig;
+use crate::global::GLOBAL;
+use crate::models::UserRole;
+use crate::utils::secure_string::SecureString;
+
+/// The header containing the hex encoded HMAC-SHA256 of the body
+pub const SIGNATURE_HEADER: &str = "X-Webhook-Signature";
+
+/// The header containing the event's name
+pub const EVENT_HEADER: &str = "X-Webhook-Event";
+
+/// The maximum number of deliveries which are attempted at the same time
+const MAX_CONCURRENT_DELIVERIES: usize = 16;
+
+/// The delay before the first retry of a failed delivery
+///
+/// It is doubled for every further retry.
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+
+/// The maximum delay between two retries of a failed delivery
+const MAX_BACKOFF: Duration = Duration::from_secs(5 * 60);
+
+/// The events downstream systems are notified about
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event")]
+pub enum WebhookEvent {
+    /// A user has been created
+    #[serde(rename = "user.created")]
+    UserCreated {
+        /// The new user
+        user: Uuid,
+    },
+    /// A user has been deleted
+    #[serde(rename = "user.deleted")]
+    UserDeleted {
+        /// The deleted user
+        user: Uuid,
+    },
+    /// A user's role has been changed
+    #[serde(rename = "user.role_changed")]
+    UserRoleChanged {
+        /// The user whose role has been changed
+        user: Uuid,
+        /// The user's previous role
+        old_role: UserRole,
+        /// The user's new role
+        new_role: UserRole,
+    },
+    /// An invite has been accepted which created a new user
+    #[serde(rename = "invite.accepted")]
+    InviteAccepted {
+        /// The accepted invite
+        invite: Uuid,
+        /// The user created by accepting the invite
+        user: Uuid,
+    },
+}
+impl WebhookEvent {
+    /// The event's name which is sent in the payload and the [`EVENT_HEADER`]
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::UserCreated { .. } => "user.created",
+            Self::UserDeleted { .. } => "user.deleted",
+            Self::UserRoleChanged { .. } => "user.role_changed",
+            Self::InviteAccepted { .. } => "invite.accepted",
+        }
+    }
+}
+
+/// The json body posted to the endpoints
+#[derive(Debug, Clone, Serialize)]
+struct WebhookPayload {
+    /// Identifies the event across retries and endpoints
+    id: Uuid,
+    /// The point in time the event occurred
+    #[serde(with = "time::serde::rfc3339")]
+    timestamp: OffsetDateTime,
+    #[serde(flatten)]
+    event: WebhookEvent,
+}
+
+/// Queue of events to be posted to the configured endpoints
+#[derive(Debug, Clone)]
+pub struct Webhooks {
+    tx: mpsc::Sender<WebhookPayload>,
+}
+
+impl Webhooks {
+    /// Constructs the webhooks from the config
+    ///
+    /// A new task will be spawned that delivers the queued events
+    pub fn new(config: &WebhooksConfig) -> Result<Self, reqwest::Error> {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(config.timeout_secs))
+            .build()?;
+        let (tx, rx) = mpsc::channel(config.queue_size);
+
+        tokio::spawn(run_webhooks(
+            rx,
+            Arc::new(Dispatcher {
+                client,
+                endpoints: config.endpoints.clone(),
+                secret: config.secret.clone(),
+                max_retries: config.max_retries,
+            }),
+        ));
+
+        Ok(Self { tx })
+    }
+
+    /// Queues an event to be posted to every endpoint
+    ///
+    /// This never waits for the event to be delivered.
+    /// If the queue is full, the event is dropped.
+    pub fn emit(&self, event: WebhookEvent) {
+        let payload = WebhookPayload {
+            id: Uuid::new_v4(),
+            timestamp: OffsetDateTime::now_utc(),
+            event,
+        };
+        match self.tx.try_send(payload) {
+            Ok(()) => {}
+            Err(TrySendError::Full(payload)) => {
+                warn!(
+                    event = payload.event.name(),
+                    "The webhook queue is full, dropping event"
+                );
+            }
+            Err(TrySendError::Closed(_)) => error!("Could not send to Webhooks: closed"),
+        }
+    }
+}
+
+/// Queues an event if webhooks have been configured
+///
+/// Shorthand for calling [`Webhooks::emit`] on [`GLOBAL.webhooks`](crate::global::GlobalEntities::webhooks).
+pub fn emit(event: WebhookEvent) {
+    if let Some(webhooks) = GLOBAL.webhooks.as_ref() {
+        webhooks.emit(event);
+    }
+}
+
+/// Everything required to deliver an event
+struct Dispatcher {
+    client: reqwest::Client,
+    endpoints: Vec<Url>,
+    secret: SecureString,
+    max_retries: u32,
+}
+
+async fn run_webhooks(mut rx: mpsc::Receiver<WebhookPayload>, dispatcher: Arc<Dispatcher>) {
+    let permits = Arc::new(Semaphore::new(MAX_CONCURRENT_DELIVERIES));
+
+    while let Some(payload) = rx.recv().await {
+        let body = match serde_json::to_vec(&payload) {
+            Ok(body) => body,
+            Err(error) => {
+                error!(error = %error, "Could not serialize webhook payload");
+                continue;
+            }
+        };
+        let signature: Arc<str> = dispatcher.sign(&body).into();
+        let body: Arc<[u8]> = body.into();
+
+        for endpoint in &dispatcher.endpoints {
+            // Waiting for a permit lets the queue fill up instead of spawning unbounded tasks
+            let Ok(permit) = permits.clone().acquire_owned().await else {
+                return;
+            };
+            tokio::spawn({
+                let dispatcher = dispatcher.clone();
+                let endpoint = endpoint.clone();
+                let body = body.clone();
+                let signature = signature.clone();
+                let event = payload.event.name();
+                async move {
+                    dispatcher
+                        .deliver(&endpoint, event, &body, &signature)
+                        .await;
+                    drop(permit);
+                }
+            });
+        }
+    }
+}
+
+impl Dispatcher {
+    /// Calculates the hex encoded HMAC-SHA256 of a body
+    fn sign(&self, body: &[u8]) -> String {
+        #[allow(clippy::expect_used)]
+        let mut mac = Hmac::<Sha256>::new_from_slice(self.secret.as_bytes())
+            .expect("HMAC accepts keys of any length");
+        mac.update(body);
+        mac.finalize()
+            .into_bytes()
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect()
+    }
+
+    /// Posts a body to an endpoint, retrying with an exponential backoff
+    async fn deliver(&self, endpoint: &Url, event: &str, body: &[u8], signature: &str) {
+        let mut backoff = INITIAL_BACKOFF;
+        for attempt in 0..=self.max_retries {
+            if attempt > 0 {
+                tokio::time::sleep(backoff).await;
+                backoff = (backoff * 2).min(MAX_BACKOFF);
+            }
+
+            let result = self
+                .client
+                .post(endpoint.clone())
+                .header(CONTENT_TYPE, "application/json")
+                .header(EVENT_HEADER, event)
+                .header(SIGNATURE_HEADER, signature)
+                .body(body.to_vec())
+                .send()
+                .await
+                .and_then(|response| response.error_for_status());
+            match result {
+                Ok(_) => {
+                    debug!(%endpoint, event, "Delivered webhook");
+                    return;
+                }
+                Err(error) => {
+                    warn!(%endpoint, event, attempt, error = %error, "Failed to deliver webhook");
+                }
+            }
+        }
+        error!(%endpoint, event, "Giving up on delivering webhook");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use time::OffsetDateTime;
+    use tokio::sync::mpsc;
+    use uuid::Uuid;
+
+    use super::Dispatcher;
+    use super::WebhookEvent;
+    use super::WebhookPayload;
+    use super::Webhooks;
+    use crate::models::UserRole;
+    use crate::utils::secure_string::SecureString;
+
+    #[test]
+    fn payload_is_tagged_with_the_event_name() -> Result<(), serde_json::Error> {
+        let user = Uuid::new_v4();
+        let event = WebhookEvent::UserRoleChanged {
+            user,
+            old_role: UserRole::Internal,
+            new_role: UserRole::Administrator,
+        };
+        let payload = WebhookPayload {
+            id: Uuid::new_v4(),
+            timestamp: OffsetDateTime::UNIX_EPOCH,
+            event: event.clone(),
+        };
+
+        let value = serde_json::to_value(&payload)?;
+        assert_eq!(value["event"], event.name());
+        assert_eq!(value["user"], json!(user));
+        assert_eq!(value["old_role"], "Internal");
+        assert_eq!(value["timestamp"], "1970-01-01T00:00:00Z");
+        Ok(())
+    }
+
+    #[test]
+    fn body_is_signed_with_hex_encoded_hmac_sha256() {
+        // Test case 2 of RFC 4231
+        let dispatcher = Dispatcher {
+            client: reqwest::Client::new(),
+            endpoints: Vec::new(),
+            secret: SecureString::new("Jefe".to_string()),
+            max_retries: 0,
+        };
+        assert_eq!(
+            dispatcher.sign(b"what do ya want for nothing?"),
+            "5bdcc146bf60754e6a042426089575c75a003f083d9a39839dec58b964ec3843"
+        );
+    }
+
+    #[test]
+    fn events_are_dropped_when_the_queue_is_full() {
+        let (tx, mut rx) = mpsc::channel(1);
+        let webhooks = Webhooks { tx };
+        let first = Uuid::new_v4();
+        webhooks.emit(WebhookEvent::UserCreated { user: first });
+        webhooks.emit(WebhookEvent::UserDeleted { user: first });
+
+        assert!(matches!(
+            rx.try_recv(),
+            Ok(WebhookPayload {
+                event: WebhookEvent::UserCreated { user },
+                ..
+            }) if user == first
+        ));
+        assert!(rx.try_recv().is_err());
+    }
+}